@@ -0,0 +1,90 @@
+//! Clients connected to the board, see `lorelay_proto::connections`.
+//!
+//! Each client is served by its own task, which registers the CCCD writes of its connection
+//! here. The values of the node are set once and notified to every client subscribed to them.
+use core::cell::RefCell;
+use defmt::warn;
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::signal::Signal;
+use lorelay_proto::connections::{Characteristic, ConnectionRegistry};
+use nrf_softdevice::ble::gatt_server::NotifyValueError;
+use nrf_softdevice::ble::Connection;
+
+/// Clients connected at once, such as a phone and a logging tablet.
+pub const MAX_CONNECTIONS: usize = 3;
+
+static CONNECTIONS: Mutex<
+    ThreadModeRawMutex,
+    RefCell<ConnectionRegistry<Connection, MAX_CONNECTIONS>>,
+> = Mutex::new(RefCell::new(ConnectionRegistry::new()));
+
+static CONNECTED: Signal<ThreadModeRawMutex, ()> = Signal::new();
+
+static DISCONNECTED: Signal<ThreadModeRawMutex, ()> = Signal::new();
+
+/// Registers a new client, returning the handle of its connection. `None` if it disconnected
+/// already, or no more clients fit.
+pub fn register(connection: &Connection) -> Option<u16> {
+    let handle = connection.handle()?;
+    CONNECTIONS
+        .lock(|connections| connections.borrow_mut().add(handle, connection.clone()))
+        .ok()?;
+    CONNECTED.signal(());
+    Some(handle)
+}
+
+/// Forgets a client that disconnected.
+pub fn unregister(handle: u16) {
+    CONNECTIONS.lock(|connections| connections.borrow_mut().remove(handle));
+    DISCONNECTED.signal(());
+}
+
+/// Waits until a client is connected.
+pub async fn wait_for_client() {
+    while CONNECTIONS.lock(|connections| connections.borrow().is_empty()) {
+        CONNECTED.wait().await;
+    }
+}
+
+/// Waits until another client can connect.
+pub async fn wait_for_room() {
+    while CONNECTIONS.lock(|connections| connections.borrow().is_full()) {
+        DISCONNECTED.wait().await;
+    }
+}
+
+/// Records a CCCD write of the client on `handle`.
+pub fn set_subscribed(handle: u16, characteristic: Characteristic, subscribed: bool) {
+    CONNECTIONS.lock(|connections| {
+        connections
+            .borrow_mut()
+            .set_subscribed(handle, characteristic, subscribed);
+    });
+}
+
+pub fn is_subscribed(handle: u16, characteristic: Characteristic) -> bool {
+    CONNECTIONS.lock(|connections| {
+        connections
+            .borrow()
+            .subscriptions(handle)
+            .is_some_and(|subscriptions| subscriptions.contains(characteristic))
+    })
+}
+
+/// Notifies every client subscribed to `characteristic` with `notify`.
+pub fn notify_subscribers(
+    characteristic: Characteristic,
+    mut notify: impl FnMut(&Connection) -> Result<(), NotifyValueError>,
+) {
+    CONNECTIONS.lock(|connections| {
+        for (handle, connection) in connections.borrow().subscribers(characteristic) {
+            if let Err(err) = notify(connection) {
+                warn!(
+                    "Failed to notify {} to connection {}: {}",
+                    characteristic, handle, err
+                );
+            }
+        }
+    });
+}
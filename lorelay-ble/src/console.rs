@@ -0,0 +1,153 @@
+//! Text console on the Nordic UART Service, see `lorelay_proto::console`.
+//!
+//! Every client has a console of its own, whose lines run on `LinkNode`, which answers from what
+//! the LoRa board reported last and forwards requests to it over `link`. Responses are notified
+//! to the client in chunks small enough for the default ATT MTU.
+use crate::{connections, gateway, Server};
+use core::cell::RefCell;
+use defmt::{info, unwrap, warn};
+use embassy_sync::blocking_mutex::raw::{NoopRawMutex, ThreadModeRawMutex};
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::channel::Channel;
+use embassy_time::{Duration, Timer};
+use heapless::{String, Vec};
+use lorelay_proto::connections::Characteristic;
+use lorelay_proto::console::{Busy, Console, Node};
+use lorelay_proto::device_config::DeviceConfig;
+use lorelay_proto::gateway::{NeighbourEntry, NodeStatus, OutgoingMessage};
+use lorelay_proto::link::Packet;
+use lorelay_proto::relay::NEIGHBOUR_TABLE_SIZE;
+use nrf_softdevice::ble::gatt_server::NotifyValueError;
+use nrf_softdevice::ble::Connection;
+use nrf_softdevice::RawError;
+
+/// Largest write to the RX characteristic.
+pub const MAX_WRITE_SIZE: usize = 128;
+
+/// Notified at once, the payload of the default ATT MTU.
+pub const CHUNK_SIZE: usize = 20;
+
+/// Responses to the lines of one write, the rest of longer ones is dropped.
+const MAX_RESPONSE_SIZE: usize = 1024;
+
+/// Bytes written by one client.
+pub type Writes = Channel<NoopRawMutex, Vec<u8, MAX_WRITE_SIZE>, 4>;
+
+/// Requests for the LoRa board, taken by `link`.
+pub static REQUESTS: Channel<ThreadModeRawMutex, Packet, 2> = Channel::new();
+
+/// What the LoRa board reported last.
+struct NodeState {
+    status: Option<NodeStatus>,
+    neighbours: Vec<NeighbourEntry, NEIGHBOUR_TABLE_SIZE>,
+    config: Option<DeviceConfig>,
+}
+
+static NODE_STATE: Mutex<ThreadModeRawMutex, RefCell<NodeState>> =
+    Mutex::new(RefCell::new(NodeState {
+        status: None,
+        neighbours: Vec::new(),
+        config: None,
+    }));
+
+pub fn on_status(status: NodeStatus) {
+    NODE_STATE.lock(|state| state.borrow_mut().status = Some(status));
+}
+
+pub fn on_neighbours(neighbours: &Vec<NeighbourEntry, NEIGHBOUR_TABLE_SIZE>) {
+    NODE_STATE.lock(|state| state.borrow_mut().neighbours = neighbours.clone());
+}
+
+pub fn on_config(config: DeviceConfig) {
+    NODE_STATE.lock(|state| state.borrow_mut().config = Some(config));
+}
+
+fn request(packet: Packet) -> Result<(), Busy> {
+    REQUESTS.try_send(packet).map_err(|_| Busy)
+}
+
+/// The node, as seen from the BLE board.
+struct LinkNode;
+
+impl Node for LinkNode {
+    fn status(&self) -> Option<NodeStatus> {
+        NODE_STATE.lock(|state| state.borrow().status)
+    }
+
+    fn neighbours(&self) -> Vec<NeighbourEntry, NEIGHBOUR_TABLE_SIZE> {
+        NODE_STATE.lock(|state| state.borrow().neighbours.clone())
+    }
+
+    fn config(&self) -> Option<DeviceConfig> {
+        NODE_STATE.lock(|state| state.borrow().config)
+    }
+
+    fn send(&mut self, message: OutgoingMessage) -> Result<(), Busy> {
+        gateway::OUTGOING.try_send(message).map_err(|_| Busy)
+    }
+
+    fn set_config(&mut self, config: DeviceConfig) -> Result<(), Busy> {
+        request(Packet::SetConfig(config))
+    }
+
+    fn set_mode(&mut self, mode: u8) -> Result<(), Busy> {
+        request(Packet::SetMode(mode))
+    }
+
+    /// Reboots the LoRa board, then the BLE board.
+    fn reboot(&mut self) -> Result<(), Busy> {
+        request(Packet::Reboot)
+    }
+}
+
+/// Queues the bytes written to the RX characteristic.
+pub fn on_rx_write(writes: &Writes, value: &[u8]) {
+    // Cannot fail, the characteristic is as large as the writes.
+    let bytes = unwrap!(Vec::from_slice(value));
+    if writes.try_send(bytes).is_err() {
+        warn!("Console writes queue full, dropping {} bytes", value.len());
+    }
+}
+
+/// Runs the lines the client on `handle` writes, notifying it of the responses.
+pub async fn serve(server: &Server, connection: &Connection, handle: u16, writes: &Writes) {
+    let mut console = Console::new();
+    loop {
+        let bytes = writes.recv().await;
+        let mut response: String<MAX_RESPONSE_SIZE> = String::new();
+        if console
+            .on_bytes(&bytes, &mut LinkNode, &mut response)
+            .is_err()
+        {
+            warn!("Console response truncated");
+        }
+        if !connections::is_subscribed(handle, Characteristic::ConsoleTx) {
+            info!("Console client {} not subscribed", handle);
+            continue;
+        }
+        for chunk in response.as_bytes().chunks(CHUNK_SIZE) {
+            if !notify(server, connection, chunk).await {
+                break;
+            }
+        }
+    }
+}
+
+/// Notifies `chunk`, waiting for room in the notification queue. False if the client will not
+/// get it.
+async fn notify(server: &Server, connection: &Connection, chunk: &[u8]) -> bool {
+    // Cannot fail, chunks are as large as the characteristic at most.
+    let value = unwrap!(Vec::from_slice(chunk));
+    loop {
+        match server.nus.tx_notify(connection, &value) {
+            Ok(()) => return true,
+            Err(NotifyValueError::Raw(RawError::Resources)) => {
+                Timer::after(Duration::from_millis(10)).await
+            }
+            Err(err) => {
+                warn!("Failed to notify the console: {}", err);
+                return false;
+            }
+        }
+    }
+}
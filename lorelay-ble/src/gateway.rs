@@ -0,0 +1,122 @@
+//! Bridge between the lorelay GATT service and the LoRa board.
+//!
+//! Messages written by the client wait in `OUTGOING` for the link to the LoRa board, which fills
+//! `INCOMING`, `NODE_STATUS`, `NEIGHBOURS` and `DELIVERY` in return. The values are encoded with
+//! `lorelay_proto::gateway`, as on the LoRa board.
+use crate::{connections, Server};
+use defmt::{info, unwrap, warn};
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_sync::signal::Signal;
+use futures::future::join4;
+use heapless::Vec;
+use lorelay_proto::ack::Delivery;
+use lorelay_proto::connections::Characteristic;
+use lorelay_proto::gateway::{
+    encode_delivery, encode_neighbours, IncomingMessage, NeighbourEntry, NodeStatus,
+    OutgoingMessage, DELIVERY_SIZE, MAX_INCOMING_SIZE, MAX_NEIGHBOURS_SIZE, STATUS_SIZE,
+};
+use lorelay_proto::relay::NEIGHBOUR_TABLE_SIZE;
+
+/// Messages written by the client, to send over the mesh.
+pub static OUTGOING: Channel<ThreadModeRawMutex, OutgoingMessage, 4> = Channel::new();
+
+/// Messages the mesh delivered to the LoRa board.
+pub static INCOMING: Channel<ThreadModeRawMutex, IncomingMessage, 4> = Channel::new();
+
+pub static NODE_STATUS: Signal<ThreadModeRawMutex, NodeStatus> = Signal::new();
+
+pub static NEIGHBOURS: Signal<ThreadModeRawMutex, Vec<NeighbourEntry, NEIGHBOUR_TABLE_SIZE>> =
+    Signal::new();
+
+/// Outcome of the last message the client wanted acknowledged.
+pub static DELIVERY: Signal<ThreadModeRawMutex, Delivery> = Signal::new();
+
+/// Queues the message written to the outgoing characteristic.
+pub fn on_outgoing_write(value: &[u8]) {
+    let message = match OutgoingMessage::decode(value) {
+        Ok(message) => message,
+        Err(err) => {
+            warn!("Invalid outgoing message: {}", err);
+            return;
+        }
+    };
+    info!(
+        "Outgoing message to {}: {=[u8]:x}",
+        message.destination_uid, message.payload
+    );
+    if OUTGOING.try_send(message).is_err() {
+        warn!("Outgoing queue full, dropping message");
+    }
+}
+
+/// Notifies the clients of the messages, status and neighbours of the LoRa board, and of the
+/// delivery of their messages.
+///
+/// Values are set for every client to read them, and notified to those that subscribed.
+pub async fn notify(server: &Server) {
+    join4(
+        notify_incoming(server),
+        notify_status(server),
+        notify_neighbours(server),
+        notify_delivery(server),
+    )
+    .await;
+}
+
+async fn notify_incoming(server: &Server) {
+    loop {
+        let message = INCOMING.recv().await;
+        let mut buf = [0u8; MAX_INCOMING_SIZE];
+        let len = unwrap!(message.encode(&mut buf));
+        // Cannot fail, the buffer is as large as the characteristic.
+        let value = unwrap!(Vec::from_slice(&buf[..len]));
+
+        info!("Incoming message from {}", message.origin_uid);
+        unwrap!(server.lorelay.incoming_set(&value));
+        connections::notify_subscribers(Characteristic::Incoming, |connection| {
+            server.lorelay.incoming_notify(connection, &value)
+        });
+    }
+}
+
+async fn notify_status(server: &Server) {
+    loop {
+        let status = NODE_STATUS.wait().await;
+        let mut value = [0u8; STATUS_SIZE];
+        unwrap!(status.encode(&mut value));
+
+        unwrap!(server.lorelay.status_set(&value));
+        connections::notify_subscribers(Characteristic::Status, |connection| {
+            server.lorelay.status_notify(connection, &value)
+        });
+    }
+}
+
+async fn notify_neighbours(server: &Server) {
+    loop {
+        let neighbours = NEIGHBOURS.wait().await;
+        let mut buf = [0u8; MAX_NEIGHBOURS_SIZE];
+        let len = unwrap!(encode_neighbours(&neighbours, &mut buf));
+        let value = unwrap!(Vec::from_slice(&buf[..len]));
+
+        unwrap!(server.lorelay.neighbours_set(&value));
+        connections::notify_subscribers(Characteristic::Neighbours, |connection| {
+            server.lorelay.neighbours_notify(connection, &value)
+        });
+    }
+}
+
+async fn notify_delivery(server: &Server) {
+    loop {
+        let delivery = DELIVERY.wait().await;
+        let mut value = [0u8; DELIVERY_SIZE];
+        unwrap!(encode_delivery(&delivery, &mut value));
+
+        info!("Message delivery: {}", delivery);
+        unwrap!(server.lorelay.delivery_set(&value));
+        connections::notify_subscribers(Characteristic::Delivery, |connection| {
+            server.lorelay.delivery_notify(connection, &value)
+        });
+    }
+}
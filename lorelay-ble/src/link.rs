@@ -0,0 +1,145 @@
+//! Link to the LoRa board over UARTE0, see `lorelay_proto::link`.
+//!
+//! Messages written by the BLE client and the requests of the `console` go to the LoRa board,
+//! which sends back the messages, status, neighbours and deliveries of the `gateway`, the range
+//! test statistics and its configuration.
+//!
+//! Once the LoRa board acknowledged a reboot request, or stopped responding, the BLE board
+//! reboots too.
+use crate::{console, gateway, RANGE_TEST_SUMMARY};
+use defmt::{debug, info, warn};
+use embassy_nrf::peripherals::{TIMER1, UARTE0};
+use embassy_nrf::uarte::{UarteRxWithIdle, UarteTx};
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_time::{Instant, Timer};
+use futures::future::{pending, select, Either};
+use futures::pin_mut;
+use heapless::Vec;
+use lorelay_proto::link::{Link, LinkConfig, LinkEvent, Packet};
+
+pub type LinkTx = UarteTx<'static, UARTE0>;
+pub type LinkRx = UarteRxWithIdle<'static, UARTE0, TIMER1>;
+
+/// Largest read from the UARTE.
+const READ_SIZE: usize = 64;
+
+/// Bytes read from the LoRa board, as they arrived before the line went idle.
+static READS: Channel<ThreadModeRawMutex, Vec<u8, READ_SIZE>, 4> = Channel::new();
+
+enum Event {
+    Read(Vec<u8, READ_SIZE>),
+    Deadline,
+    Send(Packet),
+}
+
+/// Next packet for the LoRa board, once the link is `ready` for it.
+async fn next_packet(ready: bool) -> Packet {
+    if !ready {
+        return pending().await;
+    }
+    let outgoing_fut = gateway::OUTGOING.recv();
+    let request_fut = console::REQUESTS.recv();
+    pin_mut!(outgoing_fut);
+    pin_mut!(request_fut);
+    match select(outgoing_fut, request_fut).await {
+        Either::Left((message, _)) => Packet::Outgoing(message),
+        Either::Right((request, _)) => request,
+    }
+}
+
+fn on_event(event: LinkEvent) {
+    match event {
+        LinkEvent::Received(Packet::Incoming(message)) => {
+            if gateway::INCOMING.try_send(message).is_err() {
+                warn!("Incoming queue full, dropping message");
+            }
+        }
+        LinkEvent::Received(Packet::Status(status)) => {
+            console::on_status(status);
+            gateway::NODE_STATUS.signal(status)
+        }
+        LinkEvent::Received(Packet::Neighbours(neighbours)) => {
+            console::on_neighbours(&neighbours);
+            gateway::NEIGHBOURS.signal(neighbours)
+        }
+        LinkEvent::Received(Packet::Config(config)) => console::on_config(config),
+        LinkEvent::Received(Packet::Delivery(delivery)) => gateway::DELIVERY.signal(delivery),
+        LinkEvent::Received(Packet::RangeTestSummary(summary)) => {
+            RANGE_TEST_SUMMARY.signal(summary)
+        }
+        LinkEvent::Received(
+            Packet::Outgoing(_) | Packet::SetConfig(_) | Packet::SetMode(_) | Packet::Reboot,
+        ) => warn!("Unexpected packet from the LoRa board"),
+        LinkEvent::Delivered => debug!("Message delivered to the LoRa board"),
+        LinkEvent::Dropped(_) => warn!("LoRa board not responding, message dropped"),
+        LinkEvent::Invalid(err) => warn!("Invalid frame from the LoRa board: {}", err),
+    }
+}
+
+/// Reads the UARTE for `link`, which cannot read while it is busy with other events.
+#[embassy_executor::task]
+pub async fn link_reader(mut rx: LinkRx) {
+    let mut buf = [0u8; READ_SIZE];
+    loop {
+        match rx.read_until_idle(&mut buf).await {
+            Ok(len) => {
+                let bytes = Vec::from_slice(&buf[..len]).expect("reads fit the buffer");
+                READS.send(bytes).await
+            }
+            Err(err) => warn!("Link read error: {}", err),
+        }
+    }
+}
+
+/// Runs the link, starting its sequence numbers from `seed`.
+///
+/// `seed` must be random, for the other board to tell a reset of the link from a retransmission.
+#[embassy_executor::task]
+pub async fn link(mut tx: LinkTx, seed: u32) {
+    let mut link: Link<4> = Link::new(LinkConfig::default(), seed);
+    // Whether the packet in flight is a reboot request.
+    let mut rebooting = false;
+    loop {
+        let event = {
+            let deadline = link
+                .next_deadline()
+                .map_or(Instant::MAX, Instant::from_millis);
+            let read_fut = READS.recv();
+            let timer_fut = Timer::at(deadline);
+            let packet_fut = next_packet(link.is_ready());
+            pin_mut!(read_fut);
+            pin_mut!(timer_fut);
+            pin_mut!(packet_fut);
+            match select(read_fut, select(timer_fut, packet_fut)).await {
+                Either::Left((bytes, _)) => Event::Read(bytes),
+                Either::Right((Either::Left(_), _)) => Event::Deadline,
+                Either::Right((Either::Right((packet, _)), _)) => Event::Send(packet),
+            }
+        };
+
+        let now = Instant::now().as_millis();
+        match event {
+            Event::Read(bytes) => link.on_bytes(&bytes, now),
+            Event::Deadline => {}
+            Event::Send(packet) => {
+                rebooting = packet == Packet::Reboot;
+                // Cannot fail, packets are only taken while the link is ready.
+                let _ = link.send(packet, now);
+            }
+        }
+
+        while let Some(frame) = link.poll_transmit(now) {
+            if let Err(err) = tx.write(&frame).await {
+                warn!("Link write error: {}", err);
+            }
+        }
+        while let Some(event) = link.poll_event() {
+            if rebooting && matches!(event, LinkEvent::Delivered | LinkEvent::Dropped(_)) {
+                info!("Rebooting on request of the console");
+                cortex_m::peripheral::SCB::sys_reset();
+            }
+            on_event(event);
+        }
+    }
+}
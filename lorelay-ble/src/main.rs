@@ -1,17 +1,29 @@
-//! This example showcases how to notify a connected client via BLE of new SAADC data.
-//! Using, for example, nRF-Connect on iOS/Android we can connect to the device "HelloRust"
-//! and see the battery level characteristic getting updated in real-time.
+//! BLE side of a lorelay node: a gateway through which a phone sends and receives payloads over
+//! the mesh of the LoRa board, and follows its state.
 //!
-//! The SAADC is initialized in single-ended mode and a single measurement is taken every second.
-//! This value is then used to update the battery_level characteristic.
-//! We are using embassy-time for time-keeping purposes.
-//! Everytime a new value is recorded, it gets sent to the connected clients via a GATT Notification.
+//! Using, for example, nRF-Connect on iOS/Android we can connect to the device "lorelay". The
+//! lorelay service, whose values are described in `lorelay_proto::gateway`, has characteristics:
+//!  - outgoing: written with a destination uid and a payload to send over the mesh.
+//!  - incoming: notified with the payloads the mesh delivers to the node.
+//!  - status and neighbours: read or notified with the state of the node.
+//!  - delivery: read or notified with the outcome of the last outgoing payload written with an
+//!    acknowledgment requested.
 //!
-//! The ADC doesn't gather data unless a valid connection exists with a client. This is guaranteed
-//! by using the "select" crate to wait for either the `gatt_server::run` future or the `adc_fut` future
-//! to complete.
+//! The LoRa board is reached over a UART, see `link`.
 //!
-//! Only a single BLE connection is supported in this example so that RAM usage remains minimal.
+//! A Nordic UART Service runs a text console, with which a terminal app such as nRF Toolbox or
+//! Serial Bluetooth Terminal inspects the node, sends messages and changes its configuration,
+//! see `console`.
+//!
+//! Next to it, the battery service gets updated every second with the charge and voltage of the
+//! battery, measured with the SAADC, and the range test service notifies the statistics of the
+//! range test.
+//!
+//! Up to `connections::MAX_CONNECTIONS` clients connect at once, such as a phone and a logging
+//! tablet. Each one is served by a `connection_task` and subscribes to the characteristics it
+//! wants, which are notified to every client subscribed to them.
+//!
+//! The ADC doesn't gather data unless a client is connected.
 //!
 //! The internal RC oscillator is used to generate the LFCLK.
 //!
@@ -28,50 +40,196 @@ use panic_probe as _;
 
 use core::mem;
 
+mod connections;
+mod console;
+mod gateway;
+mod link;
+
 use defmt::{info, *};
 use embassy_executor::Spawner;
 use embassy_nrf::gpio::{AnyPin, Level, Output, OutputDrive, Pin};
 use embassy_nrf::interrupt::{Interrupt, InterruptExt};
 use embassy_nrf::saadc::{ChannelConfig, Saadc};
+use embassy_nrf::uarte::{self, Uarte};
 use embassy_nrf::{bind_interrupts, interrupt, peripherals, saadc, spim};
 use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
 use embassy_sync::mutex::Mutex;
+use embassy_sync::signal::Signal;
 use embassy_time::{Duration, Timer};
-use futures::future::{select, Either};
+use futures::future::{join, join3, select, Either};
 use futures::pin_mut;
+use heapless::Vec;
+use lorelay_proto::battery::{BatteryConfig, BatteryMonitor};
+use lorelay_proto::connections::Characteristic;
+use lorelay_proto::gateway::{
+    DELIVERY_SIZE, MAX_INCOMING_SIZE, MAX_NEIGHBOURS_SIZE, MAX_OUTGOING_SIZE, STATUS_SIZE,
+};
+use lorelay_proto::range_test::{Summary, SUMMARY_SIZE};
 use nrf_softdevice::ble::{gatt_server, peripheral, Connection};
 use nrf_softdevice::{raw, Softdevice};
 
 static mut LED_FLAG: Mutex<ThreadModeRawMutex, bool> = Mutex::new(false);
+
+/// Battery samples averaged, a few seconds worth.
+const BATTERY_SAMPLES: usize = 8;
+
+/// Latest range test statistics about a sender heard by the LoRa board.
+static RANGE_TEST_SUMMARY: Signal<ThreadModeRawMutex, Summary> = Signal::new();
 bind_interrupts!(struct Irqs {
     SPIM3 => spim::InterruptHandler<peripherals::SPI3>;
     SAADC => saadc::InterruptHandler;
+    UARTE0_UART0 => uarte::InterruptHandler<peripherals::UARTE0>;
 });
 
-/// Reads the current ADC value every second and notifies the connected client.
+/// Measures the battery every second while a client is connected, and notifies the clients.
 async fn notify_adc_value<'a>(
     saadc: &'a mut Saadc<'_, 1>,
+    battery: &'a mut BatteryMonitor<BATTERY_SAMPLES>,
     server: &'a Server,
-    connection: &'a Connection,
 ) {
     loop {
+        connections::wait_for_client().await;
         let mut buf = [0i16; 1];
         saadc.sample(&mut buf).await;
 
         // We only sampled one ADC channel.
-        let adc_raw_value: i16 = buf[0];
+        let reading = battery.push(buf[0]);
+        let voltage = reading.voltage_characteristic();
+        info!(
+            "Battery at {} %, {} mV",
+            reading.percent, reading.millivolts
+        );
 
-        // Try and notify the connected client of the new ADC value.
-        match server.bas.battery_level_notify(connection, &adc_raw_value) {
-            Ok(_) => info!("Battery adc_raw_value: {=i16}", &adc_raw_value),
-            Err(_) => unwrap!(server.bas.battery_level_set(&adc_raw_value)),
-        };
+        unwrap!(server.bas.battery_level_set(&reading.percent));
+        unwrap!(server.bas.battery_voltage_set(&voltage));
+        connections::notify_subscribers(Characteristic::BatteryLevel, |connection| {
+            server
+                .bas
+                .battery_level_notify(connection, &reading.percent)
+        });
+        connections::notify_subscribers(Characteristic::BatteryVoltage, |connection| {
+            server.bas.battery_voltage_notify(connection, &voltage)
+        });
 
         // Sleep for one second.
         Timer::after(Duration::from_secs(1)).await
     }
 }
 
+/// Notifies the clients of every range test summary coming from the LoRa board.
+async fn notify_range_test(server: &Server) {
+    loop {
+        let summary = RANGE_TEST_SUMMARY.wait().await;
+        let mut value = [0u8; SUMMARY_SIZE];
+        unwrap!(summary.encode(&mut value));
+        info!(
+            "Range test summary of node {}: PER {} permille",
+            summary.origin_uid, summary.packet_error_rate_permille
+        );
+
+        unwrap!(server.range_test.summary_set(&value));
+        connections::notify_subscribers(Characteristic::RangeTestSummary, |connection| {
+            server.range_test.summary_notify(connection, &value)
+        });
+    }
+}
+
+/// Serves the client of `connection`, registered with `handle`, until it disconnects.
+#[embassy_executor::task(pool_size = connections::MAX_CONNECTIONS)]
+async fn connection_task(server: &'static Server, connection: Connection, handle: u16) {
+    let console_writes = console::Writes::new();
+    let mut custom_value: i16 = 0;
+
+    // Event enums (ServerEvent's) are generated by nrf_softdevice::gatt_server
+    // proc macro when applied to the Server struct above
+    let gatt_fut = gatt_server::run(&connection, server, |e| match e {
+        ServerEvent::Bas(e) => match e {
+            BatteryServiceEvent::BatteryLevelCccdWrite { notifications } => {
+                info!("battery notifications: {}", notifications);
+                connections::set_subscribed(handle, Characteristic::BatteryLevel, notifications);
+                if custom_value != 0 {
+                    info!("custom value: {}", custom_value);
+                }
+            }
+            BatteryServiceEvent::BatteryVoltageCccdWrite { notifications } => {
+                info!("battery voltage notifications: {}", notifications);
+                connections::set_subscribed(handle, Characteristic::BatteryVoltage, notifications);
+            }
+        },
+        ServerEvent::Custom(e) => match e {
+            CustomServiceEvent::CustomValueWrite(value) => {
+                if let Ok(cstr) = CStr::from_bytes_until_nul(&value) {
+                    if let Ok(ret) = cstr.to_str() {
+                        info!("custom value written: {}", ret.clone());
+                        ret
+                    } else {
+                        warn!("invalid utf8");
+                        "invalid utf8"
+                    }
+                } else {
+                    warn!("invalid utf8");
+                    "invalid utf8"
+                };
+
+                custom_value = value[0] as i16;
+                unsafe {
+                    let flag = LED_FLAG.get_mut();
+                    *flag = true;
+                }
+            }
+        },
+        ServerEvent::Nus(e) => match e {
+            NusServiceEvent::RxWrite(value) => console::on_rx_write(&console_writes, &value),
+            NusServiceEvent::TxCccdWrite { notifications } => {
+                info!("console notifications: {}", notifications);
+                connections::set_subscribed(handle, Characteristic::ConsoleTx, notifications);
+            }
+        },
+        ServerEvent::RangeTest(e) => match e {
+            RangeTestServiceEvent::SummaryCccdWrite { notifications } => {
+                info!("range test notifications: {}", notifications);
+                connections::set_subscribed(
+                    handle,
+                    Characteristic::RangeTestSummary,
+                    notifications,
+                );
+            }
+        },
+        ServerEvent::Lorelay(e) => match e {
+            LorelayServiceEvent::OutgoingWrite(value) => gateway::on_outgoing_write(&value),
+            LorelayServiceEvent::IncomingCccdWrite { notifications } => {
+                info!("incoming message notifications: {}", notifications);
+                connections::set_subscribed(handle, Characteristic::Incoming, notifications);
+            }
+            LorelayServiceEvent::StatusCccdWrite { notifications } => {
+                info!("status notifications: {}", notifications);
+                connections::set_subscribed(handle, Characteristic::Status, notifications);
+            }
+            LorelayServiceEvent::NeighboursCccdWrite { notifications } => {
+                info!("neighbours notifications: {}", notifications);
+                connections::set_subscribed(handle, Characteristic::Neighbours, notifications);
+            }
+            LorelayServiceEvent::DeliveryCccdWrite { notifications } => {
+                info!("delivery notifications: {}", notifications);
+                connections::set_subscribed(handle, Characteristic::Delivery, notifications);
+            }
+        },
+    });
+    let console_fut = console::serve(server, &connection, handle, &console_writes);
+
+    pin_mut!(gatt_fut);
+    pin_mut!(console_fut);
+
+    // The console of the client stops with its GATT server.
+    if let Either::Left((res, _)) = select(gatt_fut, console_fut).await {
+        info!(
+            "GATT server of connection {} finished with result {:?}",
+            handle, res
+        );
+    }
+    connections::unregister(handle);
+}
+
 #[embassy_executor::task]
 async fn blink_once(pin: AnyPin) {
     let mut led = Output::new(pin, Level::Low, OutputDrive::Standard);
@@ -95,6 +253,15 @@ async fn blink_once(pin: AnyPin) {
     }
 }
 
+/// Random number from the SoftDevice, whose pool fills up shortly after it is enabled.
+async fn random_u32(sd: &Softdevice) -> u32 {
+    let mut bytes = [0u8; 4];
+    while nrf_softdevice::random_bytes(sd, &mut bytes).is_err() {
+        Timer::after(Duration::from_millis(1)).await;
+    }
+    u32::from_le_bytes(bytes)
+}
+
 #[embassy_executor::task]
 async fn softdevice_task(sd: &'static Softdevice) -> ! {
     sd.run().await
@@ -106,16 +273,66 @@ struct CustomService {
     custom_value: [u8; 16],
 }
 
+/// Nordic UART Service, the serial port of terminal apps, for `console`.
+#[nrf_softdevice::gatt_service(uuid = "6e400001-b5a3-f393-e0a9-e50e24dcca9e")]
+struct NusService {
+    /// Bytes written by the client.
+    #[characteristic(
+        uuid = "6e400002-b5a3-f393-e0a9-e50e24dcca9e",
+        write,
+        write_without_response
+    )]
+    rx: Vec<u8, console::MAX_WRITE_SIZE>,
+    /// Responses to the client.
+    #[characteristic(uuid = "6e400003-b5a3-f393-e0a9-e50e24dcca9e", notify)]
+    tx: Vec<u8, console::CHUNK_SIZE>,
+}
+
 #[nrf_softdevice::gatt_service(uuid = "180f")]
 struct BatteryService {
+    /// Charge in percent.
     #[characteristic(uuid = "2a19", read, notify)]
-    battery_level: i16,
+    battery_level: u8,
+    /// Voltage in units of 1/64 V.
+    #[characteristic(uuid = "2b18", read, notify)]
+    battery_voltage: u16,
+}
+
+/// Statistics of the range test running on the LoRa board, one sender at a time.
+#[nrf_softdevice::gatt_service(uuid = "6c720100-8d2e-4b1a-9f5c-3a7e0b6d2c41")]
+struct RangeTestService {
+    /// `range_test::Summary`, little endian encoded.
+    #[characteristic(uuid = "6c720101-8d2e-4b1a-9f5c-3a7e0b6d2c41", read, notify)]
+    summary: [u8; SUMMARY_SIZE],
+}
+
+/// Gateway to the mesh, with the uuids of `lorelay_proto::gateway`.
+#[nrf_softdevice::gatt_service(uuid = "6c720200-8d2e-4b1a-9f5c-3a7e0b6d2c41")]
+struct LorelayService {
+    /// `gateway::OutgoingMessage`.
+    #[characteristic(uuid = "6c720201-8d2e-4b1a-9f5c-3a7e0b6d2c41", write)]
+    outgoing: Vec<u8, MAX_OUTGOING_SIZE>,
+    /// `gateway::IncomingMessage`.
+    #[characteristic(uuid = "6c720202-8d2e-4b1a-9f5c-3a7e0b6d2c41", read, notify)]
+    incoming: Vec<u8, MAX_INCOMING_SIZE>,
+    /// `gateway::NodeStatus`.
+    #[characteristic(uuid = "6c720203-8d2e-4b1a-9f5c-3a7e0b6d2c41", read, notify)]
+    status: [u8; STATUS_SIZE],
+    /// `gateway::NeighbourEntry` of every live neighbour.
+    #[characteristic(uuid = "6c720204-8d2e-4b1a-9f5c-3a7e0b6d2c41", read, notify)]
+    neighbours: Vec<u8, MAX_NEIGHBOURS_SIZE>,
+    /// `gateway::encode_delivery` of the last acknowledged `gateway::OutgoingMessage`.
+    #[characteristic(uuid = "6c720205-8d2e-4b1a-9f5c-3a7e0b6d2c41", read, notify)]
+    delivery: [u8; DELIVERY_SIZE],
 }
 
 #[nrf_softdevice::gatt_server]
 struct Server {
     bas: BatteryService,
     custom: CustomService,
+    nus: NusService,
+    range_test: RangeTestService,
+    lorelay: LorelayService,
 }
 
 #[embassy_executor::main]
@@ -131,11 +348,21 @@ async fn main(spawner: Spawner) {
     // Then we initialize the ADC. We are only using one channel in this example.
     let channel_config = ChannelConfig::single_ended(&mut p.P0_02);
     unsafe { interrupt::SAADC::steal() }.set_priority(interrupt::Priority::P2);
-    let mut saadc = Saadc::new(p.SAADC, Irqs, saadc::Config::default(), [channel_config]);
+    let mut saadc_config = saadc::Config::default();
+    saadc_config.resolution = saadc::Resolution::_12BIT;
+    let mut saadc = Saadc::new(p.SAADC, Irqs, saadc_config, [channel_config]);
+    // Matches the resolution, the default gain and reference of the channel, and the divider on
+    // P0_02.
+    let mut battery = BatteryMonitor::new(BatteryConfig::default());
     // Indicated: wait for ADC calibration.
     saadc.calibrate().await;
     info!("ADC calibrated");
 
+    // Link to the LoRa board.
+    unsafe { interrupt::UARTE0_UART0::steal() }.set_priority(interrupt::Priority::P2);
+    let uarte = Uarte::new(p.UARTE0, Irqs, p.P1_02, p.P1_01, uarte::Config::default());
+    let (link_tx, link_rx) = uarte.split_with_idle(p.TIMER1, p.PPI_CH0, p.PPI_CH1);
+
     let config = nrf_softdevice::Config {
         clock: Some(raw::nrf_clock_lf_cfg_t {
             source: raw::NRF_CLOCK_LF_SRC_RC as u8,
@@ -144,7 +371,7 @@ async fn main(spawner: Spawner) {
             accuracy: raw::NRF_CLOCK_LF_ACCURACY_500_PPM as u8,
         }),
         conn_gap: Some(raw::ble_gap_conn_cfg_t {
-            conn_count: 1,
+            conn_count: connections::MAX_CONNECTIONS as u8,
             event_length: 24,
         }),
         conn_gatt: Some(raw::ble_gatt_conn_cfg_t { att_mtu: 256 }),
@@ -153,15 +380,15 @@ async fn main(spawner: Spawner) {
         }),
         gap_role_count: Some(raw::ble_gap_cfg_role_count_t {
             adv_set_count: raw::BLE_GAP_ADV_SET_COUNT_DEFAULT as u8,
-            periph_role_count: raw::BLE_GAP_ROLE_COUNT_PERIPH_DEFAULT as u8,
+            periph_role_count: connections::MAX_CONNECTIONS as u8,
             central_role_count: 0,
             central_sec_count: 0,
             _bitfield_1: raw::ble_gap_cfg_role_count_t::new_bitfield_1(0),
         }),
         gap_device_name: Some(raw::ble_gap_cfg_device_name_t {
-            p_value: b"HelloRust" as *const u8 as _,
-            current_len: 9,
-            max_len: 9,
+            p_value: b"lorelay" as *const u8 as _,
+            current_len: 7,
+            max_len: 7,
             write_perm: unsafe { mem::zeroed() },
             _bitfield_1: raw::ble_gap_cfg_device_name_t::new_bitfield_1(
                 raw::BLE_GATTS_VLOC_STACK as u8,
@@ -173,89 +400,59 @@ async fn main(spawner: Spawner) {
     info!("Enabling softdevice");
     let sd = Softdevice::enable(&config);
     info!("Softdevice enabled");
-    let server = unwrap!(Server::new(sd));
+    // Shared by the tasks of the connections.
+    let server: &'static Server =
+        unwrap!(cortex_m::singleton!(: Server = unwrap!(Server::new(sd))));
 
     spawner.spawn(blink_once(p.P0_13.degrade())).unwrap();
     unwrap!(spawner.spawn(softdevice_task(sd)));
+    unwrap!(spawner.spawn(link::link_reader(link_rx)));
+    unwrap!(spawner.spawn(link::link(link_tx, random_u32(sd).await)));
 
     #[rustfmt::skip]
         let adv_data = &[
         0x02, 0x01, raw::BLE_GAP_ADV_FLAGS_LE_ONLY_GENERAL_DISC_MODE as u8,
         0x03, 0x03, 0x09, 0x18,
-        0x0a, 0x09, b'H', b'e', b'l', b'l', b'o', b'R', b'u', b's', b't',
+        0x08, 0x09, b'l', b'o', b'r', b'e', b'l', b'a', b'y',
     ];
+    // The lorelay service, for phones to find gateways.
     #[rustfmt::skip]
         let scan_data = &[
         0x03, 0x03, 0x09, 0x18,
+        0x11, 0x07, 0x41, 0x2c, 0x6d, 0x0b, 0x7e, 0x3a, 0x5c, 0x9f,
+        0x1a, 0x4b, 0x2e, 0x8d, 0x00, 0x02, 0x72, 0x6c,
     ];
 
-    let mut custom_value: i16 = 0;
-
-    info!("starting advertising");
-    loop {
-        let config = peripheral::Config::default();
-
-        let adv = peripheral::ConnectableAdvertisement::ScannableUndirected {
-            adv_data,
-            scan_data,
-        };
-        let conn = unwrap!(peripheral::advertise_connectable(sd, adv, &config).await);
-        info!("advertising done! I have a connection.");
-
-        // We have a GATT connection. Now we will create two futures:
-        //  - An infinite loop gathering data from the ADC and notifying the clients.
-        //  - A GATT server listening for events from the connected client.
-        //
-        // Event enums (ServerEvent's) are generated by nrf_softdevice::gatt_server
-        // proc macro when applied to the Server struct above
-        let adc_fut = notify_adc_value(&mut saadc, &server, &conn);
-        let gatt_fut = gatt_server::run(&conn, &server, |e| match e {
-            ServerEvent::Bas(e) => match e {
-                BatteryServiceEvent::BatteryLevelCccdWrite { notifications } => {
-                    info!("battery notifications: {}", notifications);
-                    if custom_value != 0 {
-                        info!("custom value: {}", custom_value);
-                    }
-                }
-            },
-            ServerEvent::Custom(e) => match e {
-                CustomServiceEvent::CustomValueWrite(value) => {
-                    if let Ok(cstr) = CStr::from_bytes_until_nul(&value) {
-                        if let Ok(ret) = cstr.to_str() {
-                            info!("custom value written: {}", ret.clone());
-                            ret
-                        } else {
-                            warn!("invalid utf8");
-                            "invalid utf8"
-                        }
-                    } else {
-                        warn!("invalid utf8");
-                        "invalid utf8"
-                    };
-
-                    custom_value = value[0] as i16;
-                    unsafe {
-                        let flag = LED_FLAG.get_mut();
-                        *flag = true;
-                    }
-                }
-            },
-        });
+    let advertise_fut = async {
+        info!("starting advertising");
+        loop {
+            connections::wait_for_room().await;
+            let config = peripheral::Config::default();
 
-        pin_mut!(adc_fut);
-        pin_mut!(gatt_fut);
+            let adv = peripheral::ConnectableAdvertisement::ScannableUndirected {
+                adv_data,
+                scan_data,
+            };
+            let conn = unwrap!(peripheral::advertise_connectable(sd, adv, &config).await);
+            // Dropping the connection disconnects the client, if it still is connected.
+            let Some(handle) = connections::register(&conn) else {
+                warn!("Client disconnected before it was served");
+                continue;
+            };
+            info!("advertising done! Connection {} established.", handle);
+            unwrap!(spawner.spawn(connection_task(server, conn, handle)));
+        }
+    };
 
-        // We are using "select" to wait for either one of the futures to complete.
-        // There are some advantages to this approach:
-        //  - we only gather data when a client is connected, therefore saving some power.
-        //  - when the GATT server finishes operating, our ADC future is also automatically aborted.
-        match select(adc_fut, gatt_fut).await {
-            Either::Left((_, _)) => {
-                info!("ADC encountered an error and stopped!")
-            }
-            Either::Right((res, _)) => {
-                info!("GATT server finished with result {:?}", res);
-            }
-        };
-    }
+    // Infinite loops gathering data from the ADC, the range test and the LoRa board, notifying
+    // the clients, while more clients connect.
+    join(
+        join3(
+            notify_adc_value(&mut saadc, &mut battery, server),
+            notify_range_test(server),
+            gateway::notify(server),
+        ),
+        advertise_fut,
+    )
+    .await;
 }
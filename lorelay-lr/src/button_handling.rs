@@ -1,9 +1,13 @@
 use defmt::info;
 use embassy_stm32::exti::ExtiInput;
-use embassy_stm32::gpio::Input;
+use embassy_stm32::gpio::{Input, Pin};
 use embassy_stm32::peripherals::{PA0, PA1, PC6};
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
-use embassy_sync::signal::Signal;
+use embassy_sync::channel::Channel;
+use embassy_time::{Instant, Timer};
+use futures::future::{select, Either};
+use futures::pin_mut;
+use lorelay_proto::gesture::{ButtonEvent, GestureConfig, Gestures};
 
 pub type Button1 = Input<'static, PA0>;
 pub type Button2 = Input<'static, PA1>;
@@ -13,37 +17,55 @@ type ExtiButton1 = ExtiInput<'static, PA0>;
 type ExtiButton2 = ExtiInput<'static, PA1>;
 type ExtiButton3 = ExtiInput<'static, PC6>;
 
-pub static BUTTON_PRESS_SIGNAL: Signal<CriticalSectionRawMutex, ButtonPress> = Signal::new();
+/// Gestures of every button, in the order they were made.
+pub static BUTTON_EVENTS: Channel<CriticalSectionRawMutex, (Button, ButtonEvent), 8> =
+    Channel::new();
 
-pub enum ButtonPress {
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Button {
     Button1,
     Button2,
     Button3,
 }
 
-#[embassy_executor::task]
-pub async fn button_1_press(mut button_exti: ExtiButton1) {
+/// Recognizes the gestures made with `button` and sends them to `BUTTON_EVENTS`.
+async fn recognize<T: Pin>(button: Button, mut exti: ExtiInput<'static, T>, config: GestureConfig) {
+    let mut gestures = Gestures::new(config);
     loop {
-        button_exti.wait_for_rising_edge().await;
-        info!("Button 1 pressed");
-        BUTTON_PRESS_SIGNAL.signal(ButtonPress::Button1);
+        let deadline = gestures
+            .next_deadline()
+            .map_or(Instant::MAX, Instant::from_millis);
+        let edge = {
+            let edge_fut = exti.wait_for_any_edge();
+            let timer_fut = Timer::at(deadline);
+            pin_mut!(edge_fut);
+            pin_mut!(timer_fut);
+            matches!(select(edge_fut, timer_fut).await, Either::Left(_))
+        };
+        let now = Instant::now().as_millis();
+        if edge {
+            // The buttons are pulled up, pressing them grounds the input.
+            gestures.on_edge(exti.is_low(), now);
+        }
+        while let Some(event) = gestures.poll(now) {
+            info!("{}: {}", button, event);
+            // Waits for room rather than losing the event.
+            BUTTON_EVENTS.send((button, event)).await;
+        }
     }
 }
 
 #[embassy_executor::task]
-pub async fn button_2_press(mut button_exti: ExtiButton2) {
-    loop {
-        button_exti.wait_for_rising_edge().await;
-        info!("Button 2 pressed");
-        BUTTON_PRESS_SIGNAL.signal(ButtonPress::Button2);
-    }
+pub async fn button_1_press(button_exti: ExtiButton1) {
+    recognize(Button::Button1, button_exti, GestureConfig::default()).await
 }
 
 #[embassy_executor::task]
-pub async fn button_3_press(mut button_exti: ExtiButton3) {
-    loop {
-        button_exti.wait_for_rising_edge().await;
-        info!("Button 3 pressed");
-        BUTTON_PRESS_SIGNAL.signal(ButtonPress::Button3);
-    }
+pub async fn button_2_press(button_exti: ExtiButton2) {
+    recognize(Button::Button2, button_exti, GestureConfig::default()).await
+}
+
+#[embassy_executor::task]
+pub async fn button_3_press(button_exti: ExtiButton3) {
+    recognize(Button::Button3, button_exti, GestureConfig::default()).await
 }
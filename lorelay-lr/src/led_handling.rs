@@ -1,56 +1,106 @@
-use defmt::debug;
-use embassy_stm32::gpio::Output;
+use defmt::{debug, warn};
+use embassy_stm32::gpio::{Output, Pin};
 use embassy_stm32::peripherals::{PB11, PB15, PB9};
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
-use embassy_sync::signal::Signal;
-use embassy_time::{Duration, Timer};
+use embassy_sync::channel::Channel;
+use embassy_time::{Instant, Timer};
+use futures::future::{select, Either};
+use futures::pin_mut;
+use lorelay_proto::led::{DeviceState, LedEngine, LedPattern, Priority};
 
 pub type BlueLed = Output<'static, PB15>;
 pub type GreenLed = Output<'static, PB9>;
 pub type RedLed = Output<'static, PB11>;
 
-pub static LED_BLUE_BLINK_SIGNAL: Signal<CriticalSectionRawMutex, ()> = Signal::new();
-pub static LED_RED_BLINK_SIGNAL: Signal<CriticalSectionRawMutex, ()> = Signal::new();
-pub static LED_GREEN_BLINK_SIGNAL: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+/// Lights up for transmissions.
+pub static BLUE_LED: Led = Led::new();
+/// Shows whether the node found neighbours, and lights up for receptions.
+pub static GREEN_LED: Led = Led::new();
+/// Shows errors and warnings.
+pub static RED_LED: Led = Led::new();
 
-#[embassy_executor::task]
-pub async fn blue_led_handler(mut led: BlueLed) {
-    loop {
-        // Wait for the signal to blink the LED
-        LED_BLUE_BLINK_SIGNAL.wait().await;
-
-        debug!("Blinking blue LED");
-        // Blink the LED
-        led.set_high();
-        Timer::after(Duration::from_secs(1)).await;
-        led.set_low();
+/// Patterns requested for one LED, shown by its handler task.
+pub struct Led {
+    requests: Channel<CriticalSectionRawMutex, (Priority, Option<LedPattern>), 8>,
+}
+
+impl Led {
+    pub const fn new() -> Self {
+        Led {
+            requests: Channel::new(),
+        }
+    }
+
+    /// Shows `pattern` in place of the one requested at `priority` before.
+    pub fn show(&self, priority: Priority, pattern: LedPattern) {
+        self.request(priority, Some(pattern));
+    }
+
+    pub fn clear(&self, priority: Priority) {
+        self.request(priority, None);
+    }
+
+    /// Blinks once, over the status shown.
+    pub fn blink(&self) {
+        self.show(Priority::Activity, LedPattern::BLINK);
+    }
+
+    pub fn show_state(&self, state: DeviceState) {
+        let (priority, pattern) = state.indication();
+        self.show(priority, pattern);
+    }
+
+    fn request(&self, priority: Priority, pattern: Option<LedPattern>) {
+        // No task should ever wait for an LED.
+        if self.requests.try_send((priority, pattern)).is_err() {
+            warn!("Too many LED requests, dropping {}", pattern);
+        }
     }
 }
 
-#[embassy_executor::task]
-pub async fn green_led_handler(mut led: GreenLed) {
+/// Shows the patterns requested for `led` on `output`.
+async fn drive<T: Pin>(mut output: Output<'static, T>, led: &'static Led) {
+    let mut engine = LedEngine::new();
     loop {
-        // Wait for the signal to blink the LED
-        LED_GREEN_BLINK_SIGNAL.wait().await;
-
-        debug!("Blinking green LED");
-        // Blink the LED
-        led.set_high();
-        Timer::after(Duration::from_secs(1)).await;
-        led.set_low();
+        let deadline = engine
+            .next_deadline()
+            .map_or(Instant::MAX, Instant::from_millis);
+        let request = {
+            let request_fut = led.requests.recv();
+            let timer_fut = Timer::at(deadline);
+            pin_mut!(request_fut);
+            pin_mut!(timer_fut);
+            match select(request_fut, timer_fut).await {
+                Either::Left((request, _)) => Some(request),
+                Either::Right(_) => None,
+            }
+        };
+
+        let now = Instant::now().as_millis();
+        if let Some((priority, pattern)) = request {
+            debug!("LED pattern {} at {}", pattern, priority);
+            engine.set(priority, pattern, now);
+        }
+        engine.poll(now);
+        if engine.is_on() {
+            output.set_high();
+        } else {
+            output.set_low();
+        }
     }
 }
 
 #[embassy_executor::task]
-pub async fn red_led_handler(mut led: RedLed) {
-    loop {
-        // Wait for the signal to blink the LED
-        LED_RED_BLINK_SIGNAL.wait().await;
-
-        debug!("Blinking red LED");
-        // Blink the LED
-        led.set_high();
-        Timer::after(Duration::from_secs(1)).await;
-        led.set_low();
-    }
+pub async fn blue_led_handler(led: BlueLed) {
+    drive(led, &BLUE_LED).await
+}
+
+#[embassy_executor::task]
+pub async fn green_led_handler(led: GreenLed) {
+    drive(led, &GREEN_LED).await
+}
+
+#[embassy_executor::task]
+pub async fn red_led_handler(led: RedLed) {
+    drive(led, &RED_LED).await
 }
@@ -0,0 +1,179 @@
+//! Link to the BLE board over USART1, see `lorelay_proto::link`.
+//!
+//! Messages of the BLE client go to `OUTBOX`, while the payloads of `INBOX`, the status of the
+//! relay, the outcome of the messages the client wanted acknowledged, the range test statistics
+//! and the stored configuration go to the BLE board, one packet at a time. The BLE board may also switch modes, store a configuration or reboot the board.
+use crate::mode::{Mode, RANGE_TEST_SUMMARY};
+use crate::relay::{Outgoing, GATEWAY_DELIVERY, INBOX, NEIGHBOURS, NODE_STATUS, OUTBOX};
+use defmt::{debug, info, warn};
+use embassy_stm32::peripherals::{DMA1_CH3, DMA1_CH4, USART1};
+use embassy_stm32::usart::{UartRx, UartTx};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_sync::signal::Signal;
+use embassy_time::{Instant, Timer};
+use futures::future::{pending, select, Either};
+use futures::pin_mut;
+use heapless::Vec;
+use lorelay_proto::device_config::DeviceConfig;
+use lorelay_proto::link::{Link, LinkConfig, LinkEvent, Packet};
+
+pub type LinkTx = UartTx<'static, USART1, DMA1_CH3>;
+pub type LinkRx = UartRx<'static, USART1, DMA1_CH4>;
+
+/// Largest read from the UART.
+const READ_SIZE: usize = 64;
+
+/// Bytes read from the BLE board, as they arrived before the line went idle.
+static READS: Channel<CriticalSectionRawMutex, Vec<u8, READ_SIZE>, 4> = Channel::new();
+
+/// Modes requested by the BLE board.
+pub static MODE_REQUESTS: Signal<CriticalSectionRawMutex, Mode> = Signal::new();
+
+/// Configurations the BLE board requested to store.
+pub static CONFIG_REQUESTS: Signal<CriticalSectionRawMutex, DeviceConfig> = Signal::new();
+
+/// Configuration stored, for the BLE board.
+pub static DEVICE_CONFIG: Signal<CriticalSectionRawMutex, DeviceConfig> = Signal::new();
+
+enum Event {
+    Read(Vec<u8, READ_SIZE>),
+    Deadline,
+    Send(Packet),
+}
+
+/// Next packet for the BLE board, once the link is `ready` for it.
+async fn next_packet(ready: bool) -> Packet {
+    if !ready {
+        return pending().await;
+    }
+    loop {
+        let inbox_fut = INBOX.recv();
+        let status_fut = NODE_STATUS.wait();
+        let neighbours_fut = NEIGHBOURS.wait();
+        let summary_fut = RANGE_TEST_SUMMARY.wait();
+        let config_fut = DEVICE_CONFIG.wait();
+        let delivery_fut = GATEWAY_DELIVERY.wait();
+        pin_mut!(inbox_fut);
+        pin_mut!(status_fut);
+        pin_mut!(neighbours_fut);
+        pin_mut!(summary_fut);
+        pin_mut!(config_fut);
+        pin_mut!(delivery_fut);
+        let relay = select(inbox_fut, select(status_fut, neighbours_fut));
+        let others = select(summary_fut, select(config_fut, delivery_fut));
+        match select(relay, others).await {
+            Either::Left((Either::Left((incoming, _)), _)) => match incoming.to_gateway() {
+                Some(message) => return Packet::Incoming(message),
+                None => warn!(
+                    "Payload from {} too large for the BLE board",
+                    incoming.origin_uid
+                ),
+            },
+            Either::Left((Either::Right((Either::Left((status, _)), _)), _)) => {
+                return Packet::Status(status)
+            }
+            Either::Left((Either::Right((Either::Right((neighbours, _)), _)), _)) => {
+                return Packet::Neighbours(neighbours)
+            }
+            Either::Right((Either::Left((summary, _)), _)) => {
+                return Packet::RangeTestSummary(summary)
+            }
+            Either::Right((Either::Right((Either::Left((config, _)), _)), _)) => {
+                return Packet::Config(config)
+            }
+            Either::Right((Either::Right((Either::Right((delivery, _)), _)), _)) => {
+                return Packet::Delivery(delivery)
+            }
+        }
+    }
+}
+
+fn on_event(event: LinkEvent, tx: &mut LinkTx) {
+    match event {
+        LinkEvent::Received(Packet::Outgoing(message)) => {
+            debug!("Message from the BLE board to {}", message.destination_uid);
+            if OUTBOX.try_send(Outgoing::from(message)).is_err() {
+                warn!("Outbox full, dropping message from the BLE board");
+            }
+        }
+        LinkEvent::Received(Packet::SetMode(id)) => match Mode::from_id(id) {
+            Some(mode) => MODE_REQUESTS.signal(mode),
+            None => warn!("Unknown mode {} requested by the BLE board", id),
+        },
+        LinkEvent::Received(Packet::SetConfig(config)) => CONFIG_REQUESTS.signal(config),
+        LinkEvent::Received(Packet::Reboot) => {
+            // The acknowledgment was written before the events are handled, but the UART may
+            // still be shifting out its last bytes.
+            info!("Rebooting on request of the BLE board");
+            if let Err(err) = tx.blocking_flush() {
+                warn!("Link flush error: {}", err);
+            }
+            cortex_m::peripheral::SCB::sys_reset()
+        }
+        LinkEvent::Received(_) => warn!("Unexpected packet from the BLE board"),
+        LinkEvent::Delivered => {}
+        LinkEvent::Dropped(_) => warn!("BLE board not responding, packet dropped"),
+        LinkEvent::Invalid(err) => warn!("Invalid frame from the BLE board: {}", err),
+    }
+}
+
+/// Reads the UART for `link`, which cannot read while it is busy with other events.
+#[embassy_executor::task]
+pub async fn link_reader(mut rx: LinkRx) {
+    let mut buf = [0u8; READ_SIZE];
+    loop {
+        match rx.read_until_idle(&mut buf).await {
+            Ok(len) => {
+                let bytes = Vec::from_slice(&buf[..len]).expect("reads fit the buffer");
+                READS.send(bytes).await
+            }
+            Err(err) => warn!("Link read error: {}", err),
+        }
+    }
+}
+
+/// Runs the link, starting its sequence numbers from `seed`.
+///
+/// `seed` must be random, for the other board to tell a reset of the link from a retransmission.
+#[embassy_executor::task]
+pub async fn link(mut tx: LinkTx, seed: u32) {
+    let mut link: Link<4> = Link::new(LinkConfig::default(), seed);
+    loop {
+        let event = {
+            let deadline = link
+                .next_deadline()
+                .map_or(Instant::MAX, Instant::from_millis);
+            let read_fut = READS.recv();
+            let timer_fut = Timer::at(deadline);
+            let packet_fut = next_packet(link.is_ready());
+            pin_mut!(read_fut);
+            pin_mut!(timer_fut);
+            pin_mut!(packet_fut);
+            match select(read_fut, select(timer_fut, packet_fut)).await {
+                Either::Left((bytes, _)) => Event::Read(bytes),
+                Either::Right((Either::Left(_), _)) => Event::Deadline,
+                Either::Right((Either::Right((packet, _)), _)) => Event::Send(packet),
+            }
+        };
+
+        let now = Instant::now().as_millis();
+        match event {
+            Event::Read(bytes) => link.on_bytes(&bytes, now),
+            Event::Deadline => {}
+            Event::Send(packet) => {
+                // Cannot fail, packets are only taken while the link is ready.
+                let _ = link.send(packet, now);
+            }
+        }
+
+        while let Some(frame) = link.poll_transmit(now) {
+            if let Err(err) = tx.write(&frame).await {
+                warn!("Link write error: {}", err);
+            }
+        }
+        while let Some(event) = link.poll_event() {
+            on_event(event, &mut tx);
+        }
+    }
+}
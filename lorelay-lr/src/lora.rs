@@ -1,189 +1,150 @@
-pub mod message;
-pub mod neighbour;
-
-use crate::led_handling::{LED_BLUE_BLINK_SIGNAL, LED_GREEN_BLINK_SIGNAL, LED_RED_BLINK_SIGNAL};
+use crate::led_handling::BLUE_LED;
 use crate::{SpiLora, Stm32wlIv};
-use core::fmt::Write;
 use defmt::{debug, error, info};
-use embassy_time::{Duration, Timer};
-use heapless::String;
+use embassy_time::{Instant, Timer};
 use lora_phy::mod_params::{
     Bandwidth, CodingRate, ModulationParams, PacketParams, RadioError, SpreadingFactor,
 };
 use lora_phy::sx1261_2::SX1261_2;
 use lora_phy::LoRa;
+use lorelay_proto::radio::{self, ConfigError, PacketStatus, Radio, RadioConfig, ReceivedFrame};
+use lorelay_proto::regulated::Clock;
 
 type Lora = LoRa<SX1261_2<SpiLora, Stm32wlIv>>;
 
-const LORA_FREQUENCY_IN_HZ: u32 = 433_220_000;
-
 const RX_BUF_SIZE: usize = 100;
 
-const OUTPUT_POWER: i32 = 20;
+#[derive(defmt::Format)]
+pub enum Error {
+    Invalid(ConfigError),
+    /// The sync word is set when the radio is created, changing it needs a restart.
+    SyncWordChange,
+    Radio(RadioError),
+}
+
+impl From<RadioError> for Error {
+    fn from(err: RadioError) -> Self {
+        Error::Radio(err)
+    }
+}
 
-const FIRST_MESSAGE: [u8; 8] = [b'h', b'e', b'l', b'l', b'o', b' ', b'0', b'\0'];
+/// Clock of the `Regulated` radio, the embassy time driver.
+pub struct EmbassyClock;
+
+impl Clock for EmbassyClock {
+    fn now_ms(&self) -> u64 {
+        Instant::now().as_millis()
+    }
+
+    async fn sleep_until(&mut self, at_ms: u64) {
+        Timer::at(Instant::from_millis(at_ms)).await
+    }
+}
 
 pub struct LoraRadio {
     lora: Lora,
+    config: RadioConfig,
     mdltn_params: ModulationParams,
     rx_pkt_params: PacketParams,
     tx_pkt_params: PacketParams,
 }
 
 impl LoraRadio {
-    pub async fn new(mut lora: Lora) -> Self {
-        let mdltn_params = modulation_params(&mut lora).expect("Failed to create modulation params");
-
-        let mut tx_pkt_params =
-            create_tx_packet(&mut lora, &mdltn_params).expect("Failed to create TX packet params");
-
-        let rx_pkt_params =
-            create_rx_packet(&mut lora, &mdltn_params).expect("Failed to create RX packet params");
-
-        LoraRadio {
+    /// Wraps a radio created with the sync word of `config`.
+    pub async fn new(mut lora: Lora, config: RadioConfig) -> Result<Self, Error> {
+        config.validate().map_err(Error::Invalid)?;
+        let mdltn_params = modulation_params(&mut lora, &config)?;
+        let tx_pkt_params = create_tx_packet(&mut lora, &config, &mdltn_params)?;
+        let rx_pkt_params = create_rx_packet(&mut lora, &config, &mdltn_params)?;
+
+        Ok(LoraRadio {
             lora,
+            config,
             mdltn_params,
             tx_pkt_params,
-            rx_pkt_params
-        }
+            rx_pkt_params,
+        })
     }
+}
+
+impl Radio for LoraRadio {
+    type Error = Error;
 
-    pub async fn send(&mut self, message: &[u8]) -> Result<(), RadioError> {
-        match self.lora.prepare_for_tx(&self.mdltn_params, OUTPUT_POWER, false).await {
+    fn config(&self) -> RadioConfig {
+        self.config
+    }
+
+    async fn send(&mut self, message: &[u8]) -> Result<(), Error> {
+        let output_power = self.config.output_power as i32;
+        match self.lora.prepare_for_tx(&self.mdltn_params, output_power, false).await {
             Ok(()) => {
                 debug!("Radio prepared for TX");
             }
             Err(err) => {
                 error!("Radio error = {}", err);
-                return Err(err);
+                return Err(err.into());
             }
         };
 
-        Timer::after(Duration::from_secs(1)).await;
-
         match self.lora.tx(&self.mdltn_params, &mut self.tx_pkt_params, message, 0xffffff).await {
             Ok(()) => {
-                info!("Sending message: {}", core::str::from_utf8(message).unwrap());
-                LED_BLUE_BLINK_SIGNAL.signal(());
+                info!("Sending message: {=[u8]:x}", message);
+                BLUE_LED.blink();
             }
             Err(err) => {
                 error!("Radio error = {}", err);
-                return Err(err);
+                return Err(err.into());
             }
         };
         Ok(())
     }
 
-    pub async fn receive(&mut self) -> Result<[u8; RX_BUF_SIZE], RadioError>
-    {
+    async fn receive(&mut self) -> Result<ReceivedFrame, Error> {
         let mut rx_buffer: [u8; RX_BUF_SIZE] = [0; RX_BUF_SIZE];
 
+        prepare_rx(&mut self.lora, &self.mdltn_params, &self.rx_pkt_params).await?;
+
         match self.lora.rx(&self.rx_pkt_params, &mut rx_buffer).await {
             Err(err) => {
                 info!("rx unsuccessful = {}", err);
-                Err(err)
+                Err(err.into())
             }
-            Ok((received_len, _rx_pkt_status)) => {
-                Ok(rx_buffer)
+            Ok((received_len, rx_pkt_status)) => {
+                let len = (received_len as usize).min(RX_BUF_SIZE);
+                let status = PacketStatus {
+                    rssi: rx_pkt_status.rssi,
+                    snr: rx_pkt_status.snr,
+                };
+                Ok(ReceivedFrame::new(
+                    &rx_buffer[..len],
+                    status,
+                    Instant::now().as_millis(),
+                ))
             }
         }
     }
-}
-
-#[embassy_executor::task]
-pub async fn idle_task(mut lora: Lora) {
-    let mut rx_buffer = [0u8; RX_BUF_SIZE];
-
-    info!("Starting RX/TX");
-    let mdltn_params = modulation_params(&mut lora).expect("Failed to create modulation params");
-
-    let mut tx_pkt_params =
-        create_tx_packet(&mut lora, &mdltn_params).expect("Failed to create TX packet params");
-
-    let rx_pkt_params =
-        create_rx_packet(&mut lora, &mdltn_params).expect("Failed to create RX packet params");
-
-    LED_RED_BLINK_SIGNAL.signal(());
-    Timer::after(Duration::from_secs(5)).await;
-
-    if let Err(e) = prepare_tx(&mut lora, &mdltn_params).await {
-        error!("Failed to prepare TX: {}", e);
-        return;
-    }
-
-    info!("Starting First TX");
-    if let Err(e) = tx_buffer(&mut lora, &mdltn_params, &mut tx_pkt_params, &FIRST_MESSAGE).await {
-        error!("Failed to TX: {}", e);
-        return;
-    }
-
-    info!("Preparing RX");
-
-    if let Err(e) = prepare_rx(&mut lora, &mdltn_params, &rx_pkt_params).await {
-        error!("Failed to prepare RX: {}", e);
-        return;
-    }
-
-    info!("Starting RXTX loop");
-
-    loop {
-        rx_buffer.fill(0);
-
-        info!("Starting RXTX loop cycle");
-        match lora.rx(&rx_pkt_params, &mut rx_buffer).await {
-            Err(err) => info!("rx unsuccessful = {}", err),
-            Ok((received_len, _rx_pkt_status)) => {
-                if received_len <= 12 && rx_buffer.starts_with("hello".as_bytes()) {
-                    info!(
-                        "Received message: {}",
-                        core::str::from_utf8(&rx_buffer).unwrap()
-                    );
-                    // Green led for message reception
-                    LED_GREEN_BLINK_SIGNAL.signal(());
-                    Timer::after(Duration::from_secs(1)).await;
-
-                    if let Err(e) = prepare_tx(&mut lora, &mdltn_params).await {
-                        error!("Failed to prepare TX: {}", e);
-                        return;
-                    }
-
-                    let new_message = create_message(rx_buffer);
-                    Timer::after(Duration::from_secs(1)).await;
 
-                    if let Err(e) = tx_buffer(
-                        &mut lora,
-                        &mdltn_params,
-                        &mut tx_pkt_params,
-                        new_message.as_bytes(),
-                    )
-                    .await
-                    {
-                        error!("Failed to TX: {}", e);
-                        return;
-                    }
-                } else {
-                    info!("rx unknown packet");
-                }
-            }
-        }
-        if let Err(e) = prepare_rx(&mut lora, &mdltn_params, &rx_pkt_params).await {
-            error!("Failed to prepare RX: {}", e);
-            return;
+    async fn configure(&mut self, config: RadioConfig) -> Result<(), Error> {
+        config.validate().map_err(Error::Invalid)?;
+        if config.sync_word != self.config.sync_word {
+            return Err(Error::SyncWordChange);
         }
+        let mdltn_params = modulation_params(&mut self.lora, &config)?;
+        let tx_pkt_params = create_tx_packet(&mut self.lora, &config, &mdltn_params)?;
+        let rx_pkt_params = create_rx_packet(&mut self.lora, &config, &mdltn_params)?;
+
+        self.config = config;
+        self.mdltn_params = mdltn_params;
+        self.tx_pkt_params = tx_pkt_params;
+        self.rx_pkt_params = rx_pkt_params;
+        info!("Radio reconfigured: {}", config);
+        Ok(())
     }
-}
 
-fn create_message(rx_buffer: [u8; 100]) -> String<20> {
-    let msg = core::str::from_utf8(&rx_buffer).unwrap();
-    let (hello, number_str) = core::ffi::CStr::from_bytes_until_nul(msg.as_bytes())
-        .unwrap()
-        .to_str()
-        .unwrap()
-        .split_at(msg.find(' ').unwrap());
-    let number: u32 = number_str.trim().parse().unwrap();
-    let mut new_message: String<20> = String::new();
-    write!(&mut new_message, "{} {}", hello, number + 1).expect("Failed to write to string");
-    new_message
+    async fn cad(&mut self) -> Result<bool, Error> {
+        self.lora.prepare_for_cad(&self.mdltn_params, true).await?;
+        Ok(self.lora.cad().await?)
+    }
 }
 
 async fn prepare_rx(
@@ -212,78 +173,92 @@ async fn prepare_rx(
     Ok(())
 }
 
-async fn tx_buffer(
+fn create_rx_packet(
     lora: &mut Lora,
+    config: &RadioConfig,
     mdltn_params: &ModulationParams,
-    tx_pkt_params: &mut PacketParams,
-    buff: &[u8],
-) -> Result<(), RadioError> {
-    match lora.tx(mdltn_params, tx_pkt_params, buff, 0xffffff).await {
-        Ok(()) => {
-            info!("Sending message: {}", core::str::from_utf8(buff).unwrap());
-            LED_BLUE_BLINK_SIGNAL.signal(());
-        }
-        Err(err) => {
-            error!("Radio error = {}", err);
-            return Err(err);
-        }
-    };
-    Ok(())
+) -> Result<PacketParams, RadioError> {
+    lora.create_rx_packet_params(
+        config.preamble_length,
+        config.implicit_header,
+        RX_BUF_SIZE as u8,
+        config.crc,
+        false,
+        mdltn_params,
+    )
+    .map_err(|err| {
+        error!("Radio error = {}", err);
+        err
+    })
 }
 
-async fn prepare_tx(
+fn create_tx_packet(
     lora: &mut Lora,
+    config: &RadioConfig,
     mdltn_params: &ModulationParams,
-) -> Result<(), RadioError> {
-    match lora.prepare_for_tx(mdltn_params, OUTPUT_POWER, false).await {
-        Ok(()) => {
-            debug!("Radio prepared for TX");
-        }
-        Err(err) => {
-            error!("Radio error = {}", err);
-            return Err(err);
-        }
-    };
-    Ok(())
+) -> Result<PacketParams, RadioError> {
+    lora.create_tx_packet_params(
+        config.preamble_length,
+        config.implicit_header,
+        config.crc,
+        false,
+        mdltn_params,
+    )
+    .map_err(|err| {
+        error!("Radio error = {}", err);
+        err
+    })
 }
 
-fn create_rx_packet(lora: &mut Lora, mdltn_params: &ModulationParams) -> Option<PacketParams> {
-    Some(
-        match lora.create_rx_packet_params(4, false, RX_BUF_SIZE as u8, true, false, mdltn_params) {
-            Ok(pp) => pp,
-            Err(err) => {
-                error!("Radio error = {}", err);
-                return None;
-            }
-        },
+fn modulation_params(
+    lora: &mut Lora,
+    config: &RadioConfig,
+) -> Result<ModulationParams, RadioError> {
+    lora.create_modulation_params(
+        spreading_factor(config.spreading_factor),
+        bandwidth(config.bandwidth),
+        coding_rate(config.coding_rate),
+        config.frequency_hz,
     )
+    .map_err(|err| {
+        error!("Radio error = {}", err);
+        err
+    })
 }
 
-fn create_tx_packet(lora: &mut Lora, mdltn_params: &ModulationParams) -> Option<PacketParams> {
-    Some(
-        match lora.create_tx_packet_params(4, false, true, false, mdltn_params) {
-            Ok(pp) => pp,
-            Err(err) => {
-                error!("Radio error = {}", err);
-                return None;
-            }
-        },
-    )
+fn spreading_factor(spreading_factor: radio::SpreadingFactor) -> SpreadingFactor {
+    match spreading_factor {
+        radio::SpreadingFactor::Sf5 => SpreadingFactor::_5,
+        radio::SpreadingFactor::Sf6 => SpreadingFactor::_6,
+        radio::SpreadingFactor::Sf7 => SpreadingFactor::_7,
+        radio::SpreadingFactor::Sf8 => SpreadingFactor::_8,
+        radio::SpreadingFactor::Sf9 => SpreadingFactor::_9,
+        radio::SpreadingFactor::Sf10 => SpreadingFactor::_10,
+        radio::SpreadingFactor::Sf11 => SpreadingFactor::_11,
+        radio::SpreadingFactor::Sf12 => SpreadingFactor::_12,
+    }
 }
 
-fn modulation_params(lora: &mut Lora) -> Option<ModulationParams> {
-    Some(
-        match lora.create_modulation_params(
-            SpreadingFactor::_10,
-            Bandwidth::_250KHz,
-            CodingRate::_4_8,
-            LORA_FREQUENCY_IN_HZ,
-        ) {
-            Ok(mp) => mp,
-            Err(err) => {
-                error!("Radio error = {}", err);
-                return None;
-            }
-        },
-    )
+fn bandwidth(bandwidth: radio::Bandwidth) -> Bandwidth {
+    match bandwidth {
+        radio::Bandwidth::Khz7_8 => Bandwidth::_7KHz,
+        radio::Bandwidth::Khz10_4 => Bandwidth::_10KHz,
+        radio::Bandwidth::Khz15_6 => Bandwidth::_15KHz,
+        radio::Bandwidth::Khz20_8 => Bandwidth::_20KHz,
+        radio::Bandwidth::Khz31_25 => Bandwidth::_31KHz,
+        radio::Bandwidth::Khz41_7 => Bandwidth::_41KHz,
+        radio::Bandwidth::Khz62_5 => Bandwidth::_62KHz,
+        radio::Bandwidth::Khz125 => Bandwidth::_125KHz,
+        radio::Bandwidth::Khz250 => Bandwidth::_250KHz,
+        radio::Bandwidth::Khz500 => Bandwidth::_500KHz,
+    }
+}
+
+fn coding_rate(coding_rate: radio::CodingRate) -> CodingRate {
+    match coding_rate {
+        radio::CodingRate::Cr4_5 => CodingRate::_4_5,
+        radio::CodingRate::Cr4_6 => CodingRate::_4_6,
+        radio::CodingRate::Cr4_7 => CodingRate::_4_7,
+        radio::CodingRate::Cr4_8 => CodingRate::_4_8,
+    }
 }
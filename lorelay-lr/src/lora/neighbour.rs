@@ -1,7 +0,0 @@
-
-
-pub struct Neighbour {
-    pub uid: u16,
-    pub rssi: i16,
-    pub last_seen: u32,
-}
\ No newline at end of file
@@ -8,57 +8,136 @@
 
 mod button_handling;
 mod led_handling;
+mod link;
 mod lora;
+mod mode;
+mod relay;
+mod storage;
 
-use crate::button_handling::{Button1, Button3, BUTTON_PRESS_SIGNAL, ButtonPress};
+use crate::button_handling::{Button1, Button3, BUTTON_EVENTS};
 use button_handling::Button2;
-use defmt::info;
+use defmt::{error, info, warn};
 use embassy_executor::Spawner;
 use embassy_lora::iv::InterruptHandler;
 use embassy_lora::iv::Stm32wlInterfaceVariant;
 use embassy_stm32::bind_interrupts;
 use embassy_stm32::exti::ExtiInput;
+use embassy_stm32::flash::Flash;
 use embassy_stm32::gpio::{AnyPin, Input, Level, Output, Pin, Pull, Speed};
-use embassy_stm32::peripherals::{DMA1_CH1, DMA1_CH2};
+use embassy_stm32::peripherals::{self, DMA1_CH1, DMA1_CH2};
+use embassy_stm32::rng::Rng;
 use embassy_stm32::spi::Spi;
+use embassy_stm32::usart::{self, Uart};
 use embassy_time::Delay;
+use futures::future::{select, Either};
+use futures::pin_mut;
 use led_handling::{BlueLed, GreenLed, RedLed};
 use lora_phy::mod_params::*;
 use lora_phy::sx1261_2::SX1261_2;
 use lora_phy::LoRa;
 use {defmt_rtt as _, panic_probe as _};
-use crate::lora::LoraRadio;
-use crate::lora::neighbour::Neighbour;
+use crate::lora::{EmbassyClock, LoraRadio};
+use crate::mode::Mode;
+use crate::storage::ConfigStore;
+use lorelay_proto::airtime::{link_budget_db, time_on_air_us};
+use lorelay_proto::channel_access::{ChannelAccess, ChannelAccessConfig, RandomBackoff};
+use lorelay_proto::device_config::DeviceConfig;
+use lorelay_proto::gesture::ButtonEvent;
+use lorelay_proto::message::{Message, MAX_MESSAGE_SIZE};
+use lorelay_proto::radio::Radio;
+use lorelay_proto::regulated::Regulated;
+use rand_core::RngCore;
 
 type SpiLora = Spi<'static, embassy_stm32::peripherals::SUBGHZSPI, DMA1_CH1, DMA1_CH2>;
 type Stm32wlIv = Stm32wlInterfaceVariant<Output<'static, AnyPin>>;
 
 bind_interrupts!(struct Irqs{
     SUBGHZ_RADIO => InterruptHandler;
+    USART1 => usart::InterruptHandler<peripherals::USART1>;
 });
 
-pub struct Device {
+pub struct Device<R = Regulated<LoraRadio, EmbassyClock, RandomBackoff>> {
     uuid: u16,
-    pub lora: LoraRadio,
-    neighbours: heapless::Vec<Neighbour, 16>,
+    /// Random at every boot, for what must differ across resets.
+    seed: u32,
+    pub radio: R,
+}
+
+impl<R: Radio> Device<R> {
+    pub async fn send_message(&mut self, message: &Message) -> Result<(), R::Error> {
+        let mut tx_buffer = [0u8; MAX_MESSAGE_SIZE];
+        let len = message
+            .encode(&mut tx_buffer)
+            .expect("TX buffer is sized for the largest message");
+        self.radio.send(&tx_buffer[..len]).await
+    }
 }
 
 
+/// Runs the mode selected with the buttons or the BLE board, starting with `mode`.
+///
+/// The mode is stored whenever it changes, so that the node starts in it again after a reset.
 #[embassy_executor::task]
-pub async fn state_machine(mut device: Device) {
+pub async fn state_machine(mut device: Device, mut mode: Mode, mut store: ConfigStore) {
+    loop {
+        link::DEVICE_CONFIG.signal(*store.config());
+        mode.enter(&mut device).await;
+        let next = mode.run(&mut device, next_mode(mode, &mut store)).await;
+        mode.exit(&mut device).await;
+        mode = next;
+
+        let config = DeviceConfig {
+            mode: mode.id(),
+            ..*store.config()
+        };
+        if let Err(err) = store.store(config) {
+            warn!("Failed to store the mode: {}", err);
+        }
+    }
+}
 
+/// Waits for a click on a button, or a request of the BLE board, selecting another mode than
+/// `current`.
+///
+/// Configurations requested by the BLE board meanwhile are stored for the next reset, if the radio
+/// can run with them in the region they name.
+async fn next_mode(current: Mode, store: &mut ConfigStore) -> Mode {
     loop {
-        match BUTTON_PRESS_SIGNAL.wait().await {
-            ButtonPress::Button1 => {
-                todo!("Mode 1")
-            }
-            ButtonPress::Button2 => {
-                todo!("Mode 2")
-            }
-            ButtonPress::Button3 => {
-                todo!("Mode 3")
+        let mode = {
+            let button_fut = BUTTON_EVENTS.recv();
+            let mode_fut = link::MODE_REQUESTS.wait();
+            let config_fut = link::CONFIG_REQUESTS.wait();
+            pin_mut!(button_fut);
+            pin_mut!(mode_fut);
+            pin_mut!(config_fut);
+            match select(button_fut, select(mode_fut, config_fut)).await {
+                Either::Left(((button, ButtonEvent::Click), _)) => Mode::from_button(button),
+                Either::Left(_) => continue,
+                Either::Right((Either::Left((mode, _)), _)) => mode,
+                Either::Right((Either::Right((config, _)), _)) => {
+                    if let Err(err) = storage::check(&config) {
+                        warn!("Configuration not allowed: {}", err);
+                        continue;
+                    }
+                    let config = DeviceConfig {
+                        mode: current.id(),
+                        ..config
+                    };
+                    match store.store(config) {
+                        Ok(()) => {
+                            info!("Configuration stored, applied after a reset");
+                            link::DEVICE_CONFIG.signal(config);
+                        }
+                        Err(err) => warn!("Failed to store the configuration: {}", err),
+                    }
+                    continue;
+                }
             }
+        };
+        if mode != current {
+            return mode;
         }
+        info!("Already in {} mode", mode);
     }
 }
 
@@ -66,7 +145,13 @@ pub async fn state_machine(mut device: Device) {
 async fn main(spawner: Spawner) {
     let mut config = embassy_stm32::Config::default();
     config.rcc.mux = embassy_stm32::rcc::ClockSrc::HSE32;
+    // Clock of the RNG.
+    config.rcc.enable_lsi = true;
     let p = embassy_stm32::init(config);
+    embassy_stm32::pac::RCC
+        .ccipr()
+        .modify(|w| w.set_rngsel(0b01));
+    let mut rng = Rng::new(p.RNG);
 
     let spi = Spi::new_subghz(p.SUBGHZSPI, p.DMA1_CH1, p.DMA1_CH2);
 
@@ -78,10 +163,13 @@ async fn main(spawner: Spawner) {
 
     let mut delay = Delay;
 
+    let store = ConfigStore::load(Flash::new_blocking(p.FLASH));
+    let region = store.config().region;
+    let radio_config = store.config().radio;
     let lora = {
         match LoRa::new(
             SX1261_2::new(BoardType::Stm32wlSx1262, spi, iv),
-            false,
+            radio_config.is_public_network(),
             &mut delay,
         )
         .await
@@ -93,11 +181,39 @@ async fn main(spawner: Spawner) {
             }
         }
     };
+    // One backoff slot lets the longest frame through.
+    let backoff = RandomBackoff::new(
+        rng.next_u32(),
+        time_on_air_us(&radio_config, MAX_MESSAGE_SIZE) / 1_000,
+        4,
+    );
+    let channel_access = ChannelAccess::new(ChannelAccessConfig::default(), backoff);
+    let lora = match LoraRadio::new(lora, radio_config).await {
+        Ok(lora) => lora,
+        Err(err) => {
+            error!("Invalid radio configuration: {}", err);
+            return;
+        }
+    };
+    let lora = match Regulated::new(lora, EmbassyClock, region, channel_access) {
+        Ok(lora) => lora,
+        Err(err) => {
+            error!("Radio configuration not allowed: {}", err);
+            return;
+        }
+    };
+    info!(
+        "Link budget {} dB, {} us on air for a full frame",
+        link_budget_db(&radio_config),
+        time_on_air_us(&radio_config, usize::from(u8::MAX))
+    );
     let device = Device {
-        uuid: 1,
-        lora: LoraRadio::new(lora).await,
-        neighbours: heapless::Vec::new(),
+        uuid: store.config().uid_or(storage::unique_id_uid()),
+        seed: rng.next_u32(),
+        radio: lora,
     };
+    info!("Node UID {}", device.uuid);
+    let mode = Mode::from_id(store.config().mode).unwrap_or(Mode::Relay);
 
     let blue_led: BlueLed = Output::new(p.PB15, Level::Low, Speed::Low);
     let green_led: GreenLed = Output::new(p.PB9, Level::Low, Speed::Low);
@@ -111,6 +227,18 @@ async fn main(spawner: Spawner) {
     let exti_2 = ExtiInput::new(button_2, p.EXTI1);
     let exti_3 = ExtiInput::new(button_3, p.EXTI6);
 
+    // Link to the BLE board.
+    let uart = Uart::new(
+        p.USART1,
+        p.PB7,
+        p.PB6,
+        Irqs,
+        p.DMA1_CH3,
+        p.DMA1_CH4,
+        usart::Config::default(),
+    );
+    let (link_tx, link_rx) = uart.split();
+
     spawner
         .spawn(led_handling::blue_led_handler(blue_led))
         .expect("spawner failed");
@@ -130,6 +258,12 @@ async fn main(spawner: Spawner) {
         .spawn(button_handling::button_3_press(exti_3))
         .expect("spawner failed");
     spawner
-        .spawn(lora::idle_task(lora))
+        .spawn(link::link_reader(link_rx))
+        .expect("spawner failed");
+    spawner
+        .spawn(link::link(link_tx, rng.next_u32()))
+        .expect("spawner failed");
+    spawner
+        .spawn(state_machine(device, mode, store))
         .expect("spawner failed");
 }
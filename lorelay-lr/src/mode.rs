@@ -0,0 +1,308 @@
+//! Operating modes of the node, one per button.
+//!
+//! A mode owns the radio while it runs, and only gives it back once its current transmission is
+//! over: the request to switch is awaited alongside reception and timers, never during a send.
+use crate::button_handling::Button;
+use crate::led_handling::{Led, BLUE_LED, GREEN_LED, RED_LED};
+use crate::relay::relay;
+use crate::Device;
+use core::future::Future;
+use defmt::{debug, error, info, warn};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant, Timer};
+use futures::future::{select, Either};
+use futures::pin_mut;
+use heapless::Vec;
+use lorelay_proto::led::DeviceState;
+use lorelay_proto::message::{MessageBuilder, MessageType, BROADCAST_UID};
+use lorelay_proto::radio::{PacketStatus, Radio};
+use lorelay_proto::range_test::{Probe, RangeStats, Reception, Summary, PROBE_SIZE};
+use lorelay_proto::relay::RelayConfig;
+
+/// Time between two range test probes.
+const PROBE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Number of probes the packet error rate is computed over.
+const PER_WINDOW: usize = 20;
+
+/// Senders the range test keeps statistics for.
+const MAX_PROBE_SENDERS: usize = 8;
+
+/// Probes heard from a sender between two logs of its statistics.
+const SUMMARY_INTERVAL: u32 = 10;
+
+/// Latest statistics of the range test, about the last sender heard.
+pub static RANGE_TEST_SUMMARY: Signal<CriticalSectionRawMutex, Summary> = Signal::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Mode {
+    /// Takes part in the network, see `relay`.
+    Relay,
+    /// Broadcasts numbered probes, and keeps and logs statistics about the ones heard from other
+    /// nodes, to survey links.
+    RangeTest,
+    /// Logs every frame heard, decoded when possible, and never transmits.
+    Sniffer,
+}
+
+impl Mode {
+    pub fn from_button(button: Button) -> Self {
+        match button {
+            Button::Button1 => Mode::Relay,
+            Button::Button2 => Mode::RangeTest,
+            Button::Button3 => Mode::Sniffer,
+        }
+    }
+
+    /// Number of the mode in the device configuration.
+    pub fn id(self) -> u8 {
+        self as u8
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        [Mode::Relay, Mode::RangeTest, Mode::Sniffer]
+            .get(usize::from(id))
+            .copied()
+    }
+
+    /// LED blinked when the mode is entered.
+    fn led(self) -> &'static Led {
+        match self {
+            Mode::Relay => &GREEN_LED,
+            Mode::RangeTest => &BLUE_LED,
+            Mode::Sniffer => &RED_LED,
+        }
+    }
+
+    pub async fn enter<R: Radio>(self, device: &mut Device<R>) {
+        info!("Node {} entering {} mode", device.uuid, self);
+        self.led().blink();
+    }
+
+    pub async fn exit<R: Radio>(self, device: &mut Device<R>) {
+        info!("Node {} leaving {} mode", device.uuid, self);
+    }
+
+    /// Runs the mode on `device` until `stop` completes.
+    pub async fn run<R: Radio, S: Future>(self, device: &mut Device<R>, stop: S) -> S::Output
+    where
+        R::Error: defmt::Format,
+    {
+        match self {
+            Mode::Relay => relay(device, RelayConfig::default(), stop).await,
+            Mode::RangeTest => range_test(device, stop).await,
+            Mode::Sniffer => sniffer(device, stop).await,
+        }
+    }
+}
+
+/// Statistics of the probes heard from `origin_uid`, made room for if needed.
+///
+/// Once the table is full, the sender heard the least often is forgotten.
+fn stats_of(
+    table: &mut Vec<RangeStats<PER_WINDOW>, MAX_PROBE_SENDERS>,
+    origin_uid: u16,
+) -> &mut RangeStats<PER_WINDOW> {
+    let index = match table
+        .iter()
+        .position(|stats| stats.origin_uid() == origin_uid)
+    {
+        Some(index) => index,
+        None => {
+            if table.is_full() {
+                let least = table
+                    .iter()
+                    .enumerate()
+                    .min_by_key(|(_, stats)| stats.received())
+                    .map(|(index, _)| index)
+                    .expect("the table is full");
+                table.swap_remove(least);
+            }
+            // Cannot fail, there is room left.
+            let _ = table.push(RangeStats::new(origin_uid));
+            table.len() - 1
+        }
+    };
+    &mut table[index]
+}
+
+fn log_summary(summary: &Summary) {
+    info!(
+        "Node {}: {} probes heard, {} lost, {} duplicated, PER {} permille over the last {}, \
+         RSSI {}/{}/{} dBm, SNR {}/{}/{} dB (min/mean/max), sent at {} dBm SF{}",
+        summary.origin_uid,
+        summary.received,
+        summary.lost,
+        summary.duplicates,
+        summary.packet_error_rate_permille,
+        PER_WINDOW,
+        summary.rssi.min,
+        summary.rssi.mean,
+        summary.rssi.max,
+        summary.snr.min,
+        summary.snr.mean,
+        summary.snr.max,
+        summary.probe.output_power,
+        summary.probe.spreading_factor.value()
+    );
+}
+
+async fn range_test<R: Radio, S: Future>(device: &mut Device<R>, stop: S) -> S::Output
+where
+    R::Error: defmt::Format,
+{
+    // Probes measure single links, they must not be relayed.
+    let mut builder = MessageBuilder::new(device.uuid).with_hop_limit(1);
+    let mut stats = Vec::<RangeStats<PER_WINDOW>, MAX_PROBE_SENDERS>::new();
+    let mut sequence: u32 = 0;
+    let mut next_probe = Instant::now();
+    pin_mut!(stop);
+
+    loop {
+        let event = {
+            let rx_fut = device.radio.receive();
+            let timer_fut = Timer::at(next_probe);
+            pin_mut!(rx_fut);
+            pin_mut!(timer_fut);
+            match select(select(rx_fut, timer_fut), stop.as_mut()).await {
+                Either::Left((Either::Left((received, _)), _)) => Some(received),
+                Either::Left((Either::Right(_), _)) => None,
+                Either::Right((output, _)) => {
+                    info!("Range test over: {} probes sent", sequence);
+                    stats
+                        .iter()
+                        .filter_map(RangeStats::summary)
+                        .for_each(|summary| log_summary(&summary));
+                    return output;
+                }
+            }
+        };
+
+        match event {
+            Some(Ok(frame)) => {
+                let status = frame.status;
+                let Ok(message) = frame.decode() else {
+                    continue;
+                };
+                let Some(probe) = Probe::decode(message.payload()) else {
+                    continue;
+                };
+                let sender = stats_of(&mut stats, message.origin_uid());
+                match sender.on_probe(&probe, status) {
+                    Reception::Received { missed } => info!(
+                        "Probe {} from {}: RSSI {} dBm, SNR {} dB, {} missed, PER {} permille",
+                        probe.sequence,
+                        sender.origin_uid(),
+                        status.rssi,
+                        status.snr,
+                        missed,
+                        sender.packet_error_rate_permille()
+                    ),
+                    Reception::Duplicate => {
+                        debug!(
+                            "Probe {} from {} again",
+                            probe.sequence,
+                            sender.origin_uid()
+                        )
+                    }
+                    Reception::Restarted => info!(
+                        "Node {} restarted its range test at probe {}",
+                        sender.origin_uid(),
+                        probe.sequence
+                    ),
+                }
+                if let Some(summary) = sender.summary() {
+                    if summary.received % SUMMARY_INTERVAL == 0 {
+                        log_summary(&summary);
+                    }
+                    RANGE_TEST_SUMMARY.signal(summary);
+                }
+                GREEN_LED.blink();
+            }
+            Some(Err(err)) => {
+                error!("Radio error = {}", err);
+                RED_LED.show_state(DeviceState::RadioError);
+                Timer::after(Duration::from_secs(1)).await;
+            }
+            None => {
+                // Sent with the settings in effect, which the receivers report along their
+                // statistics.
+                let mut payload = [0u8; PROBE_SIZE];
+                Probe::new(sequence, &device.radio.config())
+                    .encode(&mut payload)
+                    .expect("payload is sized for a probe");
+                if let Some(probe) = builder.normal(BROADCAST_UID, &payload) {
+                    // A probe that failed to go out counts as lost for the receivers.
+                    if let Err(err) = device.send_message(&probe).await {
+                        warn!("Failed to send probe {}: {}", sequence, err);
+                    }
+                    sequence = sequence.wrapping_add(1);
+                }
+                // From the end of the send, which may have waited for the duty cycle: probes
+                // missed meanwhile are skipped rather than sent back to back.
+                next_probe = Instant::now() + PROBE_INTERVAL;
+            }
+        }
+    }
+}
+
+async fn sniffer<R: Radio, S: Future>(device: &mut Device<R>, stop: S) -> S::Output
+where
+    R::Error: defmt::Format,
+{
+    let mut frames: u32 = 0;
+    pin_mut!(stop);
+
+    loop {
+        let received = {
+            let rx_fut = device.radio.receive();
+            pin_mut!(rx_fut);
+            match select(rx_fut, stop.as_mut()).await {
+                Either::Left((received, _)) => received,
+                Either::Right((output, _)) => {
+                    info!("Sniffer over: {} frames heard", frames);
+                    return output;
+                }
+            }
+        };
+
+        match received {
+            Ok(frame) => {
+                frames += 1;
+                let PacketStatus { rssi, snr } = frame.status;
+                info!(
+                    "Frame {} of {} bytes, RSSI {} dBm, SNR {} dB: {=[u8]:x}",
+                    frames,
+                    frame.len(),
+                    rssi,
+                    snr,
+                    frame.payload()
+                );
+                match frame.decode() {
+                    Ok(message) => {
+                        let kind = match message.message_type() {
+                            MessageType::Normal { .. } => "Normal",
+                            MessageType::Ping { .. } => "Ping",
+                            MessageType::Pong { .. } => "Pong",
+                            MessageType::Ack { .. } => "Ack",
+                        };
+                        info!(
+                            "{} sent by {}, from {} to {}",
+                            kind,
+                            message.sender_uid(),
+                            message.origin_uid(),
+                            message.destination_uid()
+                        );
+                    }
+                    Err(err) => info!("Undecodable: {}", err),
+                }
+            }
+            Err(err) => {
+                error!("Radio error = {}", err);
+                RED_LED.show_state(DeviceState::RadioError);
+                Timer::after(Duration::from_secs(1)).await;
+            }
+        }
+    }
+}
@@ -0,0 +1,246 @@
+//! Main loop of a relay node, driving the `Relay` of `lorelay_proto` with the radio, the clock
+//! and the channels of the firmware.
+//!
+//! Outgoing messages may ask for an acknowledgment: they are then retransmitted until the `Ack`
+//! comes back or the retries run out, and the outcome is published on the caller's signal.
+//! Payloads larger than a message are fragmented, and always acknowledged by their destination.
+//!
+//! The status of the node and its neighbours are published every `STATUS_INTERVAL` for the BLE
+//! gateway, whose messages convert to and from `Outgoing` and `Incoming`.
+use crate::led_handling::{GREEN_LED, RED_LED};
+use crate::mode::Mode;
+use crate::Device;
+use core::future::Future;
+use defmt::{debug, error, info, warn};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant, Timer};
+use futures::future::{select, Either};
+use futures::pin_mut;
+use heapless::Vec;
+use lorelay_proto::ack::Delivery;
+use lorelay_proto::gateway::{IncomingMessage, NeighbourEntry, NodeStatus, OutgoingMessage};
+use lorelay_proto::led::{DeviceState, Priority};
+use lorelay_proto::radio::{Radio, ReceivedFrame};
+use lorelay_proto::relay::{self, Payload, Relay, RelayConfig, NEIGHBOUR_TABLE_SIZE};
+
+/// Time between two publications of the status of the node.
+const STATUS_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Payloads to originate from this node.
+pub static OUTBOX: Channel<CriticalSectionRawMutex, Outgoing, 4> = Channel::new();
+
+/// Payloads addressed to this node or broadcast.
+pub static INBOX: Channel<CriticalSectionRawMutex, Incoming, 4> = Channel::new();
+
+/// Outcome of an acknowledged message, awaited by the code that queued it.
+pub type DeliverySignal = Signal<CriticalSectionRawMutex, Delivery>;
+
+/// Outcome of the last acknowledged payload sent through the gateway, for the BLE board.
+pub static GATEWAY_DELIVERY: DeliverySignal = Signal::new();
+
+/// Latest status of the node while it relays.
+pub static NODE_STATUS: Signal<CriticalSectionRawMutex, NodeStatus> = Signal::new();
+
+/// Latest live neighbours of the node while it relays.
+pub static NEIGHBOURS: Signal<CriticalSectionRawMutex, Vec<NeighbourEntry, NEIGHBOUR_TABLE_SIZE>> =
+    Signal::new();
+
+pub struct Outgoing {
+    pub destination_uid: u16,
+    pub payload: Payload,
+    /// Requests an acknowledgment from the destination and receives the outcome.
+    ///
+    /// Broadcasts are never acknowledged, the signal is completed with `TimedOut` right away.
+    pub delivery: Option<&'static DeliverySignal>,
+}
+
+impl From<OutgoingMessage> for Outgoing {
+    fn from(message: OutgoingMessage) -> Self {
+        Outgoing {
+            destination_uid: message.destination_uid,
+            payload: Payload::from_slice(&message.payload)
+                .expect("gateway payloads are smaller than relay ones"),
+            delivery: message.ack.then_some(&GATEWAY_DELIVERY),
+        }
+    }
+}
+
+pub struct Incoming {
+    pub origin_uid: u16,
+    pub payload: Payload,
+}
+
+impl Incoming {
+    /// The payload for the gateway, `None` if it is too large for it.
+    pub fn to_gateway(&self) -> Option<IncomingMessage> {
+        Some(IncomingMessage {
+            origin_uid: self.origin_uid,
+            payload: Vec::from_slice(&self.payload).ok()?,
+        })
+    }
+}
+
+// Only ever lives on the stack of the relay loop, one at a time.
+#[allow(clippy::large_enum_variant)]
+enum Event<E, S> {
+    Received(Result<ReceivedFrame, E>),
+    Deadline,
+    Send(Outgoing),
+    Stop(S),
+}
+
+/// Runs the relay on `device`, whatever its radio, until `stop` completes.
+///
+/// `stop` is only awaited while the radio listens, never during a transmission. Payloads in
+/// flight then time out, while those still in `OUTBOX` wait for the next run.
+pub async fn relay<R: Radio, S: Future>(
+    device: &mut Device<R>,
+    config: RelayConfig,
+    stop: S,
+) -> S::Output
+where
+    R::Error: defmt::Format,
+{
+    // Differs across resets, and across the runs of one boot.
+    let seed = device.seed ^ Instant::now().as_ticks() as u32;
+    let mut relay: Relay<&'static DeliverySignal> =
+        Relay::new(device.uuid, config, Instant::now().as_millis(), seed);
+
+    info!("Starting relay: {}", config);
+    GREEN_LED.show_state(DeviceState::Joining);
+    let mut joined = false;
+    let mut next_status = Instant::now();
+    pin_mut!(stop);
+
+    loop {
+        let event = {
+            let rx_fut = device.radio.receive();
+            let deadline = Instant::from_millis(relay.next_deadline()).min(next_status);
+            let timer_fut = Timer::at(deadline);
+            let outbox_fut = OUTBOX.recv();
+            pin_mut!(rx_fut);
+            pin_mut!(timer_fut);
+            pin_mut!(outbox_fut);
+            let wait = select(rx_fut, select(timer_fut, outbox_fut));
+            match select(wait, stop.as_mut()).await {
+                Either::Left((Either::Left((received, _)), _)) => Event::Received(received),
+                Either::Left((Either::Right((Either::Left(_), _)), _)) => Event::Deadline,
+                Either::Left((Either::Right((Either::Right((outgoing, _)), _)), _)) => {
+                    Event::Send(outgoing)
+                }
+                Either::Right((output, _)) => Event::Stop(output),
+            }
+        };
+
+        let now = Instant::now().as_millis();
+        match event {
+            Event::Received(Ok(frame)) => {
+                if let Err(err) = relay.on_frame(frame.payload(), frame.status, frame.received_at) {
+                    warn!("Dropping undecodable frame: {}", err);
+                }
+            }
+            Event::Received(Err(err)) => {
+                error!("Radio error = {}", err);
+                RED_LED.show_state(DeviceState::RadioError);
+                Timer::after(Duration::from_secs(1)).await;
+            }
+            Event::Send(outgoing) => {
+                let sent = relay.send(
+                    outgoing.destination_uid,
+                    &outgoing.payload,
+                    outgoing.delivery,
+                    now,
+                );
+                if let Err(err) = sent {
+                    warn!(
+                        "Cannot send payload to {}: {}",
+                        outgoing.destination_uid, err
+                    );
+                }
+            }
+            Event::Deadline => {}
+            Event::Stop(output) => {
+                info!("Stopping relay");
+                relay.abandon();
+                publish_events(&mut relay);
+                GREEN_LED.clear(Priority::Status);
+                return output;
+            }
+        }
+
+        while let Some(message) = relay.poll(now) {
+            debug!(
+                "Sending message from {} to {}",
+                message.origin_uid(),
+                message.destination_uid()
+            );
+            if let Err(err) = device.send_message(&message).await {
+                error!("Failed to send message: {}", err);
+            }
+        }
+
+        publish_events(&mut relay);
+
+        let has_neighbours = relay.neighbours().iter_alive(now).next().is_some();
+        if has_neighbours != joined {
+            joined = has_neighbours;
+            info!("{} neighbours", if joined { "Found" } else { "Lost all" });
+            GREEN_LED.show_state(if joined {
+                DeviceState::Relaying
+            } else {
+                DeviceState::Joining
+            });
+        }
+
+        if Instant::now() >= next_status {
+            publish_status(&relay, now);
+            next_status = Instant::now() + STATUS_INTERVAL;
+        }
+    }
+}
+
+fn publish_status(relay: &Relay<&'static DeliverySignal>, now: u64) {
+    let neighbours: Vec<NeighbourEntry, NEIGHBOUR_TABLE_SIZE> = relay
+        .neighbours()
+        .iter_alive(now)
+        .map(|neighbour| NeighbourEntry::new(neighbour, now))
+        .collect();
+    let routes = relay.routing().iter().filter(|route| route.is_reachable());
+    let status = NodeStatus {
+        uid: relay.uid(),
+        mode: Mode::Relay.id(),
+        neighbours: neighbours.len() as u8,
+        routes: routes.count() as u8,
+        uptime_s: (now / 1_000) as u32,
+    };
+    debug!("Status: {}", status);
+    NODE_STATUS.signal(status);
+    NEIGHBOURS.signal(neighbours);
+}
+
+/// Hands received payloads to `INBOX` and outcomes to the signals awaiting them.
+fn publish_events(relay: &mut Relay<&'static DeliverySignal>) {
+    while let Some(event) = relay.poll_event() {
+        match event {
+            relay::Event::Received {
+                origin_uid,
+                payload,
+            } => {
+                info!("Payload from {}: {=[u8]:x}", origin_uid, payload);
+                let incoming = Incoming {
+                    origin_uid,
+                    payload,
+                };
+                if INBOX.try_send(incoming).is_err() {
+                    warn!("Inbox full, dropping payload");
+                }
+            }
+            relay::Event::Delivery(signal, delivery) => {
+                info!("Outgoing payload: {}", delivery);
+                signal.signal(delivery);
+            }
+        }
+    }
+}
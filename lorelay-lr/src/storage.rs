@@ -0,0 +1,126 @@
+//! Device configuration kept in the last page of flash.
+//!
+//! The page is reserved by convention only: the firmware is far from filling the flash, so the
+//! linker never places anything there.
+use defmt::{info, warn};
+use embassy_stm32::flash::{Blocking, Error, Flash};
+use lorelay_proto::device_config::{
+    uid_from_unique_id, DeviceConfig, RecordError, DEFAULT_REGION, RECORD_SIZE,
+};
+use lorelay_proto::radio::ConfigError;
+use lorelay_proto::region::RegionError;
+
+/// Flash of the STM32WL55JC.
+const FLASH_SIZE: u32 = 256 * 1024;
+
+/// Smallest erasable unit.
+const PAGE_SIZE: u32 = 2 * 1024;
+
+/// Flash is programmed by double words.
+const WRITE_SIZE: usize = 8;
+
+const CONFIG_OFFSET: u32 = FLASH_SIZE - PAGE_SIZE;
+
+/// A record, padded to whole double words.
+const SLOT_SIZE: usize = (RECORD_SIZE + WRITE_SIZE - 1) / WRITE_SIZE * WRITE_SIZE;
+
+/// UID derived from the 96-bit unique device ID of the chip.
+pub fn unique_id_uid() -> u16 {
+    uid_from_unique_id(embassy_stm32::uid::uid())
+}
+
+/// Why the radio cannot run with a configuration.
+#[derive(defmt::Format)]
+pub enum NotAllowed {
+    Invalid(ConfigError),
+    Region(RegionError),
+}
+
+/// Checks that the radio can run with the radio configuration of `config` in its region.
+pub fn check(config: &DeviceConfig) -> Result<(), NotAllowed> {
+    config.radio.validate().map_err(NotAllowed::Invalid)?;
+    config
+        .region
+        .check(&config.radio)
+        .map_err(NotAllowed::Region)
+}
+
+pub struct ConfigStore {
+    flash: Flash<'static, Blocking>,
+    /// Configuration in flash, or the default one if none could be read.
+    config: DeviceConfig,
+}
+
+impl ConfigStore {
+    /// Reads the stored configuration, falling back to the defaults of `DEFAULT_REGION` when
+    /// there is none or it is unusable.
+    ///
+    /// A stored radio configuration that its region does not allow is replaced by the default one
+    /// of the region, in flash too: the radio would refuse to start with it, and only reflashing
+    /// would help.
+    pub fn load(mut flash: Flash<'static, Blocking>) -> Self {
+        let default = DeviceConfig::new(DEFAULT_REGION);
+        let mut slot = [0u8; SLOT_SIZE];
+        let config = match flash.blocking_read(CONFIG_OFFSET, &mut slot) {
+            Ok(()) => match DeviceConfig::decode(&slot) {
+                Ok(config) => {
+                    info!("Loaded configuration {}", config);
+                    config
+                }
+                Err(RecordError::Erased) => {
+                    info!("No stored configuration, using the defaults");
+                    default
+                }
+                Err(err) => {
+                    warn!(
+                        "Stored configuration unusable ({}), using the defaults",
+                        err
+                    );
+                    default
+                }
+            },
+            Err(err) => {
+                warn!("Failed to read the configuration: {}", err);
+                default
+            }
+        };
+        let mut store = ConfigStore { flash, config };
+        if let Err(err) = check(&config) {
+            warn!(
+                "Stored radio configuration not allowed ({}), using the default one",
+                err
+            );
+            let config = DeviceConfig {
+                radio: config.region.default_config(),
+                ..config
+            };
+            if let Err(err) = store.store(config) {
+                warn!("Failed to store the configuration: {}", err);
+                store.config = config;
+            }
+        }
+        store
+    }
+
+    pub fn config(&self) -> &DeviceConfig {
+        &self.config
+    }
+
+    /// Writes `config` to flash, unless it is the one there already.
+    ///
+    /// Every write erases the page, which wears it: only store what the user changed.
+    pub fn store(&mut self, config: DeviceConfig) -> Result<(), Error> {
+        if config == self.config {
+            return Ok(());
+        }
+        let mut slot = [0xff; SLOT_SIZE];
+        config
+            .encode(&mut slot)
+            .expect("slot is sized for a record");
+        self.flash
+            .blocking_erase(CONFIG_OFFSET, CONFIG_OFFSET + PAGE_SIZE)?;
+        self.flash.blocking_write(CONFIG_OFFSET, &slot)?;
+        self.config = config;
+        Ok(())
+    }
+}
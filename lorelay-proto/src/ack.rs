@@ -0,0 +1,150 @@
+//! Acknowledged delivery of `Normal` messages.
+//!
+//! A message sent with the ack flag is kept in `PendingAcks` until the matching `Ack` comes back.
+//! Each time its deadline passes it is retransmitted with a bumped attempt counter, the timeout
+//! doubling up to `RetryConfig::max_timeout`, until `RetryConfig::max_retries` is reached.
+use crate::message::{Message, MessageType, MAX_ATTEMPT};
+use heapless::Vec;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RetryConfig {
+    /// Milliseconds to wait for the `Ack` of the first transmission.
+    pub timeout: u64,
+    /// Upper bound of the timeout once doubled by the backoff.
+    pub max_timeout: u64,
+    /// Retransmissions attempted before giving up.
+    pub max_retries: u8,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        RetryConfig {
+            timeout: 4_000,
+            max_timeout: 30_000,
+            max_retries: 3,
+        }
+    }
+}
+
+impl RetryConfig {
+    /// Milliseconds to wait for an `Ack` after transmission number `attempt`, counted from 0.
+    pub fn backoff(&self, attempt: u8) -> u64 {
+        self.timeout
+            .saturating_mul(1 << attempt.min(32))
+            .min(self.max_timeout)
+    }
+}
+
+/// Final outcome of an acknowledged message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Delivery {
+    /// The destination acknowledged the message after `attempts` transmissions.
+    Delivered { attempts: u8 },
+    /// No `Ack` came back, retransmissions included.
+    TimedOut,
+}
+
+/// Work for the caller of `PendingAcks::poll`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Retry<T> {
+    /// The message to put on air again.
+    Retransmit(Message),
+    /// The message was dropped after its last retransmission; `T` is the token given to `push`.
+    Failed(T),
+}
+
+struct Pending<T> {
+    message: Message,
+    token: T,
+    deadline: u64,
+}
+
+/// Messages awaiting an `Ack`, each with a caller token `T` handed back with its outcome.
+pub struct PendingAcks<T, const N: usize> {
+    config: RetryConfig,
+    pending: Vec<Pending<T>, N>,
+}
+
+impl<T, const N: usize> PendingAcks<T, N> {
+    pub const fn new(config: RetryConfig) -> Self {
+        PendingAcks {
+            config,
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn config(&self) -> &RetryConfig {
+        &self.config
+    }
+
+    /// Starts waiting for the `Ack` of `message`, just transmitted at `now`.
+    ///
+    /// Gives `token` back when the queue is full.
+    pub fn push(&mut self, message: Message, token: T, now: u64) -> Result<(), T> {
+        let deadline = now.saturating_add(self.config.backoff(message.attempt()));
+        self.pending
+            .push(Pending {
+                message,
+                token,
+                deadline,
+            })
+            .map_err(|pending| pending.token)
+    }
+
+    /// Matches a received `Ack` against the pending messages.
+    ///
+    /// Returns the token and outcome of the acknowledged message, or `None` if the frame is not
+    /// an `Ack` of a pending message, for instance the duplicate `Ack` of a retransmission.
+    pub fn on_ack(&mut self, ack: &Message) -> Option<(T, Delivery)> {
+        let MessageType::Ack {
+            origin_uid,
+            acked_seq,
+            ..
+        } = *ack.message_type()
+        else {
+            return None;
+        };
+        let i = self.pending.iter().position(|p| {
+            p.message.seq() == Some(acked_seq) && p.message.destination_uid() == origin_uid
+        })?;
+        let pending = self.pending.swap_remove(i);
+        let attempts = pending.message.attempt() + 1;
+        Some((pending.token, Delivery::Delivered { attempts }))
+    }
+
+    /// Retransmits or gives up on the first message whose deadline passed.
+    ///
+    /// Call again until it returns `None`, several deadlines may have passed at once.
+    pub fn poll(&mut self, now: u64) -> Option<Retry<T>> {
+        let i = self.pending.iter().position(|p| p.deadline <= now)?;
+        let pending = &mut self.pending[i];
+        if pending.message.attempt() >= self.config.max_retries.min(MAX_ATTEMPT) {
+            return Some(Retry::Failed(self.pending.swap_remove(i).token));
+        }
+        pending.message = pending.message.retransmission();
+        pending.deadline = now.saturating_add(self.config.backoff(pending.message.attempt()));
+        Some(Retry::Retransmit(pending.message.clone()))
+    }
+
+    /// Gives up on a pending message without waiting for its retries, returning its token.
+    ///
+    /// Call again until it returns `None` to give up on all of them.
+    pub fn abandon(&mut self) -> Option<T> {
+        self.pending.pop().map(|pending| pending.token)
+    }
+
+    /// Earliest deadline among the pending messages.
+    pub fn next_deadline(&self) -> Option<u64> {
+        self.pending.iter().map(|p| p.deadline).min()
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
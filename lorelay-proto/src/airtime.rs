@@ -0,0 +1,158 @@
+//! Time on air and link budget of LoRa frames, after the formulas of the SX126x datasheet.
+use crate::radio::{Bandwidth, CodingRate, RadioConfig, SpreadingFactor};
+
+/// Noise figure of the SX126x receiver, in dB.
+const NOISE_FIGURE_DB: f32 = 6.0;
+
+/// Thermal noise density at room temperature, in dBm/Hz.
+const THERMAL_NOISE_DBM_HZ: f32 = -174.0;
+
+/// Duration of one symbol in microseconds.
+pub fn symbol_time_us(spreading_factor: SpreadingFactor, bandwidth: Bandwidth) -> u64 {
+    (1_000_000u64 << spreading_factor.value()) / u64::from(bandwidth.hz())
+}
+
+/// Whether the driver turns the low data rate optimization on for this modulation.
+///
+/// The SX126x driver of lora-phy 1.2 only does for SF11 and SF12 at 125 kHz and SF12 at 250 kHz,
+/// not for the narrower bandwidths although their symbols are as long.
+pub fn low_data_rate_optimize(spreading_factor: SpreadingFactor, bandwidth: Bandwidth) -> bool {
+    matches!(
+        (spreading_factor, bandwidth),
+        (SpreadingFactor::Sf11, Bandwidth::Khz125)
+            | (SpreadingFactor::Sf12, Bandwidth::Khz125)
+            | (SpreadingFactor::Sf12, Bandwidth::Khz250)
+    )
+}
+
+/// Everything the duration of a frame depends on, besides its length.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PacketTiming {
+    pub spreading_factor: SpreadingFactor,
+    pub bandwidth: Bandwidth,
+    pub coding_rate: CodingRate,
+    /// Preamble length in symbols.
+    pub preamble_length: u16,
+    pub implicit_header: bool,
+    pub crc: bool,
+    pub low_data_rate_optimize: bool,
+}
+
+impl PacketTiming {
+    /// Timing of the frames sent and received by a radio set up with `config`.
+    pub fn from_config(config: &RadioConfig) -> Self {
+        PacketTiming {
+            spreading_factor: config.spreading_factor,
+            bandwidth: config.bandwidth,
+            coding_rate: config.coding_rate,
+            preamble_length: config.preamble_length,
+            implicit_header: config.implicit_header,
+            crc: config.crc,
+            low_data_rate_optimize: low_data_rate_optimize(
+                config.spreading_factor,
+                config.bandwidth,
+            ),
+        }
+    }
+
+    pub fn symbol_time_us(&self) -> u64 {
+        symbol_time_us(self.spreading_factor, self.bandwidth)
+    }
+
+    /// Length of the preamble, sync word and start of frame delimiter, in quarter symbols.
+    fn preamble_quarter_symbols(&self) -> u64 {
+        let sync = match self.spreading_factor {
+            SpreadingFactor::Sf5 | SpreadingFactor::Sf6 => 25,
+            _ => 17,
+        };
+        4 * u64::from(self.preamble_length) + sync
+    }
+
+    /// Number of symbols after the preamble, header included, for `payload_len` bytes.
+    pub fn payload_symbols(&self, payload_len: usize) -> u64 {
+        let sf = u64::from(self.spreading_factor.value());
+        // The SF5 and SF6 frames carry no extra header bits and ignore the optimization.
+        let (extra_bits, reduced) = match self.spreading_factor {
+            SpreadingFactor::Sf5 | SpreadingFactor::Sf6 => (0, 0),
+            _ if self.low_data_rate_optimize => (8, 2),
+            _ => (8, 0),
+        };
+        let bits = 8 * payload_len as u64
+            + if self.crc { 16 } else { 0 }
+            + if self.implicit_header { 0 } else { 20 }
+            + extra_bits;
+        let bits_per_block = 4 * (sf - reduced);
+        let blocks = (bits.saturating_sub(4 * sf) + bits_per_block - 1) / bits_per_block;
+        8 + blocks * u64::from(self.coding_rate.denominator())
+    }
+
+    /// Duration of the preamble, in microseconds.
+    pub fn preamble_time_us(&self) -> u64 {
+        self.quarter_symbols_us(self.preamble_quarter_symbols())
+    }
+
+    /// Time on air of a frame of `payload_len` bytes, in microseconds.
+    pub fn time_on_air_us(&self, payload_len: usize) -> u64 {
+        let quarters = self.preamble_quarter_symbols() + 4 * self.payload_symbols(payload_len);
+        self.quarter_symbols_us(quarters)
+    }
+
+    fn quarter_symbols_us(&self, quarters: u64) -> u64 {
+        (quarters << self.spreading_factor.value()) * 1_000_000
+            / (4 * u64::from(self.bandwidth.hz()))
+    }
+}
+
+/// Time on air in microseconds of a frame of `payload_len` bytes sent with `config`.
+pub fn time_on_air_us(config: &RadioConfig, payload_len: usize) -> u64 {
+    PacketTiming::from_config(config).time_on_air_us(payload_len)
+}
+
+/// Lowest SNR at which frames can still be demodulated, in dB.
+pub fn demodulation_floor_db(spreading_factor: SpreadingFactor) -> f32 {
+    match spreading_factor {
+        SpreadingFactor::Sf5 => -2.5,
+        SpreadingFactor::Sf6 => -5.0,
+        SpreadingFactor::Sf7 => -7.5,
+        SpreadingFactor::Sf8 => -10.0,
+        SpreadingFactor::Sf9 => -12.5,
+        SpreadingFactor::Sf10 => -15.0,
+        SpreadingFactor::Sf11 => -17.5,
+        SpreadingFactor::Sf12 => -20.0,
+    }
+}
+
+/// `10 * log10(bandwidth)`, tabulated since `log10` is not available without `std`.
+fn bandwidth_db(bandwidth: Bandwidth) -> f32 {
+    match bandwidth {
+        Bandwidth::Khz7_8 => 38.93,
+        Bandwidth::Khz10_4 => 40.18,
+        Bandwidth::Khz15_6 => 41.94,
+        Bandwidth::Khz20_8 => 43.19,
+        Bandwidth::Khz31_25 => 44.95,
+        Bandwidth::Khz41_7 => 46.20,
+        Bandwidth::Khz62_5 => 47.96,
+        Bandwidth::Khz125 => 50.97,
+        Bandwidth::Khz250 => 53.98,
+        Bandwidth::Khz500 => 56.99,
+    }
+}
+
+/// Weakest signal the receiver can demodulate, in dBm.
+pub fn sensitivity_dbm(spreading_factor: SpreadingFactor, bandwidth: Bandwidth) -> f32 {
+    THERMAL_NOISE_DBM_HZ
+        + bandwidth_db(bandwidth)
+        + NOISE_FIGURE_DB
+        + demodulation_floor_db(spreading_factor)
+}
+
+/// Largest path loss between two nodes using `config`, in dB, antenna gains excluded.
+pub fn link_budget_db(config: &RadioConfig) -> f32 {
+    f32::from(config.output_power) - sensitivity_dbm(config.spreading_factor, config.bandwidth)
+}
+
+/// How far above the demodulation floor a frame was received, in dB.
+pub fn snr_margin_db(spreading_factor: SpreadingFactor, snr: i16) -> f32 {
+    f32::from(snr) - demodulation_floor_db(spreading_factor)
+}
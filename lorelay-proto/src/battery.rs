@@ -0,0 +1,207 @@
+//! Battery voltage and charge, from the samples of an ADC such as the SAADC of the nRF52840.
+//!
+//! Samples are converted to the voltage of the battery, averaged over the last few, and mapped
+//! to a charge through the discharge curve of its chemistry.
+use heapless::HistoryBuffer;
+
+/// Resistor divider between the battery and the ADC input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Divider {
+    /// Between the battery and the input, in ohms.
+    pub top_ohm: u32,
+    /// Between the input and the ground, in ohms, not zero.
+    pub bottom_ohm: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AdcConfig {
+    pub resolution_bits: u8,
+    pub reference_mv: u32,
+    /// Gain of the input stage, as the fraction `gain_numerator / gain_denominator`.
+    pub gain_numerator: u32,
+    pub gain_denominator: u32,
+    /// `None` when the battery is wired to the input.
+    pub divider: Option<Divider>,
+}
+
+impl Default for AdcConfig {
+    /// The SAADC of the nRF52840 with its default channel: 0.6 V internal reference and gain 1/6,
+    /// for a 3.6 V full scale, sampled on 12 bits.
+    ///
+    /// The battery is wired through a divider of two 100 kΩ resistors, halving the 4.2 V of a
+    /// charged Li-ion cell to fit under the full scale.
+    fn default() -> Self {
+        AdcConfig {
+            resolution_bits: 12,
+            reference_mv: 600,
+            gain_numerator: 1,
+            gain_denominator: 6,
+            divider: Some(Divider {
+                top_ohm: 100_000,
+                bottom_ohm: 100_000,
+            }),
+        }
+    }
+}
+
+impl AdcConfig {
+    /// Voltage of the battery in mV, rounded, for a single ended `sample`.
+    pub fn millivolts(&self, sample: i16) -> u32 {
+        // Single ended samples go slightly below zero around 0 V.
+        let sample = u64::from(sample.max(0).unsigned_abs());
+        let (top, bottom) = self
+            .divider
+            .map_or((0, 1), |divider| (divider.top_ohm, divider.bottom_ohm));
+        let numerator = sample
+            * u64::from(self.reference_mv)
+            * u64::from(self.gain_denominator)
+            * (u64::from(top) + u64::from(bottom));
+        let denominator =
+            (u64::from(self.gain_numerator) << self.resolution_bits) * u64::from(bottom);
+        ((numerator + denominator / 2) / denominator) as u32
+    }
+}
+
+/// Voltage of one cell in mV against its charge in percent, by decreasing voltage.
+pub type DischargeCurve = [(u16, u8)];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Chemistry {
+    LiIon,
+    LiFePo4,
+    Alkaline,
+}
+
+impl Chemistry {
+    /// Typical curve of a cell under the light load of a node.
+    pub fn curve(self) -> &'static DischargeCurve {
+        match self {
+            Chemistry::LiIon => &[
+                (4200, 100),
+                (4100, 90),
+                (3970, 75),
+                (3870, 60),
+                (3820, 45),
+                (3780, 35),
+                (3740, 20),
+                (3690, 10),
+                (3610, 5),
+                (3300, 0),
+            ],
+            // Flat over most of the charge, which the voltage barely tells apart.
+            Chemistry::LiFePo4 => &[
+                (3600, 100),
+                (3400, 95),
+                (3350, 90),
+                (3320, 70),
+                (3300, 50),
+                (3270, 30),
+                (3200, 20),
+                (3000, 10),
+                (2500, 0),
+            ],
+            Chemistry::Alkaline => &[
+                (1600, 100),
+                (1500, 90),
+                (1400, 70),
+                (1300, 45),
+                (1200, 25),
+                (1100, 10),
+                (1000, 3),
+                (900, 0),
+            ],
+        }
+    }
+}
+
+/// Charge in percent at `millivolts` on `curve`, linearly interpolated between its points and
+/// clamped to its ends.
+pub fn charge_percent(curve: &DischargeCurve, millivolts: u32) -> u8 {
+    let Some(&(first_mv, first_percent)) = curve.first() else {
+        return 0;
+    };
+    if millivolts >= u32::from(first_mv) {
+        return first_percent;
+    }
+    for window in curve.windows(2) {
+        let [(high_mv, high_percent), (low_mv, low_percent)] = [window[0], window[1]];
+        let (high_mv, low_mv) = (u32::from(high_mv), u32::from(low_mv));
+        if millivolts >= low_mv {
+            let span = u32::from(high_percent - low_percent);
+            let above = (millivolts - low_mv) * span;
+            let range = high_mv - low_mv;
+            return low_percent + ((above + range / 2) / range) as u8;
+        }
+    }
+    curve[curve.len() - 1].1
+}
+
+/// Battery powering a node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BatteryConfig {
+    pub adc: AdcConfig,
+    pub chemistry: Chemistry,
+    /// Cells in series.
+    pub cells: u8,
+}
+
+impl Default for BatteryConfig {
+    /// A single Li-ion cell, wired to the SAADC of the nRF52840 as in `AdcConfig::default`.
+    fn default() -> Self {
+        BatteryConfig {
+            adc: AdcConfig::default(),
+            chemistry: Chemistry::LiIon,
+            cells: 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BatteryReading {
+    pub millivolts: u32,
+    /// Charge, for the Battery Level characteristic.
+    pub percent: u8,
+}
+
+impl BatteryReading {
+    /// Value of the Voltage characteristic (0x2B18), in units of 1/64 V.
+    pub fn voltage_characteristic(&self) -> u16 {
+        (self.millivolts * 64 / 1_000).min(u32::from(u16::MAX)) as u16
+    }
+}
+
+/// Averages the voltage over the last `N` samples, for the load of the radio not to show.
+pub struct BatteryMonitor<const N: usize> {
+    config: BatteryConfig,
+    millivolts: HistoryBuffer<u32, N>,
+}
+
+impl<const N: usize> BatteryMonitor<N> {
+    pub fn new(config: BatteryConfig) -> Self {
+        BatteryMonitor {
+            config,
+            millivolts: HistoryBuffer::new(),
+        }
+    }
+
+    pub fn config(&self) -> &BatteryConfig {
+        &self.config
+    }
+
+    /// Adds an ADC sample, returning the average of the samples kept.
+    pub fn push(&mut self, sample: i16) -> BatteryReading {
+        self.millivolts.write(self.config.adc.millivolts(sample));
+        let samples = self.millivolts.as_slice();
+        let millivolts = samples.iter().sum::<u32>() / samples.len() as u32;
+        let cell_mv = millivolts / u32::from(self.config.cells.max(1));
+        BatteryReading {
+            millivolts,
+            percent: charge_percent(self.config.chemistry.curve(), cell_mv),
+        }
+    }
+}
@@ -0,0 +1,112 @@
+//! Listen before talk: channel activity detection before every transmission, and a random
+//! backoff while the channel is busy.
+//!
+//! `ChannelAccess` only decides what to do after each detection; the caller runs the detection on
+//! the radio and does the waiting.
+use crate::rng::XorShift32;
+
+/// Policy spacing out the detections while the channel is busy.
+pub trait Backoff {
+    /// Milliseconds to wait after the `attempt`th detection in a row found the channel busy,
+    /// counting from 1.
+    fn delay_ms(&mut self, attempt: u8) -> u64;
+}
+
+/// Binary exponential backoff: after the `n`th busy detection, waits a random number of slots
+/// between 1 and `2^n`, the exponent being capped at `max_exponent`.
+#[derive(Debug, Clone)]
+pub struct RandomBackoff {
+    rng: XorShift32,
+    /// Milliseconds per slot, best close to the time on air of a frame.
+    pub slot_ms: u64,
+    pub max_exponent: u8,
+}
+
+impl RandomBackoff {
+    pub const fn new(seed: u32, slot_ms: u64, max_exponent: u8) -> Self {
+        RandomBackoff {
+            rng: XorShift32::new(seed),
+            slot_ms,
+            max_exponent,
+        }
+    }
+}
+
+impl Backoff for RandomBackoff {
+    fn delay_ms(&mut self, attempt: u8) -> u64 {
+        let slots = 1u64 << attempt.min(self.max_exponent).min(32);
+        self.slot_ms * (1 + self.rng.up_to(slots - 1))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ChannelAccessConfig {
+    /// Detections run for one transmission before giving up, the first one included.
+    pub max_attempts: u8,
+}
+
+impl Default for ChannelAccessConfig {
+    fn default() -> Self {
+        ChannelAccessConfig { max_attempts: 5 }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Access {
+    /// The channel is clear, transmit now.
+    Transmit,
+    /// Run another detection after this many milliseconds.
+    Wait(u64),
+    /// The channel stayed busy for all the attempts, the transmission is given up.
+    ChannelBusy,
+}
+
+pub struct ChannelAccess<B> {
+    config: ChannelAccessConfig,
+    backoff: B,
+    /// Busy detections so far for the current transmission.
+    attempts: u8,
+}
+
+impl<B: Backoff> ChannelAccess<B> {
+    pub const fn new(config: ChannelAccessConfig, backoff: B) -> Self {
+        ChannelAccess {
+            config,
+            backoff,
+            attempts: 0,
+        }
+    }
+
+    pub fn config(&self) -> &ChannelAccessConfig {
+        &self.config
+    }
+
+    pub fn backoff_mut(&mut self) -> &mut B {
+        &mut self.backoff
+    }
+
+    /// Takes the outcome of a detection, `busy` when activity was found on the channel.
+    ///
+    /// The next transmission starts afresh once this returns `Transmit` or `ChannelBusy`, or after
+    /// `reset`.
+    pub fn on_detection(&mut self, busy: bool) -> Access {
+        if !busy {
+            self.attempts = 0;
+            return Access::Transmit;
+        }
+        self.attempts = self.attempts.saturating_add(1);
+        if self.attempts >= self.config.max_attempts {
+            self.attempts = 0;
+            return Access::ChannelBusy;
+        }
+        Access::Wait(self.backoff.delay_ms(self.attempts))
+    }
+
+    /// Abandons the current transmission, such as when a detection failed, so that the next one
+    /// gets all its attempts.
+    pub fn reset(&mut self) {
+        self.attempts = 0;
+    }
+}
@@ -0,0 +1,63 @@
+//! Consistent Overhead Byte Stuffing, which removes every zero byte from a frame so that zeros
+//! can delimit frames on a byte stream.
+//!
+//! The encoding costs one byte, plus one per 254 bytes of data.
+use crate::message::EncodeError;
+
+/// Largest encoding of `len` bytes, without the delimiter.
+pub const fn max_encoded_len(len: usize) -> usize {
+    len + len / 254 + 1
+}
+
+/// Encodes `data` into `buf`, returning the encoded length.
+pub fn encode(data: &[u8], buf: &mut [u8]) -> Result<usize, EncodeError> {
+    if buf.len() < max_encoded_len(data.len()) {
+        return Err(EncodeError::BufferTooSmall);
+    }
+    // Each block starts with the offset of the next zero, or the end of the block.
+    let mut code_at = 0;
+    let mut len = 1;
+    let mut code = 1u8;
+    for &byte in data {
+        if byte != 0 {
+            buf[len] = byte;
+            len += 1;
+            code += 1;
+        }
+        if byte == 0 || code == 0xff {
+            buf[code_at] = code;
+            code_at = len;
+            len += 1;
+            code = 1;
+        }
+    }
+    buf[code_at] = code;
+    Ok(len)
+}
+
+/// Decodes the frame `encoded`, without its delimiter, into `buf`.
+///
+/// Returns the decoded length, `None` if `encoded` is not valid COBS or `buf` is too small.
+pub fn decode(encoded: &[u8], buf: &mut [u8]) -> Option<usize> {
+    let mut len = 0;
+    let mut at = 0;
+    while at < encoded.len() {
+        let code = usize::from(encoded[at]);
+        if code == 0 {
+            return None;
+        }
+        let block = encoded.get(at + 1..at + code)?;
+        if block.contains(&0) {
+            return None;
+        }
+        buf.get_mut(len..len + block.len())?.copy_from_slice(block);
+        len += block.len();
+        at += code;
+        // A zero follows every block but full ones and the last.
+        if code != 0xff && at < encoded.len() {
+            *buf.get_mut(len)? = 0;
+            len += 1;
+        }
+    }
+    Some(len)
+}
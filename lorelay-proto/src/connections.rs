@@ -0,0 +1,151 @@
+//! BLE clients connected to the BLE board, and the characteristics each one subscribed to.
+//!
+//! Clients enable notifications by writing the CCCD of a characteristic, per connection. Values
+//! the node updates go to every client subscribed to them, the others read them when they want.
+use heapless::Vec;
+
+/// Characteristics notified by the BLE board.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Characteristic {
+    BatteryLevel,
+    BatteryVoltage,
+    RangeTestSummary,
+    Incoming,
+    Status,
+    Neighbours,
+    ConsoleTx,
+    Delivery,
+}
+
+impl Characteristic {
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+/// Characteristics one client subscribed to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Subscriptions(u8);
+
+impl Subscriptions {
+    pub const fn new() -> Self {
+        Subscriptions(0)
+    }
+
+    pub fn contains(&self, characteristic: Characteristic) -> bool {
+        self.0 & characteristic.bit() != 0
+    }
+
+    pub fn set(&mut self, characteristic: Characteristic, subscribed: bool) {
+        if subscribed {
+            self.0 |= characteristic.bit();
+        } else {
+            self.0 &= !characteristic.bit();
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+}
+
+/// Connection `C` of a client, known by its connection handle.
+#[derive(Debug, Clone)]
+struct Entry<C> {
+    handle: u16,
+    connection: C,
+    subscriptions: Subscriptions,
+}
+
+/// Up to `N` clients connected at once, in the order they connected.
+#[derive(Debug, Clone)]
+pub struct ConnectionRegistry<C, const N: usize> {
+    entries: Vec<Entry<C>, N>,
+}
+
+impl<C, const N: usize> Default for ConnectionRegistry<C, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<C, const N: usize> ConnectionRegistry<C, N> {
+    pub const fn new() -> Self {
+        ConnectionRegistry {
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.entries.is_full()
+    }
+
+    /// Adds a client, without subscriptions. Gives the connection back when the registry is full
+    /// or the handle already taken.
+    pub fn add(&mut self, handle: u16, connection: C) -> Result<(), C> {
+        if self.position(handle).is_some() {
+            return Err(connection);
+        }
+        self.entries
+            .push(Entry {
+                handle,
+                connection,
+                subscriptions: Subscriptions::new(),
+            })
+            .map_err(|entry| entry.connection)
+    }
+
+    /// Removes a client that disconnected, returning its connection.
+    pub fn remove(&mut self, handle: u16) -> Option<C> {
+        let position = self.position(handle)?;
+        Some(self.entries.remove(position).connection)
+    }
+
+    pub fn get(&self, handle: u16) -> Option<&C> {
+        let position = self.position(handle)?;
+        Some(&self.entries[position].connection)
+    }
+
+    pub fn subscriptions(&self, handle: u16) -> Option<Subscriptions> {
+        let position = self.position(handle)?;
+        Some(self.entries[position].subscriptions)
+    }
+
+    /// Records a CCCD write of the client, false if it is not registered.
+    pub fn set_subscribed(
+        &mut self,
+        handle: u16,
+        characteristic: Characteristic,
+        subscribed: bool,
+    ) -> bool {
+        let Some(position) = self.position(handle) else {
+            return false;
+        };
+        self.entries[position]
+            .subscriptions
+            .set(characteristic, subscribed);
+        true
+    }
+
+    /// Clients subscribed to `characteristic`, with their handles.
+    pub fn subscribers(&self, characteristic: Characteristic) -> impl Iterator<Item = (u16, &C)> {
+        self.entries
+            .iter()
+            .filter(move |entry| entry.subscriptions.contains(characteristic))
+            .map(|entry| (entry.handle, &entry.connection))
+    }
+
+    fn position(&self, handle: u16) -> Option<usize> {
+        self.entries.iter().position(|entry| entry.handle == handle)
+    }
+}
@@ -0,0 +1,501 @@
+//! Line based command shell, to inspect and drive a node from a serial terminal such as the
+//! Nordic UART Service of the BLE board.
+//!
+//! `Console` collects the bytes written by the client into lines, parses each one into a
+//! `Command` and runs it on a `Node`, writing the response as text. Lines end with `\n` or `\r`,
+//! and words are separated by spaces:
+//!  - `status`, `neighbours`
+//!  - `send <uid|all> <text>`
+//!  - `config get [key]`, `config set <key> <value>`, with the keys of `ConfigKey`
+//!  - `mode [name]`
+//!  - `reboot`, `help`
+use crate::device_config::DeviceConfig;
+use crate::gateway::{NeighbourEntry, NodeStatus, OutgoingMessage, MAX_PAYLOAD_SIZE};
+use crate::message::BROADCAST_UID;
+use crate::radio::{Bandwidth, CodingRate, ConfigError, SpreadingFactor};
+use crate::region::{Region, RegionError};
+use crate::relay::NEIGHBOUR_TABLE_SIZE;
+use core::fmt::{self, Write};
+use heapless::Vec;
+
+/// Longest line, the rest of a longer one is dropped.
+pub const MAX_LINE_SIZE: usize = 256;
+
+/// Names of the modes of the LoRa board, by number.
+pub const MODES: [&str; 3] = ["relay", "range-test", "sniffer"];
+
+/// The node could not take the request right now.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Busy;
+
+/// What the console can see of the node and ask of it.
+pub trait Node {
+    /// Latest status, `None` until it is known.
+    fn status(&self) -> Option<NodeStatus>;
+    fn neighbours(&self) -> Vec<NeighbourEntry, NEIGHBOUR_TABLE_SIZE>;
+    /// Configuration stored by the node, `None` until it is known.
+    fn config(&self) -> Option<DeviceConfig>;
+    fn send(&mut self, message: OutgoingMessage) -> Result<(), Busy>;
+    /// Stores `config`, which the node runs with after its next reboot.
+    fn set_config(&mut self, config: DeviceConfig) -> Result<(), Busy>;
+    /// Switches to the mode numbered `mode`.
+    fn set_mode(&mut self, mode: u8) -> Result<(), Busy>;
+    fn reboot(&mut self) -> Result<(), Busy>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ConfigKey {
+    /// `auto` for the one derived from the chip.
+    Uid,
+    /// In Hz.
+    Frequency,
+    /// In dBm.
+    Power,
+    SpreadingFactor,
+    /// In Hz.
+    Bandwidth,
+    /// Denominator of the 4/n rate.
+    CodingRate,
+    /// In symbols.
+    Preamble,
+    /// Name of the `Region`; switching resets the radio settings to the defaults of the region.
+    Region,
+}
+
+impl ConfigKey {
+    pub const ALL: [ConfigKey; 8] = [
+        ConfigKey::Uid,
+        ConfigKey::Frequency,
+        ConfigKey::Power,
+        ConfigKey::SpreadingFactor,
+        ConfigKey::Bandwidth,
+        ConfigKey::CodingRate,
+        ConfigKey::Preamble,
+        ConfigKey::Region,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ConfigKey::Uid => "uid",
+            ConfigKey::Frequency => "freq",
+            ConfigKey::Power => "power",
+            ConfigKey::SpreadingFactor => "sf",
+            ConfigKey::Bandwidth => "bw",
+            ConfigKey::CodingRate => "cr",
+            ConfigKey::Preamble => "preamble",
+            ConfigKey::Region => "region",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|key| key.name() == name)
+    }
+
+    fn write_value(self, config: &DeviceConfig, out: &mut impl Write) -> fmt::Result {
+        let radio = &config.radio;
+        match self {
+            ConfigKey::Uid => match config.uid {
+                Some(uid) => write!(out, "{}", uid),
+                None => out.write_str("auto"),
+            },
+            ConfigKey::Frequency => write!(out, "{}", radio.frequency_hz),
+            ConfigKey::Power => write!(out, "{}", radio.output_power),
+            ConfigKey::SpreadingFactor => write!(out, "{}", radio.spreading_factor.value()),
+            ConfigKey::Bandwidth => write!(out, "{}", radio.bandwidth.hz()),
+            ConfigKey::CodingRate => write!(out, "{}", radio.coding_rate.denominator()),
+            ConfigKey::Preamble => write!(out, "{}", radio.preamble_length),
+            ConfigKey::Region => out.write_str(config.region.name()),
+        }
+    }
+
+    /// Sets the key to `value` in `config`, which may then need to be validated.
+    fn set(self, config: &mut DeviceConfig, value: &str) -> Option<()> {
+        let radio = &mut config.radio;
+        match self {
+            ConfigKey::Uid if value == "auto" => config.uid = None,
+            ConfigKey::Uid => {
+                config.uid = Some(parse_uid(value).filter(|&uid| uid != BROADCAST_UID)?)
+            }
+            ConfigKey::Frequency => radio.frequency_hz = value.parse().ok()?,
+            ConfigKey::Power => radio.output_power = value.parse().ok()?,
+            ConfigKey::SpreadingFactor => {
+                radio.spreading_factor = SpreadingFactor::from_value(value.parse().ok()?)?
+            }
+            ConfigKey::Bandwidth => {
+                let hz = value.parse().ok()?;
+                radio.bandwidth = Bandwidth::ALL.into_iter().find(|bw| bw.hz() == hz)?;
+            }
+            ConfigKey::CodingRate => {
+                let denominator = value.parse().ok()?;
+                radio.coding_rate = CodingRate::ALL
+                    .into_iter()
+                    .find(|cr| cr.denominator() == denominator)?;
+            }
+            ConfigKey::Preamble => radio.preamble_length = value.parse().ok()?,
+            ConfigKey::Region => {
+                let region = Region::from_name(value)?;
+                config.region = region;
+                config.radio = region.default_config();
+            }
+        }
+        Some(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command<'a> {
+    Help,
+    Status,
+    Neighbours,
+    Send {
+        destination_uid: u16,
+        text: &'a str,
+    },
+    /// One key, or all of them.
+    ConfigGet(Option<ConfigKey>),
+    ConfigSet {
+        key: ConfigKey,
+        value: &'a str,
+    },
+    /// Shows the mode, or switches to the one numbered.
+    Mode(Option<u8>),
+    Reboot,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseError<'a> {
+    UnknownCommand(&'a str),
+    /// Names the missing argument.
+    MissingArgument(&'static str),
+    /// Names the argument, with its invalid value.
+    InvalidArgument(&'static str, &'a str),
+    TooManyArguments,
+    /// Longer than `MAX_PAYLOAD_SIZE`.
+    TextTooLong,
+}
+
+impl fmt::Display for ParseError<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::UnknownCommand(name) => write!(f, "unknown command {}, try help", name),
+            ParseError::MissingArgument(name) => write!(f, "missing {}", name),
+            ParseError::InvalidArgument(name, value) => write!(f, "invalid {} {}", name, value),
+            ParseError::TooManyArguments => f.write_str("too many arguments"),
+            ParseError::TextTooLong => write!(f, "text longer than {} bytes", MAX_PAYLOAD_SIZE),
+        }
+    }
+}
+
+/// Decimal or `0x` prefixed hexadecimal UID.
+fn parse_uid(word: &str) -> Option<u16> {
+    match word.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => word.parse().ok(),
+    }
+}
+
+/// Cursor over the words of a line.
+struct Words<'a>(&'a str);
+
+impl<'a> Words<'a> {
+    fn next(&mut self) -> Option<&'a str> {
+        let rest = self.0.trim_start();
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        let (word, rest) = rest.split_at(end);
+        self.0 = rest;
+        (!word.is_empty()).then_some(word)
+    }
+
+    fn expect(&mut self, name: &'static str) -> Result<&'a str, ParseError<'a>> {
+        self.next().ok_or(ParseError::MissingArgument(name))
+    }
+
+    /// The rest of the line, spaces included.
+    fn rest(&mut self, name: &'static str) -> Result<&'a str, ParseError<'a>> {
+        let rest = core::mem::take(&mut self.0).trim();
+        (!rest.is_empty())
+            .then_some(rest)
+            .ok_or(ParseError::MissingArgument(name))
+    }
+
+    fn end(&mut self) -> Result<(), ParseError<'a>> {
+        match self.next() {
+            Some(_) => Err(ParseError::TooManyArguments),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Parses a line, `None` if it is blank.
+pub fn parse(line: &str) -> Option<Result<Command<'_>, ParseError<'_>>> {
+    let mut words = Words(line);
+    let name = words.next()?;
+    Some(parse_command(name, &mut words))
+}
+
+fn parse_command<'a>(name: &'a str, words: &mut Words<'a>) -> Result<Command<'a>, ParseError<'a>> {
+    let command = match name {
+        "help" => Command::Help,
+        "status" => Command::Status,
+        "neighbours" => Command::Neighbours,
+        "send" => {
+            let uid = words.expect("uid")?;
+            let destination_uid = match uid {
+                "all" => BROADCAST_UID,
+                uid => parse_uid(uid).ok_or(ParseError::InvalidArgument("uid", uid))?,
+            };
+            let text = words.rest("text")?;
+            if text.len() > MAX_PAYLOAD_SIZE {
+                return Err(ParseError::TextTooLong);
+            }
+            Command::Send {
+                destination_uid,
+                text,
+            }
+        }
+        "config" => match words.expect("get or set")? {
+            "get" => Command::ConfigGet(match words.next() {
+                Some(key) => {
+                    Some(ConfigKey::from_name(key).ok_or(ParseError::InvalidArgument("key", key))?)
+                }
+                None => None,
+            }),
+            "set" => {
+                let key = words.expect("key")?;
+                let key =
+                    ConfigKey::from_name(key).ok_or(ParseError::InvalidArgument("key", key))?;
+                Command::ConfigSet {
+                    key,
+                    value: words.expect("value")?,
+                }
+            }
+            action => return Err(ParseError::InvalidArgument("action", action)),
+        },
+        "mode" => Command::Mode(match words.next() {
+            Some(mode) => Some(
+                MODES
+                    .iter()
+                    .position(|&name| name == mode)
+                    .ok_or(ParseError::InvalidArgument("mode", mode))? as u8,
+            ),
+            None => None,
+        }),
+        "reboot" => Command::Reboot,
+        name => return Err(ParseError::UnknownCommand(name)),
+    };
+    words.end()?;
+    Ok(command)
+}
+
+fn write_busy(result: Result<(), Busy>, done: &str, out: &mut impl Write) -> fmt::Result {
+    match result {
+        Ok(()) => writeln!(out, "{}", done),
+        Err(Busy) => writeln!(out, "error: busy, try again"),
+    }
+}
+
+fn mode_name(mode: u8) -> &'static str {
+    MODES.get(usize::from(mode)).copied().unwrap_or("unknown")
+}
+
+/// Runs `command` on `node`, writing the response to `out`.
+pub fn dispatch(command: Command<'_>, node: &mut impl Node, out: &mut impl Write) -> fmt::Result {
+    match command {
+        Command::Help => {
+            writeln!(out, "status, neighbours, send <uid|all> <text>,")?;
+            writeln!(out, "config get [key], config set <key> <value>,")?;
+            out.write_str("mode [")?;
+            for (index, mode) in MODES.iter().enumerate() {
+                let separator = if index == 0 { "" } else { "|" };
+                write!(out, "{}{}", separator, mode)?;
+            }
+            writeln!(out, "], reboot")?;
+            out.write_str("keys:")?;
+            for key in ConfigKey::ALL {
+                write!(out, " {}", key.name())?;
+            }
+            writeln!(out)
+        }
+        Command::Status => {
+            let Some(status) = node.status() else {
+                return writeln!(out, "status unknown");
+            };
+            writeln!(out, "uid {}", status.uid)?;
+            writeln!(out, "mode {}", mode_name(status.mode))?;
+            writeln!(
+                out,
+                "{} neighbours, {} routes",
+                status.neighbours, status.routes
+            )?;
+            writeln!(out, "up {} s", status.uptime_s)
+        }
+        Command::Neighbours => {
+            let neighbours = node.neighbours();
+            if neighbours.is_empty() {
+                return writeln!(out, "no neighbours");
+            }
+            for neighbour in neighbours {
+                writeln!(
+                    out,
+                    "{}: {} dBm, SNR {} dB, {} s ago",
+                    neighbour.uid, neighbour.rssi, neighbour.snr, neighbour.age_s
+                )?;
+            }
+            Ok(())
+        }
+        Command::Send {
+            destination_uid,
+            text,
+        } => {
+            let Ok(payload) = Vec::from_slice(text.as_bytes()) else {
+                return writeln!(out, "error: {}", ParseError::TextTooLong);
+            };
+            let message = OutgoingMessage {
+                destination_uid,
+                // Broadcasts are never acknowledged.
+                ack: destination_uid != BROADCAST_UID,
+                payload,
+            };
+            write_busy(node.send(message), "queued", out)
+        }
+        Command::ConfigGet(key) => {
+            let Some(config) = node.config() else {
+                return writeln!(out, "config unknown");
+            };
+            let keys = match &key {
+                Some(key) => core::slice::from_ref(key),
+                None => &ConfigKey::ALL[..],
+            };
+            for key in keys {
+                write!(out, "{} ", key.name())?;
+                key.write_value(&config, out)?;
+                writeln!(out)?;
+            }
+            Ok(())
+        }
+        Command::ConfigSet { key, value } => {
+            let Some(mut config) = node.config() else {
+                return writeln!(out, "config unknown");
+            };
+            if key.set(&mut config, value).is_none() {
+                return writeln!(out, "error: invalid {} {}", key.name(), value);
+            }
+            if let Err(err) = config.radio.validate() {
+                return writeln!(out, "error: {}", ConfigErrorText(err));
+            }
+            if let Err(err) = config.region.check(&config.radio) {
+                return writeln!(out, "error: {}", RegionErrorText(err));
+            }
+            write_busy(
+                node.set_config(config),
+                "stored, applied after a reboot",
+                out,
+            )
+        }
+        Command::Mode(None) => match node.config() {
+            Some(config) => writeln!(out, "mode {}", mode_name(config.mode)),
+            None => writeln!(out, "mode unknown"),
+        },
+        Command::Mode(Some(mode)) => write_busy(node.set_mode(mode), "switching", out),
+        Command::Reboot => write_busy(node.reboot(), "rebooting", out),
+    }
+}
+
+struct ConfigErrorText(ConfigError);
+
+impl fmt::Display for ConfigErrorText {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            ConfigError::FrequencyOutOfRange(hz) => write!(f, "frequency {} Hz out of range", hz),
+            ConfigError::OutputPowerOutOfRange(dbm) => write!(f, "power {} dBm out of range", dbm),
+            ConfigError::BandwidthTooWide(bandwidth) => {
+                write!(
+                    f,
+                    "bandwidth {} Hz too wide for the frequency",
+                    bandwidth.hz()
+                )
+            }
+            ConfigError::PreambleTooShort(symbols) => {
+                write!(f, "preamble of {} symbols too short", symbols)
+            }
+            ConfigError::UnsupportedSyncWord(word) => write!(f, "unsupported sync word {}", word),
+        }
+    }
+}
+
+struct RegionErrorText(RegionError);
+
+impl fmt::Display for RegionErrorText {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            RegionError::FrequencyNotAllowed(hz) => {
+                write!(f, "frequency {} Hz not allowed in the region", hz)
+            }
+            RegionError::OutputPowerTooHigh(dbm) => {
+                write!(f, "power {} dBm too high for the region", dbm)
+            }
+        }
+    }
+}
+
+/// Shell of one client.
+#[derive(Debug, Clone)]
+pub struct Console {
+    line: Vec<u8, MAX_LINE_SIZE>,
+    /// Whether the line being read is too long, and dropped.
+    overflow: bool,
+}
+
+impl Default for Console {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Console {
+    pub const fn new() -> Self {
+        Console {
+            line: Vec::new(),
+            overflow: false,
+        }
+    }
+
+    /// Handles bytes written by the client, running each line completed on `node` and writing
+    /// the responses to `out`.
+    pub fn on_bytes(
+        &mut self,
+        bytes: &[u8],
+        node: &mut impl Node,
+        out: &mut impl Write,
+    ) -> fmt::Result {
+        for &byte in bytes {
+            if byte != b'\n' && byte != b'\r' {
+                if self.line.push(byte).is_err() {
+                    self.overflow = true;
+                }
+                continue;
+            }
+            if self.overflow {
+                writeln!(out, "error: line too long")?;
+            } else {
+                self.run_line(node, out)?;
+            }
+            self.line.clear();
+            self.overflow = false;
+        }
+        Ok(())
+    }
+
+    fn run_line(&self, node: &mut impl Node, out: &mut impl Write) -> fmt::Result {
+        let Ok(line) = core::str::from_utf8(&self.line) else {
+            return writeln!(out, "error: invalid UTF-8");
+        };
+        match parse(line) {
+            None => Ok(()),
+            Some(Ok(command)) => dispatch(command, node, out),
+            Some(Err(err)) => writeln!(out, "error: {}", err),
+        }
+    }
+}
@@ -0,0 +1,21 @@
+//! Checksums of data kept or carried outside of LoRa frames, which the radio already protects.
+
+/// CRC-16/CCITT-FALSE: polynomial 0x1021, initial value 0xffff, no reflection.
+pub fn crc16(data: &[u8]) -> u16 {
+    crc16_update(0xffff, data)
+}
+
+/// Carries on `crc` over `data`, for data that is not contiguous.
+pub fn crc16_update(mut crc: u16, data: &[u8]) -> u16 {
+    for &byte in data {
+        crc ^= u16::from(byte) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
@@ -0,0 +1,213 @@
+//! Settings a node keeps across reboots, and how they are laid out in flash.
+//!
+//! A record is a header, a body whose layout depends on the version and a CRC over both:
+//!
+//! | bytes | field                                  |
+//! |-------|----------------------------------------|
+//! | 4     | `RECORD_MAGIC`                         |
+//! | 1     | version                                |
+//! | 1     | body length                            |
+//! | n     | body, see `DeviceConfig::encode`       |
+//! | 2     | CRC-16 of everything before, LE        |
+//!
+//! Records of older versions are migrated when decoded, so that new firmwares keep the settings
+//! of the ones they replace. Bytes after the CRC are ignored, to allow for padding.
+use crate::crc::crc16;
+use crate::message::{EncodeError, BROADCAST_UID};
+use crate::radio::{Bandwidth, CodingRate, ConfigError, RadioConfig, SpreadingFactor};
+use crate::region::Region;
+
+pub const RECORD_MAGIC: [u8; 4] = *b"LRcf";
+
+/// Version written by `DeviceConfig::encode`.
+///
+/// 1. UID override and radio configuration.
+/// 2. Adds the mode to start in.
+/// 3. Adds the region the node operates in.
+pub const RECORD_VERSION: u8 = 3;
+
+/// Region of the nodes that stored no record, or one older than version 3: the one lorelay was
+/// first deployed in.
+pub const DEFAULT_REGION: Region = Region::Eu433;
+
+const HEADER_SIZE: usize = RECORD_MAGIC.len() + 2;
+const CRC_SIZE: usize = 2;
+
+/// frequency, output power, spreading factor, bandwidth, coding rate, preamble, sync word, flags
+const RADIO_SIZE: usize = 12;
+
+const BODY_SIZE_V1: usize = 2 + RADIO_SIZE;
+const BODY_SIZE_V2: usize = BODY_SIZE_V1 + 1;
+const BODY_SIZE_V3: usize = BODY_SIZE_V2 + 1;
+
+/// Size of a record of the current version.
+pub const RECORD_SIZE: usize = HEADER_SIZE + BODY_SIZE_V3 + CRC_SIZE;
+
+const FLAG_CRC: u8 = 1 << 0;
+const FLAG_IMPLICIT_HEADER: u8 = 1 << 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RecordError {
+    /// Nothing was ever written: the bytes are those of erased flash.
+    Erased,
+    BadMagic,
+    /// Written by a newer firmware.
+    UnsupportedVersion(u8),
+    /// The body length does not match the version, or the record is cut short.
+    InvalidLength(u8),
+    BadCrc,
+    /// A setting has no meaning, such as spreading factor 13.
+    InvalidField,
+    InvalidRadioConfig(ConfigError),
+}
+
+impl From<ConfigError> for RecordError {
+    fn from(err: ConfigError) -> Self {
+        RecordError::InvalidRadioConfig(err)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DeviceConfig {
+    /// UID to use instead of the one derived from the unique ID of the chip.
+    pub uid: Option<u16>,
+    pub radio: RadioConfig,
+    /// Mode to start in, as numbered by the firmware; 0 is the one records of version 1 start in.
+    pub mode: u8,
+    /// Region whose rules the radio follows.
+    pub region: Region,
+}
+
+impl DeviceConfig {
+    /// Default settings for a node operating in `region`.
+    pub fn new(region: Region) -> Self {
+        DeviceConfig {
+            uid: None,
+            radio: region.default_config(),
+            mode: 0,
+            region,
+        }
+    }
+
+    /// UID of the node, `derived` unless overridden.
+    pub fn uid_or(&self, derived: u16) -> u16 {
+        self.uid.unwrap_or(derived)
+    }
+
+    /// Encodes a record of the current version, `RECORD_SIZE` bytes long.
+    ///
+    /// The body holds the UID override (`BROADCAST_UID` for none, since no node has that UID),
+    /// the radio configuration, the mode and the region, as its index in `Region::ALL`.
+    pub fn encode(&self, buf: &mut [u8]) -> Result<usize, EncodeError> {
+        let buf = buf
+            .get_mut(..RECORD_SIZE)
+            .ok_or(EncodeError::BufferTooSmall)?;
+        buf[..RECORD_MAGIC.len()].copy_from_slice(&RECORD_MAGIC);
+        buf[4] = RECORD_VERSION;
+        buf[5] = BODY_SIZE_V3 as u8;
+        let body = &mut buf[HEADER_SIZE..HEADER_SIZE + BODY_SIZE_V3];
+        body[0..2].copy_from_slice(&self.uid.unwrap_or(BROADCAST_UID).to_le_bytes());
+        encode_radio(&self.radio, &mut body[2..2 + RADIO_SIZE]);
+        body[2 + RADIO_SIZE] = self.mode;
+        body[3 + RADIO_SIZE] = self.region as u8;
+        let crc = crc16(&buf[..HEADER_SIZE + BODY_SIZE_V3]);
+        buf[HEADER_SIZE + BODY_SIZE_V3..].copy_from_slice(&crc.to_le_bytes());
+        Ok(RECORD_SIZE)
+    }
+
+    /// Decodes a record of any version up to the current one, checking its radio configuration.
+    pub fn decode(buf: &[u8]) -> Result<Self, RecordError> {
+        let header = buf
+            .get(..HEADER_SIZE)
+            .ok_or(RecordError::InvalidLength(0))?;
+        if header.iter().all(|&byte| byte == 0xff) {
+            return Err(RecordError::Erased);
+        }
+        if header[..RECORD_MAGIC.len()] != RECORD_MAGIC {
+            return Err(RecordError::BadMagic);
+        }
+        let (version, length) = (header[4], header[5]);
+        let expected = match version {
+            1 => BODY_SIZE_V1,
+            2 => BODY_SIZE_V2,
+            3 => BODY_SIZE_V3,
+            version => return Err(RecordError::UnsupportedVersion(version)),
+        };
+        let end = HEADER_SIZE + usize::from(length);
+        if usize::from(length) != expected || buf.len() < end + CRC_SIZE {
+            return Err(RecordError::InvalidLength(length));
+        }
+        let crc = u16::from_le_bytes([buf[end], buf[end + 1]]);
+        if crc16(&buf[..end]) != crc {
+            return Err(RecordError::BadCrc);
+        }
+
+        let body = &buf[HEADER_SIZE..end];
+        let uid = match u16::from_le_bytes([body[0], body[1]]) {
+            BROADCAST_UID => None,
+            uid => Some(uid),
+        };
+        let radio = decode_radio(&body[2..2 + RADIO_SIZE]).ok_or(RecordError::InvalidField)?;
+        radio.validate()?;
+        let mode = match version {
+            1 => 0,
+            _ => body[2 + RADIO_SIZE],
+        };
+        let region = match version {
+            1 | 2 => DEFAULT_REGION,
+            _ => *Region::ALL
+                .get(usize::from(body[3 + RADIO_SIZE]))
+                .ok_or(RecordError::InvalidField)?,
+        };
+        Ok(DeviceConfig {
+            uid,
+            radio,
+            mode,
+            region,
+        })
+    }
+}
+
+fn encode_radio(config: &RadioConfig, buf: &mut [u8]) {
+    buf[0..4].copy_from_slice(&config.frequency_hz.to_le_bytes());
+    buf[4] = config.output_power as u8;
+    buf[5] = config.spreading_factor.value();
+    buf[6] = config.bandwidth as u8;
+    buf[7] = config.coding_rate.denominator();
+    buf[8..10].copy_from_slice(&config.preamble_length.to_le_bytes());
+    buf[10] = config.sync_word;
+    let mut flags = 0;
+    if config.crc {
+        flags |= FLAG_CRC;
+    }
+    if config.implicit_header {
+        flags |= FLAG_IMPLICIT_HEADER;
+    }
+    buf[11] = flags;
+}
+
+fn decode_radio(buf: &[u8]) -> Option<RadioConfig> {
+    Some(RadioConfig {
+        frequency_hz: u32::from_le_bytes(buf[0..4].try_into().ok()?),
+        output_power: buf[4] as i8,
+        spreading_factor: SpreadingFactor::from_value(buf[5])?,
+        bandwidth: *Bandwidth::ALL.get(usize::from(buf[6]))?,
+        coding_rate: *CodingRate::ALL.get(usize::from(buf[7].checked_sub(5)?))?,
+        preamble_length: u16::from_le_bytes([buf[8], buf[9]]),
+        sync_word: buf[10],
+        crc: buf[11] & FLAG_CRC != 0,
+        implicit_header: buf[11] & FLAG_IMPLICIT_HEADER != 0,
+    })
+}
+
+/// UID derived from the unique ID of a chip, such as the 96 bits of the STM32WL.
+///
+/// Distinct chips may end up with the same UID, which is what `DeviceConfig::uid` is for.
+pub fn uid_from_unique_id(unique_id: &[u8]) -> u16 {
+    match crc16(unique_id) {
+        BROADCAST_UID => BROADCAST_UID - 1,
+        uid => uid,
+    }
+}
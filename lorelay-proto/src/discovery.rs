@@ -0,0 +1,105 @@
+//! Neighbour discovery: periodic `Ping` beacons and delayed `Pong` answers.
+//!
+//! `Discovery` only decides *what* to send and *when*; the caller owns the clock and the radio,
+//! and feeds every received message to the neighbour table itself.
+use crate::message::{Message, MessageBuilder, MessageType, RouteAdverts, BROADCAST_UID};
+use crate::rng::XorShift32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BeaconConfig {
+    /// Milliseconds between two beacons, before jitter.
+    pub interval: u64,
+    /// Random delay of up to this many milliseconds added to every beacon and pong.
+    pub jitter: u64,
+    /// Milliseconds a neighbour stays in the table after its last frame.
+    pub ttl: u64,
+    /// Whether received pings are answered with a `Pong`.
+    pub answer_pings: bool,
+}
+
+impl Default for BeaconConfig {
+    fn default() -> Self {
+        BeaconConfig {
+            interval: 60_000,
+            jitter: 5_000,
+            ttl: 3 * 60_000,
+            answer_pings: true,
+        }
+    }
+}
+
+pub struct Discovery {
+    config: BeaconConfig,
+    builder: MessageBuilder,
+    rng: XorShift32,
+    next_beacon: u64,
+    /// Due time and destination of the pong we owe.
+    pending_pong: Option<(u64, u16)>,
+}
+
+impl Discovery {
+    /// Creates the scheduler; the first beacon goes out after a random delay of at most `jitter`.
+    pub fn new(uid: u16, config: BeaconConfig, now: u64, seed: u32) -> Self {
+        let mut rng = XorShift32::new(seed);
+        let next_beacon = now + rng.up_to(config.jitter);
+        Discovery {
+            config,
+            builder: MessageBuilder::new(uid),
+            rng,
+            next_beacon,
+            pending_pong: None,
+        }
+    }
+
+    pub fn config(&self) -> &BeaconConfig {
+        &self.config
+    }
+
+    /// Time at which `poll` next has something to send.
+    pub fn next_deadline(&self) -> u64 {
+        match self.pending_pong {
+            Some((due, _)) => due.min(self.next_beacon),
+            None => self.next_beacon,
+        }
+    }
+
+    /// Returns the message to transmit at `now`, if any.
+    ///
+    /// `neighbour_count` is only used when a pong is due, `routes` when a beacon is. Call again
+    /// until it returns `None`.
+    pub fn poll(
+        &mut self,
+        now: u64,
+        neighbour_count: u8,
+        routes: impl FnOnce() -> RouteAdverts,
+    ) -> Option<Message> {
+        if let Some((due, destination_uid)) = self.pending_pong {
+            if due <= now {
+                self.pending_pong = None;
+                return Some(self.builder.pong(destination_uid, neighbour_count));
+            }
+        }
+        if self.next_beacon <= now {
+            self.next_beacon = now + self.config.interval + self.rng.up_to(self.config.jitter);
+            return Some(self.builder.ping_with_routes(routes()));
+        }
+        None
+    }
+
+    /// Handles a received message, scheduling a pong if it is a ping we should answer.
+    pub fn on_message(&mut self, message: &Message, now: u64) {
+        if !self.config.answer_pings || !matches!(message.message_type(), MessageType::Ping { .. })
+        {
+            return;
+        }
+        self.pending_pong = match self.pending_pong {
+            // Several nodes pinged before we answered: one broadcast pong serves them all.
+            Some((due, _)) => Some((due, BROADCAST_UID)),
+            None => Some((
+                now + self.rng.up_to(self.config.jitter),
+                message.sender_uid(),
+            )),
+        };
+    }
+}
@@ -0,0 +1,87 @@
+//! Per sub-band off-time enforcing the regional duty cycle.
+//!
+//! As in LoRaWAN, a transmission of airtime `t` in a sub-band of duty cycle `dc` keeps the
+//! sub-band silent until `t / dc` after its start, that is for `t * (1 / dc - 1)` after its end.
+//! The airtime started over any window of `DUTY_CYCLE_WINDOW_MS`, an hour as in ETSI EN 300
+//! 220, then stays within the limit but for the last frame, whose off-time runs into the next
+//! window. The off-times are kept in RAM only, a reboot forgets the one of the last transmission
+//! of each sub-band.
+use crate::radio::Bandwidth;
+use crate::region::{Region, MAX_SUB_BANDS};
+
+/// Period over which the duty cycle is measured, in milliseconds.
+pub const DUTY_CYCLE_WINDOW_MS: u64 = 3_600_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Denied {
+    /// The transmission would not fit in any sub-band of the region.
+    OutOfBand(u32),
+    /// Longer, in microseconds, than the dwell time or the whole budget of the sub-band.
+    TooLong(u64),
+    /// The sub-band is off until the given time, in milliseconds since boot.
+    Deferred { until: u64 },
+}
+
+pub struct AirtimeAccountant {
+    region: Region,
+    /// When each sub-band may transmit again, in milliseconds since boot.
+    off_until: [u64; MAX_SUB_BANDS],
+}
+
+impl AirtimeAccountant {
+    /// Creates an accountant whose sub-bands may all transmit from `now` on.
+    pub fn new(region: Region, now: u64) -> Self {
+        AirtimeAccountant {
+            region,
+            off_until: [now; MAX_SUB_BANDS],
+        }
+    }
+
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    /// When the sub-band of `frequency_hz` may transmit again, `now` if it already may.
+    pub fn ready_at(&self, frequency_hz: u32, bandwidth: Bandwidth, now: u64) -> Option<u64> {
+        let (i, _) = self.region.sub_band(frequency_hz, bandwidth)?;
+        Some(self.off_until[i].max(now))
+    }
+
+    /// Reserves `airtime_us` for a transmission starting at `now`, or tells why it may not happen.
+    pub fn reserve(
+        &mut self,
+        frequency_hz: u32,
+        bandwidth: Bandwidth,
+        airtime_us: u64,
+        now: u64,
+    ) -> Result<(), Denied> {
+        let (i, band) = self
+            .region
+            .sub_band(frequency_hz, bandwidth)
+            .ok_or(Denied::OutOfBand(frequency_hz))?;
+        if self
+            .region
+            .plan()
+            .max_dwell_time_us
+            .is_some_and(|dwell| airtime_us > dwell)
+        {
+            return Err(Denied::TooLong(airtime_us));
+        }
+        let permille = u64::from(band.duty_cycle_permille);
+        if permille >= 1000 {
+            return Ok(());
+        }
+        // Microseconds per millisecond and permille cancel out.
+        if airtime_us > DUTY_CYCLE_WINDOW_MS * permille {
+            return Err(Denied::TooLong(airtime_us));
+        }
+
+        let off_until = &mut self.off_until[i];
+        if now < *off_until {
+            return Err(Denied::Deferred { until: *off_until });
+        }
+        *off_until = now + (airtime_us + permille - 1) / permille;
+        Ok(())
+    }
+}
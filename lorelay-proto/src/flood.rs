@@ -0,0 +1,148 @@
+//! Multi-hop relaying of `Normal` messages.
+//!
+//! A message is unicast along the routing table when a route to its destination is known, and
+//! flooded otherwise: every node then rebroadcasts it once, unless it was addressed to it or has
+//! used up its hops. Frames are identified by `(origin_uid, seq, attempt)` to suppress
+//! duplicates, so that retransmissions of an unacknowledged message are relayed again, while the
+//! destination delivers each `(origin_uid, seq)` only once.
+use crate::message::{Message, BROADCAST_UID};
+use crate::routing::Routes;
+use heapless::Deque;
+
+/// Remembers the last `N` keys seen, evicting the oldest first.
+pub struct SeenCache<K, const N: usize> {
+    entries: Deque<K, N>,
+}
+
+impl<K: PartialEq, const N: usize> SeenCache<K, N> {
+    pub const fn new() -> Self {
+        SeenCache {
+            entries: Deque::new(),
+        }
+    }
+
+    pub fn contains(&self, key: &K) -> bool {
+        self.entries.iter().any(|entry| entry == key)
+    }
+
+    /// Records a key and returns `false` if it had already been seen.
+    pub fn insert(&mut self, key: K) -> bool {
+        if self.contains(&key) {
+            return false;
+        }
+        if self.entries.is_full() {
+            self.entries.pop_front();
+        }
+        // Cannot fail, room was just made.
+        let _ = self.entries.push_back(key);
+        true
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl<K: PartialEq, const N: usize> Default for SeenCache<K, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// What a node should do with a received message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Handling {
+    /// The message is for this node and should be handed to the application.
+    pub deliver: bool,
+    /// Frame to rebroadcast, already stamped with this node as sender.
+    pub forward: Option<Message>,
+    /// The message is for this node and its origin asked for an `Ack`, even if it was already
+    /// delivered: the previous `Ack` may have been lost.
+    pub acknowledge: bool,
+}
+
+impl Handling {
+    const IGNORE: Handling = Handling {
+        deliver: false,
+        forward: None,
+        acknowledge: false,
+    };
+}
+
+/// Relaying state of one node.
+pub struct Flood<const N: usize> {
+    uid: u16,
+    seen: SeenCache<(u16, u16, u8), N>,
+    delivered: SeenCache<(u16, u16), N>,
+}
+
+impl<const N: usize> Flood<N> {
+    pub const fn new(uid: u16) -> Self {
+        Flood {
+            uid,
+            seen: SeenCache::new(),
+            delivered: SeenCache::new(),
+        }
+    }
+
+    pub fn uid(&self) -> u16 {
+        self.uid
+    }
+
+    /// Addresses a message originated by this node to its next hop, and registers it so that its
+    /// echoes are not relayed.
+    ///
+    /// Retransmissions go through here again, so that they follow the current route.
+    pub fn originate(&mut self, message: Message, routes: &impl Routes) -> Message {
+        let Some(seq) = message.seq() else {
+            return message;
+        };
+        self.seen
+            .insert((message.origin_uid(), seq, message.attempt()));
+        let next_hop_uid = Self::next_hop(message.destination_uid(), routes);
+        message.with_next_hop(next_hop_uid)
+    }
+
+    /// Decides whether a received message is delivered locally and/or relayed.
+    ///
+    /// Only `Normal` and `Ack` messages flooded or addressed to this node as next hop are
+    /// considered; everything else is ignored.
+    pub fn on_message(&mut self, message: &Message, routes: &impl Routes) -> Handling {
+        let Some(seq) = message.seq() else {
+            return Handling::IGNORE;
+        };
+        let origin_uid = message.origin_uid();
+        let destination_uid = message.destination_uid();
+        let next_hop_uid = message.next_hop_uid();
+
+        if next_hop_uid != BROADCAST_UID && next_hop_uid != self.uid {
+            return Handling::IGNORE;
+        }
+        if origin_uid == self.uid || !self.seen.insert((origin_uid, seq, message.attempt())) {
+            return Handling::IGNORE;
+        }
+
+        let for_us = destination_uid == self.uid;
+        let for_all = destination_uid == BROADCAST_UID;
+        Handling {
+            deliver: (for_us || for_all) && self.delivered.insert((origin_uid, seq)),
+            forward: if for_us {
+                None
+            } else {
+                message.relayed_by(self.uid, Self::next_hop(destination_uid, routes))
+            },
+            acknowledge: for_us && message.ack_requested(),
+        }
+    }
+
+    fn next_hop(destination_uid: u16, routes: &impl Routes) -> u16 {
+        if destination_uid == BROADCAST_UID {
+            return BROADCAST_UID;
+        }
+        routes.next_hop(destination_uid).unwrap_or(BROADCAST_UID)
+    }
+}
@@ -0,0 +1,396 @@
+//! Fragmentation of payloads larger than a `Normal` message.
+//!
+//! A payload is split into at most `MAX_FRAGMENTS` numbered fragments, each carried by a `Normal`
+//! message flagged as a fragment. When the receiver gets the last fragment it answers with a
+//! `Status` frame listing the missing ones, which the sender retransmits; an empty list completes
+//! the transfer. If no `Status` comes back, the sender repeats the last fragment to ask again.
+use crate::ack::{Delivery, RetryConfig};
+use crate::message::{DecodeError, EncodeError, MAX_PAYLOAD_SIZE};
+use heapless::{Deque, Vec};
+
+/// kind, transfer id, fragment index, fragment count
+const DATA_HEADER_SIZE: usize = 4;
+
+/// kind, transfer id, missing fragments bitmap
+const STATUS_SIZE: usize = 6;
+
+const KIND_DATA: u8 = 0;
+const KIND_STATUS: u8 = 1;
+
+/// Payload bytes carried by every fragment but the last.
+pub const FRAGMENT_DATA_SIZE: usize = MAX_PAYLOAD_SIZE - DATA_HEADER_SIZE;
+
+/// Number of fragments of a transfer, bounded by the width of the `Status` bitmap.
+pub const MAX_FRAGMENTS: usize = 32;
+
+/// Size of the largest payload that can be fragmented.
+pub const MAX_TRANSFER_SIZE: usize = MAX_FRAGMENTS * FRAGMENT_DATA_SIZE;
+
+/// Number of completed transfers remembered, within the timeout, to answer a repeated last
+/// fragment.
+const COMPLETED_CACHE_SIZE: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Fragment<'a> {
+    /// Per-sender counter that tells transfers apart.
+    pub transfer_id: u8,
+    pub index: u8,
+    pub count: u8,
+    pub data: &'a [u8],
+}
+
+/// Payload of a `Normal` message flagged as a fragment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Frame<'a> {
+    Data(Fragment<'a>),
+    /// Fragments the receiver still lacks, bit `i` of `missing` standing for fragment `i`.
+    Status {
+        transfer_id: u8,
+        missing: u32,
+    },
+}
+
+impl Frame<'_> {
+    pub fn encoded_len(&self) -> usize {
+        match self {
+            Frame::Data(fragment) => DATA_HEADER_SIZE + fragment.data.len(),
+            Frame::Status { .. } => STATUS_SIZE,
+        }
+    }
+
+    pub fn encode(&self, buf: &mut [u8]) -> Result<usize, EncodeError> {
+        let len = self.encoded_len();
+        let buf = buf.get_mut(..len).ok_or(EncodeError::BufferTooSmall)?;
+        match self {
+            Frame::Data(fragment) => {
+                buf[0] = KIND_DATA;
+                buf[1] = fragment.transfer_id;
+                buf[2] = fragment.index;
+                buf[3] = fragment.count;
+                buf[DATA_HEADER_SIZE..].copy_from_slice(fragment.data);
+            }
+            Frame::Status {
+                transfer_id,
+                missing,
+            } => {
+                buf[0] = KIND_STATUS;
+                buf[1] = *transfer_id;
+                buf[2..6].copy_from_slice(&missing.to_le_bytes());
+            }
+        }
+        Ok(len)
+    }
+
+    /// Decodes a frame, checking that fragments are consistent with their index and count.
+    pub fn decode(buf: &[u8]) -> Result<Frame<'_>, DecodeError> {
+        let length = buf.len() as u8;
+        match *buf.first().ok_or(DecodeError::Truncated)? {
+            KIND_DATA if buf.len() > DATA_HEADER_SIZE => {
+                let fragment = Fragment {
+                    transfer_id: buf[1],
+                    index: buf[2],
+                    count: buf[3],
+                    data: &buf[DATA_HEADER_SIZE..],
+                };
+                if fragment.count as usize > MAX_FRAGMENTS || fragment.index >= fragment.count {
+                    return Err(DecodeError::InvalidFragment);
+                }
+                let is_last = fragment.index == fragment.count - 1;
+                if fragment.data.len() > FRAGMENT_DATA_SIZE
+                    || (!is_last && fragment.data.len() != FRAGMENT_DATA_SIZE)
+                {
+                    return Err(DecodeError::InvalidLength(length));
+                }
+                Ok(Frame::Data(fragment))
+            }
+            KIND_STATUS if buf.len() == STATUS_SIZE => Ok(Frame::Status {
+                transfer_id: buf[1],
+                missing: u32::from_le_bytes([buf[2], buf[3], buf[4], buf[5]]),
+            }),
+            KIND_DATA | KIND_STATUS => Err(DecodeError::InvalidLength(length)),
+            kind => Err(DecodeError::UnknownType(kind)),
+        }
+    }
+}
+
+/// Bitmap with one bit set for each of `count` fragments.
+fn all_fragments(count: u8) -> u32 {
+    match count {
+        0 => 0,
+        count => u32::MAX >> (32 - u32::from(count.min(32))),
+    }
+}
+
+/// Work for the caller of `Transfer::poll`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step<'a> {
+    /// Frame to put on air, then poll again.
+    Send(Frame<'a>),
+    /// Nothing to do until the given deadline or the next `Status`.
+    Wait(u64),
+    /// The transfer is over.
+    Done(Delivery),
+}
+
+/// Sending side of a fragmented payload of at most `N` bytes.
+pub struct Transfer<const N: usize> {
+    destination_uid: u16,
+    transfer_id: u8,
+    config: RetryConfig,
+    payload: Vec<u8, N>,
+    count: u8,
+    /// Fragments to (re)transmit, same layout as a `Status` bitmap.
+    to_send: u32,
+    deadline: u64,
+    retries: u8,
+    outcome: Option<Delivery>,
+}
+
+impl<const N: usize> Transfer<N> {
+    /// Prepares the transfer of `payload` to `destination_uid`, waiting for each `Status` as
+    /// specified by `config`.
+    ///
+    /// Returns `None` if the payload is empty or larger than `N` or `MAX_TRANSFER_SIZE`.
+    pub fn new(
+        destination_uid: u16,
+        transfer_id: u8,
+        payload: &[u8],
+        config: RetryConfig,
+    ) -> Option<Self> {
+        if payload.is_empty() || payload.len() > MAX_TRANSFER_SIZE {
+            return None;
+        }
+        let count = ((payload.len() + FRAGMENT_DATA_SIZE - 1) / FRAGMENT_DATA_SIZE) as u8;
+        Some(Transfer {
+            destination_uid,
+            transfer_id,
+            config,
+            payload: Vec::from_slice(payload).ok()?,
+            count,
+            to_send: all_fragments(count),
+            deadline: 0,
+            retries: 0,
+            outcome: None,
+        })
+    }
+
+    pub fn destination_uid(&self) -> u16 {
+        self.destination_uid
+    }
+
+    pub fn transfer_id(&self) -> u8 {
+        self.transfer_id
+    }
+
+    /// Number of fragments the payload is split into.
+    pub fn count(&self) -> u8 {
+        self.count
+    }
+
+    fn fragment(&self, index: u8) -> Fragment<'_> {
+        let start = index as usize * FRAGMENT_DATA_SIZE;
+        let end = (start + FRAGMENT_DATA_SIZE).min(self.payload.len());
+        Fragment {
+            transfer_id: self.transfer_id,
+            index,
+            count: self.count,
+            data: &self.payload[start..end],
+        }
+    }
+
+    /// Returns the next frame to send, or what to wait for.
+    pub fn poll(&mut self, now: u64) -> Step<'_> {
+        if let Some(outcome) = self.outcome {
+            return Step::Done(outcome);
+        }
+        if self.to_send == 0 {
+            if now < self.deadline {
+                return Step::Wait(self.deadline);
+            }
+            if self.retries >= self.config.max_retries {
+                self.outcome = Some(Delivery::TimedOut);
+                return Step::Done(Delivery::TimedOut);
+            }
+            // The receiver answers the last fragment with a `Status`, even if it has it already.
+            self.retries += 1;
+            self.to_send = 1 << (self.count - 1);
+        }
+
+        let index = self.to_send.trailing_zeros() as u8;
+        self.to_send &= self.to_send - 1;
+        if self.to_send == 0 {
+            self.deadline = now.saturating_add(self.config.backoff(self.retries));
+        }
+        Step::Send(Frame::Data(self.fragment(index)))
+    }
+
+    /// Takes the `Status` sent by the destination into account.
+    pub fn on_status(&mut self, missing: u32) {
+        if self.outcome.is_some() {
+            return;
+        }
+        let missing = missing & all_fragments(self.count);
+        if missing == 0 {
+            self.outcome = Some(Delivery::Delivered {
+                attempts: self.retries + 1,
+            });
+        } else if self.retries >= self.config.max_retries {
+            self.outcome = Some(Delivery::TimedOut);
+        } else {
+            self.retries += 1;
+            self.to_send |= missing;
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.outcome.is_some()
+    }
+}
+
+/// Outcome of `Reassembler::on_fragment`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reassembly<const N: usize> {
+    /// The fragment was stored, more are expected.
+    Pending,
+    /// The last fragment arrived but others did not: answer with this `Status` bitmap.
+    Missing(u32),
+    /// Every fragment arrived; answer with an empty `Status`.
+    Complete(Vec<u8, N>),
+    /// Fragment of a transfer reassembled earlier, whose `Status` the sender did not get; answer
+    /// with an empty `Status` again.
+    AlreadyComplete,
+    /// No buffer is free, or the fragment does not match its transfer or fit in `N` bytes.
+    Rejected,
+}
+
+struct Slot<const N: usize> {
+    origin_uid: u16,
+    transfer_id: u8,
+    count: u8,
+    received: u32,
+    len: usize,
+    data: [u8; N],
+    updated_at: u64,
+}
+
+/// Receiving side: up to `S` transfers of at most `N` bytes reassembled at once.
+pub struct Reassembler<const S: usize, const N: usize> {
+    slots: Vec<Slot<N>, S>,
+    /// Transfers reassembled and when, oldest first. Past the timeout, their sender may have
+    /// restarted and be reusing the transfer id.
+    completed: Deque<((u16, u8), u64), COMPLETED_CACHE_SIZE>,
+    timeout: u64,
+}
+
+impl<const S: usize, const N: usize> Reassembler<S, N> {
+    /// Creates a reassembler that gives up on transfers idle for `timeout` milliseconds.
+    pub const fn new(timeout: u64) -> Self {
+        Reassembler {
+            slots: Vec::new(),
+            completed: Deque::new(),
+            timeout,
+        }
+    }
+
+    /// Stores a fragment sent by `origin_uid`.
+    pub fn on_fragment(&mut self, origin_uid: u16, fragment: &Fragment, now: u64) -> Reassembly<N> {
+        let key = (origin_uid, fragment.transfer_id);
+        self.expire_completed(now);
+        if self
+            .completed
+            .iter()
+            .any(|(completed, _)| *completed == key)
+        {
+            return Reassembly::AlreadyComplete;
+        }
+        let start = fragment.index as usize * FRAGMENT_DATA_SIZE;
+        let end = start + fragment.data.len();
+        if end > N {
+            return Reassembly::Rejected;
+        }
+
+        let i = match self
+            .slots
+            .iter()
+            .position(|s| (s.origin_uid, s.transfer_id) == key)
+        {
+            Some(i) if self.slots[i].count != fragment.count => return Reassembly::Rejected,
+            Some(i) => i,
+            None => {
+                if self.slots.is_full() {
+                    self.expire(now);
+                }
+                let slot = Slot {
+                    origin_uid,
+                    transfer_id: fragment.transfer_id,
+                    count: fragment.count,
+                    received: 0,
+                    len: 0,
+                    data: [0; N],
+                    updated_at: now,
+                };
+                if self.slots.push(slot).is_err() {
+                    return Reassembly::Rejected;
+                }
+                self.slots.len() - 1
+            }
+        };
+
+        let slot = &mut self.slots[i];
+        slot.data[start..end].copy_from_slice(fragment.data);
+        slot.received |= 1 << fragment.index;
+        slot.updated_at = now;
+        let is_last = fragment.index == fragment.count - 1;
+        if is_last {
+            slot.len = end;
+        }
+
+        let missing = all_fragments(slot.count) & !slot.received;
+        if missing == 0 {
+            let slot = self.slots.swap_remove(i);
+            if self.completed.is_full() {
+                self.completed.pop_front();
+            }
+            // Cannot fail, room was just made.
+            let _ = self.completed.push_back((key, now));
+            // Cannot fail, `len` is at most `N`.
+            Reassembly::Complete(Vec::from_slice(&slot.data[..slot.len]).unwrap_or_default())
+        } else if is_last {
+            Reassembly::Missing(missing)
+        } else {
+            Reassembly::Pending
+        }
+    }
+
+    /// Drops the transfers not heard from within the timeout and returns how many were dropped.
+    ///
+    /// Transfers completed before the timeout are forgotten too.
+    pub fn expire(&mut self, now: u64) -> usize {
+        self.expire_completed(now);
+        let before = self.slots.len();
+        let timeout = self.timeout;
+        self.slots
+            .retain(|s| now.saturating_sub(s.updated_at) < timeout);
+        before - self.slots.len()
+    }
+
+    fn expire_completed(&mut self, now: u64) {
+        while let Some((_, completed_at)) = self.completed.front() {
+            if now.saturating_sub(*completed_at) < self.timeout {
+                break;
+            }
+            self.completed.pop_front();
+        }
+    }
+
+    /// Number of transfers being reassembled.
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+}
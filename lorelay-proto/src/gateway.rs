@@ -0,0 +1,236 @@
+//! Values of the lorelay GATT service, through which a phone sends and receives payloads over the
+//! mesh and follows the state of the node.
+//!
+//! The BLE board serves them and the LoRa board produces and consumes them, so both encode them
+//! here. Every value is little endian and fits a single ATT packet.
+use crate::ack::Delivery;
+use crate::message::{DecodeError, EncodeError};
+use crate::neighbour::Neighbour;
+use crate::relay::NEIGHBOUR_TABLE_SIZE;
+use heapless::Vec;
+
+pub const SERVICE_UUID: &str = "6c720200-8d2e-4b1a-9f5c-3a7e0b6d2c41";
+/// `OutgoingMessage`, written by the client.
+pub const OUTGOING_UUID: &str = "6c720201-8d2e-4b1a-9f5c-3a7e0b6d2c41";
+/// `IncomingMessage`, notified.
+pub const INCOMING_UUID: &str = "6c720202-8d2e-4b1a-9f5c-3a7e0b6d2c41";
+/// `NodeStatus`, read and notified.
+pub const STATUS_UUID: &str = "6c720203-8d2e-4b1a-9f5c-3a7e0b6d2c41";
+/// Array of `NeighbourEntry`, read and notified.
+pub const NEIGHBOURS_UUID: &str = "6c720204-8d2e-4b1a-9f5c-3a7e0b6d2c41";
+/// `Delivery` of the last acknowledged `OutgoingMessage`, read and notified.
+pub const DELIVERY_UUID: &str = "6c720205-8d2e-4b1a-9f5c-3a7e0b6d2c41";
+
+/// Largest payload going through the service, for values to fit the 247 bytes ATT MTU of BLE
+/// data length extension.
+pub const MAX_PAYLOAD_SIZE: usize = 240;
+
+/// destination, flags
+const OUTGOING_HEADER_SIZE: usize = 3;
+
+pub const MAX_OUTGOING_SIZE: usize = OUTGOING_HEADER_SIZE + MAX_PAYLOAD_SIZE;
+
+/// origin
+const INCOMING_HEADER_SIZE: usize = 2;
+
+pub const MAX_INCOMING_SIZE: usize = INCOMING_HEADER_SIZE + MAX_PAYLOAD_SIZE;
+
+/// uid, mode, neighbours, routes, uptime
+pub const STATUS_SIZE: usize = 9;
+
+/// uid, RSSI, SNR, age
+pub const NEIGHBOUR_ENTRY_SIZE: usize = 8;
+
+pub const MAX_NEIGHBOURS_SIZE: usize = NEIGHBOUR_TABLE_SIZE * NEIGHBOUR_ENTRY_SIZE;
+
+/// attempts, zero when the payload timed out
+pub const DELIVERY_SIZE: usize = 1;
+
+const FLAG_ACK: u8 = 1 << 0;
+
+pub type GatewayPayload = Vec<u8, MAX_PAYLOAD_SIZE>;
+
+/// Values are longer than a length byte can tell, which saturates.
+fn invalid_length(buf: &[u8]) -> DecodeError {
+    DecodeError::InvalidLength(u8::try_from(buf.len()).unwrap_or(u8::MAX))
+}
+
+/// Payload for the mesh to carry from this node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutgoingMessage {
+    pub destination_uid: u16,
+    /// Whether the destination should acknowledge the payload.
+    pub ack: bool,
+    pub payload: GatewayPayload,
+}
+
+impl OutgoingMessage {
+    pub fn encode(&self, buf: &mut [u8]) -> Result<usize, EncodeError> {
+        let len = OUTGOING_HEADER_SIZE + self.payload.len();
+        let buf = buf.get_mut(..len).ok_or(EncodeError::BufferTooSmall)?;
+        buf[0..2].copy_from_slice(&self.destination_uid.to_le_bytes());
+        buf[2] = if self.ack { FLAG_ACK } else { 0 };
+        buf[OUTGOING_HEADER_SIZE..].copy_from_slice(&self.payload);
+        Ok(len)
+    }
+
+    pub fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+        if buf.len() < OUTGOING_HEADER_SIZE {
+            return Err(DecodeError::Truncated);
+        }
+        let payload = &buf[OUTGOING_HEADER_SIZE..];
+        Ok(OutgoingMessage {
+            destination_uid: u16::from_le_bytes([buf[0], buf[1]]),
+            ack: buf[2] & FLAG_ACK != 0,
+            payload: Vec::from_slice(payload).map_err(|()| invalid_length(buf))?,
+        })
+    }
+}
+
+/// Payload the mesh delivered to this node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IncomingMessage {
+    pub origin_uid: u16,
+    pub payload: GatewayPayload,
+}
+
+impl IncomingMessage {
+    pub fn encode(&self, buf: &mut [u8]) -> Result<usize, EncodeError> {
+        let len = INCOMING_HEADER_SIZE + self.payload.len();
+        let buf = buf.get_mut(..len).ok_or(EncodeError::BufferTooSmall)?;
+        buf[0..2].copy_from_slice(&self.origin_uid.to_le_bytes());
+        buf[INCOMING_HEADER_SIZE..].copy_from_slice(&self.payload);
+        Ok(len)
+    }
+
+    pub fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+        if buf.len() < INCOMING_HEADER_SIZE {
+            return Err(DecodeError::Truncated);
+        }
+        Ok(IncomingMessage {
+            origin_uid: u16::from_le_bytes([buf[0], buf[1]]),
+            payload: Vec::from_slice(&buf[INCOMING_HEADER_SIZE..])
+                .map_err(|()| invalid_length(buf))?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct NodeStatus {
+    pub uid: u16,
+    /// Mode the node runs in, as numbered in its configuration.
+    pub mode: u8,
+    /// Live neighbours and reachable destinations.
+    pub neighbours: u8,
+    pub routes: u8,
+    pub uptime_s: u32,
+}
+
+impl NodeStatus {
+    pub fn encode(&self, buf: &mut [u8]) -> Result<usize, EncodeError> {
+        let buf = buf
+            .get_mut(..STATUS_SIZE)
+            .ok_or(EncodeError::BufferTooSmall)?;
+        buf[0..2].copy_from_slice(&self.uid.to_le_bytes());
+        buf[2] = self.mode;
+        buf[3] = self.neighbours;
+        buf[4] = self.routes;
+        buf[5..9].copy_from_slice(&self.uptime_s.to_le_bytes());
+        Ok(STATUS_SIZE)
+    }
+
+    pub fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+        if buf.len() != STATUS_SIZE {
+            return Err(invalid_length(buf));
+        }
+        Ok(NodeStatus {
+            uid: u16::from_le_bytes([buf[0], buf[1]]),
+            mode: buf[2],
+            neighbours: buf[3],
+            routes: buf[4],
+            uptime_s: u32::from_le_bytes([buf[5], buf[6], buf[7], buf[8]]),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct NeighbourEntry {
+    pub uid: u16,
+    pub rssi: i16,
+    pub snr: i16,
+    /// Seconds since the neighbour was last heard, saturated.
+    pub age_s: u16,
+}
+
+impl NeighbourEntry {
+    pub fn new(neighbour: &Neighbour, now: u64) -> Self {
+        let age_s = now.saturating_sub(neighbour.last_seen) / 1_000;
+        NeighbourEntry {
+            uid: neighbour.uid,
+            rssi: neighbour.rssi,
+            snr: neighbour.snr,
+            age_s: age_s.min(u64::from(u16::MAX)) as u16,
+        }
+    }
+
+    fn encode(&self, buf: &mut [u8]) {
+        buf[0..2].copy_from_slice(&self.uid.to_le_bytes());
+        buf[2..4].copy_from_slice(&self.rssi.to_le_bytes());
+        buf[4..6].copy_from_slice(&self.snr.to_le_bytes());
+        buf[6..8].copy_from_slice(&self.age_s.to_le_bytes());
+    }
+
+    fn decode(buf: &[u8]) -> Self {
+        let field = |at: usize| [buf[at], buf[at + 1]];
+        NeighbourEntry {
+            uid: u16::from_le_bytes(field(0)),
+            rssi: i16::from_le_bytes(field(2)),
+            snr: i16::from_le_bytes(field(4)),
+            age_s: u16::from_le_bytes(field(6)),
+        }
+    }
+}
+
+/// Encodes `entries` one after the other.
+pub fn encode_neighbours(entries: &[NeighbourEntry], buf: &mut [u8]) -> Result<usize, EncodeError> {
+    let len = entries.len() * NEIGHBOUR_ENTRY_SIZE;
+    let buf = buf.get_mut(..len).ok_or(EncodeError::BufferTooSmall)?;
+    for (entry, chunk) in entries
+        .iter()
+        .zip(buf.chunks_exact_mut(NEIGHBOUR_ENTRY_SIZE))
+    {
+        entry.encode(chunk);
+    }
+    Ok(len)
+}
+
+pub fn decode_neighbours(
+    buf: &[u8],
+) -> Result<Vec<NeighbourEntry, NEIGHBOUR_TABLE_SIZE>, DecodeError> {
+    if buf.len() % NEIGHBOUR_ENTRY_SIZE != 0 || buf.len() > MAX_NEIGHBOURS_SIZE {
+        return Err(invalid_length(buf));
+    }
+    Ok(buf
+        .chunks_exact(NEIGHBOUR_ENTRY_SIZE)
+        .map(NeighbourEntry::decode)
+        .collect())
+}
+
+/// Encodes `delivery` as the transmissions it took, zero if it timed out.
+pub fn encode_delivery(delivery: &Delivery, buf: &mut [u8]) -> Result<usize, EncodeError> {
+    *buf.first_mut().ok_or(EncodeError::BufferTooSmall)? = match *delivery {
+        Delivery::Delivered { attempts } => attempts,
+        Delivery::TimedOut => 0,
+    };
+    Ok(DELIVERY_SIZE)
+}
+
+pub fn decode_delivery(buf: &[u8]) -> Result<Delivery, DecodeError> {
+    match *buf {
+        [0] => Ok(Delivery::TimedOut),
+        [attempts] => Ok(Delivery::Delivered { attempts }),
+        _ => Err(invalid_length(buf)),
+    }
+}
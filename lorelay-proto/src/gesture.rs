@@ -0,0 +1,199 @@
+//! Gestures recognized from the raw edges of a push button.
+//!
+//! `Gestures` is fed every edge with its timestamp and is polled for events. An edge only counts
+//! once the level stayed put for the debounce time, and it counts from the time of the edge, so
+//! that bounces do not shift the timings of the gestures.
+//!
+//! A press released within `long_press_ms` is a click, unless the button is pressed and released
+//! again within `double_click_ms`, which makes a double click instead. A press held for
+//! `long_press_ms` is a long press, followed every `hold_interval_ms` by a hold event for as long
+//! as it lasts.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct GestureConfig {
+    /// Time a level must stay put to count.
+    pub debounce_ms: u64,
+    /// Longest time between the release of a click and the press of the next one to make a double
+    /// click; 0 disables double clicks, making clicks come out at once.
+    pub double_click_ms: u64,
+    pub long_press_ms: u64,
+    pub hold_interval_ms: u64,
+}
+
+impl Default for GestureConfig {
+    fn default() -> Self {
+        GestureConfig {
+            debounce_ms: 20,
+            double_click_ms: 300,
+            long_press_ms: 800,
+            hold_interval_ms: 500,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ButtonEvent {
+    Click,
+    DoubleClick,
+    /// The button has been held for `long_press_ms`.
+    LongPress,
+    /// The button is still held, since the given number of milliseconds.
+    Hold(u64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Released,
+    Pressed {
+        since: u64,
+    },
+    /// A click waiting for a second one.
+    Clicked {
+        at: u64,
+    },
+    SecondPress {
+        since: u64,
+    },
+    /// Past the long press, the next hold event being due at `next`.
+    Held {
+        since: u64,
+        next: u64,
+    },
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Step {
+    /// The level changed at the given time.
+    Level(u64),
+    /// The timer of the state expired at the given time.
+    Timer(u64),
+}
+
+/// Gesture recognizer of one button.
+#[derive(Debug, Clone)]
+pub struct Gestures {
+    config: GestureConfig,
+    state: State,
+    /// Debounced level.
+    pressed: bool,
+    /// Level after the last edge, and its time.
+    raw: bool,
+    raw_since: u64,
+}
+
+impl Gestures {
+    /// Recognizer of a button that is released.
+    pub fn new(config: GestureConfig) -> Self {
+        Gestures {
+            config,
+            state: State::Released,
+            pressed: false,
+            raw: false,
+            raw_since: 0,
+        }
+    }
+
+    pub fn config(&self) -> &GestureConfig {
+        &self.config
+    }
+
+    /// Records that the button was found `pressed` at `now`, right after an edge.
+    pub fn on_edge(&mut self, pressed: bool, now: u64) {
+        if pressed != self.raw {
+            self.raw = pressed;
+            self.raw_since = now;
+        }
+    }
+
+    fn timer(&self) -> Option<u64> {
+        match self.state {
+            State::Released => None,
+            State::Pressed { since } | State::SecondPress { since } => {
+                Some(since + self.config.long_press_ms)
+            }
+            State::Clicked { at } => Some(at + self.config.double_click_ms),
+            State::Held { next, .. } => Some(next),
+        }
+    }
+
+    /// What happened first, and when it can be handled.
+    ///
+    /// A timer that expires after an edge waits for the edge to be debounced: the edge may be a
+    /// release that cancels it.
+    fn next_step(&self) -> Option<(u64, Step)> {
+        let change = (self.raw != self.pressed).then_some(self.raw_since);
+        match (change, self.timer()) {
+            (Some(edge), Some(timer)) if timer < edge => Some((timer, Step::Timer(timer))),
+            (Some(edge), _) => Some((edge + self.config.debounce_ms, Step::Level(edge))),
+            (None, Some(timer)) => Some((timer, Step::Timer(timer))),
+            (None, None) => None,
+        }
+    }
+
+    /// When `poll` may have something new, `None` while the button is idle.
+    pub fn next_deadline(&self) -> Option<u64> {
+        self.next_step().map(|(due, _)| due)
+    }
+
+    /// Next gesture recognized by `now`, to be called until it returns `None`.
+    pub fn poll(&mut self, now: u64) -> Option<ButtonEvent> {
+        loop {
+            let event = match self.next_step().filter(|(due, _)| *due <= now)?.1 {
+                Step::Level(at) => {
+                    self.pressed = self.raw;
+                    self.on_level(at)
+                }
+                Step::Timer(at) => self.on_timer(at),
+            };
+            if event.is_some() {
+                return event;
+            }
+        }
+    }
+
+    fn on_level(&mut self, at: u64) -> Option<ButtonEvent> {
+        let (state, event) = match (self.state, self.pressed) {
+            (State::Released, true) => (State::Pressed { since: at }, None),
+            (State::Pressed { .. }, false) if self.config.double_click_ms == 0 => {
+                (State::Released, Some(ButtonEvent::Click))
+            }
+            (State::Pressed { .. }, false) => (State::Clicked { at }, None),
+            (State::Clicked { .. }, true) => (State::SecondPress { since: at }, None),
+            (State::SecondPress { .. }, false) => (State::Released, Some(ButtonEvent::DoubleClick)),
+            (State::Held { .. }, false) => (State::Released, None),
+            // Levels alternate, so nothing else can happen.
+            (state, _) => (state, None),
+        };
+        self.state = state;
+        event
+    }
+
+    fn on_timer(&mut self, at: u64) -> Option<ButtonEvent> {
+        // A zero interval would repeat hold events forever.
+        let interval = self.config.hold_interval_ms.max(1);
+        let (state, event) = match self.state {
+            State::Pressed { since } => (
+                State::Held {
+                    since,
+                    next: at + interval,
+                },
+                ButtonEvent::LongPress,
+            ),
+            State::Clicked { .. } => (State::Released, ButtonEvent::Click),
+            // The first press was a click, and the second one goes on as a first one.
+            State::SecondPress { since } => (State::Pressed { since }, ButtonEvent::Click),
+            State::Held { since, next } => (
+                State::Held {
+                    since,
+                    next: next + interval,
+                },
+                ButtonEvent::Hold(next - since),
+            ),
+            State::Released => return None,
+        };
+        self.state = state;
+        Some(event)
+    }
+}
@@ -0,0 +1,317 @@
+//! Blink patterns of the status LEDs, and which one shows when several are requested.
+//!
+//! A pattern is a sequence of on and off segments. `LedEngine` keeps one pattern per `Priority`
+//! and shows the highest one: an error overrides the blink of a transmission, and once a finite
+//! pattern is over, the one below it starts over where the LED was taken from it.
+//!
+//! Brightness is faked with software PWM, toggling the LED every few milliseconds.
+
+/// Period of the software PWM of `LedPattern::Breathe`.
+pub const PWM_PERIOD_MS: u16 = 10;
+
+/// Length of a dot of `LedPattern::Sos`.
+const SOS_UNIT_MS: u16 = 150;
+
+/// Dots and dashes, with the gaps between them and a word gap at the end.
+const SOS: [u16; 18] = {
+    let (dot, dash) = (SOS_UNIT_MS, 3 * SOS_UNIT_MS);
+    let gap = SOS_UNIT_MS;
+    let letter = 3 * SOS_UNIT_MS;
+    let word = 7 * SOS_UNIT_MS;
+    [
+        dot, gap, dot, gap, dot, letter, //
+        dash, gap, dash, gap, dash, letter, //
+        dot, gap, dot, gap, dot, word,
+    ]
+};
+
+/// Two beats and a rest.
+const HEARTBEAT: [u16; 4] = [80, 120, 80, 720];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum LedPattern {
+    Off,
+    Solid,
+    /// `count` blinks, then the pattern is over.
+    Blink {
+        count: u8,
+        on_ms: u16,
+        off_ms: u16,
+    },
+    Heartbeat,
+    /// Brightness ramping up and down over `period_ms`.
+    Breathe {
+        period_ms: u16,
+    },
+    /// "... --- ..." once, then the pattern is over.
+    Sos,
+    /// Durations the LED is alternately on and off, starting on.
+    Custom {
+        steps: &'static [u16],
+        repeat: bool,
+    },
+}
+
+impl LedPattern {
+    /// The single blink the LEDs used to be limited to.
+    pub const BLINK: LedPattern = LedPattern::Blink {
+        count: 1,
+        on_ms: 1_000,
+        off_ms: 0,
+    };
+
+    /// Number of segments after which a repeating pattern starts over.
+    fn cycle(&self) -> Option<u32> {
+        match self {
+            LedPattern::Heartbeat => Some(HEARTBEAT.len() as u32),
+            LedPattern::Breathe { period_ms } => Some(2 * breathe_slots(*period_ms)),
+            LedPattern::Custom { steps, repeat } if *repeat => Some(steps.len() as u32),
+            _ => None,
+        }
+    }
+
+    /// Level and duration of segment `index`; no duration means for good, and `None` that the
+    /// pattern is over.
+    fn segment(&self, index: u32) -> Option<(bool, Option<u16>)> {
+        let on = index % 2 == 0;
+        match *self {
+            LedPattern::Off if index == 0 => Some((false, None)),
+            LedPattern::Solid if index == 0 => Some((true, None)),
+            LedPattern::Off | LedPattern::Solid => None,
+            LedPattern::Blink {
+                count,
+                on_ms,
+                off_ms,
+            } => (index < 2 * u32::from(count))
+                .then_some((on, Some(if on { on_ms } else { off_ms }))),
+            LedPattern::Heartbeat => step(&HEARTBEAT, index),
+            LedPattern::Breathe { period_ms } => {
+                let slots = breathe_slots(period_ms);
+                let slot = index / 2;
+                let half = slots / 2;
+                // Triangle from 0 to the full PWM period and back.
+                let rising = slot.min(slots - slot);
+                let duty = (rising * u32::from(PWM_PERIOD_MS) / half) as u16;
+                Some((on, Some(if on { duty } else { PWM_PERIOD_MS - duty })))
+            }
+            LedPattern::Sos => step(&SOS, index),
+            // A pattern that never lights the LED would spin forever.
+            LedPattern::Custom { steps, .. } if steps.iter().all(|&ms| ms == 0) => None,
+            LedPattern::Custom { steps, .. } => step(steps, index),
+        }
+    }
+}
+
+fn step(steps: &[u16], index: u32) -> Option<(bool, Option<u16>)> {
+    let duration = *steps.get(index as usize)?;
+    Some((index % 2 == 0, Some(duration)))
+}
+
+/// PWM periods in a breath, an even number of at least two.
+fn breathe_slots(period_ms: u16) -> u32 {
+    (u32::from(period_ms / PWM_PERIOD_MS) & !1).max(2)
+}
+
+/// From the lowest priority to the highest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Priority {
+    /// What the node is doing, shown whenever nothing else is.
+    Status,
+    /// Short lived indications such as a frame sent or received.
+    Activity,
+    Warning,
+    Error,
+}
+
+impl Priority {
+    pub const ALL: [Priority; 4] = [
+        Priority::Status,
+        Priority::Activity,
+        Priority::Warning,
+        Priority::Error,
+    ];
+}
+
+const PRIORITIES: usize = Priority::ALL.len();
+
+/// States of the node shown on its LEDs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DeviceState {
+    /// Looking for neighbours.
+    Joining,
+    /// Part of a network.
+    Relaying,
+    LowBattery,
+    RadioError,
+}
+
+impl DeviceState {
+    pub fn indication(self) -> (Priority, LedPattern) {
+        match self {
+            DeviceState::Joining => (Priority::Status, LedPattern::Heartbeat),
+            DeviceState::Relaying => (Priority::Status, LedPattern::Breathe { period_ms: 3_000 }),
+            DeviceState::LowBattery => (
+                Priority::Warning,
+                LedPattern::Custom {
+                    steps: &[50, 1_950],
+                    repeat: true,
+                },
+            ),
+            DeviceState::RadioError => (Priority::Error, LedPattern::Sos),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Sequence {
+    pattern: LedPattern,
+    index: u32,
+    on: bool,
+    /// End of the current segment, `None` if it lasts for good.
+    until: Option<u64>,
+    /// When the pattern ended, if it did.
+    ended: Option<u64>,
+}
+
+impl Sequence {
+    fn start(pattern: LedPattern, now: u64) -> Self {
+        let mut sequence = Sequence {
+            pattern,
+            index: 0,
+            on: false,
+            until: None,
+            ended: None,
+        };
+        sequence.enter(now);
+        sequence
+    }
+
+    /// Enters segment `index` at `start`, skipping empty ones.
+    fn enter(&mut self, start: u64) {
+        loop {
+            match self.pattern.segment(self.index) {
+                None => {
+                    self.ended = Some(start);
+                    self.on = false;
+                    self.until = None;
+                    return;
+                }
+                Some((_, Some(0))) => self.next_index(),
+                Some((on, duration)) => {
+                    self.on = on;
+                    self.until = duration.map(|ms| start + u64::from(ms));
+                    return;
+                }
+            }
+        }
+    }
+
+    fn next_index(&mut self) {
+        self.index += 1;
+        if let Some(cycle) = self.pattern.cycle() {
+            self.index %= cycle;
+        }
+    }
+
+    fn advance(&mut self, now: u64) {
+        while let Some(until) = self.until.filter(|&until| until <= now) {
+            self.next_index();
+            self.enter(until);
+        }
+    }
+}
+
+/// Patterns requested for one LED.
+#[derive(Debug, Clone)]
+pub struct LedEngine {
+    layers: [Option<Sequence>; PRIORITIES],
+    /// Layer on show.
+    active: Option<usize>,
+}
+
+impl Default for LedEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LedEngine {
+    pub const fn new() -> Self {
+        LedEngine {
+            layers: [None; PRIORITIES],
+            active: None,
+        }
+    }
+
+    /// Shows `pattern` at `priority` from `now`, replacing the previous one; `None` clears it.
+    pub fn set(&mut self, priority: Priority, pattern: Option<LedPattern>, now: u64) {
+        self.layers[priority as usize] = pattern.map(|pattern| Sequence::start(pattern, now));
+        self.update(now);
+    }
+
+    pub fn show(&mut self, state: DeviceState, now: u64) {
+        let (priority, pattern) = state.indication();
+        self.set(priority, Some(pattern), now);
+    }
+
+    /// Whether the LED is lit.
+    pub fn is_on(&self) -> bool {
+        self.current().is_some_and(|sequence| sequence.on)
+    }
+
+    /// Priority of the pattern on show, `None` if the LED is idle.
+    pub fn priority(&self) -> Option<Priority> {
+        self.current()?;
+        Some(Priority::ALL[self.active?])
+    }
+
+    /// When the LED changes next, `None` if it stays as it is until the next `set`.
+    pub fn next_deadline(&self) -> Option<u64> {
+        self.current()?.until
+    }
+
+    /// Moves the pattern on show forward to `now`.
+    pub fn poll(&mut self, now: u64) {
+        while let Some(index) = self.active {
+            let Some(sequence) = self.layers[index].as_mut() else {
+                return;
+            };
+            sequence.advance(now);
+            let Some(ended) = sequence.ended else {
+                return;
+            };
+            // The layer below takes over from the end of this one, and catches up with `now`.
+            self.layers[index] = None;
+            self.update(ended);
+        }
+    }
+
+    fn current(&self) -> Option<&Sequence> {
+        self.layers[self.active?].as_ref()
+    }
+
+    /// Puts the highest layer on show, starting it over if it just got the LED.
+    fn update(&mut self, now: u64) {
+        loop {
+            let highest = self.layers.iter().rposition(Option::is_some);
+            if highest != self.active {
+                if let Some(index) = highest {
+                    let pattern = self.layers[index].map(|sequence| sequence.pattern);
+                    self.layers[index] = pattern.map(|pattern| Sequence::start(pattern, now));
+                }
+                self.active = highest;
+            }
+            match highest {
+                Some(index)
+                    if self.layers[index].is_some_and(|sequence| sequence.ended.is_some()) =>
+                {
+                    self.layers[index] = None;
+                }
+                _ => return,
+            }
+        }
+    }
+}
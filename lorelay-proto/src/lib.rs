@@ -0,0 +1,41 @@
+//! Hardware independent part of the lorelay protocol, shared by both firmwares.
+//!
+//! Nothing in here depends on a HAL, so the whole crate builds and is tested on the host.
+//!
+//! With the `std` feature, `sim` provides radios connected through an in-memory medium, to run
+//! whole networks on the host.
+#![no_std]
+#![feature(async_fn_in_trait)]
+#![allow(incomplete_features)]
+
+#[cfg(feature = "std")]
+extern crate std;
+
+pub mod ack;
+pub mod airtime;
+pub mod battery;
+pub mod channel_access;
+pub mod cobs;
+pub mod connections;
+pub mod console;
+pub mod crc;
+pub mod device_config;
+pub mod discovery;
+pub mod duty_cycle;
+pub mod flood;
+pub mod fragment;
+pub mod gateway;
+pub mod gesture;
+pub mod led;
+pub mod link;
+pub mod message;
+pub mod neighbour;
+pub mod radio;
+pub mod range_test;
+pub mod region;
+pub mod regulated;
+pub mod relay;
+pub mod rng;
+pub mod routing;
+#[cfg(feature = "std")]
+pub mod sim;
@@ -0,0 +1,445 @@
+//! Serial link between the BLE and LoRa boards of a node.
+//!
+//! Frames are COBS encoded and delimited by zeros. Each one holds a kind and a sequence number,
+//! a `Packet` for data frames, and the CRC-16 of all that.
+//!
+//! Data frames go one at a time: the next one waits for the acknowledgment of the previous one,
+//! which is retransmitted when the acknowledgment times out or the peer reports a corrupted
+//! frame. A peer that has no room for a packet rejects it as busy, and it is sent again after
+//! `LinkConfig::busy_backoff` without counting as a retry. Sequence numbers tell retransmissions
+//! from new packets. Data frames are flagged until one is acknowledged, for the peer to
+//! resynchronize with a link that was reset, and the first sequence number is random so that a
+//! reset link is unlikely to repeat the frame the peer received last.
+use crate::ack::Delivery;
+use crate::cobs;
+use crate::crc::crc16;
+use crate::device_config::{DeviceConfig, RECORD_SIZE};
+use crate::gateway::{
+    decode_delivery, decode_neighbours, encode_delivery, encode_neighbours, IncomingMessage,
+    NeighbourEntry, NodeStatus, OutgoingMessage, MAX_INCOMING_SIZE, MAX_NEIGHBOURS_SIZE,
+    MAX_OUTGOING_SIZE,
+};
+use crate::message::{DecodeError, EncodeError};
+use crate::range_test::{Summary, SUMMARY_SIZE};
+use crate::relay::NEIGHBOUR_TABLE_SIZE;
+use crate::rng::XorShift32;
+use heapless::{Deque, Vec};
+
+pub const FRAME_DELIMITER: u8 = 0;
+
+const KIND_DATA: u8 = 0;
+const KIND_ACK: u8 = 1;
+const KIND_NAK_BUSY: u8 = 2;
+const KIND_NAK_CORRUPTED: u8 = 3;
+const KIND_MASK: u8 = 0x0f;
+/// Set on data frames while the peer may not know the sequence numbers.
+const FLAG_SYNC: u8 = 0x80;
+
+/// kind, sequence
+const HEADER_SIZE: usize = 2;
+const CRC_SIZE: usize = 2;
+
+const TAG_OUTGOING: u8 = 0;
+const TAG_INCOMING: u8 = 1;
+const TAG_STATUS: u8 = 2;
+const TAG_NEIGHBOURS: u8 = 3;
+const TAG_RANGE_TEST_SUMMARY: u8 = 4;
+const TAG_CONFIG: u8 = 5;
+const TAG_SET_CONFIG: u8 = 6;
+const TAG_SET_MODE: u8 = 7;
+const TAG_REBOOT: u8 = 8;
+const TAG_DELIVERY: u8 = 9;
+
+const fn max(a: usize, b: usize) -> usize {
+    if a > b {
+        a
+    } else {
+        b
+    }
+}
+
+/// Tag and largest value.
+pub const MAX_PACKET_SIZE: usize = 1 + max(
+    max(MAX_OUTGOING_SIZE, MAX_INCOMING_SIZE),
+    max(max(MAX_NEIGHBOURS_SIZE, SUMMARY_SIZE), RECORD_SIZE),
+);
+
+/// Frame before COBS encoding.
+pub const MAX_FRAME_SIZE: usize = HEADER_SIZE + MAX_PACKET_SIZE + CRC_SIZE;
+
+/// Frame on the wire, delimiter included.
+pub const MAX_ENCODED_FRAME_SIZE: usize = cobs::max_encoded_len(MAX_FRAME_SIZE) + 1;
+
+pub type EncodedFrame = Vec<u8, MAX_ENCODED_FRAME_SIZE>;
+
+/// What the boards tell each other.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Packet {
+    /// Written by the BLE client, for the LoRa board to send over the mesh.
+    Outgoing(OutgoingMessage),
+    /// Delivered by the mesh, for the LoRa board to hand to the BLE client.
+    Incoming(IncomingMessage),
+    Status(NodeStatus),
+    Neighbours(Vec<NeighbourEntry, NEIGHBOUR_TABLE_SIZE>),
+    RangeTestSummary(Summary),
+    /// Configuration stored by the LoRa board.
+    Config(DeviceConfig),
+    /// Configuration for the LoRa board to store, and run with after its next reboot.
+    SetConfig(DeviceConfig),
+    /// Number of the mode for the LoRa board to switch to.
+    SetMode(u8),
+    /// For the LoRa board to reboot, once it acknowledged the packet.
+    Reboot,
+    /// Outcome of the last `Outgoing` message the BLE client asked an acknowledgment for.
+    Delivery(Delivery),
+}
+
+impl Packet {
+    pub fn encode(&self, buf: &mut [u8]) -> Result<usize, EncodeError> {
+        let (tag, body) = buf.split_first_mut().ok_or(EncodeError::BufferTooSmall)?;
+        let (packet_tag, len) = match self {
+            Packet::Outgoing(message) => (TAG_OUTGOING, message.encode(body)?),
+            Packet::Incoming(message) => (TAG_INCOMING, message.encode(body)?),
+            Packet::Status(status) => (TAG_STATUS, status.encode(body)?),
+            Packet::Neighbours(entries) => (TAG_NEIGHBOURS, encode_neighbours(entries, body)?),
+            Packet::RangeTestSummary(summary) => (TAG_RANGE_TEST_SUMMARY, summary.encode(body)?),
+            Packet::Config(config) => (TAG_CONFIG, config.encode(body)?),
+            Packet::SetConfig(config) => (TAG_SET_CONFIG, config.encode(body)?),
+            Packet::SetMode(mode) => {
+                *body.first_mut().ok_or(EncodeError::BufferTooSmall)? = *mode;
+                (TAG_SET_MODE, 1)
+            }
+            Packet::Reboot => (TAG_REBOOT, 0),
+            Packet::Delivery(delivery) => (TAG_DELIVERY, encode_delivery(delivery, body)?),
+        };
+        *tag = packet_tag;
+        Ok(1 + len)
+    }
+
+    pub fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+        let (&tag, body) = buf.split_first().ok_or(DecodeError::Truncated)?;
+        Ok(match tag {
+            TAG_OUTGOING => Packet::Outgoing(OutgoingMessage::decode(body)?),
+            TAG_INCOMING => Packet::Incoming(IncomingMessage::decode(body)?),
+            TAG_STATUS => Packet::Status(NodeStatus::decode(body)?),
+            TAG_NEIGHBOURS => Packet::Neighbours(decode_neighbours(body)?),
+            TAG_RANGE_TEST_SUMMARY => Packet::RangeTestSummary(
+                Summary::decode(body).ok_or(DecodeError::InvalidLength(body.len() as u8))?,
+            ),
+            TAG_CONFIG => Packet::Config(decode_config(body)?),
+            TAG_SET_CONFIG => Packet::SetConfig(decode_config(body)?),
+            TAG_SET_MODE => match *body {
+                [mode] => Packet::SetMode(mode),
+                _ => return Err(DecodeError::InvalidLength(body.len() as u8)),
+            },
+            TAG_REBOOT if body.is_empty() => Packet::Reboot,
+            TAG_REBOOT => return Err(DecodeError::InvalidLength(body.len() as u8)),
+            TAG_DELIVERY => Packet::Delivery(decode_delivery(body)?),
+            tag => return Err(DecodeError::UnknownType(tag)),
+        })
+    }
+}
+
+/// Device configuration record, checked as the one in flash is.
+fn decode_config(buf: &[u8]) -> Result<DeviceConfig, DecodeError> {
+    DeviceConfig::decode(buf).map_err(|_| DecodeError::InvalidLength(buf.len() as u8))
+}
+
+/// Why a frame was dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FrameError {
+    /// Longer than `MAX_ENCODED_FRAME_SIZE`.
+    Oversized,
+    /// Not valid COBS, or shorter than a header and a CRC.
+    Malformed,
+    BadCrc,
+    UnknownKind(u8),
+    /// Intact, but carrying a packet that could not be decoded; it is acknowledged all the same.
+    InvalidPacket(DecodeError),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LinkConfig {
+    /// Milliseconds to wait for the acknowledgment of a data frame.
+    pub ack_timeout: u64,
+    /// Retransmissions attempted before giving up on a packet.
+    pub max_retries: u8,
+    /// Milliseconds to wait before sending a packet the peer had no room for again.
+    pub busy_backoff: u64,
+}
+
+impl Default for LinkConfig {
+    /// Suits a UART at 115200 baud, which carries the largest frame in 22 ms.
+    fn default() -> Self {
+        LinkConfig {
+            ack_timeout: 100,
+            max_retries: 3,
+            busy_backoff: 50,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LinkEvent {
+    Received(Packet),
+    /// The peer acknowledged the packet sent.
+    Delivered,
+    /// The packet sent went unacknowledged after `LinkConfig::max_retries` retransmissions.
+    Dropped(Packet),
+    /// A frame from the peer was dropped.
+    Invalid(FrameError),
+}
+
+struct InFlight {
+    packet: Packet,
+    sequence: u8,
+    /// Retransmissions so far.
+    retries: u8,
+    /// When it is sent next, or when its acknowledgment times out once sent.
+    due: u64,
+    /// Whether the acknowledgment is being waited for, rather than the time to send.
+    sent: bool,
+}
+
+/// One end of the link, fed with the bytes read from the peer and polled for the bytes to write.
+///
+/// Received packets wait among the events, of which there are at most `Q`: when they are not
+/// drained, new packets are rejected as busy.
+pub struct Link<const Q: usize> {
+    config: LinkConfig,
+    /// Encoded frame being read.
+    reader: Vec<u8, MAX_ENCODED_FRAME_SIZE>,
+    /// Whether the frame being read is dropped for being too long.
+    overflow: bool,
+    next_sequence: u8,
+    /// Whether the peer acknowledged a data frame since the link was created.
+    synced: bool,
+    in_flight: Option<InFlight>,
+    /// Sequence number of the last packet received, and whether it was flagged.
+    last_received: Option<(u8, bool)>,
+    /// Acknowledgments to send, ahead of any data frame.
+    replies: Deque<(u8, u8), 4>,
+    events: Deque<LinkEvent, Q>,
+}
+
+impl<const Q: usize> Link<Q> {
+    /// Creates a link whose first sequence number is picked with `seed`.
+    pub fn new(config: LinkConfig, seed: u32) -> Self {
+        Link {
+            config,
+            reader: Vec::new(),
+            overflow: false,
+            next_sequence: XorShift32::new(seed).next_u32() as u8,
+            synced: false,
+            in_flight: None,
+            last_received: None,
+            replies: Deque::new(),
+            events: Deque::new(),
+        }
+    }
+
+    pub fn config(&self) -> &LinkConfig {
+        &self.config
+    }
+
+    /// Whether `send` accepts a packet.
+    pub fn is_ready(&self) -> bool {
+        self.in_flight.is_none()
+    }
+
+    /// Sends `packet` from `now`, or gives it back while the previous one is in flight.
+    // Given back for the caller to keep it, which is as large as it gets.
+    #[allow(clippy::result_large_err)]
+    pub fn send(&mut self, packet: Packet, now: u64) -> Result<(), Packet> {
+        if !self.is_ready() {
+            return Err(packet);
+        }
+        self.in_flight = Some(InFlight {
+            packet,
+            sequence: self.next_sequence,
+            retries: 0,
+            due: now,
+            sent: false,
+        });
+        self.next_sequence = self.next_sequence.wrapping_add(1);
+        Ok(())
+    }
+
+    /// Handles bytes read from the peer at `now`.
+    pub fn on_bytes(&mut self, bytes: &[u8], now: u64) {
+        for &byte in bytes {
+            if byte != FRAME_DELIMITER {
+                if self.reader.push(byte).is_err() {
+                    self.overflow = true;
+                }
+                continue;
+            }
+            if self.overflow {
+                self.overflow = false;
+                self.reject(FrameError::Oversized);
+            } else if !self.reader.is_empty() {
+                // Moved out, for `on_frame` to borrow `self`.
+                let encoded = core::mem::take(&mut self.reader);
+                self.on_frame(&encoded, now);
+            }
+            self.reader.clear();
+        }
+    }
+
+    fn on_frame(&mut self, encoded: &[u8], now: u64) {
+        let mut frame = [0u8; MAX_FRAME_SIZE];
+        let Some(len) = cobs::decode(encoded, &mut frame) else {
+            return self.reject(FrameError::Malformed);
+        };
+        if len < HEADER_SIZE + CRC_SIZE {
+            return self.reject(FrameError::Malformed);
+        }
+        let (frame, crc) = frame[..len].split_at(len - CRC_SIZE);
+        if crc16(frame) != u16::from_le_bytes([crc[0], crc[1]]) {
+            return self.reject(FrameError::BadCrc);
+        }
+
+        let (kind, sequence) = (frame[0], frame[1]);
+        match kind & KIND_MASK {
+            KIND_DATA => self.on_data(kind & FLAG_SYNC != 0, sequence, &frame[HEADER_SIZE..]),
+            KIND_ACK => self.on_ack(sequence),
+            KIND_NAK_BUSY => self.on_busy(sequence, now),
+            KIND_NAK_CORRUPTED => {
+                // Whatever the peer could not read, what is in flight goes again.
+                if let Some(in_flight) = self.in_flight.as_mut().filter(|in_flight| in_flight.sent)
+                {
+                    in_flight.due = now;
+                    in_flight.sent = false;
+                    in_flight.retries = in_flight.retries.saturating_add(1);
+                }
+            }
+            kind => self.reject(FrameError::UnknownKind(kind)),
+        }
+    }
+
+    fn on_data(&mut self, sync: bool, sequence: u8, body: &[u8]) {
+        if self.last_received == Some((sequence, sync)) {
+            // The acknowledgment was lost, the packet was already received.
+            return self.reply(KIND_ACK, sequence);
+        }
+        let event = match Packet::decode(body) {
+            Ok(packet) => LinkEvent::Received(packet),
+            Err(err) => LinkEvent::Invalid(FrameError::InvalidPacket(err)),
+        };
+        if self.events.push_back(event).is_err() {
+            return self.reply(KIND_NAK_BUSY, sequence);
+        }
+        self.last_received = Some((sequence, sync));
+        self.reply(KIND_ACK, sequence);
+    }
+
+    fn on_ack(&mut self, sequence: u8) {
+        if self
+            .in_flight
+            .as_ref()
+            .is_some_and(|in_flight| in_flight.sequence == sequence)
+        {
+            self.in_flight = None;
+            self.synced = true;
+            self.push_event(LinkEvent::Delivered);
+        }
+    }
+
+    fn on_busy(&mut self, sequence: u8, now: u64) {
+        if let Some(in_flight) = self
+            .in_flight
+            .as_mut()
+            .filter(|in_flight| in_flight.sequence == sequence && in_flight.sent)
+        {
+            in_flight.due = now + self.config.busy_backoff;
+            in_flight.sent = false;
+        }
+    }
+
+    /// Drops the frame being read, and asks the peer to send again.
+    fn reject(&mut self, err: FrameError) {
+        self.push_event(LinkEvent::Invalid(err));
+        self.reply(KIND_NAK_CORRUPTED, 0);
+    }
+
+    fn reply(&mut self, kind: u8, sequence: u8) {
+        // When the replies pile up, the peer times out and retransmits instead.
+        let _ = self.replies.push_back((kind, sequence));
+    }
+
+    fn push_event(&mut self, event: LinkEvent) {
+        // Only lost if the caller does not drain the events after each call.
+        let _ = self.events.push_back(event);
+    }
+
+    /// When `poll_transmit` has something to send, `None` while idle.
+    pub fn next_deadline(&self) -> Option<u64> {
+        if !self.replies.is_empty() {
+            return Some(0);
+        }
+        self.in_flight.as_ref().map(|in_flight| in_flight.due)
+    }
+
+    /// Next frame to write to the peer by `now`, to be called until it returns `None`.
+    pub fn poll_transmit(&mut self, now: u64) -> Option<EncodedFrame> {
+        if let Some((kind, sequence)) = self.replies.pop_front() {
+            return Some(encode_frame(kind, sequence, &[]));
+        }
+
+        let in_flight = self
+            .in_flight
+            .as_mut()
+            .filter(|in_flight| in_flight.due <= now)?;
+        if in_flight.sent {
+            // The acknowledgment timed out.
+            in_flight.retries = in_flight.retries.saturating_add(1);
+        }
+        if in_flight.retries > self.config.max_retries {
+            let packet = self.in_flight.take()?.packet;
+            self.push_event(LinkEvent::Dropped(packet));
+            return None;
+        }
+        in_flight.due = now + self.config.ack_timeout;
+        in_flight.sent = true;
+
+        let mut body = [0u8; MAX_PACKET_SIZE];
+        let len = in_flight
+            .packet
+            .encode(&mut body)
+            .expect("buffer is sized for the largest packet");
+        let kind = if self.synced {
+            KIND_DATA
+        } else {
+            KIND_DATA | FLAG_SYNC
+        };
+        Some(encode_frame(kind, in_flight.sequence, &body[..len]))
+    }
+
+    /// Next event, to be called until it returns `None`.
+    pub fn poll_event(&mut self) -> Option<LinkEvent> {
+        self.events.pop_front()
+    }
+}
+
+fn encode_frame(kind: u8, sequence: u8, body: &[u8]) -> EncodedFrame {
+    let mut frame = [0u8; MAX_FRAME_SIZE];
+    let len = HEADER_SIZE + body.len();
+    frame[0] = kind;
+    frame[1] = sequence;
+    frame[HEADER_SIZE..len].copy_from_slice(body);
+    let crc = crc16(&frame[..len]);
+    frame[len..len + CRC_SIZE].copy_from_slice(&crc.to_le_bytes());
+
+    let mut encoded = EncodedFrame::new();
+    encoded
+        .resize_default(MAX_ENCODED_FRAME_SIZE)
+        .expect("capacity is the largest frame");
+    let encoded_len = cobs::encode(&frame[..len + CRC_SIZE], &mut encoded)
+        .expect("buffer is sized for the largest frame");
+    encoded.truncate(encoded_len);
+    // Cannot fail, the delimiter is accounted for.
+    let _ = encoded.push(FRAME_DELIMITER);
+    encoded
+}
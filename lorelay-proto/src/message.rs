@@ -0,0 +1,583 @@
+use crate::fragment::Frame;
+use heapless::Vec;
+
+/// Version of the on-air encoding, bumped whenever the header layout changes.
+pub const PROTOCOL_VERSION: u8 = 5;
+
+/// Destination used by messages that are not addressed to a single node.
+pub const BROADCAST_UID: u16 = 0xffff;
+
+/// Maximum payload carried by a `MessageType::Normal`.
+pub const MAX_PAYLOAD_SIZE: usize = 64;
+
+/// Number of transmissions a new `Normal` message is allowed, the originator's included.
+pub const DEFAULT_HOP_LIMIT: u8 = 4;
+
+/// version, type tag, sender uid, destination uid, body length
+pub const HEADER_SIZE: usize = 7;
+
+/// origin uid, sequence number, hop limit, next hop uid
+const ENVELOPE_SIZE: usize = 7;
+
+/// envelope, flags
+const NORMAL_OVERHEAD: usize = ENVELOPE_SIZE + 1;
+
+/// envelope, acknowledged sequence number
+const ACK_BODY_SIZE: usize = ENVELOPE_SIZE + 2;
+
+const FLAG_ACK_REQUESTED: u8 = 0x80;
+
+const FLAG_FRAGMENT: u8 = 0x40;
+
+/// Highest retransmission counter that fits in the flags byte.
+pub const MAX_ATTEMPT: u8 = 0x3f;
+
+/// Number of routes a `Ping` can carry.
+pub const MAX_ADVERTISED_ROUTES: usize = 12;
+
+/// destination uid, next hop uid, metric
+const ROUTE_ADVERT_SIZE: usize = 5;
+
+/// Size of the largest message body.
+pub const MAX_BODY_SIZE: usize = NORMAL_OVERHEAD + MAX_PAYLOAD_SIZE;
+
+/// Size of the largest encoded message.
+pub const MAX_MESSAGE_SIZE: usize = HEADER_SIZE + MAX_BODY_SIZE;
+
+const TAG_NORMAL: u8 = 0;
+const TAG_PING: u8 = 1;
+const TAG_PONG: u8 = 2;
+const TAG_ACK: u8 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum EncodeError {
+    BufferTooSmall,
+    /// The length of a `Normal` payload is above `MAX_PAYLOAD_SIZE`.
+    InvalidLength(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DecodeError {
+    Truncated,
+    UnsupportedVersion(u8),
+    UnknownType(u8),
+    InvalidLength(u8),
+    /// Fragment index out of range or fragment count above `fragment::MAX_FRAGMENTS`.
+    InvalidFragment,
+}
+
+/// One entry of the routing table of the node sending a `Ping`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RouteAdvert {
+    pub destination_uid: u16,
+    /// Neighbour the advertising node forwards through, so that it can be poisoned in return.
+    pub next_hop_uid: u16,
+    pub metric: u8,
+}
+
+pub type RouteAdverts = Vec<RouteAdvert, MAX_ADVERTISED_ROUTES>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MessageType {
+    /// Application data, relayed hop by hop until it reaches `destination_uid`.
+    Normal {
+        destination_uid: u16,
+        /// Node that created the message; the frame's sender is the last node to relay it.
+        origin_uid: u16,
+        /// Per-origin counter that identifies the message for duplicate suppression.
+        seq: u16,
+        /// Transmissions left, this one included.
+        hop_limit: u8,
+        /// Neighbour expected to forward the frame, `BROADCAST_UID` to flood it.
+        next_hop_uid: u16,
+        /// The destination must answer with an `Ack`.
+        ack_requested: bool,
+        /// Retransmission counter, 0 for the first transmission.
+        attempt: u8,
+        /// The data is a `fragment::Frame` rather than a whole application payload.
+        fragment: bool,
+        length: u8,
+        data: [u8; MAX_PAYLOAD_SIZE],
+    },
+    /// Discovery beacon, carrying part of the sender's routing table.
+    Ping { routes: RouteAdverts },
+    /// Answer to a `Ping`, advertising how many neighbours the responder currently has.
+    Pong {
+        destination_uid: u16,
+        neighbour_count: u8,
+    },
+    /// Confirms the reception of the `Normal` message `acked_seq` sent by `destination_uid`.
+    ///
+    /// Relayed back to the origin of the acknowledged message like a `Normal` message.
+    Ack {
+        destination_uid: u16,
+        origin_uid: u16,
+        seq: u16,
+        hop_limit: u8,
+        next_hop_uid: u16,
+        acked_seq: u16,
+    },
+}
+
+fn write_envelope(buf: &mut [u8], origin_uid: u16, seq: u16, hop_limit: u8, next_hop_uid: u16) {
+    buf[0..2].copy_from_slice(&origin_uid.to_le_bytes());
+    buf[2..4].copy_from_slice(&seq.to_le_bytes());
+    buf[4] = hop_limit;
+    buf[5..7].copy_from_slice(&next_hop_uid.to_le_bytes());
+}
+
+impl MessageType {
+    fn tag(&self) -> u8 {
+        match self {
+            MessageType::Normal { .. } => TAG_NORMAL,
+            MessageType::Ping { .. } => TAG_PING,
+            MessageType::Pong { .. } => TAG_PONG,
+            MessageType::Ack { .. } => TAG_ACK,
+        }
+    }
+
+    fn body_len(&self) -> usize {
+        match self {
+            MessageType::Normal { length, .. } => NORMAL_OVERHEAD + *length as usize,
+            MessageType::Ping { routes } => routes.len() * ROUTE_ADVERT_SIZE,
+            MessageType::Pong { .. } => 1,
+            MessageType::Ack { .. } => ACK_BODY_SIZE,
+        }
+    }
+
+    fn write_body(&self, buf: &mut [u8]) {
+        match self {
+            MessageType::Normal {
+                origin_uid,
+                seq,
+                hop_limit,
+                next_hop_uid,
+                ack_requested,
+                attempt,
+                fragment,
+                length,
+                data,
+                ..
+            } => {
+                write_envelope(buf, *origin_uid, *seq, *hop_limit, *next_hop_uid);
+                let mut flags = (*attempt).min(MAX_ATTEMPT);
+                if *ack_requested {
+                    flags |= FLAG_ACK_REQUESTED;
+                }
+                if *fragment {
+                    flags |= FLAG_FRAGMENT;
+                }
+                buf[ENVELOPE_SIZE] = flags;
+                buf[NORMAL_OVERHEAD..].copy_from_slice(&data[..*length as usize]);
+            }
+            MessageType::Ack {
+                origin_uid,
+                seq,
+                hop_limit,
+                next_hop_uid,
+                acked_seq,
+                ..
+            } => {
+                write_envelope(buf, *origin_uid, *seq, *hop_limit, *next_hop_uid);
+                buf[ENVELOPE_SIZE..ACK_BODY_SIZE].copy_from_slice(&acked_seq.to_le_bytes());
+            }
+            MessageType::Ping { routes } => {
+                for (route, chunk) in routes.iter().zip(buf.chunks_exact_mut(ROUTE_ADVERT_SIZE)) {
+                    chunk[0..2].copy_from_slice(&route.destination_uid.to_le_bytes());
+                    chunk[2..4].copy_from_slice(&route.next_hop_uid.to_le_bytes());
+                    chunk[4] = route.metric;
+                }
+            }
+            MessageType::Pong {
+                neighbour_count, ..
+            } => buf[0] = *neighbour_count,
+        }
+    }
+
+    fn read_body(tag: u8, destination_uid: u16, body: &[u8]) -> Result<Self, DecodeError> {
+        let length = body.len() as u8;
+        match tag {
+            TAG_NORMAL if body.len() >= NORMAL_OVERHEAD => {
+                let payload = &body[NORMAL_OVERHEAD..];
+                let mut data = [0; MAX_PAYLOAD_SIZE];
+                data[..payload.len()].copy_from_slice(payload);
+                Ok(MessageType::Normal {
+                    destination_uid,
+                    origin_uid: u16::from_le_bytes([body[0], body[1]]),
+                    seq: u16::from_le_bytes([body[2], body[3]]),
+                    hop_limit: body[4],
+                    next_hop_uid: u16::from_le_bytes([body[5], body[6]]),
+                    ack_requested: body[ENVELOPE_SIZE] & FLAG_ACK_REQUESTED != 0,
+                    attempt: body[ENVELOPE_SIZE] & MAX_ATTEMPT,
+                    fragment: body[ENVELOPE_SIZE] & FLAG_FRAGMENT != 0,
+                    length: payload.len() as u8,
+                    data,
+                })
+            }
+            TAG_ACK if body.len() == ACK_BODY_SIZE => Ok(MessageType::Ack {
+                destination_uid,
+                origin_uid: u16::from_le_bytes([body[0], body[1]]),
+                seq: u16::from_le_bytes([body[2], body[3]]),
+                hop_limit: body[4],
+                next_hop_uid: u16::from_le_bytes([body[5], body[6]]),
+                acked_seq: u16::from_le_bytes([body[7], body[8]]),
+            }),
+            TAG_PING
+                if body.len() % ROUTE_ADVERT_SIZE == 0
+                    && body.len() / ROUTE_ADVERT_SIZE <= MAX_ADVERTISED_ROUTES =>
+            {
+                let routes = body
+                    .chunks_exact(ROUTE_ADVERT_SIZE)
+                    .map(|chunk| RouteAdvert {
+                        destination_uid: u16::from_le_bytes([chunk[0], chunk[1]]),
+                        next_hop_uid: u16::from_le_bytes([chunk[2], chunk[3]]),
+                        metric: chunk[4],
+                    })
+                    .collect();
+                Ok(MessageType::Ping { routes })
+            }
+            TAG_PONG if body.len() == 1 => Ok(MessageType::Pong {
+                destination_uid,
+                neighbour_count: body[0],
+            }),
+            TAG_NORMAL | TAG_PING | TAG_PONG | TAG_ACK => Err(DecodeError::InvalidLength(length)),
+            tag => Err(DecodeError::UnknownType(tag)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    sender_uid: u16,
+    message_type: MessageType,
+}
+
+impl Message {
+    /// Message of `sender_uid` with a `message_type` built by hand rather than by a
+    /// `MessageBuilder`, which `encode` checks.
+    pub fn new(sender_uid: u16, message_type: MessageType) -> Self {
+        Message {
+            sender_uid,
+            message_type,
+        }
+    }
+
+    pub fn sender_uid(&self) -> u16 {
+        self.sender_uid
+    }
+
+    pub fn message_type(&self) -> &MessageType {
+        &self.message_type
+    }
+
+    pub fn destination_uid(&self) -> u16 {
+        match self.message_type {
+            MessageType::Normal {
+                destination_uid, ..
+            }
+            | MessageType::Pong {
+                destination_uid, ..
+            }
+            | MessageType::Ack {
+                destination_uid, ..
+            } => destination_uid,
+            MessageType::Ping { .. } => BROADCAST_UID,
+        }
+    }
+
+    /// Node that created the message, which differs from the sender once it has been relayed.
+    pub fn origin_uid(&self) -> u16 {
+        match self.message_type {
+            MessageType::Normal { origin_uid, .. } | MessageType::Ack { origin_uid, .. } => {
+                origin_uid
+            }
+            _ => self.sender_uid,
+        }
+    }
+
+    /// Sequence number of relayed messages, `None` for messages that only travel one hop.
+    pub fn seq(&self) -> Option<u16> {
+        match self.message_type {
+            MessageType::Normal { seq, .. } | MessageType::Ack { seq, .. } => Some(seq),
+            _ => None,
+        }
+    }
+
+    /// Whether the destination of this `Normal` message must acknowledge it.
+    pub fn ack_requested(&self) -> bool {
+        matches!(
+            self.message_type,
+            MessageType::Normal {
+                ack_requested: true,
+                ..
+            }
+        )
+    }
+
+    /// Whether the payload of this `Normal` message is a fragmentation frame.
+    pub fn is_fragment(&self) -> bool {
+        matches!(
+            self.message_type,
+            MessageType::Normal { fragment: true, .. }
+        )
+    }
+
+    /// Retransmission counter of a `Normal` message, 0 for other message types.
+    pub fn attempt(&self) -> u8 {
+        match self.message_type {
+            MessageType::Normal { attempt, .. } => attempt,
+            _ => 0,
+        }
+    }
+
+    /// Application data carried by the message, empty for control messages.
+    pub fn payload(&self) -> &[u8] {
+        match &self.message_type {
+            MessageType::Normal { length, data, .. } => {
+                &data[..usize::from(*length).min(MAX_PAYLOAD_SIZE)]
+            }
+            MessageType::Ping { .. } | MessageType::Pong { .. } | MessageType::Ack { .. } => &[],
+        }
+    }
+
+    /// Neighbour expected to forward a relayed message, `BROADCAST_UID` when flooding.
+    pub fn next_hop_uid(&self) -> u16 {
+        match self.message_type {
+            MessageType::Normal { next_hop_uid, .. } | MessageType::Ack { next_hop_uid, .. } => {
+                next_hop_uid
+            }
+            _ => BROADCAST_UID,
+        }
+    }
+
+    /// Addresses a relayed message to `next_hop_uid`; other message types are left as is.
+    pub fn with_next_hop(mut self, next_hop_uid: u16) -> Message {
+        if let MessageType::Normal {
+            next_hop_uid: next_hop,
+            ..
+        }
+        | MessageType::Ack {
+            next_hop_uid: next_hop,
+            ..
+        } = &mut self.message_type
+        {
+            *next_hop = next_hop_uid;
+        }
+        self
+    }
+
+    /// Asks the destination of a `Normal` message to acknowledge it.
+    pub fn with_ack_requested(mut self) -> Message {
+        if let MessageType::Normal { ack_requested, .. } = &mut self.message_type {
+            *ack_requested = true;
+        }
+        self
+    }
+
+    /// Copy of a `Normal` message to send again after its acknowledgment timed out.
+    pub fn retransmission(&self) -> Message {
+        let mut message = self.clone();
+        if let MessageType::Normal { attempt, .. } = &mut message.message_type {
+            *attempt = attempt.saturating_add(1).min(MAX_ATTEMPT);
+        }
+        message
+    }
+
+    /// Copy of a relayed message as retransmitted by `relay_uid` towards `next_hop_uid`, with one
+    /// hop less.
+    ///
+    /// Returns `None` for other message types and for messages that used up their hops.
+    pub fn relayed_by(&self, relay_uid: u16, next_hop_uid: u16) -> Option<Message> {
+        let mut message_type = self.message_type.clone();
+        match &mut message_type {
+            MessageType::Normal { hop_limit, .. } | MessageType::Ack { hop_limit, .. }
+                if *hop_limit > 1 =>
+            {
+                *hop_limit -= 1
+            }
+            _ => return None,
+        }
+        let relayed = Message {
+            sender_uid: relay_uid,
+            message_type,
+        };
+        Some(relayed.with_next_hop(next_hop_uid))
+    }
+
+    /// Number of bytes `encode` will write for this message.
+    pub fn encoded_len(&self) -> usize {
+        HEADER_SIZE + self.message_type.body_len()
+    }
+
+    /// Writes the message into `buf` and returns the number of bytes used.
+    ///
+    /// Multi-byte fields are little-endian.
+    pub fn encode(&self, buf: &mut [u8]) -> Result<usize, EncodeError> {
+        if let MessageType::Normal { length, .. } = self.message_type {
+            if usize::from(length) > MAX_PAYLOAD_SIZE {
+                return Err(EncodeError::InvalidLength(length));
+            }
+        }
+        let len = self.encoded_len();
+        if buf.len() < len {
+            return Err(EncodeError::BufferTooSmall);
+        }
+
+        buf[0] = PROTOCOL_VERSION;
+        buf[1] = self.message_type.tag();
+        buf[2..4].copy_from_slice(&self.sender_uid.to_le_bytes());
+        buf[4..6].copy_from_slice(&self.destination_uid().to_le_bytes());
+        buf[6] = (len - HEADER_SIZE) as u8;
+        self.message_type.write_body(&mut buf[HEADER_SIZE..len]);
+
+        Ok(len)
+    }
+
+    /// Parses a message from `buf`, ignoring any trailing bytes after the body.
+    pub fn decode(buf: &[u8]) -> Result<Message, DecodeError> {
+        if buf.len() < HEADER_SIZE {
+            return Err(DecodeError::Truncated);
+        }
+        if buf[0] != PROTOCOL_VERSION {
+            return Err(DecodeError::UnsupportedVersion(buf[0]));
+        }
+        let sender_uid = u16::from_le_bytes([buf[2], buf[3]]);
+        let destination_uid = u16::from_le_bytes([buf[4], buf[5]]);
+        let length = buf[6];
+        if length as usize > MAX_BODY_SIZE {
+            return Err(DecodeError::InvalidLength(length));
+        }
+        let body = buf
+            .get(HEADER_SIZE..HEADER_SIZE + length as usize)
+            .ok_or(DecodeError::Truncated)?;
+        let message_type = MessageType::read_body(buf[1], destination_uid, body)?;
+
+        Ok(Message {
+            sender_uid,
+            message_type,
+        })
+    }
+}
+
+pub struct MessageBuilder {
+    sender_uid: u16,
+    next_seq: u16,
+    hop_limit: u8,
+}
+
+impl MessageBuilder {
+    pub fn new(sender_uid: u16) -> Self {
+        Self::with_seq(sender_uid, 0)
+    }
+
+    /// Numbers the messages from `first_seq` on.
+    ///
+    /// A node restarting from a random number keeps clear of the numbers its neighbours still
+    /// remember from before the restart, and would take for duplicates.
+    pub fn with_seq(sender_uid: u16, first_seq: u16) -> Self {
+        MessageBuilder {
+            sender_uid,
+            next_seq: first_seq,
+            hop_limit: DEFAULT_HOP_LIMIT,
+        }
+    }
+
+    /// Sets the hop limit given to subsequent `Normal` messages.
+    pub fn with_hop_limit(mut self, hop_limit: u8) -> Self {
+        self.hop_limit = hop_limit;
+        self
+    }
+
+    pub fn ping(&self) -> Message {
+        self.ping_with_routes(RouteAdverts::new())
+    }
+
+    pub fn ping_with_routes(&self, routes: RouteAdverts) -> Message {
+        Message {
+            sender_uid: self.sender_uid,
+            message_type: MessageType::Ping { routes },
+        }
+    }
+
+    pub fn pong(&self, destination_uid: u16, neighbour_count: u8) -> Message {
+        Message {
+            sender_uid: self.sender_uid,
+            message_type: MessageType::Pong {
+                destination_uid,
+                neighbour_count,
+            },
+        }
+    }
+
+    /// Builds a flooded `Normal` message with the next sequence number, or returns `None` if
+    /// `payload` does not fit.
+    pub fn normal(&mut self, destination_uid: u16, payload: &[u8]) -> Option<Message> {
+        if payload.len() > MAX_PAYLOAD_SIZE {
+            return None;
+        }
+        let mut data = [0; MAX_PAYLOAD_SIZE];
+        data[..payload.len()].copy_from_slice(payload);
+        let seq = self.next_seq();
+        Some(Message {
+            sender_uid: self.sender_uid,
+            message_type: MessageType::Normal {
+                destination_uid,
+                origin_uid: self.sender_uid,
+                seq,
+                hop_limit: self.hop_limit,
+                next_hop_uid: BROADCAST_UID,
+                ack_requested: false,
+                attempt: 0,
+                fragment: false,
+                length: payload.len() as u8,
+                data,
+            },
+        })
+    }
+
+    /// Builds a `Normal` message carrying a fragmentation frame, with the next sequence number.
+    ///
+    /// Returns `None` if the frame does not fit, which cannot happen for frames built by
+    /// `fragment::Transfer`.
+    pub fn fragment(&mut self, destination_uid: u16, frame: &Frame) -> Option<Message> {
+        let mut payload = [0; MAX_PAYLOAD_SIZE];
+        let len = frame.encode(&mut payload).ok()?;
+        let mut message = self.normal(destination_uid, &payload[..len])?;
+        if let MessageType::Normal { fragment, .. } = &mut message.message_type {
+            *fragment = true;
+        }
+        Some(message)
+    }
+
+    /// Builds the `Ack` of a received `Normal` message, or returns `None` for other messages.
+    pub fn ack(&mut self, message: &Message) -> Option<Message> {
+        let MessageType::Normal {
+            origin_uid,
+            seq: acked_seq,
+            ..
+        } = *message.message_type()
+        else {
+            return None;
+        };
+        Some(Message {
+            sender_uid: self.sender_uid,
+            message_type: MessageType::Ack {
+                destination_uid: origin_uid,
+                origin_uid: self.sender_uid,
+                seq: self.next_seq(),
+                hop_limit: self.hop_limit,
+                next_hop_uid: BROADCAST_UID,
+                acked_seq,
+            },
+        })
+    }
+
+    fn next_seq(&mut self) -> u16 {
+        let seq = self.next_seq;
+        self.next_seq = self.next_seq.wrapping_add(1);
+        seq
+    }
+}
@@ -0,0 +1,136 @@
+use heapless::Vec;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Neighbour {
+    pub uid: u16,
+    pub rssi: i16,
+    pub snr: i16,
+    /// Milliseconds since boot at which the neighbour was last heard.
+    pub last_seen: u64,
+}
+
+impl Neighbour {
+    pub fn is_alive(&self, now: u64, timeout: u64) -> bool {
+        now.saturating_sub(self.last_seen) < timeout
+    }
+}
+
+/// Outcome of `NeighbourTable::upsert`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Upsert {
+    /// The neighbour was already known and has been refreshed.
+    Updated,
+    /// The neighbour was added to a table that had room for it.
+    Inserted,
+    /// The neighbour took the place of the weakest entry of a full table.
+    Replaced(Neighbour),
+    /// The table is full of stronger, live neighbours.
+    Rejected,
+}
+
+/// Fixed capacity set of the nodes heard directly, keyed by uid.
+pub struct NeighbourTable<const N: usize> {
+    neighbours: Vec<Neighbour, N>,
+    timeout: u64,
+}
+
+impl<const N: usize> NeighbourTable<N> {
+    /// Creates an empty table whose entries expire `timeout` milliseconds after their last frame.
+    pub const fn new(timeout: u64) -> Self {
+        NeighbourTable {
+            neighbours: Vec::new(),
+            timeout,
+        }
+    }
+
+    pub fn timeout(&self) -> u64 {
+        self.timeout
+    }
+
+    /// Records a frame received from `uid`.
+    ///
+    /// When the table is full, expired entries are dropped first, then the entry with the
+    /// lowest RSSI is replaced if the new neighbour is heard more strongly.
+    pub fn upsert(&mut self, uid: u16, rssi: i16, snr: i16, now: u64) -> Upsert {
+        let neighbour = Neighbour {
+            uid,
+            rssi,
+            snr,
+            last_seen: now,
+        };
+
+        if let Some(known) = self.neighbours.iter_mut().find(|n| n.uid == uid) {
+            *known = neighbour;
+            return Upsert::Updated;
+        }
+
+        if self.neighbours.is_full() {
+            self.evict_expired(now);
+        }
+        if self.neighbours.push(neighbour).is_ok() {
+            return Upsert::Inserted;
+        }
+
+        let weakest = self
+            .neighbours
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, n)| n.rssi)
+            .map(|(i, _)| i);
+        match weakest {
+            Some(i) if self.neighbours[i].rssi < rssi => {
+                let evicted = core::mem::replace(&mut self.neighbours[i], neighbour);
+                Upsert::Replaced(evicted)
+            }
+            _ => Upsert::Rejected,
+        }
+    }
+
+    /// Drops every neighbour not heard within the timeout and returns how many were removed.
+    pub fn evict_expired(&mut self, now: u64) -> usize {
+        let before = self.neighbours.len();
+        let timeout = self.timeout;
+        self.neighbours.retain(|n| n.is_alive(now, timeout));
+        before - self.neighbours.len()
+    }
+
+    pub fn remove(&mut self, uid: u16) -> Option<Neighbour> {
+        let i = self.neighbours.iter().position(|n| n.uid == uid)?;
+        Some(self.neighbours.swap_remove(i))
+    }
+
+    pub fn get(&self, uid: u16) -> Option<&Neighbour> {
+        self.neighbours.iter().find(|n| n.uid == uid)
+    }
+
+    /// Strongest neighbour in the table, expired or not.
+    pub fn best_by_rssi(&self) -> Option<&Neighbour> {
+        self.neighbours.iter().max_by_key(|n| n.rssi)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Neighbour> {
+        self.neighbours.iter()
+    }
+
+    /// Neighbours heard within the timeout.
+    pub fn iter_alive(&self, now: u64) -> impl Iterator<Item = &Neighbour> {
+        let timeout = self.timeout;
+        self.neighbours
+            .iter()
+            .filter(move |n| n.is_alive(now, timeout))
+    }
+
+    pub fn len(&self) -> usize {
+        self.neighbours.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.neighbours.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        N
+    }
+}
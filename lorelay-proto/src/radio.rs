@@ -0,0 +1,290 @@
+//! LoRa modulation and packet parameters, independent of the radio driver.
+//!
+//! The ranges checked by `RadioConfig::validate` are the ones of the SX126x family, which covers
+//! the sub-GHz radio of the STM32WL.
+//!
+//! `Radio` is what the relay needs from a transceiver, so that it runs the same against the
+//! SX126x driver and against the simulated radios of `sim`.
+use crate::message::{DecodeError, Message};
+
+/// Sync word of private networks, such as lorelay's own.
+pub const PRIVATE_SYNC_WORD: u8 = 0x12;
+
+/// Sync word of public networks such as LoRaWAN.
+pub const PUBLIC_SYNC_WORD: u8 = 0x34;
+
+pub const MIN_FREQUENCY_HZ: u32 = 150_000_000;
+pub const MAX_FREQUENCY_HZ: u32 = 960_000_000;
+
+/// Output power range of the high power amplifier, in dBm.
+pub const MIN_OUTPUT_POWER: i8 = -9;
+pub const MAX_OUTPUT_POWER: i8 = 22;
+
+/// Largest frame a LoRa radio sends.
+pub const MAX_FRAME_SIZE: usize = 255;
+
+/// Lowest frequency the 250 and 500 kHz bandwidths are supported at.
+pub const MIN_WIDE_BANDWIDTH_FREQUENCY_HZ: u32 = 400_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SpreadingFactor {
+    Sf5,
+    Sf6,
+    Sf7,
+    Sf8,
+    Sf9,
+    Sf10,
+    Sf11,
+    Sf12,
+}
+
+impl SpreadingFactor {
+    pub const ALL: [SpreadingFactor; 8] = [
+        SpreadingFactor::Sf5,
+        SpreadingFactor::Sf6,
+        SpreadingFactor::Sf7,
+        SpreadingFactor::Sf8,
+        SpreadingFactor::Sf9,
+        SpreadingFactor::Sf10,
+        SpreadingFactor::Sf11,
+        SpreadingFactor::Sf12,
+    ];
+
+    /// Number of bits per symbol, 5 to 12.
+    pub fn value(self) -> u8 {
+        self as u8 + 5
+    }
+
+    pub fn from_value(value: u8) -> Option<Self> {
+        Self::ALL.get(value.checked_sub(5)? as usize).copied()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Bandwidth {
+    Khz7_8,
+    Khz10_4,
+    Khz15_6,
+    Khz20_8,
+    Khz31_25,
+    Khz41_7,
+    Khz62_5,
+    Khz125,
+    Khz250,
+    Khz500,
+}
+
+impl Bandwidth {
+    pub const ALL: [Bandwidth; 10] = [
+        Bandwidth::Khz7_8,
+        Bandwidth::Khz10_4,
+        Bandwidth::Khz15_6,
+        Bandwidth::Khz20_8,
+        Bandwidth::Khz31_25,
+        Bandwidth::Khz41_7,
+        Bandwidth::Khz62_5,
+        Bandwidth::Khz125,
+        Bandwidth::Khz250,
+        Bandwidth::Khz500,
+    ];
+
+    pub fn hz(self) -> u32 {
+        match self {
+            Bandwidth::Khz7_8 => 7_810,
+            Bandwidth::Khz10_4 => 10_420,
+            Bandwidth::Khz15_6 => 15_630,
+            Bandwidth::Khz20_8 => 20_830,
+            Bandwidth::Khz31_25 => 31_250,
+            Bandwidth::Khz41_7 => 41_670,
+            Bandwidth::Khz62_5 => 62_500,
+            Bandwidth::Khz125 => 125_000,
+            Bandwidth::Khz250 => 250_000,
+            Bandwidth::Khz500 => 500_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CodingRate {
+    Cr4_5,
+    Cr4_6,
+    Cr4_7,
+    Cr4_8,
+}
+
+impl CodingRate {
+    pub const ALL: [CodingRate; 4] = [
+        CodingRate::Cr4_5,
+        CodingRate::Cr4_6,
+        CodingRate::Cr4_7,
+        CodingRate::Cr4_8,
+    ];
+
+    /// `n` of the 4/n rate, 5 to 8.
+    pub fn denominator(self) -> u8 {
+        self as u8 + 5
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ConfigError {
+    FrequencyOutOfRange(u32),
+    OutputPowerOutOfRange(i8),
+    /// The bandwidth is too wide for the frequency, see `MIN_WIDE_BANDWIDTH_FREQUENCY_HZ`.
+    BandwidthTooWide(Bandwidth),
+    /// The preamble is shorter than the minimum for the spreading factor.
+    PreambleTooShort(u16),
+    /// Only the private and public sync words are supported by the radio driver.
+    UnsupportedSyncWord(u8),
+}
+
+/// Everything two nodes must agree on to hear each other, plus the transmit power.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RadioConfig {
+    pub frequency_hz: u32,
+    /// Transmit power in dBm.
+    pub output_power: i8,
+    pub spreading_factor: SpreadingFactor,
+    pub bandwidth: Bandwidth,
+    pub coding_rate: CodingRate,
+    /// Preamble length in symbols.
+    pub preamble_length: u16,
+    /// `PRIVATE_SYNC_WORD` or `PUBLIC_SYNC_WORD`.
+    pub sync_word: u8,
+    pub crc: bool,
+    /// Leaves the header out of the frame, so both ends must assume the same payload length.
+    pub implicit_header: bool,
+}
+
+impl Default for RadioConfig {
+    /// The parameters lorelay has been deployed with so far, at 433.22 MHz, at the power allowed
+    /// by the EU433 plan.
+    fn default() -> Self {
+        RadioConfig {
+            frequency_hz: 433_220_000,
+            output_power: 12,
+            spreading_factor: SpreadingFactor::Sf10,
+            bandwidth: Bandwidth::Khz250,
+            coding_rate: CodingRate::Cr4_8,
+            preamble_length: 4,
+            sync_word: PRIVATE_SYNC_WORD,
+            crc: true,
+            implicit_header: false,
+        }
+    }
+}
+
+impl RadioConfig {
+    /// Shortest preamble the radio can lock onto at the configured spreading factor.
+    pub fn min_preamble_length(&self) -> u16 {
+        match self.spreading_factor {
+            SpreadingFactor::Sf5 | SpreadingFactor::Sf6 => 12,
+            _ => 1,
+        }
+    }
+
+    /// Whether the sync word is the one of public networks.
+    pub fn is_public_network(&self) -> bool {
+        self.sync_word == PUBLIC_SYNC_WORD
+    }
+
+    /// Checks that the radio can be set up with this configuration.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if !(MIN_FREQUENCY_HZ..=MAX_FREQUENCY_HZ).contains(&self.frequency_hz) {
+            return Err(ConfigError::FrequencyOutOfRange(self.frequency_hz));
+        }
+        if !(MIN_OUTPUT_POWER..=MAX_OUTPUT_POWER).contains(&self.output_power) {
+            return Err(ConfigError::OutputPowerOutOfRange(self.output_power));
+        }
+        if self.bandwidth >= Bandwidth::Khz250
+            && self.frequency_hz < MIN_WIDE_BANDWIDTH_FREQUENCY_HZ
+        {
+            return Err(ConfigError::BandwidthTooWide(self.bandwidth));
+        }
+        if self.preamble_length < self.min_preamble_length() {
+            return Err(ConfigError::PreambleTooShort(self.preamble_length));
+        }
+        if self.sync_word != PRIVATE_SYNC_WORD && self.sync_word != PUBLIC_SYNC_WORD {
+            return Err(ConfigError::UnsupportedSyncWord(self.sync_word));
+        }
+        Ok(())
+    }
+}
+
+/// Link quality a frame was received at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PacketStatus {
+    /// Received signal strength in dBm.
+    pub rssi: i16,
+    /// Signal to noise ratio in dB.
+    pub snr: i16,
+}
+
+/// Frame received by a radio, with the link quality it was heard at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReceivedFrame {
+    buffer: [u8; MAX_FRAME_SIZE],
+    len: usize,
+    pub status: PacketStatus,
+    /// Milliseconds since boot at which the reception completed.
+    pub received_at: u64,
+}
+
+impl ReceivedFrame {
+    /// Copies `payload`, truncated to `MAX_FRAME_SIZE`.
+    pub fn new(payload: &[u8], status: PacketStatus, received_at: u64) -> Self {
+        let len = payload.len().min(MAX_FRAME_SIZE);
+        let mut buffer = [0; MAX_FRAME_SIZE];
+        buffer[..len].copy_from_slice(&payload[..len]);
+        ReceivedFrame {
+            buffer,
+            len,
+            status,
+            received_at,
+        }
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.buffer[..self.len]
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn decode(&self) -> Result<Message, DecodeError> {
+        Message::decode(self.payload())
+    }
+}
+
+/// Half-duplex LoRa transceiver.
+pub trait Radio {
+    type Error;
+
+    /// Configuration in effect.
+    fn config(&self) -> RadioConfig;
+
+    /// Transmits `frame`, returning once it is on air.
+    async fn send(&mut self, frame: &[u8]) -> Result<(), Self::Error>;
+
+    /// Waits for the next frame.
+    async fn receive(&mut self) -> Result<ReceivedFrame, Self::Error>;
+
+    /// Applies `config` from the next transmission or reception on.
+    ///
+    /// On error the previous configuration stays in effect.
+    async fn configure(&mut self, config: RadioConfig) -> Result<(), Self::Error>;
+
+    /// Runs a channel activity detection, returning whether a LoRa preamble was heard.
+    async fn cad(&mut self) -> Result<bool, Self::Error>;
+}
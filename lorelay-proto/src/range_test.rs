@@ -0,0 +1,320 @@
+//! Range test probes, and the statistics a receiver keeps about the probes of each sender.
+//!
+//! A probe carries its sequence number and the radio settings it was sent with, so that a
+//! receiver can tell how far and at what power a link holds. `RangeStats` turns the probes heard
+//! from one sender into gaps, signal statistics and a packet error rate over the last `W` probes.
+use crate::message::EncodeError;
+use crate::radio::{Bandwidth, CodingRate, PacketStatus, RadioConfig, SpreadingFactor};
+use heapless::Deque;
+
+/// Start of the payload of every probe.
+pub const PROBE_MAGIC: [u8; 2] = *b"rt";
+
+/// sequence, frequency, output power, spreading factor, bandwidth, coding rate
+const PROBE_FIELDS_SIZE: usize = 12;
+
+pub const PROBE_SIZE: usize = PROBE_MAGIC.len() + PROBE_FIELDS_SIZE;
+
+/// origin, probe fields, received, lost, duplicates, packet error rate, RSSI and SNR
+pub const SUMMARY_SIZE: usize = 2 + PROBE_FIELDS_SIZE + 12 + 2 + 2 * SIGNAL_SIZE;
+
+/// last, min, max, mean
+const SIGNAL_SIZE: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Probe {
+    /// Counts up from 0 with every probe sent.
+    pub sequence: u32,
+    pub frequency_hz: u32,
+    /// Transmit power in dBm.
+    pub output_power: i8,
+    pub spreading_factor: SpreadingFactor,
+    pub bandwidth: Bandwidth,
+    pub coding_rate: CodingRate,
+}
+
+impl Probe {
+    /// Probe number `sequence` sent with `config`.
+    pub fn new(sequence: u32, config: &RadioConfig) -> Self {
+        Probe {
+            sequence,
+            frequency_hz: config.frequency_hz,
+            output_power: config.output_power,
+            spreading_factor: config.spreading_factor,
+            bandwidth: config.bandwidth,
+            coding_rate: config.coding_rate,
+        }
+    }
+
+    pub fn encode(&self, buf: &mut [u8]) -> Result<usize, EncodeError> {
+        let buf = buf
+            .get_mut(..PROBE_SIZE)
+            .ok_or(EncodeError::BufferTooSmall)?;
+        buf[..PROBE_MAGIC.len()].copy_from_slice(&PROBE_MAGIC);
+        self.encode_fields(&mut buf[PROBE_MAGIC.len()..]);
+        Ok(PROBE_SIZE)
+    }
+
+    /// Decodes the payload of a probe, `None` for any other payload.
+    pub fn decode(payload: &[u8]) -> Option<Self> {
+        let fields = payload.strip_prefix(&PROBE_MAGIC)?;
+        if fields.len() != PROBE_FIELDS_SIZE {
+            return None;
+        }
+        Self::decode_fields(fields)
+    }
+
+    fn encode_fields(&self, buf: &mut [u8]) {
+        buf[0..4].copy_from_slice(&self.sequence.to_le_bytes());
+        buf[4..8].copy_from_slice(&self.frequency_hz.to_le_bytes());
+        buf[8] = self.output_power as u8;
+        buf[9] = self.spreading_factor.value();
+        buf[10] = self.bandwidth as u8;
+        buf[11] = self.coding_rate.denominator();
+    }
+
+    fn decode_fields(buf: &[u8]) -> Option<Self> {
+        Some(Probe {
+            sequence: u32::from_le_bytes(buf[0..4].try_into().ok()?),
+            frequency_hz: u32::from_le_bytes(buf[4..8].try_into().ok()?),
+            output_power: buf[8] as i8,
+            spreading_factor: SpreadingFactor::from_value(buf[9])?,
+            bandwidth: *Bandwidth::ALL.get(usize::from(buf[10]))?,
+            coding_rate: *CodingRate::ALL.get(usize::from(buf[11].checked_sub(5)?))?,
+        })
+    }
+}
+
+/// How a probe relates to the ones heard before it from the same sender.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Reception {
+    /// The probe follows the last one heard, after `missed` lost probes.
+    Received { missed: u32 },
+    /// The last probe heard, again.
+    Duplicate,
+    /// The sequence went backwards: the sender started a new range test, and the statistics
+    /// started over.
+    Restarted,
+}
+
+/// Signal quality over the probes heard, in dBm for the RSSI and dB for the SNR.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SignalStats {
+    pub last: i16,
+    pub min: i16,
+    pub max: i16,
+    pub mean: i16,
+}
+
+impl SignalStats {
+    fn encode(&self, buf: &mut [u8]) {
+        for (field, value) in buf
+            .chunks_exact_mut(2)
+            .zip([self.last, self.min, self.max, self.mean])
+        {
+            field.copy_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    fn decode(buf: &[u8]) -> Self {
+        let field = |i: usize| i16::from_le_bytes([buf[2 * i], buf[2 * i + 1]]);
+        SignalStats {
+            last: field(0),
+            min: field(1),
+            max: field(2),
+            mean: field(3),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct SignalAccumulator {
+    last: i16,
+    min: i16,
+    max: i16,
+    sum: i64,
+}
+
+impl SignalAccumulator {
+    fn add(&mut self, value: i16, first: bool) {
+        if first {
+            *self = SignalAccumulator {
+                last: value,
+                min: value,
+                max: value,
+                sum: 0,
+            };
+        }
+        self.last = value;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.sum += i64::from(value);
+    }
+
+    fn stats(&self, count: u32) -> SignalStats {
+        SignalStats {
+            last: self.last,
+            min: self.min,
+            max: self.max,
+            mean: (self.sum / i64::from(count.max(1))) as i16,
+        }
+    }
+}
+
+/// Everything known about the probes of one sender.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Summary {
+    pub origin_uid: u16,
+    /// Last probe heard.
+    pub probe: Probe,
+    pub received: u32,
+    /// Probes never heard between the first and the last one heard.
+    pub lost: u32,
+    pub duplicates: u32,
+    /// Share of lost probes over the sliding window.
+    pub packet_error_rate_permille: u16,
+    pub rssi: SignalStats,
+    pub snr: SignalStats,
+}
+
+impl Summary {
+    /// Fixed size little endian encoding, as exposed over BLE.
+    pub fn encode(&self, buf: &mut [u8]) -> Result<usize, EncodeError> {
+        let buf = buf
+            .get_mut(..SUMMARY_SIZE)
+            .ok_or(EncodeError::BufferTooSmall)?;
+        buf[0..2].copy_from_slice(&self.origin_uid.to_le_bytes());
+        self.probe.encode_fields(&mut buf[2..14]);
+        buf[14..18].copy_from_slice(&self.received.to_le_bytes());
+        buf[18..22].copy_from_slice(&self.lost.to_le_bytes());
+        buf[22..26].copy_from_slice(&self.duplicates.to_le_bytes());
+        buf[26..28].copy_from_slice(&self.packet_error_rate_permille.to_le_bytes());
+        self.rssi.encode(&mut buf[28..36]);
+        self.snr.encode(&mut buf[36..44]);
+        Ok(SUMMARY_SIZE)
+    }
+
+    pub fn decode(buf: &[u8]) -> Option<Self> {
+        if buf.len() != SUMMARY_SIZE {
+            return None;
+        }
+        let word = |at: usize| u32::from_le_bytes([buf[at], buf[at + 1], buf[at + 2], buf[at + 3]]);
+        Some(Summary {
+            origin_uid: u16::from_le_bytes([buf[0], buf[1]]),
+            probe: Probe::decode_fields(&buf[2..14])?,
+            received: word(14),
+            lost: word(18),
+            duplicates: word(22),
+            packet_error_rate_permille: u16::from_le_bytes([buf[26], buf[27]]),
+            rssi: SignalStats::decode(&buf[28..36]),
+            snr: SignalStats::decode(&buf[36..44]),
+        })
+    }
+}
+
+/// Statistics about the probes heard from one sender, the packet error rate being computed over
+/// the last `W` probes sent.
+pub struct RangeStats<const W: usize> {
+    origin_uid: u16,
+    last_probe: Option<Probe>,
+    received: u32,
+    lost: u32,
+    duplicates: u32,
+    /// Whether each of the last `W` probes sent was heard, oldest first.
+    window: Deque<bool, W>,
+    rssi: SignalAccumulator,
+    snr: SignalAccumulator,
+}
+
+impl<const W: usize> RangeStats<W> {
+    pub fn new(origin_uid: u16) -> Self {
+        RangeStats {
+            origin_uid,
+            last_probe: None,
+            received: 0,
+            lost: 0,
+            duplicates: 0,
+            window: Deque::new(),
+            rssi: SignalAccumulator::default(),
+            snr: SignalAccumulator::default(),
+        }
+    }
+
+    pub fn origin_uid(&self) -> u16 {
+        self.origin_uid
+    }
+
+    /// Probes heard, duplicates aside.
+    pub fn received(&self) -> u32 {
+        self.received
+    }
+
+    /// Records `probe`, heard at `status`.
+    ///
+    /// Duplicates are counted but leave the rest of the statistics alone.
+    pub fn on_probe(&mut self, probe: &Probe, status: PacketStatus) -> Reception {
+        let reception = match self.last_probe {
+            None => Reception::Received { missed: 0 },
+            Some(last) if probe.sequence == last.sequence => {
+                self.duplicates += 1;
+                return Reception::Duplicate;
+            }
+            Some(last) if probe.sequence < last.sequence => {
+                *self = Self::new(self.origin_uid);
+                Reception::Restarted
+            }
+            Some(last) => Reception::Received {
+                missed: probe.sequence - last.sequence - 1,
+            },
+        };
+        if let Reception::Received { missed } = reception {
+            self.lost += missed;
+            // Older losses would be pushed out of the window by the ones after them anyway.
+            for _ in 0..(missed as usize).min(W) {
+                self.record(false);
+            }
+        }
+        self.record(true);
+        let first = self.received == 0;
+        self.received += 1;
+        self.rssi.add(status.rssi, first);
+        self.snr.add(status.snr, first);
+        self.last_probe = Some(*probe);
+        reception
+    }
+
+    fn record(&mut self, heard: bool) {
+        if self.window.is_full() {
+            self.window.pop_front();
+        }
+        // Cannot fail, there is room left.
+        let _ = self.window.push_back(heard);
+    }
+
+    /// Share of the probes of the window that were lost, `None` before the first probe.
+    pub fn packet_error_rate_permille(&self) -> Option<u16> {
+        if self.window.is_empty() {
+            return None;
+        }
+        let lost = self.window.iter().filter(|heard| !**heard).count();
+        Some((lost * 1_000 / self.window.len()) as u16)
+    }
+
+    /// `None` until a probe is heard.
+    pub fn summary(&self) -> Option<Summary> {
+        Some(Summary {
+            origin_uid: self.origin_uid,
+            probe: self.last_probe?,
+            received: self.received,
+            lost: self.lost,
+            duplicates: self.duplicates,
+            packet_error_rate_permille: self.packet_error_rate_permille()?,
+            rssi: self.rssi.stats(self.received),
+            snr: self.snr.stats(self.received),
+        })
+    }
+}
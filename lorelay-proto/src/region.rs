@@ -0,0 +1,217 @@
+//! Regional frequency plans: where a node may transmit, how loud and how often.
+//!
+//! The limits follow the LoRaWAN regional parameters, which apply the local regulations (ETSI EN
+//! 300 220 in Europe, FCC part 15 in the US) to LoRa modulation.
+use crate::radio::{
+    Bandwidth, CodingRate, RadioConfig, SpreadingFactor, MAX_OUTPUT_POWER, PRIVATE_SYNC_WORD,
+};
+
+/// Most sub-bands a region defines, the EU868 ones.
+pub const MAX_SUB_BANDS: usize = 6;
+
+/// Frequency range sharing one transmit power limit and one duty cycle budget.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SubBand {
+    pub min_hz: u32,
+    pub max_hz: u32,
+    /// Share of the time a node may spend transmitting, in thousandths; 1000 means no limit.
+    pub duty_cycle_permille: u16,
+    /// Transmit power limit in dBm.
+    pub max_power: i8,
+}
+
+impl SubBand {
+    /// Whether a transmission of `bandwidth` centred on `frequency_hz` stays within the sub-band.
+    pub fn contains(&self, frequency_hz: u32, bandwidth: Bandwidth) -> bool {
+        let half = bandwidth.hz() / 2;
+        frequency_hz.saturating_sub(half) >= self.min_hz
+            && frequency_hz.saturating_add(half) <= self.max_hz
+    }
+}
+
+pub struct RegionPlan {
+    pub first_channel_hz: u32,
+    pub channel_spacing_hz: u32,
+    pub channel_count: u8,
+    pub sub_bands: &'static [SubBand],
+    /// Longest single transmission allowed, in microseconds.
+    pub max_dwell_time_us: Option<u64>,
+    /// Modulation lorelay uses in the region, on its first channel and at the highest power
+    /// allowed there.
+    pub default_config: RadioConfig,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RegionError {
+    /// The transmission would not fit in any sub-band of the region.
+    FrequencyNotAllowed(u32),
+    OutputPowerTooHigh(i8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Region {
+    Eu433,
+    Eu868,
+    Us915,
+    As923,
+}
+
+const fn config(
+    frequency_hz: u32,
+    output_power: i8,
+    spreading_factor: SpreadingFactor,
+    bandwidth: Bandwidth,
+    coding_rate: CodingRate,
+) -> RadioConfig {
+    RadioConfig {
+        frequency_hz,
+        output_power,
+        spreading_factor,
+        bandwidth,
+        coding_rate,
+        preamble_length: 8,
+        sync_word: PRIVATE_SYNC_WORD,
+        crc: true,
+        implicit_header: false,
+    }
+}
+
+const fn sub_band(min_hz: u32, max_hz: u32, duty_cycle_permille: u16, max_power: i8) -> SubBand {
+    SubBand {
+        min_hz,
+        max_hz,
+        duty_cycle_permille,
+        max_power,
+    }
+}
+
+static EU433: RegionPlan = RegionPlan {
+    first_channel_hz: 433_175_000,
+    channel_spacing_hz: 200_000,
+    channel_count: 3,
+    sub_bands: &[sub_band(433_050_000, 434_790_000, 100, 12)],
+    max_dwell_time_us: None,
+    default_config: config(
+        433_175_000,
+        12,
+        SpreadingFactor::Sf10,
+        Bandwidth::Khz125,
+        CodingRate::Cr4_8,
+    ),
+};
+
+static EU868: RegionPlan = RegionPlan {
+    first_channel_hz: 868_100_000,
+    channel_spacing_hz: 200_000,
+    channel_count: 3,
+    sub_bands: &[
+        sub_band(863_000_000, 865_000_000, 1, 14),
+        sub_band(865_000_000, 868_000_000, 10, 14),
+        sub_band(868_000_000, 868_600_000, 10, 14),
+        sub_band(868_700_000, 869_200_000, 1, 14),
+        sub_band(869_400_000, 869_650_000, 100, MAX_OUTPUT_POWER),
+        sub_band(869_700_000, 870_000_000, 10, 14),
+    ],
+    max_dwell_time_us: None,
+    default_config: config(
+        868_100_000,
+        14,
+        SpreadingFactor::Sf10,
+        Bandwidth::Khz125,
+        CodingRate::Cr4_8,
+    ),
+};
+
+static US915: RegionPlan = RegionPlan {
+    first_channel_hz: 902_300_000,
+    channel_spacing_hz: 200_000,
+    channel_count: 64,
+    sub_bands: &[sub_band(902_000_000, 928_000_000, 1000, MAX_OUTPUT_POWER)],
+    max_dwell_time_us: Some(400_000),
+    default_config: config(
+        902_300_000,
+        MAX_OUTPUT_POWER,
+        SpreadingFactor::Sf9,
+        Bandwidth::Khz500,
+        CodingRate::Cr4_5,
+    ),
+};
+
+static AS923: RegionPlan = RegionPlan {
+    first_channel_hz: 923_200_000,
+    channel_spacing_hz: 200_000,
+    channel_count: 2,
+    sub_bands: &[sub_band(915_000_000, 928_000_000, 10, 16)],
+    max_dwell_time_us: Some(400_000),
+    default_config: config(
+        923_200_000,
+        16,
+        SpreadingFactor::Sf8,
+        Bandwidth::Khz125,
+        CodingRate::Cr4_5,
+    ),
+};
+
+impl Region {
+    pub const ALL: [Region; 4] = [Region::Eu433, Region::Eu868, Region::Us915, Region::As923];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Region::Eu433 => "eu433",
+            Region::Eu868 => "eu868",
+            Region::Us915 => "us915",
+            Region::As923 => "as923",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|region| region.name() == name)
+    }
+
+    pub fn plan(self) -> &'static RegionPlan {
+        match self {
+            Region::Eu433 => &EU433,
+            Region::Eu868 => &EU868,
+            Region::Us915 => &US915,
+            Region::As923 => &AS923,
+        }
+    }
+
+    pub fn default_config(self) -> RadioConfig {
+        self.plan().default_config
+    }
+
+    /// Centre frequency of channel `index`, `None` past the last channel.
+    pub fn channel_hz(self, index: u8) -> Option<u32> {
+        let plan = self.plan();
+        (index < plan.channel_count)
+            .then(|| plan.first_channel_hz + u32::from(index) * plan.channel_spacing_hz)
+    }
+
+    /// Index and limits of the sub-band a transmission falls in.
+    pub fn sub_band(
+        self,
+        frequency_hz: u32,
+        bandwidth: Bandwidth,
+    ) -> Option<(usize, &'static SubBand)> {
+        self.plan()
+            .sub_bands
+            .iter()
+            .enumerate()
+            .find(|(_, band)| band.contains(frequency_hz, bandwidth))
+    }
+
+    /// Checks that `config` transmits within the region's bands and power limits.
+    pub fn check(self, config: &RadioConfig) -> Result<(), RegionError> {
+        let (_, band) = self
+            .sub_band(config.frequency_hz, config.bandwidth)
+            .ok_or(RegionError::FrequencyNotAllowed(config.frequency_hz))?;
+        if config.output_power > band.max_power {
+            return Err(RegionError::OutputPowerTooHigh(config.output_power));
+        }
+        Ok(())
+    }
+}
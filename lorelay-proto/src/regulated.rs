@@ -0,0 +1,147 @@
+//! Radio adapter keeping the transmissions of any `Radio` within the rules of a region.
+//!
+//! Before every frame, `Regulated` takes its airtime from the duty cycle budget, waiting a little
+//! for it if needed, then listens before talking. The same rules thus apply to the SX126x of the
+//! firmware and to the simulated radios of `sim`.
+use crate::airtime::time_on_air_us;
+use crate::channel_access::{Access, Backoff, ChannelAccess};
+use crate::duty_cycle::{AirtimeAccountant, Denied};
+use crate::radio::{Radio, RadioConfig, ReceivedFrame};
+use crate::region::{Region, RegionError};
+
+/// Longest a transmission waits for duty cycle budget before it is dropped.
+pub const MAX_DUTY_CYCLE_WAIT_MS: u64 = 5_000;
+
+/// Time source of the adapter, in milliseconds since boot.
+pub trait Clock {
+    fn now_ms(&self) -> u64;
+
+    /// Waits until `at_ms`, returning at once if it is past.
+    async fn sleep_until(&mut self, at_ms: u64);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RegulatedError<E> {
+    /// The configuration breaks the rules of the region the radio operates in.
+    Region(RegionError),
+    /// The frame would break the duty cycle or dwell time rules of the region.
+    Airtime(Denied),
+    /// Channel activity detection kept finding the channel busy.
+    ChannelBusy,
+    Radio(E),
+}
+
+/// `radio`, operated within a region and listening before every transmission.
+pub struct Regulated<R, C, B> {
+    radio: R,
+    clock: C,
+    airtime: AirtimeAccountant,
+    channel_access: ChannelAccess<B>,
+}
+
+impl<R: Radio, C: Clock, B: Backoff> Regulated<R, C, B> {
+    /// Wraps `radio`, to be operated within `region` and to listen before every transmission as
+    /// `channel_access` says. Fails if the configuration of `radio` breaks the rules of `region`.
+    pub fn new(
+        radio: R,
+        clock: C,
+        region: Region,
+        channel_access: ChannelAccess<B>,
+    ) -> Result<Self, RegulatedError<R::Error>> {
+        region
+            .check(&radio.config())
+            .map_err(RegulatedError::Region)?;
+        let now = clock.now_ms();
+        Ok(Regulated {
+            radio,
+            clock,
+            airtime: AirtimeAccountant::new(region, now),
+            channel_access,
+        })
+    }
+
+    pub fn region(&self) -> Region {
+        self.airtime.region()
+    }
+
+    pub fn radio(&self) -> &R {
+        &self.radio
+    }
+
+    /// Takes the airtime of `frame` from the duty cycle budget, waiting a little for it if
+    /// needed.
+    async fn reserve_airtime(&mut self, frame: &[u8]) -> Result<(), Denied> {
+        let config = self.radio.config();
+        let airtime_us = time_on_air_us(&config, frame.len());
+        let (frequency_hz, bandwidth) = (config.frequency_hz, config.bandwidth);
+        let now = self.clock.now_ms();
+        match self
+            .airtime
+            .reserve(frequency_hz, bandwidth, airtime_us, now)
+        {
+            Err(Denied::Deferred { until }) if until - now <= MAX_DUTY_CYCLE_WAIT_MS => {
+                self.clock.sleep_until(until).await;
+                self.airtime
+                    .reserve(frequency_hz, bandwidth, airtime_us, until)
+            }
+            result => result,
+        }
+    }
+
+    /// Runs channel activity detections until the channel is clear, backing off while it is busy.
+    async fn listen_before_talk(&mut self) -> Result<(), RegulatedError<R::Error>> {
+        // A previous transmission may have been abandoned midway, by a failed detection or by
+        // dropping its future.
+        self.channel_access.reset();
+        loop {
+            let busy = self.radio.cad().await.map_err(RegulatedError::Radio)?;
+            match self.channel_access.on_detection(busy) {
+                Access::Transmit => return Ok(()),
+                Access::Wait(delay) => {
+                    let at = self.clock.now_ms() + delay;
+                    self.clock.sleep_until(at).await;
+                }
+                Access::ChannelBusy => return Err(RegulatedError::ChannelBusy),
+            }
+        }
+    }
+}
+
+impl<R: Radio, C: Clock, B: Backoff> Radio for Regulated<R, C, B> {
+    type Error = RegulatedError<R::Error>;
+
+    fn config(&self) -> RadioConfig {
+        self.radio.config()
+    }
+
+    /// Sends `frame` once the duty cycle allows it and the channel is clear.
+    ///
+    /// The airtime is taken from the duty cycle budget even if the channel stays busy.
+    async fn send(&mut self, frame: &[u8]) -> Result<(), Self::Error> {
+        self.reserve_airtime(frame)
+            .await
+            .map_err(RegulatedError::Airtime)?;
+        self.listen_before_talk().await?;
+        self.radio.send(frame).await.map_err(RegulatedError::Radio)
+    }
+
+    async fn receive(&mut self) -> Result<ReceivedFrame, Self::Error> {
+        self.radio.receive().await.map_err(RegulatedError::Radio)
+    }
+
+    /// Applies `config` if it follows the rules of the region.
+    async fn configure(&mut self, config: RadioConfig) -> Result<(), Self::Error> {
+        self.region()
+            .check(&config)
+            .map_err(RegulatedError::Region)?;
+        self.radio
+            .configure(config)
+            .await
+            .map_err(RegulatedError::Radio)
+    }
+
+    async fn cad(&mut self) -> Result<bool, Self::Error> {
+        self.radio.cad().await.map_err(RegulatedError::Radio)
+    }
+}
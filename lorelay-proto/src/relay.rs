@@ -0,0 +1,452 @@
+//! A whole relay node: discovery beacons carrying routes, plus relaying of `Normal` messages
+//! along those routes, or by flooding when no route is known.
+//!
+//! `Relay` ties the other modules together and, like them, never waits: received frames and
+//! outgoing payloads are fed in as they come, messages to transmit are taken out with `poll` and
+//! what became of payloads with `poll_event`. The firmware runs it against the SX126x, the
+//! simulator against simulated radios.
+//!
+//! Outgoing payloads may come with a token: they are then acknowledged by their destination and
+//! retransmitted until the `Ack` comes back or the retries run out, and the outcome is returned
+//! with the token. Payloads larger than a message are fragmented, and always acknowledged.
+use heapless::Vec;
+
+use crate::ack::{Delivery, PendingAcks, Retry, RetryConfig};
+use crate::discovery::{BeaconConfig, Discovery};
+use crate::flood::Flood;
+use crate::fragment::{Frame, Reassembler, Reassembly, Step, Transfer};
+use crate::message::{
+    DecodeError, Message, MessageBuilder, MessageType, BROADCAST_UID, MAX_PAYLOAD_SIZE,
+};
+use crate::neighbour::NeighbourTable;
+use crate::radio::PacketStatus;
+use crate::rng::XorShift32;
+use crate::routing::RoutingTable;
+
+/// Largest payload sent or received; anything above `MAX_PAYLOAD_SIZE` is fragmented.
+pub const PAYLOAD_CAPACITY: usize = 512;
+
+pub const NEIGHBOUR_TABLE_SIZE: usize = 16;
+
+pub const ROUTING_TABLE_SIZE: usize = 16;
+
+/// Number of frames remembered for duplicate suppression.
+const SEEN_CACHE_SIZE: usize = 32;
+
+/// Messages that can await their `Ack` at the same time.
+const PENDING_ACKS_SIZE: usize = 4;
+
+/// Fragmented payloads sent at the same time.
+const TRANSFERS_SIZE: usize = 2;
+
+/// Fragmented payloads received at the same time.
+const REASSEMBLY_SLOTS: usize = 2;
+
+/// Messages waiting for their transmission time.
+const QUEUE_SIZE: usize = 8;
+
+/// Events not taken out with `poll_event` yet.
+const EVENTS_SIZE: usize = 8;
+
+pub type Payload = Vec<u8, PAYLOAD_CAPACITY>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RelayConfig {
+    pub beacon: BeaconConfig,
+    pub retry: RetryConfig,
+    /// Upper bound of the random delay before rebroadcasting, so that relays do not collide.
+    pub relay_jitter: u64,
+    /// Milliseconds after which a payload missing fragments is dropped.
+    pub reassembly_timeout: u64,
+}
+
+impl Default for RelayConfig {
+    fn default() -> Self {
+        RelayConfig {
+            beacon: BeaconConfig::default(),
+            retry: RetryConfig::default(),
+            relay_jitter: 500,
+            reassembly_timeout: 60_000,
+        }
+    }
+}
+
+// Events are taken out one at a time, boxing the payload would only cost an allocator.
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event<T> {
+    /// Payload addressed to this node, or broadcast.
+    Received { origin_uid: u16, payload: Payload },
+    /// Outcome of a payload sent with a token.
+    ///
+    /// Broadcasts are never acknowledged, their outcome is `TimedOut` right away.
+    Delivery(T, Delivery),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SendError {
+    /// Larger than `PAYLOAD_CAPACITY`, or than a message for a broadcast.
+    TooLarge(usize),
+    /// Every fragmented transfer slot is taken.
+    Busy,
+}
+
+pub struct Relay<T> {
+    uid: u16,
+    config: RelayConfig,
+    rng: XorShift32,
+    discovery: Discovery,
+    flood: Flood<SEEN_CACHE_SIZE>,
+    routing: RoutingTable<ROUTING_TABLE_SIZE>,
+    neighbours: NeighbourTable<NEIGHBOUR_TABLE_SIZE>,
+    builder: MessageBuilder,
+    pending: PendingAcks<T, PENDING_ACKS_SIZE>,
+    transfers: Vec<(Transfer<PAYLOAD_CAPACITY>, Option<T>), TRANSFERS_SIZE>,
+    next_transfer_id: u8,
+    transfers_deadline: u64,
+    reassembler: Reassembler<REASSEMBLY_SLOTS, PAYLOAD_CAPACITY>,
+    /// Messages and the time they are due at, in the order they were queued.
+    queue: Vec<(u64, Message), QUEUE_SIZE>,
+    events: Vec<Event<T>, EVENTS_SIZE>,
+}
+
+impl<T> Relay<T> {
+    /// Creates the relay of node `uid` started at `now`; `seed` drives the random delays and
+    /// picks the first sequence number, so it must differ every time the relay is created.
+    pub fn new(uid: u16, config: RelayConfig, now: u64, seed: u32) -> Self {
+        Relay {
+            uid,
+            config,
+            rng: XorShift32::new(seed),
+            discovery: Discovery::new(uid, config.beacon, now, seed),
+            flood: Flood::new(uid),
+            routing: RoutingTable::new(uid, config.beacon.ttl),
+            neighbours: NeighbourTable::new(config.beacon.ttl),
+            builder: MessageBuilder::with_seq(uid, seed as u16),
+            pending: PendingAcks::new(config.retry),
+            transfers: Vec::new(),
+            // Like the sequence numbers, clear of the transfer ids used before a restart.
+            next_transfer_id: XorShift32::new(seed).next_u32() as u8,
+            transfers_deadline: u64::MAX,
+            reassembler: Reassembler::new(config.reassembly_timeout),
+            queue: Vec::new(),
+            events: Vec::new(),
+        }
+    }
+
+    pub fn uid(&self) -> u16 {
+        self.uid
+    }
+
+    pub fn config(&self) -> &RelayConfig {
+        &self.config
+    }
+
+    pub fn neighbours(&self) -> &NeighbourTable<NEIGHBOUR_TABLE_SIZE> {
+        &self.neighbours
+    }
+
+    pub fn routing(&self) -> &RoutingTable<ROUTING_TABLE_SIZE> {
+        &self.routing
+    }
+
+    /// Time at which `poll` next has something to send, or a retry or transfer times out.
+    pub fn next_deadline(&self) -> u64 {
+        self.queue
+            .iter()
+            .map(|(due, _)| *due)
+            .chain(self.pending.next_deadline())
+            .fold(self.discovery.next_deadline(), u64::min)
+            .min(self.transfers_deadline)
+    }
+
+    /// Sends `payload` to `destination_uid`, acknowledged if `token` is given.
+    ///
+    /// On error the outcome of the token is `TimedOut`.
+    pub fn send(
+        &mut self,
+        destination_uid: u16,
+        payload: &[u8],
+        token: Option<T>,
+        now: u64,
+    ) -> Result<(), SendError> {
+        let result = if payload.len() > MAX_PAYLOAD_SIZE {
+            self.send_fragmented(destination_uid, payload, token)
+        } else {
+            self.send_message(destination_uid, payload, token, now);
+            Ok(())
+        };
+        result.map_err(|(err, token)| {
+            if let Some(token) = token {
+                self.push_event(Event::Delivery(token, Delivery::TimedOut));
+            }
+            err
+        })
+    }
+
+    fn send_fragmented(
+        &mut self,
+        destination_uid: u16,
+        payload: &[u8],
+        token: Option<T>,
+    ) -> Result<(), (SendError, Option<T>)> {
+        if destination_uid == BROADCAST_UID {
+            return Err((SendError::TooLarge(payload.len()), token));
+        }
+        if self.transfers.is_full() {
+            return Err((SendError::Busy, token));
+        }
+        self.next_transfer_id = self.next_transfer_id.wrapping_add(1);
+        let transfer = Transfer::new(
+            destination_uid,
+            self.next_transfer_id,
+            payload,
+            self.config.retry,
+        );
+        match transfer {
+            Some(transfer) => {
+                self.transfers
+                    .push((transfer, token))
+                    .unwrap_or_else(|_| unreachable!("checked above"));
+                // Sent from the next `poll` on.
+                self.transfers_deadline = 0;
+                Ok(())
+            }
+            None => Err((SendError::TooLarge(payload.len()), token)),
+        }
+    }
+
+    fn send_message(&mut self, destination_uid: u16, payload: &[u8], token: Option<T>, now: u64) {
+        let Some(message) = self.builder.normal(destination_uid, payload) else {
+            return;
+        };
+        let token = match token {
+            Some(token) if destination_uid == BROADCAST_UID => {
+                self.push_event(Event::Delivery(token, Delivery::TimedOut));
+                None
+            }
+            token => token,
+        };
+        let message = match token {
+            Some(_) => message.with_ack_requested(),
+            None => message,
+        };
+        let message = self.flood.originate(message, &self.routing);
+        if let Some(token) = token {
+            // A failed transmission is retried like a lost one.
+            if let Err(token) = self.pending.push(message.clone(), token, now) {
+                self.push_event(Event::Delivery(token, Delivery::TimedOut));
+            }
+        }
+        self.schedule(now, message);
+    }
+
+    /// Handles a frame received at `now`, heard with `status`.
+    pub fn on_frame(
+        &mut self,
+        frame: &[u8],
+        status: PacketStatus,
+        now: u64,
+    ) -> Result<(), DecodeError> {
+        let message = Message::decode(frame)?;
+        self.neighbours
+            .upsert(message.sender_uid(), status.rssi, status.snr, now);
+        self.on_message(&message, now);
+        Ok(())
+    }
+
+    fn on_message(&mut self, message: &Message, now: u64) {
+        let sender_uid = message.sender_uid();
+        if let Some(neighbour) = self.neighbours.get(sender_uid) {
+            match message.message_type() {
+                MessageType::Ping { routes } => {
+                    self.routing
+                        .on_advertisement(sender_uid, neighbour.rssi, routes, now)
+                }
+                _ => self.routing.on_neighbour(sender_uid, neighbour.rssi, now),
+            }
+        }
+        self.discovery.on_message(message, now);
+
+        let handling = self.flood.on_message(message, &self.routing);
+        if let Some(forward) = handling.forward {
+            let jitter = self.rng.up_to(self.config.relay_jitter);
+            self.schedule(now + jitter, forward);
+        }
+        if handling.acknowledge {
+            if let Some(ack) = self.builder.ack(message) {
+                let ack = self.flood.originate(ack, &self.routing);
+                self.schedule(now, ack);
+            }
+        }
+        if !handling.deliver {
+            return;
+        }
+
+        let origin_uid = message.origin_uid();
+        if message.is_fragment() {
+            self.on_fragment(message, now);
+        } else if let MessageType::Ack { .. } = message.message_type() {
+            if let Some((token, delivery)) = self.pending.on_ack(message) {
+                self.push_event(Event::Delivery(token, delivery));
+            }
+        } else {
+            // Cannot fail, a message payload is smaller than the capacity.
+            let payload = Vec::from_slice(message.payload()).unwrap_or_default();
+            self.push_event(Event::Received {
+                origin_uid,
+                payload,
+            });
+        }
+    }
+
+    fn on_fragment(&mut self, message: &Message, now: u64) {
+        let origin_uid = message.origin_uid();
+        let status = match Frame::decode(message.payload()) {
+            Ok(Frame::Data(fragment)) => {
+                match self.reassembler.on_fragment(origin_uid, &fragment, now) {
+                    Reassembly::Pending | Reassembly::Rejected => None,
+                    Reassembly::Missing(missing) => Some((fragment.transfer_id, missing)),
+                    Reassembly::Complete(payload) => {
+                        self.push_event(Event::Received {
+                            origin_uid,
+                            payload,
+                        });
+                        Some((fragment.transfer_id, 0))
+                    }
+                    Reassembly::AlreadyComplete => Some((fragment.transfer_id, 0)),
+                }
+            }
+            Ok(Frame::Status {
+                transfer_id,
+                missing,
+            }) => {
+                let transfer = self.transfers.iter_mut().find(|(transfer, _)| {
+                    transfer.destination_uid() == origin_uid
+                        && transfer.transfer_id() == transfer_id
+                });
+                if let Some((transfer, _)) = transfer {
+                    transfer.on_status(missing);
+                    self.transfers_deadline = 0;
+                }
+                None
+            }
+            Err(_) => None,
+        };
+        // Broadcast payloads are not acknowledged, fragmented or not.
+        if message.destination_uid() != self.uid {
+            return;
+        }
+        if let Some((transfer_id, missing)) = status {
+            let status = Frame::Status {
+                transfer_id,
+                missing,
+            };
+            if let Some(status) = self.builder.fragment(origin_uid, &status) {
+                let status = self.flood.originate(status, &self.routing);
+                self.schedule(now, status);
+            }
+        }
+    }
+
+    /// Returns the next message to transmit at `now`. Call again until it returns `None`.
+    pub fn poll(&mut self, now: u64) -> Option<Message> {
+        if let Some(message) = self.pop_due(now) {
+            return Some(message);
+        }
+
+        while let Some(retry) = self.pending.poll(now) {
+            match retry {
+                Retry::Retransmit(message) => {
+                    return Some(self.flood.originate(message, &self.routing));
+                }
+                Retry::Failed(token) => self.push_event(Event::Delivery(token, Delivery::TimedOut)),
+            }
+        }
+
+        if self.transfers_deadline <= now {
+            if let Some(message) = self.poll_transfers(now) {
+                return Some(message);
+            }
+        }
+
+        self.reassembler.expire(now);
+        self.routing.expire(now);
+        self.neighbours.evict_expired(now);
+        let neighbour_count = self.neighbours.len().min(u8::MAX as usize) as u8;
+        let routing = &self.routing;
+        self.discovery
+            .poll(now, neighbour_count, || routing.advertisement())
+    }
+
+    fn poll_transfers(&mut self, now: u64) -> Option<Message> {
+        self.transfers_deadline = u64::MAX;
+        let mut i = 0;
+        while i < self.transfers.len() {
+            let (transfer, _) = &mut self.transfers[i];
+            let destination_uid = transfer.destination_uid();
+            match transfer.poll(now) {
+                Step::Send(frame) => {
+                    // Polled again from the next call on.
+                    self.transfers_deadline = 0;
+                    let message = self.builder.fragment(destination_uid, &frame)?;
+                    return Some(self.flood.originate(message, &self.routing));
+                }
+                Step::Wait(deadline) => {
+                    self.transfers_deadline = self.transfers_deadline.min(deadline);
+                    i += 1;
+                }
+                Step::Done(outcome) => {
+                    let (_, token) = self.transfers.swap_remove(i);
+                    if let Some(token) = token {
+                        self.push_event(Event::Delivery(token, outcome));
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// Gives up on everything in flight, before the node stops relaying: messages waiting for
+    /// their transmission time are dropped and the outcome of every payload sent with a token
+    /// becomes `TimedOut`.
+    pub fn abandon(&mut self) {
+        self.queue.clear();
+        while let Some(token) = self.pending.abandon() {
+            self.push_event(Event::Delivery(token, Delivery::TimedOut));
+        }
+        while let Some((_, token)) = self.transfers.pop() {
+            if let Some(token) = token {
+                self.push_event(Event::Delivery(token, Delivery::TimedOut));
+            }
+        }
+        self.transfers_deadline = u64::MAX;
+    }
+
+    /// Takes out the next event.
+    pub fn poll_event(&mut self) -> Option<Event<T>> {
+        (!self.events.is_empty()).then(|| self.events.remove(0))
+    }
+
+    fn push_event(&mut self, event: Event<T>) {
+        // Only lost if the caller does not drain the events after each call.
+        let _ = self.events.push(event);
+    }
+
+    fn schedule(&mut self, due: u64, message: Message) {
+        // Like a lost frame when the queue is full: retried if it needs to be.
+        let _ = self.queue.push((due, message));
+    }
+
+    fn pop_due(&mut self, now: u64) -> Option<Message> {
+        let (i, _) = self
+            .queue
+            .iter()
+            .enumerate()
+            .filter(|(_, (due, _))| *due <= now)
+            .min_by_key(|(_, (due, _))| *due)?;
+        Some(self.queue.remove(i).1)
+    }
+}
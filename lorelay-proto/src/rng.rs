@@ -0,0 +1,33 @@
+//! Small deterministic PRNG used for protocol jitter, not for anything security related.
+
+/// Xorshift32 generator.
+#[derive(Debug, Clone)]
+pub struct XorShift32 {
+    state: u32,
+}
+
+impl XorShift32 {
+    /// Creates a generator from `seed`; a zero seed is replaced since it would stay zero forever.
+    pub const fn new(seed: u32) -> Self {
+        XorShift32 {
+            state: if seed == 0 { 0x9e37_79b9 } else { seed },
+        }
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.state = x;
+        x
+    }
+
+    /// Uniform-ish value in `0..=max`.
+    pub fn up_to(&mut self, max: u64) -> u64 {
+        if max == 0 {
+            return 0;
+        }
+        self.next_u32() as u64 % (max + 1)
+    }
+}
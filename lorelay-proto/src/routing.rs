@@ -0,0 +1,196 @@
+//! Distance-vector unicast routing.
+//!
+//! Every node advertises its routes in its `Ping` beacons. A route learned from a neighbour costs
+//! the advertised metric plus the cost of the link to that neighbour, derived from its RSSI.
+//! Adverts name the next hop of each route so that the receiver can apply poison reverse: a route
+//! that goes through us is unreachable for the node that advertised it.
+use crate::message::{RouteAdvert, RouteAdverts, MAX_ADVERTISED_ROUTES};
+use heapless::Vec;
+
+/// Metric of an unreachable destination. Bounds the count to infinity after a link loss.
+pub const INFINITE_METRIC: u8 = 32;
+
+/// Cost of a hop over a link heard at `rssi` dBm: 1 for a strong link, up to 4 for a marginal one.
+pub fn link_cost(rssi: i16) -> u8 {
+    match rssi {
+        -90.. => 1,
+        -105..=-91 => 2,
+        -115..=-106 => 3,
+        _ => 4,
+    }
+}
+
+/// Lookup of the neighbour to forward a unicast message through.
+pub trait Routes {
+    fn next_hop(&self, destination_uid: u16) -> Option<u16>;
+}
+
+/// Route source for nodes that only flood.
+pub struct NoRoutes;
+
+impl Routes for NoRoutes {
+    fn next_hop(&self, _destination_uid: u16) -> Option<u16> {
+        None
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Route {
+    pub destination_uid: u16,
+    pub next_hop_uid: u16,
+    pub metric: u8,
+    /// Milliseconds since boot of the last advert confirming the route.
+    pub updated_at: u64,
+}
+
+impl Route {
+    pub fn is_reachable(&self) -> bool {
+        self.metric < INFINITE_METRIC
+    }
+}
+
+pub struct RoutingTable<const N: usize> {
+    uid: u16,
+    routes: Vec<Route, N>,
+    timeout: u64,
+}
+
+impl<const N: usize> RoutingTable<N> {
+    /// Creates the table of node `uid`; routes not confirmed for `timeout` milliseconds are
+    /// poisoned, then dropped after as long again.
+    pub const fn new(uid: u16, timeout: u64) -> Self {
+        RoutingTable {
+            uid,
+            routes: Vec::new(),
+            timeout,
+        }
+    }
+
+    /// Records that `neighbour_uid` was heard directly at `rssi`.
+    pub fn on_neighbour(&mut self, neighbour_uid: u16, rssi: i16, now: u64) {
+        if neighbour_uid != self.uid {
+            self.update(neighbour_uid, neighbour_uid, link_cost(rssi), now);
+        }
+    }
+
+    /// Learns the routes advertised by `neighbour_uid`, whose frame was received at `rssi`.
+    pub fn on_advertisement(
+        &mut self,
+        neighbour_uid: u16,
+        rssi: i16,
+        adverts: &[RouteAdvert],
+        now: u64,
+    ) {
+        if neighbour_uid == self.uid {
+            return;
+        }
+        self.on_neighbour(neighbour_uid, rssi, now);
+        let cost = link_cost(rssi);
+        for advert in adverts {
+            if advert.destination_uid == self.uid || advert.destination_uid == neighbour_uid {
+                continue;
+            }
+            let metric = if advert.next_hop_uid == self.uid {
+                INFINITE_METRIC
+            } else {
+                advert.metric.saturating_add(cost).min(INFINITE_METRIC)
+            };
+            self.update(advert.destination_uid, neighbour_uid, metric, now);
+        }
+    }
+
+    fn update(&mut self, destination_uid: u16, next_hop_uid: u16, metric: u8, now: u64) {
+        let route = Route {
+            destination_uid,
+            next_hop_uid,
+            metric,
+            updated_at: now,
+        };
+
+        if let Some(known) = self
+            .routes
+            .iter_mut()
+            .find(|r| r.destination_uid == destination_uid)
+        {
+            // The current next hop is always believed, even when the route got worse.
+            if known.next_hop_uid == next_hop_uid {
+                if metric < INFINITE_METRIC || known.is_reachable() {
+                    *known = route;
+                }
+            } else if metric < known.metric {
+                *known = route;
+            }
+            return;
+        }
+
+        if metric >= INFINITE_METRIC {
+            return;
+        }
+        if let Err(route) = self.routes.push(route) {
+            if let Some(worst) = self.routes.iter_mut().max_by_key(|r| r.metric) {
+                if metric < worst.metric {
+                    *worst = route;
+                }
+            }
+        }
+    }
+
+    /// Poisons routes that timed out and forgets the ones poisoned for a whole timeout.
+    pub fn expire(&mut self, now: u64) {
+        let timeout = self.timeout;
+        self.routes
+            .retain(|r| r.is_reachable() || now.saturating_sub(r.updated_at) < timeout);
+        for route in self.routes.iter_mut() {
+            if route.is_reachable() && now.saturating_sub(route.updated_at) >= timeout {
+                route.metric = INFINITE_METRIC;
+                route.updated_at = now;
+            }
+        }
+    }
+
+    pub fn route(&self, destination_uid: u16) -> Option<&Route> {
+        self.routes
+            .iter()
+            .find(|r| r.destination_uid == destination_uid)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Route> {
+        self.routes.iter()
+    }
+
+    /// Routes to put in the next `Ping`, poisoned ones included so neighbours learn of the loss.
+    ///
+    /// If the table holds more than `MAX_ADVERTISED_ROUTES`, the cheapest ones are advertised.
+    pub fn advertisement(&self) -> RouteAdverts {
+        let mut routes: Vec<&Route, N> = self.routes.iter().collect();
+        if routes.len() > MAX_ADVERTISED_ROUTES {
+            routes.sort_unstable_by_key(|r| r.metric);
+        }
+        routes
+            .iter()
+            .take(MAX_ADVERTISED_ROUTES)
+            .map(|r| RouteAdvert {
+                destination_uid: r.destination_uid,
+                next_hop_uid: r.next_hop_uid,
+                metric: r.metric,
+            })
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.routes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+}
+
+impl<const N: usize> Routes for RoutingTable<N> {
+    fn next_hop(&self, destination_uid: u16) -> Option<u16> {
+        self.route(destination_uid)
+            .filter(|r| r.is_reachable())
+            .map(|r| r.next_hop_uid)
+    }
+}
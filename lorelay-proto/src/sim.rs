@@ -0,0 +1,329 @@
+//! Simulated radios sharing an in-memory medium, to run whole networks on the host.
+//!
+//! A frame sent by a `SimRadio` reaches every other radio of the `Medium` that has a link to the
+//! sender and listens on the same channel, unless the link loses it. Time is virtual: the frame
+//! arrives once `Medium::advance` has moved the clock past the end of its time on air plus the
+//! latency of the medium. A `Medium` is also the `Clock` of the radios it regulates, so that
+//! their waits end as the clock moves.
+use core::cell::RefCell;
+use core::future::poll_fn;
+use core::task::{Poll, Waker};
+use std::collections::{BTreeMap, VecDeque};
+use std::vec::Vec;
+
+use crate::airtime::time_on_air_us;
+use crate::radio::{ConfigError, PacketStatus, Radio, RadioConfig, ReceivedFrame, MAX_FRAME_SIZE};
+use crate::regulated::Clock;
+use crate::rng::XorShift32;
+
+/// Radio conditions between two nodes, the same both ways.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Link {
+    /// Received signal strength in dBm.
+    pub rssi: i16,
+    /// Signal to noise ratio in dB.
+    pub snr: i16,
+    /// Share of the frames lost on the link, in thousandths.
+    pub loss_permille: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SimError {
+    Invalid(ConfigError),
+    /// The frame is longer than `MAX_FRAME_SIZE`.
+    FrameTooLong(usize),
+}
+
+/// Counters of one radio.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RadioStats {
+    pub sent: u32,
+    pub received: u32,
+    /// Frames lost on a link on their way to this radio.
+    pub lost: u32,
+    /// Time on air of the frames sent, in microseconds.
+    pub airtime_us: u64,
+}
+
+struct Arrival {
+    at: u64,
+    frame: Vec<u8>,
+    status: PacketStatus,
+}
+
+struct Node {
+    config: RadioConfig,
+    /// Frames on their way, by arrival time.
+    inbox: VecDeque<Arrival>,
+    waker: Option<Waker>,
+    /// End of the last transmission, in milliseconds.
+    on_air_until: u64,
+    stats: RadioStats,
+}
+
+struct State {
+    /// Milliseconds since the start of the simulation.
+    now: u64,
+    latency_ms: u64,
+    rng: XorShift32,
+    nodes: Vec<Node>,
+    /// Ends of the waits of `Clock::sleep_until`, with the tasks to wake then.
+    timers: Vec<(u64, Waker)>,
+    /// Links keyed by the ids of their ends, the smallest first.
+    links: BTreeMap<(usize, usize), Link>,
+}
+
+impl State {
+    fn link(&self, a: usize, b: usize) -> Option<&Link> {
+        self.links.get(&(a.min(b), a.max(b)))
+    }
+
+    /// Wakers of the radios that have a frame due, and of the waits that are over.
+    fn due_wakers(&mut self) -> Vec<Waker> {
+        let now = self.now;
+        let mut wakers: Vec<Waker> = self
+            .nodes
+            .iter_mut()
+            .filter(|node| node.inbox.front().is_some_and(|arrival| arrival.at <= now))
+            .filter_map(|node| node.waker.take())
+            .collect();
+        self.timers.retain(|(at, waker)| {
+            if *at <= now {
+                wakers.push(waker.clone());
+            }
+            *at > now
+        });
+        wakers
+    }
+}
+
+/// Whether a radio set up with `rx` demodulates the frames of one set up with `tx`.
+fn same_channel(rx: &RadioConfig, tx: &RadioConfig) -> bool {
+    rx.frequency_hz == tx.frequency_hz
+        && rx.spreading_factor == tx.spreading_factor
+        && rx.bandwidth == tx.bandwidth
+        && rx.sync_word == tx.sync_word
+}
+
+pub struct Medium {
+    state: RefCell<State>,
+}
+
+impl Medium {
+    /// Creates an empty medium at time 0; `seed` drives the frame losses.
+    pub fn new(latency_ms: u64, seed: u32) -> Self {
+        Medium {
+            state: RefCell::new(State {
+                now: 0,
+                latency_ms,
+                rng: XorShift32::new(seed),
+                nodes: Vec::new(),
+                timers: Vec::new(),
+                links: BTreeMap::new(),
+            }),
+        }
+    }
+
+    /// Adds a radio set up with `config`, linked to no other radio yet.
+    pub fn add_radio(&self, config: RadioConfig) -> Result<SimRadio<'_>, SimError> {
+        config.validate().map_err(SimError::Invalid)?;
+        let mut state = self.state.borrow_mut();
+        state.nodes.push(Node {
+            config,
+            inbox: VecDeque::new(),
+            waker: None,
+            on_air_until: 0,
+            stats: RadioStats::default(),
+        });
+        Ok(SimRadio {
+            medium: self,
+            id: state.nodes.len() - 1,
+        })
+    }
+
+    /// Sets the link between radios `a` and `b`, `None` to cut it.
+    pub fn set_link(&self, a: usize, b: usize, link: Option<Link>) {
+        let mut state = self.state.borrow_mut();
+        let key = (a.min(b), a.max(b));
+        match link {
+            Some(link) => state.links.insert(key, link),
+            None => state.links.remove(&key),
+        };
+    }
+
+    pub fn link(&self, a: usize, b: usize) -> Option<Link> {
+        self.state.borrow().link(a, b).copied()
+    }
+
+    pub fn now(&self) -> u64 {
+        self.state.borrow().now
+    }
+
+    /// Earliest arrival of a frame still on its way, if any.
+    pub fn next_arrival(&self) -> Option<u64> {
+        let state = self.state.borrow();
+        state
+            .nodes
+            .iter()
+            .filter_map(|node| node.inbox.front().map(|arrival| arrival.at))
+            .min()
+    }
+
+    /// Earliest end of a `Clock::sleep_until` wait, if any.
+    pub fn next_timer(&self) -> Option<u64> {
+        let state = self.state.borrow();
+        state.timers.iter().map(|(at, _)| *at).min()
+    }
+
+    /// Moves the clock to `time`, waking the radios waiting for a frame that has arrived.
+    pub fn advance_to(&self, time: u64) {
+        let wakers = {
+            let mut state = self.state.borrow_mut();
+            state.now = state.now.max(time);
+            state.due_wakers()
+        };
+        wakers.into_iter().for_each(Waker::wake);
+    }
+
+    pub fn advance(&self, ms: u64) {
+        self.advance_to(self.now() + ms);
+    }
+
+    pub fn stats(&self, id: usize) -> RadioStats {
+        self.state.borrow().nodes[id].stats
+    }
+
+    fn transmit(&self, from: usize, frame: &[u8]) {
+        let wakers = {
+            let mut state = self.state.borrow_mut();
+            let state = &mut *state;
+            let config = state.nodes[from].config;
+            let airtime_us = time_on_air_us(&config, frame.len());
+            let end = state.now + (airtime_us + 999) / 1_000;
+            let sender = &mut state.nodes[from];
+            sender.on_air_until = end;
+            sender.stats.sent += 1;
+            sender.stats.airtime_us += airtime_us;
+
+            for to in 0..state.nodes.len() {
+                let link = match state.links.get(&(from.min(to), from.max(to))) {
+                    Some(link) if to != from => *link,
+                    _ => continue,
+                };
+                let node = &mut state.nodes[to];
+                if !same_channel(&node.config, &config) {
+                    continue;
+                }
+                if state.rng.up_to(999) < u64::from(link.loss_permille) {
+                    node.stats.lost += 1;
+                    continue;
+                }
+                let at = end + state.latency_ms;
+                let position = node.inbox.partition_point(|arrival| arrival.at <= at);
+                node.inbox.insert(
+                    position,
+                    Arrival {
+                        at,
+                        frame: frame.to_vec(),
+                        status: PacketStatus {
+                            rssi: link.rssi,
+                            snr: link.snr,
+                        },
+                    },
+                );
+            }
+            state.due_wakers()
+        };
+        wakers.into_iter().for_each(Waker::wake);
+    }
+}
+
+impl Clock for &Medium {
+    fn now_ms(&self) -> u64 {
+        self.now()
+    }
+
+    async fn sleep_until(&mut self, at_ms: u64) {
+        poll_fn(|cx| {
+            let mut state = self.state.borrow_mut();
+            if state.now >= at_ms {
+                return Poll::Ready(());
+            }
+            state.timers.push((at_ms, cx.waker().clone()));
+            Poll::Pending
+        })
+        .await
+    }
+}
+
+/// Radio of a `Medium`, identified by the order it was added in.
+pub struct SimRadio<'a> {
+    medium: &'a Medium,
+    id: usize,
+}
+
+impl SimRadio<'_> {
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn stats(&self) -> RadioStats {
+        self.medium.stats(self.id)
+    }
+}
+
+impl Radio for SimRadio<'_> {
+    type Error = SimError;
+
+    fn config(&self) -> RadioConfig {
+        self.medium.state.borrow().nodes[self.id].config
+    }
+
+    /// Puts `frame` on air at the current time and returns at once.
+    async fn send(&mut self, frame: &[u8]) -> Result<(), SimError> {
+        if frame.len() > MAX_FRAME_SIZE {
+            return Err(SimError::FrameTooLong(frame.len()));
+        }
+        self.medium.transmit(self.id, frame);
+        Ok(())
+    }
+
+    async fn receive(&mut self) -> Result<ReceivedFrame, SimError> {
+        poll_fn(|cx| {
+            let mut state = self.medium.state.borrow_mut();
+            let now = state.now;
+            let node = &mut state.nodes[self.id];
+            let arrival = match node.inbox.front() {
+                Some(arrival) if arrival.at <= now => node.inbox.pop_front(),
+                _ => None,
+            };
+            let Some(arrival) = arrival else {
+                node.waker = Some(cx.waker().clone());
+                return Poll::Pending;
+            };
+            node.stats.received += 1;
+            Poll::Ready(Ok(ReceivedFrame::new(&arrival.frame, arrival.status, now)))
+        })
+        .await
+    }
+
+    async fn configure(&mut self, config: RadioConfig) -> Result<(), SimError> {
+        config.validate().map_err(SimError::Invalid)?;
+        self.medium.state.borrow_mut().nodes[self.id].config = config;
+        Ok(())
+    }
+
+    /// Whether a linked radio on the same channel is transmitting.
+    async fn cad(&mut self) -> Result<bool, SimError> {
+        let state = self.medium.state.borrow();
+        let config = &state.nodes[self.id].config;
+        let busy = state.nodes.iter().enumerate().any(|(id, node)| {
+            id != self.id
+                && state.now < node.on_air_until
+                && state.link(self.id, id).is_some()
+                && same_channel(config, &node.config)
+        });
+        Ok(busy)
+    }
+}
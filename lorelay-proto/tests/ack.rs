@@ -0,0 +1,115 @@
+use lorelay_proto::ack::{Delivery, PendingAcks, Retry, RetryConfig};
+use lorelay_proto::message::{Message, MessageBuilder};
+
+const CONFIG: RetryConfig = RetryConfig {
+    timeout: 1_000,
+    max_timeout: 5_000,
+    max_retries: 3,
+};
+
+fn acked(uid: u16) -> (MessageBuilder, Message) {
+    let mut builder = MessageBuilder::new(uid);
+    let message = builder.normal(9, b"data").unwrap().with_ack_requested();
+    (builder, message)
+}
+
+#[test]
+fn backoff_doubles_up_to_max() {
+    let backoffs: Vec<u64> = (0..5).map(|attempt| CONFIG.backoff(attempt)).collect();
+    assert_eq!(backoffs, [1_000, 2_000, 4_000, 5_000, 5_000]);
+    assert_eq!(CONFIG.backoff(u8::MAX), 5_000);
+}
+
+#[test]
+fn ack_completes_delivery() {
+    let (_, message) = acked(1);
+    let ack = MessageBuilder::new(9).ack(&message).unwrap();
+    let mut pending = PendingAcks::<u8, 4>::new(CONFIG);
+    pending.push(message, 42, 0).unwrap();
+    assert_eq!(pending.next_deadline(), Some(1_000));
+
+    assert_eq!(
+        pending.on_ack(&ack),
+        Some((42, Delivery::Delivered { attempts: 1 }))
+    );
+    assert!(pending.is_empty());
+    // The duplicate ack of a retransmission is ignored.
+    assert_eq!(pending.on_ack(&ack), None);
+}
+
+#[test]
+fn ack_from_another_node_is_ignored() {
+    let (_, message) = acked(1);
+    let stranger = MessageBuilder::new(8).ack(&message).unwrap();
+    let mut pending = PendingAcks::<(), 4>::new(CONFIG);
+    pending.push(message, (), 0).unwrap();
+    assert_eq!(pending.on_ack(&stranger), None);
+    assert_eq!(pending.len(), 1);
+}
+
+#[test]
+fn retransmits_with_backoff_then_gives_up() {
+    let (_, message) = acked(1);
+    let mut pending = PendingAcks::<u8, 4>::new(CONFIG);
+    pending.push(message.clone(), 7, 0).unwrap();
+
+    assert_eq!(pending.poll(999), None);
+    let mut now = 0;
+    for attempt in 1..=3 {
+        now = pending.next_deadline().unwrap();
+        match pending.poll(now) {
+            Some(Retry::Retransmit(retry)) => {
+                assert_eq!(retry.attempt(), attempt);
+                assert_eq!(retry.seq(), message.seq());
+            }
+            other => panic!("expected a retransmission, got {other:?}"),
+        }
+        assert_eq!(pending.poll(now), None);
+    }
+    assert_eq!(now, 1_000 + 2_000 + 4_000);
+
+    let now = pending.next_deadline().unwrap();
+    assert_eq!(now, 7_000 + 5_000);
+    assert_eq!(pending.poll(now), Some(Retry::Failed(7)));
+    assert!(pending.is_empty());
+    assert_eq!(pending.next_deadline(), None);
+}
+
+#[test]
+fn ack_of_retransmission_counts_attempts() {
+    let (_, message) = acked(1);
+    let ack = MessageBuilder::new(9).ack(&message).unwrap();
+    let mut pending = PendingAcks::<(), 4>::new(CONFIG);
+    pending.push(message, (), 0).unwrap();
+    pending.poll(1_000).unwrap();
+    pending.poll(3_000).unwrap();
+    assert_eq!(
+        pending.on_ack(&ack),
+        Some(((), Delivery::Delivered { attempts: 3 }))
+    );
+}
+
+#[test]
+fn full_queue_returns_token() {
+    let (mut builder, message) = acked(1);
+    let mut pending = PendingAcks::<u8, 1>::new(CONFIG);
+    pending.push(message, 1, 0).unwrap();
+    let other = builder.normal(9, b"more").unwrap().with_ack_requested();
+    assert_eq!(pending.push(other, 2, 0), Err(2));
+}
+
+#[test]
+fn several_messages_are_tracked_independently() {
+    let (mut builder, first) = acked(1);
+    let second = builder.normal(9, b"more").unwrap().with_ack_requested();
+    let second_ack = MessageBuilder::new(9).ack(&second).unwrap();
+    let mut pending = PendingAcks::<u8, 4>::new(CONFIG);
+    pending.push(first, 1, 0).unwrap();
+    pending.push(second, 2, 500).unwrap();
+
+    assert_eq!(
+        pending.on_ack(&second_ack),
+        Some((2, Delivery::Delivered { attempts: 1 }))
+    );
+    assert_eq!(pending.next_deadline(), Some(1_000));
+}
@@ -0,0 +1,203 @@
+use lorelay_proto::airtime::{
+    link_budget_db, low_data_rate_optimize, sensitivity_dbm, snr_margin_db, symbol_time_us,
+    time_on_air_us, PacketTiming,
+};
+use lorelay_proto::radio::{Bandwidth, CodingRate, RadioConfig, SpreadingFactor};
+
+fn config(spreading_factor: SpreadingFactor, bandwidth: Bandwidth) -> RadioConfig {
+    RadioConfig {
+        spreading_factor,
+        bandwidth,
+        coding_rate: CodingRate::Cr4_5,
+        preamble_length: 8,
+        crc: true,
+        implicit_header: false,
+        ..RadioConfig::default()
+    }
+}
+
+#[test]
+fn symbol_time() {
+    assert_eq!(
+        symbol_time_us(SpreadingFactor::Sf7, Bandwidth::Khz125),
+        1_024
+    );
+    assert_eq!(
+        symbol_time_us(SpreadingFactor::Sf12, Bandwidth::Khz125),
+        32_768
+    );
+    assert_eq!(
+        symbol_time_us(SpreadingFactor::Sf10, Bandwidth::Khz250),
+        4_096
+    );
+}
+
+#[test]
+fn lorawan_reference_values() {
+    // Figures of the usual LoRaWAN airtime calculators, 13 bytes of LoRaWAN overhead included.
+    let sf7 = config(SpreadingFactor::Sf7, Bandwidth::Khz125);
+    assert_eq!(time_on_air_us(&sf7, 10), 41_216);
+    assert_eq!(time_on_air_us(&sf7, 13 + 51), 118_016);
+    // Uses the low data rate optimization.
+    let sf12 = config(SpreadingFactor::Sf12, Bandwidth::Khz125);
+    assert_eq!(time_on_air_us(&sf12, 13 + 51), 2_793_472);
+}
+
+#[test]
+fn longer_payloads_take_longer() {
+    let config = RadioConfig::default();
+    let mut previous = 0;
+    for len in 0..=100 {
+        let airtime = time_on_air_us(&config, len);
+        assert!(airtime >= previous);
+        previous = airtime;
+    }
+}
+
+/// Direct transcription of the time on air formula of the SX126x datasheet (section 6.1.4).
+fn semtech_time_on_air_ms(timing: &PacketTiming, payload_len: usize) -> f64 {
+    let sf = f64::from(timing.spreading_factor.value());
+    let bw = f64::from(timing.bandwidth.hz());
+    let cr = f64::from(timing.coding_rate.denominator() - 4);
+    let crc = if timing.crc { 16.0 } else { 0.0 };
+    let header = if timing.implicit_header { 0.0 } else { 20.0 };
+    let preamble = f64::from(timing.preamble_length);
+    let bytes = payload_len as f64;
+
+    let symbols = if sf < 7.0 {
+        let payload = ((8.0 * bytes + crc - 4.0 * sf + header).max(0.0) / (4.0 * sf)).ceil();
+        preamble + 6.25 + 8.0 + payload * (cr + 4.0)
+    } else {
+        let de = if timing.low_data_rate_optimize {
+            2.0
+        } else {
+            0.0
+        };
+        let payload =
+            ((8.0 * bytes + crc - 4.0 * sf + 8.0 + header).max(0.0) / (4.0 * (sf - de))).ceil();
+        preamble + 4.25 + 8.0 + payload * (cr + 4.0)
+    };
+    symbols * 2f64.powf(sf) / bw * 1_000.0
+}
+
+#[test]
+fn matches_semtech_formula() {
+    for spreading_factor in SpreadingFactor::ALL {
+        for bandwidth in [Bandwidth::Khz62_5, Bandwidth::Khz125, Bandwidth::Khz500] {
+            for coding_rate in CodingRate::ALL {
+                for (implicit_header, crc, low_data_rate_optimize) in [
+                    (false, true, false),
+                    (true, false, false),
+                    (false, true, true),
+                ] {
+                    let timing = PacketTiming {
+                        spreading_factor,
+                        bandwidth,
+                        coding_rate,
+                        preamble_length: 12,
+                        implicit_header,
+                        crc,
+                        low_data_rate_optimize,
+                    };
+                    for len in [0, 1, 7, 20, 51, 79, 255] {
+                        let expected = semtech_time_on_air_ms(&timing, len);
+                        let actual = timing.time_on_air_us(len) as f64 / 1_000.0;
+                        assert!(
+                            (expected - actual).abs() < 0.001,
+                            "{timing:?}, {len} bytes: {actual} ms instead of {expected} ms"
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn hand_computed_values() {
+    let timing = PacketTiming {
+        spreading_factor: SpreadingFactor::Sf6,
+        bandwidth: Bandwidth::Khz125,
+        coding_rate: CodingRate::Cr4_5,
+        preamble_length: 12,
+        implicit_header: false,
+        crc: true,
+        low_data_rate_optimize: false,
+    };
+    // 12 + 6.25 + 8 + 4 * 5 symbols of 512 µs.
+    assert_eq!(timing.time_on_air_us(10), 23_680);
+
+    let timing = PacketTiming {
+        spreading_factor: SpreadingFactor::Sf9,
+        bandwidth: Bandwidth::Khz125,
+        preamble_length: 8,
+        ..timing
+    };
+    assert_eq!(timing.payload_symbols(10), 23);
+    assert_eq!(timing.time_on_air_us(10), 144_384);
+    let optimized = PacketTiming {
+        low_data_rate_optimize: true,
+        ..timing
+    };
+    assert_eq!(optimized.payload_symbols(10), 28);
+    assert_eq!(optimized.time_on_air_us(10), 164_864);
+    assert_eq!(timing.preamble_time_us(), 50_176);
+}
+
+#[test]
+fn low_data_rate_optimization_follows_the_driver() {
+    assert!(!low_data_rate_optimize(
+        SpreadingFactor::Sf10,
+        Bandwidth::Khz125
+    ));
+    assert!(low_data_rate_optimize(
+        SpreadingFactor::Sf11,
+        Bandwidth::Khz125
+    ));
+    assert!(low_data_rate_optimize(
+        SpreadingFactor::Sf12,
+        Bandwidth::Khz250
+    ));
+    assert!(!low_data_rate_optimize(
+        SpreadingFactor::Sf12,
+        Bandwidth::Khz500
+    ));
+    // Symbols as long as the SF11 ones at 125 kHz, yet the driver leaves it off.
+    assert!(!low_data_rate_optimize(
+        SpreadingFactor::Sf10,
+        Bandwidth::Khz62_5
+    ));
+    assert!(!low_data_rate_optimize(
+        SpreadingFactor::Sf12,
+        Bandwidth::Khz31_25
+    ));
+    let timing = PacketTiming::from_config(&config(SpreadingFactor::Sf12, Bandwidth::Khz125));
+    assert!(timing.low_data_rate_optimize);
+}
+
+#[test]
+fn sensitivity_matches_datasheet() {
+    // SX1261/2 datasheet, table 3-8, within a dB.
+    for (spreading_factor, bandwidth, datasheet) in [
+        (SpreadingFactor::Sf7, Bandwidth::Khz125, -124.0),
+        (SpreadingFactor::Sf12, Bandwidth::Khz125, -137.0),
+        (SpreadingFactor::Sf10, Bandwidth::Khz125, -132.0),
+    ] {
+        let sensitivity = sensitivity_dbm(spreading_factor, bandwidth);
+        assert!(
+            (sensitivity - datasheet).abs() <= 1.0,
+            "{spreading_factor:?} {bandwidth:?}: {sensitivity}"
+        );
+    }
+}
+
+#[test]
+fn link_budget() {
+    let config = RadioConfig {
+        output_power: 14,
+        ..config(SpreadingFactor::Sf12, Bandwidth::Khz125)
+    };
+    assert!((link_budget_db(&config) - 151.03).abs() < 0.01);
+    assert_eq!(snr_margin_db(SpreadingFactor::Sf12, -15), 5.0);
+    assert_eq!(snr_margin_db(SpreadingFactor::Sf7, -10), -2.5);
+}
@@ -0,0 +1,116 @@
+use lorelay_proto::battery::{
+    charge_percent, AdcConfig, BatteryConfig, BatteryMonitor, BatteryReading, Chemistry, Divider,
+};
+
+/// The SAADC of the nRF52840, with the battery wired to the input.
+fn direct() -> AdcConfig {
+    AdcConfig {
+        divider: None,
+        ..AdcConfig::default()
+    }
+}
+
+#[test]
+fn converts_samples_to_millivolts() {
+    let adc = direct();
+    assert_eq!(adc.millivolts(0), 0);
+    assert_eq!(adc.millivolts(-3), 0, "noise around 0 V");
+    assert_eq!(adc.millivolts(4095), 3599);
+    assert_eq!(adc.millivolts(2048), 1800);
+
+    // Half of the battery voltage reaches the input, on 10 bits with gain 1/4.
+    let divided = AdcConfig {
+        resolution_bits: 10,
+        gain_numerator: 1,
+        gain_denominator: 4,
+        divider: Some(Divider {
+            top_ohm: 100_000,
+            bottom_ohm: 100_000,
+        }),
+        ..adc
+    };
+    assert_eq!(divided.millivolts(853), 3998);
+    assert_eq!(divided.millivolts(i16::MAX), 153_595, "no overflow");
+
+    // The default divider halves the battery voltage.
+    assert_eq!(AdcConfig::default().millivolts(2048), 3600);
+    assert_eq!(AdcConfig::default().millivolts(4095), 7198);
+}
+
+#[test]
+fn reads_a_charged_cell_with_the_defaults() {
+    let mut monitor = BatteryMonitor::<1>::new(BatteryConfig::default());
+    // 4.2 V, halved to 2.1 V at the input.
+    assert_eq!(
+        monitor.push(2389),
+        BatteryReading {
+            millivolts: 4199,
+            percent: 100
+        }
+    );
+}
+
+#[test]
+fn interpolates_the_discharge_curves() {
+    for chemistry in [Chemistry::LiIon, Chemistry::LiFePo4, Chemistry::Alkaline] {
+        let curve = chemistry.curve();
+        assert!(curve.windows(2).all(|w| w[0].0 > w[1].0 && w[0].1 > w[1].1));
+        assert_eq!(curve.first().unwrap().1, 100);
+        assert_eq!(curve.last().unwrap().1, 0);
+        for &(mv, percent) in curve {
+            assert_eq!(charge_percent(curve, u32::from(mv)), percent);
+        }
+        // The charge never rises as the voltage drops.
+        let mut last = 100;
+        for mv in (0..5_000).rev() {
+            let percent = charge_percent(curve, mv);
+            assert!(percent <= last, "{chemistry:?} at {mv} mV");
+            last = percent;
+        }
+    }
+
+    let li_ion = Chemistry::LiIon.curve();
+    assert_eq!(charge_percent(li_ion, 4_500), 100);
+    assert_eq!(charge_percent(li_ion, 4_150), 95);
+    assert_eq!(charge_percent(li_ion, 3_845), 53);
+    assert_eq!(charge_percent(li_ion, 3_000), 0);
+    assert_eq!(charge_percent(Chemistry::Alkaline.curve(), 1_450), 80);
+    assert_eq!(charge_percent(&[], 3_700), 0);
+}
+
+#[test]
+fn averages_the_last_samples() {
+    let config = BatteryConfig {
+        adc: direct(),
+        ..BatteryConfig::default()
+    };
+    let mut monitor = BatteryMonitor::<4>::new(config);
+    // 4095 is 3599 mV, 3413 is 3000 mV.
+    assert_eq!(
+        monitor.push(4095),
+        BatteryReading {
+            millivolts: 3599,
+            percent: 5
+        }
+    );
+    let readings: Vec<_> = (0..4).map(|_| monitor.push(3413)).collect();
+    assert_eq!(readings[0].millivolts, (3599 + 3000) / 2);
+    assert_eq!(
+        readings[3].millivolts, 3000,
+        "the first sample is forgotten"
+    );
+}
+
+#[test]
+fn maps_packs_of_cells() {
+    let config = BatteryConfig {
+        adc: direct(),
+        chemistry: Chemistry::Alkaline,
+        cells: 2,
+    };
+    let mut monitor = BatteryMonitor::<1>::new(config);
+    let reading = monitor.push(3300);
+    assert_eq!(reading.millivolts, 2900);
+    assert_eq!(reading.percent, 80);
+    assert_eq!(reading.voltage_characteristic(), 185);
+}
@@ -0,0 +1,144 @@
+use std::collections::VecDeque;
+
+use lorelay_proto::channel_access::{
+    Access, Backoff, ChannelAccess, ChannelAccessConfig, RandomBackoff,
+};
+
+/// Radio whose channel activity detections return a scripted sequence, clear once it runs out.
+struct MockRadio {
+    busy: VecDeque<bool>,
+    detections: usize,
+    sent_at: Vec<u64>,
+}
+
+impl MockRadio {
+    fn new(busy: &[bool]) -> Self {
+        MockRadio {
+            busy: busy.iter().copied().collect(),
+            detections: 0,
+            sent_at: Vec::new(),
+        }
+    }
+
+    fn cad(&mut self) -> bool {
+        self.detections += 1;
+        self.busy.pop_front().unwrap_or(false)
+    }
+
+    /// Sends one frame the way the firmware does, returning the time it went out.
+    fn send<B: Backoff>(&mut self, access: &mut ChannelAccess<B>, mut now: u64) -> Option<u64> {
+        loop {
+            match access.on_detection(self.cad()) {
+                Access::Transmit => {
+                    self.sent_at.push(now);
+                    return Some(now);
+                }
+                Access::Wait(delay) => now += delay,
+                Access::ChannelBusy => return None,
+            }
+        }
+    }
+}
+
+/// Backoff waiting `attempt` times its slot, to check what the policy is asked.
+struct Linear {
+    slot_ms: u64,
+    asked: Vec<u8>,
+}
+
+impl Backoff for Linear {
+    fn delay_ms(&mut self, attempt: u8) -> u64 {
+        self.asked.push(attempt);
+        self.slot_ms * u64::from(attempt)
+    }
+}
+
+fn linear() -> Linear {
+    Linear {
+        slot_ms: 100,
+        asked: Vec::new(),
+    }
+}
+
+#[test]
+fn clear_channel_transmits_at_once() {
+    let mut access = ChannelAccess::new(ChannelAccessConfig::default(), linear());
+    let mut radio = MockRadio::new(&[]);
+    assert_eq!(radio.send(&mut access, 1_000), Some(1_000));
+    assert_eq!(radio.detections, 1);
+    assert!(access.backoff_mut().asked.is_empty());
+}
+
+#[test]
+fn busy_channel_backs_off_until_clear() {
+    let mut access = ChannelAccess::new(ChannelAccessConfig::default(), linear());
+    let mut radio = MockRadio::new(&[true, true, true]);
+    // 100 + 200 + 300 ms of backoff.
+    assert_eq!(radio.send(&mut access, 0), Some(600));
+    assert_eq!(radio.detections, 4);
+    assert_eq!(access.backoff_mut().asked, [1, 2, 3]);
+}
+
+#[test]
+fn gives_up_after_max_attempts() {
+    let config = ChannelAccessConfig { max_attempts: 3 };
+    let mut access = ChannelAccess::new(config, linear());
+    let mut radio = MockRadio::new(&[true; 6]);
+    assert_eq!(radio.send(&mut access, 0), None);
+    assert_eq!(radio.detections, 3);
+    assert_eq!(access.backoff_mut().asked, [1, 2]);
+
+    // The next transmission gets all its attempts again.
+    assert_eq!(radio.send(&mut access, 0), None);
+    assert_eq!(radio.detections, 6);
+    assert_eq!(radio.send(&mut access, 0), Some(0));
+    assert_eq!(radio.sent_at, [0]);
+}
+
+#[test]
+fn reset_abandons_the_transmission() {
+    let config = ChannelAccessConfig { max_attempts: 3 };
+    let mut access = ChannelAccess::new(config, linear());
+    assert_eq!(access.on_detection(true), Access::Wait(100));
+    assert_eq!(access.on_detection(true), Access::Wait(200));
+    // The third detection failed, the next transmission starts over.
+    access.reset();
+    assert_eq!(access.on_detection(true), Access::Wait(100));
+    assert_eq!(access.backoff_mut().asked, [1, 2, 1]);
+}
+
+#[test]
+fn single_attempt_never_waits() {
+    let config = ChannelAccessConfig { max_attempts: 1 };
+    let mut access = ChannelAccess::new(config, linear());
+    assert_eq!(access.on_detection(true), Access::ChannelBusy);
+    assert_eq!(access.on_detection(false), Access::Transmit);
+    assert!(access.backoff_mut().asked.is_empty());
+}
+
+#[test]
+fn random_backoff_is_bounded_and_grows() {
+    let mut backoff = RandomBackoff::new(7, 50, 4);
+    for attempt in 1..=10u8 {
+        let max_slots = 1u64 << attempt.min(4);
+        for _ in 0..200 {
+            let delay = backoff.delay_ms(attempt);
+            assert_eq!(delay % 50, 0);
+            assert!((50..=50 * max_slots).contains(&delay), "{attempt}: {delay}");
+        }
+    }
+    let longest = (0..200).map(|_| backoff.delay_ms(4)).max().unwrap();
+    assert!(longest > 50 * 8);
+}
+
+#[test]
+fn random_backoff_spreads_contending_nodes() {
+    // Relays that heard the same flood and found the channel busy should not retry together.
+    let delays: Vec<u64> = (1..=8)
+        .map(|seed| RandomBackoff::new(seed, 50, 4).delay_ms(3))
+        .collect();
+    let mut distinct = delays.clone();
+    distinct.sort_unstable();
+    distinct.dedup();
+    assert!(distinct.len() > 3, "{delays:?}");
+}
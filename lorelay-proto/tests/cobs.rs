@@ -0,0 +1,54 @@
+use lorelay_proto::cobs::{decode, encode, max_encoded_len};
+use lorelay_proto::message::EncodeError;
+
+fn round_trip(data: &[u8]) -> Vec<u8> {
+    let mut encoded = vec![0; max_encoded_len(data.len())];
+    let len = encode(data, &mut encoded).unwrap();
+    encoded.truncate(len);
+    assert!(!encoded.contains(&0), "{encoded:?}");
+
+    let mut decoded = vec![0; data.len()];
+    assert_eq!(decode(&encoded, &mut decoded), Some(data.len()));
+    assert_eq!(decoded, data);
+    encoded
+}
+
+#[test]
+fn known_encodings() {
+    assert_eq!(round_trip(&[]), [1]);
+    assert_eq!(round_trip(&[0]), [1, 1]);
+    assert_eq!(round_trip(&[0, 0]), [1, 1, 1]);
+    assert_eq!(round_trip(&[0x11, 0x22, 0, 0x33]), [3, 0x11, 0x22, 2, 0x33]);
+    assert_eq!(round_trip(&[0x11, 0, 0, 0]), [2, 0x11, 1, 1, 1]);
+}
+
+#[test]
+fn long_runs() {
+    let run: Vec<u8> = (1..=254).collect();
+    let encoded = round_trip(&run);
+    assert_eq!(encoded.len(), max_encoded_len(run.len()));
+    assert_eq!((encoded[0], encoded[255]), (0xff, 1));
+
+    let data: Vec<u8> = (0..1_000).map(|i| (i % 7) as u8).collect();
+    round_trip(&data);
+    let data: Vec<u8> = (0..1_000).map(|i| (i % 255 + 1) as u8).collect();
+    round_trip(&data);
+}
+
+#[test]
+fn rejects_invalid_input() {
+    let mut buf = [0; 8];
+    assert_eq!(decode(&[3, 0x11], &mut buf), None, "truncated");
+    assert_eq!(decode(&[3, 0x11, 0, 1], &mut buf), None, "zero in a block");
+    assert_eq!(decode(&[0, 0x11], &mut buf), None, "zero code");
+    assert_eq!(
+        decode(&[5, 1, 2, 3, 4], &mut buf[..3]),
+        None,
+        "buffer too small"
+    );
+
+    assert_eq!(
+        encode(&[1, 2, 3], &mut buf[..3]),
+        Err(EncodeError::BufferTooSmall)
+    );
+}
@@ -0,0 +1,81 @@
+use lorelay_proto::connections::{Characteristic, ConnectionRegistry, Subscriptions};
+
+fn subscribers(
+    registry: &ConnectionRegistry<&'static str, 3>,
+    characteristic: Characteristic,
+) -> Vec<&'static str> {
+    registry
+        .subscribers(characteristic)
+        .map(|(_, &name)| name)
+        .collect()
+}
+
+#[test]
+fn tracks_subscriptions() {
+    let mut subscriptions = Subscriptions::new();
+    assert!(subscriptions.is_empty());
+    subscriptions.set(Characteristic::Incoming, true);
+    subscriptions.set(Characteristic::ConsoleTx, true);
+    subscriptions.set(Characteristic::ConsoleTx, true);
+    assert!(subscriptions.contains(Characteristic::Incoming));
+    assert!(subscriptions.contains(Characteristic::ConsoleTx));
+    assert!(!subscriptions.contains(Characteristic::Status));
+    subscriptions.set(Characteristic::Incoming, false);
+    subscriptions.set(Characteristic::ConsoleTx, false);
+    assert!(subscriptions.is_empty());
+}
+
+#[test]
+fn registers_up_to_n_connections() {
+    let mut registry = ConnectionRegistry::<&str, 3>::new();
+    assert!(registry.is_empty());
+    assert_eq!(registry.add(1, "phone"), Ok(()));
+    assert_eq!(registry.add(1, "again"), Err("again"), "handle taken");
+    assert_eq!(registry.add(2, "tablet"), Ok(()));
+    assert_eq!(registry.add(5, "laptop"), Ok(()));
+    assert!(registry.is_full());
+    assert_eq!(registry.add(6, "watch"), Err("watch"));
+
+    assert_eq!(registry.remove(2), Some("tablet"));
+    assert_eq!(registry.remove(2), None);
+    assert_eq!(registry.len(), 2);
+    assert_eq!(registry.get(5), Some(&"laptop"));
+    assert_eq!(registry.add(2, "watch"), Ok(()));
+    assert_eq!(registry.subscriptions(2), Some(Subscriptions::new()));
+}
+
+#[test]
+fn broadcasts_to_subscribers() {
+    let mut registry = ConnectionRegistry::<&str, 3>::new();
+    for (handle, name) in [(1, "phone"), (2, "tablet"), (3, "logger")] {
+        registry.add(handle, name).unwrap();
+    }
+    assert!(subscribers(&registry, Characteristic::Incoming).is_empty());
+
+    assert!(registry.set_subscribed(1, Characteristic::Incoming, true));
+    assert!(registry.set_subscribed(3, Characteristic::Incoming, true));
+    assert!(registry.set_subscribed(3, Characteristic::Status, true));
+    assert!(!registry.set_subscribed(4, Characteristic::Incoming, true));
+    assert_eq!(
+        subscribers(&registry, Characteristic::Incoming),
+        ["phone", "logger"]
+    );
+    assert_eq!(subscribers(&registry, Characteristic::Status), ["logger"]);
+    assert_eq!(
+        registry
+            .subscribers(Characteristic::Status)
+            .map(|(handle, _)| handle)
+            .collect::<Vec<_>>(),
+        [3]
+    );
+
+    // Unsubscribed, then disconnected.
+    registry.set_subscribed(1, Characteristic::Incoming, false);
+    assert_eq!(subscribers(&registry, Characteristic::Incoming), ["logger"]);
+    registry.remove(3);
+    assert!(subscribers(&registry, Characteristic::Incoming).is_empty());
+
+    // A new client on a reused handle starts without the subscriptions of the previous one.
+    registry.add(3, "phone 2").unwrap();
+    assert_eq!(registry.subscriptions(3), Some(Subscriptions::new()));
+}
@@ -0,0 +1,359 @@
+use heapless::Vec;
+use lorelay_proto::console::{
+    dispatch, parse, Busy, Command, ConfigKey, Console, Node, ParseError,
+};
+use lorelay_proto::device_config::DeviceConfig;
+use lorelay_proto::gateway::{NeighbourEntry, NodeStatus, OutgoingMessage};
+use lorelay_proto::message::BROADCAST_UID;
+use lorelay_proto::radio::{Bandwidth, RadioConfig};
+use lorelay_proto::region::Region;
+use lorelay_proto::relay::NEIGHBOUR_TABLE_SIZE;
+
+#[derive(Default)]
+struct MockNode {
+    status: Option<NodeStatus>,
+    neighbours: Vec<NeighbourEntry, NEIGHBOUR_TABLE_SIZE>,
+    config: Option<DeviceConfig>,
+    busy: bool,
+    sent: std::vec::Vec<OutgoingMessage>,
+    stored: std::vec::Vec<DeviceConfig>,
+    modes: std::vec::Vec<u8>,
+    reboots: usize,
+}
+
+impl MockNode {
+    fn known() -> Self {
+        MockNode {
+            status: Some(NodeStatus {
+                uid: 7,
+                mode: 1,
+                neighbours: 1,
+                routes: 2,
+                uptime_s: 30,
+            }),
+            neighbours: Vec::from_slice(&[NeighbourEntry {
+                uid: 3,
+                rssi: -80,
+                snr: 6,
+                age_s: 4,
+            }])
+            .unwrap(),
+            config: Some(DeviceConfig {
+                radio: RadioConfig::default(),
+                ..DeviceConfig::new(Region::Eu433)
+            }),
+            ..MockNode::default()
+        }
+    }
+
+    fn request(&self) -> Result<(), Busy> {
+        if self.busy {
+            Err(Busy)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Node for MockNode {
+    fn status(&self) -> Option<NodeStatus> {
+        self.status
+    }
+
+    fn neighbours(&self) -> Vec<NeighbourEntry, NEIGHBOUR_TABLE_SIZE> {
+        self.neighbours.clone()
+    }
+
+    fn config(&self) -> Option<DeviceConfig> {
+        self.config
+    }
+
+    fn send(&mut self, message: OutgoingMessage) -> Result<(), Busy> {
+        self.request()?;
+        self.sent.push(message);
+        Ok(())
+    }
+
+    fn set_config(&mut self, config: DeviceConfig) -> Result<(), Busy> {
+        self.request()?;
+        self.stored.push(config);
+        Ok(())
+    }
+
+    fn set_mode(&mut self, mode: u8) -> Result<(), Busy> {
+        self.request()?;
+        self.modes.push(mode);
+        Ok(())
+    }
+
+    fn reboot(&mut self) -> Result<(), Busy> {
+        self.request()?;
+        self.reboots += 1;
+        Ok(())
+    }
+}
+
+/// Response of `node` to `line`.
+fn run(node: &mut MockNode, line: &str) -> String {
+    let mut out = String::new();
+    Console::new()
+        .on_bytes(format!("{line}\n").as_bytes(), node, &mut out)
+        .unwrap();
+    out
+}
+
+#[test]
+fn parses_commands() {
+    assert_eq!(parse(""), None);
+    assert_eq!(parse("  \t "), None);
+    assert_eq!(parse("status"), Some(Ok(Command::Status)));
+    assert_eq!(parse(" neighbours "), Some(Ok(Command::Neighbours)));
+    assert_eq!(
+        parse("send 0x1f  hello   world "),
+        Some(Ok(Command::Send {
+            destination_uid: 0x1f,
+            text: "hello   world"
+        }))
+    );
+    assert_eq!(
+        parse("send all hi"),
+        Some(Ok(Command::Send {
+            destination_uid: BROADCAST_UID,
+            text: "hi"
+        }))
+    );
+    assert_eq!(parse("config get"), Some(Ok(Command::ConfigGet(None))));
+    assert_eq!(
+        parse("config get sf"),
+        Some(Ok(Command::ConfigGet(Some(ConfigKey::SpreadingFactor))))
+    );
+    assert_eq!(
+        parse("config set bw 250000"),
+        Some(Ok(Command::ConfigSet {
+            key: ConfigKey::Bandwidth,
+            value: "250000"
+        }))
+    );
+    assert_eq!(parse("mode"), Some(Ok(Command::Mode(None))));
+    assert_eq!(parse("mode sniffer"), Some(Ok(Command::Mode(Some(2)))));
+    assert_eq!(parse("reboot"), Some(Ok(Command::Reboot)));
+
+    for key in ConfigKey::ALL {
+        assert_eq!(ConfigKey::from_name(key.name()), Some(key));
+    }
+}
+
+#[test]
+fn rejects_invalid_commands() {
+    assert_eq!(
+        parse("reset"),
+        Some(Err(ParseError::UnknownCommand("reset")))
+    );
+    assert_eq!(parse("send"), Some(Err(ParseError::MissingArgument("uid"))));
+    assert_eq!(
+        parse("send 12"),
+        Some(Err(ParseError::MissingArgument("text")))
+    );
+    assert_eq!(
+        parse("send 70000 hi"),
+        Some(Err(ParseError::InvalidArgument("uid", "70000")))
+    );
+    let text = "x".repeat(241);
+    assert_eq!(
+        parse(&format!("send 1 {text}")),
+        Some(Err(ParseError::TextTooLong))
+    );
+    assert_eq!(
+        parse("config set sf"),
+        Some(Err(ParseError::MissingArgument("value")))
+    );
+    assert_eq!(
+        parse("config get foo"),
+        Some(Err(ParseError::InvalidArgument("key", "foo")))
+    );
+    assert_eq!(
+        parse("config del sf"),
+        Some(Err(ParseError::InvalidArgument("action", "del")))
+    );
+    assert_eq!(
+        parse("mode turbo"),
+        Some(Err(ParseError::InvalidArgument("mode", "turbo")))
+    );
+    assert_eq!(parse("status now"), Some(Err(ParseError::TooManyArguments)));
+}
+
+#[test]
+fn shows_the_node() {
+    let mut node = MockNode::known();
+    assert_eq!(
+        run(&mut node, "status"),
+        "uid 7\nmode range-test\n1 neighbours, 2 routes\nup 30 s\n"
+    );
+    assert_eq!(
+        run(&mut node, "neighbours"),
+        "3: -80 dBm, SNR 6 dB, 4 s ago\n"
+    );
+    assert_eq!(run(&mut node, "config get cr"), "cr 8\n");
+    assert_eq!(
+        run(&mut node, "config get").lines().count(),
+        ConfigKey::ALL.len()
+    );
+    assert_eq!(run(&mut node, "config get uid"), "uid auto\n");
+    assert_eq!(run(&mut node, "mode"), "mode relay\n");
+    assert!(run(&mut node, "help").contains("range-test|sniffer"));
+
+    let mut unknown = MockNode::default();
+    assert_eq!(run(&mut unknown, "status"), "status unknown\n");
+    assert_eq!(run(&mut unknown, "neighbours"), "no neighbours\n");
+    assert_eq!(run(&mut unknown, "config get"), "config unknown\n");
+    assert_eq!(run(&mut unknown, "config set sf 9"), "config unknown\n");
+}
+
+#[test]
+fn sends_messages() {
+    let mut node = MockNode::known();
+    assert_eq!(run(&mut node, "send 12 hello there"), "queued\n");
+    assert_eq!(run(&mut node, "send all hi"), "queued\n");
+    let [unicast, broadcast] = &node.sent[..] else {
+        panic!("{} messages sent", node.sent.len());
+    };
+    assert_eq!(
+        (unicast.destination_uid, unicast.ack, &unicast.payload[..]),
+        (12, true, &b"hello there"[..])
+    );
+    assert_eq!(
+        (broadcast.destination_uid, broadcast.ack),
+        (BROADCAST_UID, false)
+    );
+
+    node.busy = true;
+    assert_eq!(run(&mut node, "send 12 again"), "error: busy, try again\n");
+    assert_eq!(node.sent.len(), 2);
+}
+
+#[test]
+fn stores_valid_configurations() {
+    let mut node = MockNode::known();
+    assert_eq!(
+        run(&mut node, "config set bw 125000"),
+        "stored, applied after a reboot\n"
+    );
+    assert_eq!(run(&mut node, "config set uid 0x2a").lines().count(), 1);
+    let [bandwidth, uid] = &node.stored[..] else {
+        panic!("{} configurations stored", node.stored.len());
+    };
+    assert_eq!(bandwidth.radio.bandwidth, Bandwidth::Khz125);
+    assert_eq!(uid.uid, Some(0x2a));
+    assert_eq!(uid.radio.bandwidth, Bandwidth::Khz250, "one key at a time");
+
+    assert_eq!(run(&mut node, "config set sf 13"), "error: invalid sf 13\n");
+    assert_eq!(
+        run(&mut node, "config set bw 100"),
+        "error: invalid bw 100\n"
+    );
+    assert_eq!(
+        run(&mut node, &format!("config set uid {BROADCAST_UID}")),
+        format!("error: invalid uid {BROADCAST_UID}\n")
+    );
+    assert_eq!(
+        run(&mut node, "config set freq 2400000000"),
+        "error: frequency 2400000000 Hz out of range\n"
+    );
+    assert_eq!(node.stored.len(), 2);
+}
+
+#[test]
+fn keeps_configurations_to_the_region() {
+    let mut node = MockNode::known();
+    assert_eq!(
+        run(&mut node, "config set power 20"),
+        "error: power 20 dBm too high for the region\n"
+    );
+    assert_eq!(
+        run(&mut node, "config set freq 868100000"),
+        "error: frequency 868100000 Hz not allowed in the region\n"
+    );
+    assert!(node.stored.is_empty());
+}
+
+#[test]
+fn switches_regions() {
+    let mut node = MockNode::known();
+    assert_eq!(run(&mut node, "config get region"), "region eu433\n");
+    assert_eq!(
+        run(&mut node, "config set region eu868"),
+        "stored, applied after a reboot\n"
+    );
+    assert_eq!(
+        run(&mut node, "config set region mars"),
+        "error: invalid region mars\n"
+    );
+    let [switched] = &node.stored[..] else {
+        panic!("{} configurations stored", node.stored.len());
+    };
+    // The radio settings of the old region are not allowed in the new one.
+    assert_eq!(switched.region, Region::Eu868);
+    assert_eq!(switched.radio, Region::Eu868.default_config());
+}
+
+#[test]
+fn switches_modes_and_reboots() {
+    let mut node = MockNode::known();
+    assert_eq!(run(&mut node, "mode sniffer"), "switching\n");
+    assert_eq!(run(&mut node, "reboot"), "rebooting\n");
+    assert_eq!((&node.modes[..], node.reboots), (&[2][..], 1));
+
+    node.busy = true;
+    assert_eq!(run(&mut node, "reboot"), "error: busy, try again\n");
+    assert_eq!(node.reboots, 1);
+}
+
+#[test]
+fn splits_lines_across_writes() {
+    let mut node = MockNode::known();
+    let mut console = Console::new();
+    let mut out = String::new();
+    console.on_bytes(b"mo", &mut node, &mut out).unwrap();
+    assert_eq!(out, "");
+    console
+        .on_bytes(b"de\r\n\r\nconfig get sf\nmode", &mut node, &mut out)
+        .unwrap();
+    assert_eq!(out, "mode relay\nsf 10\n");
+    console.on_bytes(b" x\n", &mut node, &mut out).unwrap();
+    assert_eq!(out, "mode relay\nsf 10\nerror: invalid mode x\n");
+}
+
+#[test]
+fn drops_overlong_and_invalid_lines() {
+    let mut node = MockNode::known();
+    let mut console = Console::new();
+    let mut out = String::new();
+    let long = [b'a'; 300];
+    console.on_bytes(&long, &mut node, &mut out).unwrap();
+    console.on_bytes(b"\nmode\n", &mut node, &mut out).unwrap();
+    assert_eq!(out, "error: line too long\nmode relay\n");
+
+    out.clear();
+    console
+        .on_bytes(b"send 1 \xff\xfe\nfoo\n", &mut node, &mut out)
+        .unwrap();
+    assert_eq!(
+        out,
+        "error: invalid UTF-8\nerror: unknown command foo, try help\n"
+    );
+    assert!(node.sent.is_empty());
+
+    // Direct dispatch of a command built by hand still checks the text fits.
+    let text = "x".repeat(241);
+    out.clear();
+    dispatch(
+        Command::Send {
+            destination_uid: 1,
+            text: &text,
+        },
+        &mut node,
+        &mut out,
+    )
+    .unwrap();
+    assert_eq!(out, "error: text longer than 240 bytes\n");
+}
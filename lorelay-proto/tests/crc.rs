@@ -0,0 +1,12 @@
+use lorelay_proto::crc::{crc16, crc16_update};
+
+#[test]
+fn matches_check_value() {
+    assert_eq!(crc16(b"123456789"), 0x29b1);
+    assert_eq!(crc16(b""), 0xffff);
+}
+
+#[test]
+fn updates_in_pieces() {
+    assert_eq!(crc16_update(crc16(b"1234"), b"56789"), crc16(b"123456789"));
+}
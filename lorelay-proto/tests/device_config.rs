@@ -0,0 +1,186 @@
+use lorelay_proto::crc::crc16;
+use lorelay_proto::device_config::{
+    uid_from_unique_id, DeviceConfig, RecordError, DEFAULT_REGION, RECORD_SIZE, RECORD_VERSION,
+};
+use lorelay_proto::message::{EncodeError, BROADCAST_UID};
+use lorelay_proto::radio::{ConfigError, RadioConfig, SpreadingFactor};
+use lorelay_proto::region::Region;
+
+fn config() -> DeviceConfig {
+    DeviceConfig {
+        uid: Some(42),
+        radio: RadioConfig {
+            spreading_factor: SpreadingFactor::Sf7,
+            output_power: 14,
+            implicit_header: true,
+            ..RadioConfig::default()
+        },
+        mode: 2,
+        region: Region::Us915,
+    }
+}
+
+fn encoded(config: &DeviceConfig) -> [u8; RECORD_SIZE] {
+    let mut buf = [0; RECORD_SIZE];
+    assert_eq!(config.encode(&mut buf), Ok(RECORD_SIZE));
+    buf
+}
+
+/// Rewrites the CRC of a record after its body was tampered with.
+fn reseal(record: &mut [u8]) {
+    let end = 6 + usize::from(record[5]);
+    let crc = crc16(&record[..end]);
+    record[end..end + 2].copy_from_slice(&crc.to_le_bytes());
+}
+
+#[test]
+fn round_trip() {
+    let record = encoded(&config());
+    assert_eq!(record[4], RECORD_VERSION);
+    assert_eq!(DeviceConfig::decode(&record), Ok(config()));
+
+    let derived = DeviceConfig {
+        uid: None,
+        ..config()
+    };
+    let record = encoded(&derived);
+    assert_eq!(DeviceConfig::decode(&record), Ok(derived));
+    assert_eq!(derived.uid_or(7), 7);
+    assert_eq!(config().uid_or(7), 42);
+}
+
+#[test]
+fn ignores_padding() {
+    let mut padded = [0xff; 32];
+    config().encode(&mut padded).unwrap();
+    assert_eq!(DeviceConfig::decode(&padded), Ok(config()));
+    assert_eq!(
+        config().encode(&mut padded[..RECORD_SIZE - 1]),
+        Err(EncodeError::BufferTooSmall)
+    );
+}
+
+#[test]
+fn migrates_version_1() {
+    // Version 1 had no mode, its records start in mode 0.
+    let mut record = encoded(&config());
+    record[4] = 1;
+    record[5] -= 2;
+    reseal(&mut record[..RECORD_SIZE - 2]);
+    assert_eq!(
+        DeviceConfig::decode(&record[..RECORD_SIZE - 2]),
+        Ok(DeviceConfig {
+            mode: 0,
+            region: DEFAULT_REGION,
+            ..config()
+        })
+    );
+}
+
+#[test]
+fn migrates_version_2() {
+    // Version 2 had no region, its nodes were all deployed in the default one.
+    let mut record = encoded(&config());
+    record[4] = 2;
+    record[5] -= 1;
+    reseal(&mut record[..RECORD_SIZE - 1]);
+    assert_eq!(
+        DeviceConfig::decode(&record[..RECORD_SIZE - 1]),
+        Ok(DeviceConfig {
+            region: DEFAULT_REGION,
+            ..config()
+        })
+    );
+    assert_eq!(DEFAULT_REGION, Region::Eu433);
+}
+
+#[test]
+fn rejects_damaged_records() {
+    assert_eq!(DeviceConfig::decode(&[0xff; 64]), Err(RecordError::Erased));
+    assert_eq!(DeviceConfig::decode(&[0; 64]), Err(RecordError::BadMagic));
+
+    let record = encoded(&config());
+    assert_eq!(
+        DeviceConfig::decode(&record[..RECORD_SIZE - 1]),
+        Err(RecordError::InvalidLength(record[5]))
+    );
+
+    let mut flipped = record;
+    flipped[8] ^= 0x10;
+    assert_eq!(DeviceConfig::decode(&flipped), Err(RecordError::BadCrc));
+
+    let mut newer = record;
+    newer[4] = RECORD_VERSION + 1;
+    assert_eq!(
+        DeviceConfig::decode(&newer),
+        Err(RecordError::UnsupportedVersion(RECORD_VERSION + 1))
+    );
+
+    let mut short = record;
+    short[5] -= 1;
+    assert_eq!(
+        DeviceConfig::decode(&short),
+        Err(RecordError::InvalidLength(short[5]))
+    );
+}
+
+#[test]
+fn rejects_invalid_radio_settings() {
+    // Spreading factor, right after the UID and the frequency.
+    let mut record = encoded(&config());
+    record[13] = 13;
+    reseal(&mut record);
+    assert_eq!(
+        DeviceConfig::decode(&record),
+        Err(RecordError::InvalidField)
+    );
+
+    // Region, the last byte of the body.
+    let mut record = encoded(&config());
+    record[RECORD_SIZE - 3] = Region::ALL.len() as u8;
+    reseal(&mut record);
+    assert_eq!(
+        DeviceConfig::decode(&record),
+        Err(RecordError::InvalidField)
+    );
+
+    let mut loud = config();
+    loud.radio.output_power = 30;
+    let record = encoded(&loud);
+    assert_eq!(
+        DeviceConfig::decode(&record),
+        Err(RecordError::InvalidRadioConfig(
+            ConfigError::OutputPowerOutOfRange(30)
+        ))
+    );
+}
+
+#[test]
+fn derived_uid_is_never_broadcast() {
+    let a = uid_from_unique_id(&[
+        0x20, 0x00, 0x43, 0x00, 0x0f, 0x51, 0x4e, 0x4b, 0x37, 0x32, 0x33, 0x20,
+    ]);
+    let b = uid_from_unique_id(&[
+        0x20, 0x00, 0x43, 0x00, 0x0f, 0x51, 0x4e, 0x4b, 0x37, 0x32, 0x33, 0x21,
+    ]);
+    assert_ne!(a, b);
+    assert_eq!(
+        a,
+        uid_from_unique_id(&[
+            0x20, 0x00, 0x43, 0x00, 0x0f, 0x51, 0x4e, 0x4b, 0x37, 0x32, 0x33, 0x20
+        ])
+    );
+
+    // Any two bytes can be appended to reach a given CRC; search for one hitting broadcast.
+    let unique_id = (0..=u16::MAX)
+        .map(|suffix| {
+            let mut id = [
+                0x20, 0x00, 0x43, 0x00, 0x0f, 0x51, 0x4e, 0x4b, 0x37, 0x32, 0, 0,
+            ];
+            id[10..].copy_from_slice(&suffix.to_be_bytes());
+            id
+        })
+        .find(|id| crc16(id) == BROADCAST_UID)
+        .unwrap();
+    assert_ne!(uid_from_unique_id(&unique_id), BROADCAST_UID);
+}
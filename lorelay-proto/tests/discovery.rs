@@ -0,0 +1,126 @@
+use lorelay_proto::discovery::{BeaconConfig, Discovery};
+use lorelay_proto::message::{
+    MessageBuilder, MessageType, RouteAdvert, RouteAdverts, BROADCAST_UID,
+};
+
+const CONFIG: BeaconConfig = BeaconConfig {
+    interval: 10_000,
+    jitter: 1_000,
+    ttl: 30_000,
+    answer_pings: true,
+};
+
+/// Steps a fake clock through `until`, collecting when each message was emitted.
+fn run(discovery: &mut Discovery, from: u64, until: u64) -> Vec<(u64, MessageType)> {
+    let mut sent = Vec::new();
+    let mut now = from;
+    while now <= until {
+        while let Some(message) = discovery.poll(now, 2, RouteAdverts::new) {
+            sent.push((now, message.message_type().clone()));
+        }
+        now = discovery.next_deadline().max(now + 1);
+    }
+    sent
+}
+
+#[test]
+fn beacons_are_periodic_with_bounded_jitter() {
+    let mut discovery = Discovery::new(1, CONFIG, 0, 42);
+    let sent = run(&mut discovery, 0, 100_000);
+
+    assert!(sent
+        .iter()
+        .all(|(_, t)| matches!(t, MessageType::Ping { .. })));
+    assert!(sent[0].0 <= CONFIG.jitter);
+    for pair in sent.windows(2) {
+        let gap = pair[1].0 - pair[0].0;
+        let allowed = CONFIG.interval..=CONFIG.interval + CONFIG.jitter;
+        assert!(allowed.contains(&gap), "gap {gap}");
+    }
+    assert!((9..=11).contains(&sent.len()));
+}
+
+#[test]
+fn jitter_differs_between_seeds() {
+    let a = run(&mut Discovery::new(1, CONFIG, 0, 1), 0, 50_000);
+    let b = run(&mut Discovery::new(2, CONFIG, 0, 2), 0, 50_000);
+    assert_ne!(
+        a.iter().map(|(t, _)| *t).collect::<Vec<_>>(),
+        b.iter().map(|(t, _)| *t).collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn ping_is_answered_with_delayed_pong() {
+    let mut discovery = Discovery::new(1, CONFIG, 0, 7);
+    // Skip the first beacon.
+    run(&mut discovery, 0, CONFIG.jitter);
+
+    let ping = MessageBuilder::new(5).ping();
+    discovery.on_message(&ping, 2_000);
+    assert!(discovery.next_deadline() <= 2_000 + CONFIG.jitter);
+
+    let sent = run(&mut discovery, 2_000, 2_000 + CONFIG.jitter);
+    assert_eq!(
+        sent.iter().map(|(_, t)| t.clone()).collect::<Vec<_>>(),
+        [MessageType::Pong {
+            destination_uid: 5,
+            neighbour_count: 2
+        }]
+    );
+}
+
+#[test]
+fn concurrent_pings_get_one_broadcast_pong() {
+    let mut discovery = Discovery::new(1, CONFIG, 0, 7);
+    run(&mut discovery, 0, CONFIG.jitter);
+
+    discovery.on_message(&MessageBuilder::new(5).ping(), 2_000);
+    discovery.on_message(&MessageBuilder::new(6).ping(), 2_001);
+
+    let sent = run(&mut discovery, 2_001, 2_000 + CONFIG.jitter);
+    assert_eq!(sent.len(), 1);
+    assert_eq!(
+        sent[0].1,
+        MessageType::Pong {
+            destination_uid: BROADCAST_UID,
+            neighbour_count: 2
+        }
+    );
+}
+
+#[test]
+fn pings_are_ignored_when_answering_is_disabled() {
+    let config = BeaconConfig {
+        answer_pings: false,
+        ..CONFIG
+    };
+    let mut discovery = Discovery::new(1, config, 0, 7);
+    run(&mut discovery, 0, CONFIG.jitter);
+
+    discovery.on_message(&MessageBuilder::new(5).ping(), 2_000);
+    assert!(run(&mut discovery, 2_000, 9_000).is_empty());
+}
+
+#[test]
+fn only_pings_trigger_pongs() {
+    let mut discovery = Discovery::new(1, CONFIG, 0, 7);
+    run(&mut discovery, 0, CONFIG.jitter);
+
+    discovery.on_message(&MessageBuilder::new(5).pong(1, 3), 2_000);
+    discovery.on_message(&MessageBuilder::new(5).normal(1, b"x").unwrap(), 2_000);
+    assert!(run(&mut discovery, 2_000, 9_000).is_empty());
+}
+
+#[test]
+fn beacons_carry_routes() {
+    let mut discovery = Discovery::new(1, CONFIG, 0, 7);
+    let advert = RouteAdvert {
+        destination_uid: 3,
+        next_hop_uid: 2,
+        metric: 2,
+    };
+    let routes = || RouteAdverts::from_slice(&[advert]).unwrap();
+    let ping = discovery.poll(CONFIG.jitter, 0, routes).unwrap();
+    assert_eq!(*ping.message_type(), MessageType::Ping { routes: routes() });
+}
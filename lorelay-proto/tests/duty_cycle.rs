@@ -0,0 +1,127 @@
+use lorelay_proto::airtime::time_on_air_us;
+use lorelay_proto::duty_cycle::{AirtimeAccountant, Denied, DUTY_CYCLE_WINDOW_MS};
+use lorelay_proto::message::MAX_MESSAGE_SIZE;
+use lorelay_proto::radio::Bandwidth;
+use lorelay_proto::region::Region;
+
+const BW: Bandwidth = Bandwidth::Khz125;
+
+#[test]
+fn one_percent_is_100_times_the_airtime_off() {
+    let mut accountant = AirtimeAccountant::new(Region::Eu868, 0);
+    assert_eq!(accountant.ready_at(868_100_000, BW, 0), Some(0));
+    assert_eq!(accountant.reserve(868_100_000, BW, 1_000_000, 0), Ok(()));
+    assert_eq!(accountant.ready_at(868_100_000, BW, 0), Some(100_000));
+    assert_eq!(
+        accountant.reserve(868_100_000, BW, 1, 0),
+        Err(Denied::Deferred { until: 100_000 })
+    );
+    assert_eq!(
+        accountant.reserve(868_100_000, BW, 1_000_000, 99_999),
+        Err(Denied::Deferred { until: 100_000 })
+    );
+    assert_eq!(
+        accountant.reserve(868_100_000, BW, 1_000_000, 100_000),
+        Ok(())
+    );
+    assert_eq!(accountant.ready_at(868_100_000, BW, 300_000), Some(300_000));
+}
+
+#[test]
+fn sub_bands_have_separate_budgets() {
+    let mut accountant = AirtimeAccountant::new(Region::Eu868, 0);
+    // 0.1% sub-band: 3.6 s per hour.
+    assert_eq!(accountant.reserve(868_900_000, BW, 3_600_000, 0), Ok(()));
+    assert!(accountant.reserve(868_900_000, BW, 1, 0).is_err());
+    assert_eq!(accountant.reserve(868_100_000, BW, 1_000_000, 0), Ok(()));
+    assert_eq!(accountant.reserve(869_525_000, BW, 1_000_000, 0), Ok(()));
+}
+
+#[test]
+fn a_spent_window_budget_blocks_for_a_window() {
+    // 10%: 360 s per hour.
+    let mut accountant = AirtimeAccountant::new(Region::Eu433, 0);
+    assert_eq!(accountant.reserve(433_175_000, BW, 360_000_000, 0), Ok(()));
+    for now in (0..DUTY_CYCLE_WINDOW_MS).step_by(60_000) {
+        assert!(
+            accountant.reserve(433_175_000, BW, 1, now).is_err(),
+            "{now}"
+        );
+    }
+    assert!(accountant
+        .reserve(433_175_000, BW, 1, DUTY_CYCLE_WINDOW_MS - 1)
+        .is_err());
+    assert_eq!(
+        accountant.reserve(433_175_000, BW, 1, DUTY_CYCLE_WINDOW_MS),
+        Ok(())
+    );
+}
+
+#[test]
+fn no_window_exceeds_the_duty_cycle() {
+    let airtime_us = time_on_air_us(&Region::Eu868.default_config(), MAX_MESSAGE_SIZE);
+    let mut accountant = AirtimeAccountant::new(Region::Eu868, 0);
+    // Transmits whenever allowed for three windows, recording the start of every frame.
+    let mut starts = Vec::new();
+    let mut now = 0;
+    while now < 3 * DUTY_CYCLE_WINDOW_MS {
+        match accountant.reserve(868_100_000, BW, airtime_us, now) {
+            Ok(()) => starts.push(now),
+            Err(Denied::Deferred { until }) => now = until,
+            Err(denied) => panic!("{denied:?}"),
+        }
+    }
+    // 1%: 36 s per hour, overrun by the last frame at most.
+    for &start in &starts {
+        let spent_us: u64 = starts
+            .iter()
+            .filter(|&&s| (start..start + DUTY_CYCLE_WINDOW_MS).contains(&s))
+            .map(|_| airtime_us)
+            .sum();
+        assert!(
+            spent_us <= 36_000_000 + airtime_us,
+            "{spent_us} µs from {start} ms"
+        );
+    }
+    let spent_us = starts.len() as u64 * airtime_us;
+    assert!((3 * 36_000_000 - airtime_us..=3 * 36_000_000 + airtime_us).contains(&spent_us));
+}
+
+#[test]
+fn impossible_transmissions_are_rejected() {
+    let mut accountant = AirtimeAccountant::new(Region::Eu868, 0);
+    assert_eq!(
+        accountant.reserve(915_000_000, BW, 1_000, 0),
+        Err(Denied::OutOfBand(915_000_000))
+    );
+    assert_eq!(
+        accountant.reserve(868_900_000, BW, 3_600_001, 0),
+        Err(Denied::TooLong(3_600_001))
+    );
+}
+
+#[test]
+fn us915_has_dwell_time_but_no_duty_cycle() {
+    let mut accountant = AirtimeAccountant::new(Region::Us915, 0);
+    for _ in 0..100_000 {
+        assert_eq!(accountant.reserve(902_300_000, BW, 400_000, 0), Ok(()));
+    }
+    assert_eq!(
+        accountant.reserve(902_300_000, BW, 400_001, 0),
+        Err(Denied::TooLong(400_001))
+    );
+}
+
+#[test]
+fn region_defaults_fit_the_dwell_time() {
+    for region in Region::ALL {
+        let config = region.default_config();
+        let airtime_us = time_on_air_us(&config, MAX_MESSAGE_SIZE);
+        let mut accountant = AirtimeAccountant::new(region, 0);
+        assert_eq!(
+            accountant.reserve(config.frequency_hz, config.bandwidth, airtime_us, 0),
+            Ok(()),
+            "{region:?}"
+        );
+    }
+}
@@ -0,0 +1,193 @@
+use lorelay_proto::flood::{Flood, SeenCache};
+use lorelay_proto::message::{Message, MessageBuilder, BROADCAST_UID};
+use lorelay_proto::routing::NoRoutes;
+use std::collections::VecDeque;
+
+/// Nodes with uid `0..links.len()`, `links[i]` listing the uids in radio range of `i`.
+struct Network {
+    nodes: Vec<Flood<32>>,
+    links: Vec<Vec<u16>>,
+}
+
+struct Outcome {
+    /// Uids that delivered the message to their application.
+    delivered: Vec<u16>,
+    /// Number of frames put on air, the original transmission included.
+    transmissions: usize,
+}
+
+impl Network {
+    fn new(links: Vec<Vec<u16>>) -> Self {
+        Network {
+            nodes: (0..links.len() as u16).map(Flood::new).collect(),
+            links,
+        }
+    }
+
+    fn line(len: u16) -> Self {
+        Network::new(
+            (0..len)
+                .map(|i| (0..len).filter(|&j| i.abs_diff(j) == 1).collect())
+                .collect(),
+        )
+    }
+
+    fn grid(side: u16) -> Self {
+        let pos = |i: u16| ((i % side) as i32, (i / side) as i32);
+        Network::new(
+            (0..side * side)
+                .map(|i| {
+                    (0..side * side)
+                        .filter(|&j| {
+                            let (a, b) = (pos(i), pos(j));
+                            (a.0 - b.0).abs() + (a.1 - b.1).abs() == 1
+                        })
+                        .collect()
+                })
+                .collect(),
+        )
+    }
+
+    /// Sends `message` from its sender and lets the flood run to completion.
+    fn flood(&mut self, message: Message) -> Outcome {
+        let mut delivered = Vec::new();
+        let mut transmissions = 0;
+        let message = self.nodes[message.sender_uid() as usize].originate(message, &NoRoutes);
+        let mut on_air = VecDeque::from([message]);
+
+        while let Some(frame) = on_air.pop_front() {
+            transmissions += 1;
+            for &uid in &self.links[frame.sender_uid() as usize] {
+                let handling = self.nodes[uid as usize].on_message(&frame, &NoRoutes);
+                if handling.deliver {
+                    delivered.push(uid);
+                }
+                on_air.extend(handling.forward);
+            }
+        }
+        Outcome {
+            delivered,
+            transmissions,
+        }
+    }
+}
+
+#[test]
+fn seen_cache_evicts_oldest() {
+    let mut seen = SeenCache::<(u16, u16), 2>::new();
+    assert!(seen.insert((1, 1)));
+    assert!(!seen.insert((1, 1)));
+    assert!(seen.insert((1, 2)));
+    assert!(seen.insert((2, 1)));
+    assert_eq!(seen.len(), 2);
+    assert!(!seen.contains(&(1, 1)));
+    assert!(seen.contains(&(1, 2)));
+    assert!(seen.contains(&(2, 1)));
+}
+
+#[test]
+fn unicast_crosses_a_line() {
+    let mut network = Network::line(5);
+    let message = MessageBuilder::new(0).normal(4, b"hi").unwrap();
+    let outcome = network.flood(message);
+    assert_eq!(outcome.delivered, [4]);
+    // Nodes 0 to 3 each transmit once; the destination does not relay.
+    assert_eq!(outcome.transmissions, 4);
+}
+
+#[test]
+fn hop_limit_bounds_reach() {
+    let mut network = Network::line(6);
+    let message = MessageBuilder::new(0)
+        .with_hop_limit(3)
+        .normal(5, b"hi")
+        .unwrap();
+    let outcome = network.flood(message);
+    assert!(outcome.delivered.is_empty());
+    assert_eq!(outcome.transmissions, 3);
+}
+
+#[test]
+fn broadcast_reaches_every_node_once() {
+    let mut network = Network::grid(4);
+    let message = MessageBuilder::new(5)
+        .with_hop_limit(8)
+        .normal(BROADCAST_UID, b"all")
+        .unwrap();
+    let mut outcome = network.flood(message);
+
+    outcome.delivered.sort();
+    let others: Vec<u16> = (0..16).filter(|&uid| uid != 5).collect();
+    assert_eq!(outcome.delivered, others);
+    // Every node transmits at most once: no broadcast storm.
+    assert!(outcome.transmissions <= 16);
+}
+
+#[test]
+fn duplicates_are_suppressed_across_messages() {
+    let mut network = Network::grid(3);
+    let mut builder = MessageBuilder::new(0).with_hop_limit(8);
+    for _ in 0..10 {
+        let outcome = network.flood(builder.normal(8, b"x").unwrap());
+        assert_eq!(outcome.delivered, [8]);
+        assert!(outcome.transmissions <= 9);
+    }
+}
+
+#[test]
+fn replayed_message_is_dropped() {
+    let mut flood = Flood::<4>::new(1);
+    let message = MessageBuilder::new(2).normal(3, b"x").unwrap();
+    assert!(flood.on_message(&message, &NoRoutes).forward.is_some());
+    let replay = flood.on_message(&message, &NoRoutes);
+    assert!(!replay.deliver);
+    assert!(replay.forward.is_none());
+}
+
+#[test]
+fn own_echo_is_not_relayed() {
+    let mut flood = Flood::<4>::new(1);
+    let message = MessageBuilder::new(1).normal(3, b"x").unwrap();
+    let message = flood.originate(message, &NoRoutes);
+    let echo = message.relayed_by(2, BROADCAST_UID).unwrap();
+    assert!(flood.on_message(&echo, &NoRoutes).forward.is_none());
+}
+
+#[test]
+fn control_messages_are_not_flooded() {
+    let mut flood = Flood::<4>::new(1);
+    let handling = flood.on_message(&MessageBuilder::new(2).ping(), &NoRoutes);
+    assert!(!handling.deliver);
+    assert!(handling.forward.is_none());
+}
+
+#[test]
+fn retransmission_is_relayed_but_delivered_once() {
+    let mut relay = Flood::<4>::new(1);
+    let mut destination = Flood::<4>::new(3);
+    let message = MessageBuilder::new(2)
+        .normal(3, b"x")
+        .unwrap()
+        .with_ack_requested();
+    let retry = message.retransmission();
+
+    assert!(relay.on_message(&message, &NoRoutes).forward.is_some());
+    assert!(relay.on_message(&retry, &NoRoutes).forward.is_some());
+
+    let first = destination.on_message(&message, &NoRoutes);
+    assert!(first.deliver && first.acknowledge);
+    let second = destination.on_message(&retry, &NoRoutes);
+    assert!(!second.deliver);
+    // The first ack may have been lost, so the retransmission is acknowledged again.
+    assert!(second.acknowledge);
+}
+
+#[test]
+fn acks_are_relayed() {
+    let mut network = Network::line(4);
+    let message = MessageBuilder::new(0).normal(3, b"x").unwrap();
+    let ack = MessageBuilder::new(3).ack(&message).unwrap();
+    let outcome = network.flood(ack);
+    assert_eq!(outcome.delivered, [0]);
+    assert_eq!(outcome.transmissions, 3);
+}
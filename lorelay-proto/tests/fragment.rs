@@ -0,0 +1,371 @@
+use lorelay_proto::ack::{Delivery, RetryConfig};
+use lorelay_proto::fragment::{
+    Fragment, Frame, Reassembler, Reassembly, Step, Transfer, FRAGMENT_DATA_SIZE, MAX_FRAGMENTS,
+    MAX_TRANSFER_SIZE,
+};
+use lorelay_proto::message::{DecodeError, Message, MessageBuilder, MAX_MESSAGE_SIZE};
+use lorelay_proto::rng::XorShift32;
+
+const CONFIG: RetryConfig = RetryConfig {
+    timeout: 1_000,
+    max_timeout: 4_000,
+    max_retries: 5,
+};
+
+const SIZE: usize = 1_000;
+
+fn payload(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 7) as u8).collect()
+}
+
+/// Owned copy of a frame, so that it outlives the transfer that produced it.
+fn encoded(frame: &Frame) -> Vec<u8> {
+    let mut buf = [0; 64];
+    let len = frame.encode(&mut buf).unwrap();
+    buf[..len].to_vec()
+}
+
+/// Drains the frames the transfer wants to send at `now`.
+fn drain(transfer: &mut Transfer<SIZE>, now: u64) -> Vec<Vec<u8>> {
+    let mut frames = Vec::new();
+    while let Step::Send(frame) = transfer.poll(now) {
+        frames.push(encoded(&frame));
+    }
+    frames
+}
+
+fn data(frame: &[u8]) -> Fragment<'_> {
+    match Frame::decode(frame).unwrap() {
+        Frame::Data(fragment) => fragment,
+        frame => panic!("expected a fragment, got {frame:?}"),
+    }
+}
+
+#[test]
+fn frames_round_trip() {
+    let fragment = Frame::Data(Fragment {
+        transfer_id: 3,
+        index: 1,
+        count: 2,
+        data: b"tail",
+    });
+    assert_eq!(Frame::decode(&encoded(&fragment)), Ok(fragment));
+
+    let status = Frame::Status {
+        transfer_id: 3,
+        missing: 0x8000_0001,
+    };
+    assert_eq!(Frame::decode(&encoded(&status)), Ok(status));
+}
+
+#[test]
+fn invalid_frames_are_rejected() {
+    assert_eq!(Frame::decode(&[]), Err(DecodeError::Truncated));
+    assert_eq!(Frame::decode(&[9, 0]), Err(DecodeError::UnknownType(9)));
+    // Index beyond the count.
+    assert_eq!(
+        Frame::decode(&[0, 1, 2, 2, 0xaa]),
+        Err(DecodeError::InvalidFragment)
+    );
+    // Too many fragments.
+    assert_eq!(
+        Frame::decode(&[0, 1, 0, MAX_FRAGMENTS as u8 + 1, 0xaa]),
+        Err(DecodeError::InvalidFragment)
+    );
+    // Only the last fragment may be short.
+    assert_eq!(
+        Frame::decode(&[0, 1, 0, 2, 0xaa]),
+        Err(DecodeError::InvalidLength(5))
+    );
+    assert_eq!(
+        Frame::decode(&[1, 1, 0, 0]),
+        Err(DecodeError::InvalidLength(4))
+    );
+}
+
+#[test]
+fn fragments_travel_in_normal_messages() {
+    let frame = Frame::Status {
+        transfer_id: 1,
+        missing: 6,
+    };
+    let message = MessageBuilder::new(1).fragment(2, &frame).unwrap();
+    assert!(message.is_fragment());
+
+    let mut buf = [0; MAX_MESSAGE_SIZE];
+    let len = message.encode(&mut buf).unwrap();
+    let decoded = Message::decode(&buf[..len]).unwrap();
+    assert!(decoded.is_fragment());
+    assert_eq!(Frame::decode(decoded.payload()), Ok(frame));
+    assert!(!MessageBuilder::new(1)
+        .normal(2, b"x")
+        .unwrap()
+        .is_fragment());
+}
+
+#[test]
+fn payload_is_split_in_full_fragments() {
+    let payload = payload(2 * FRAGMENT_DATA_SIZE + 5);
+    let mut transfer = Transfer::<SIZE>::new(2, 0, &payload, CONFIG).unwrap();
+    assert_eq!(transfer.count(), 3);
+
+    let frames = drain(&mut transfer, 0);
+    let lens: Vec<usize> = frames.iter().map(|f| data(f).data.len()).collect();
+    assert_eq!(lens, [FRAGMENT_DATA_SIZE, FRAGMENT_DATA_SIZE, 5]);
+    assert_eq!(transfer.poll(0), Step::Wait(1_000));
+}
+
+#[test]
+fn oversized_and_empty_payloads_are_refused() {
+    assert!(Transfer::<SIZE>::new(2, 0, &[], CONFIG).is_none());
+    assert!(Transfer::<SIZE>::new(2, 0, &payload(SIZE + 1), CONFIG).is_none());
+    let big = payload(MAX_TRANSFER_SIZE + 1);
+    assert!(Transfer::<{ MAX_TRANSFER_SIZE + 1 }>::new(2, 0, &big, CONFIG).is_none());
+}
+
+#[test]
+fn out_of_order_fragments_are_reassembled() {
+    let payload = payload(SIZE);
+    let mut transfer = Transfer::<SIZE>::new(2, 7, &payload, CONFIG).unwrap();
+    let mut frames = drain(&mut transfer, 0);
+    frames.reverse();
+
+    let mut reassembler = Reassembler::<2, SIZE>::new(10_000);
+    let (last, others) = frames.split_last().unwrap();
+    // The last fragment to be sent arrives first, revealing the missing ones.
+    assert_eq!(
+        reassembler.on_fragment(1, &data(&frames[0]), 0),
+        Reassembly::Missing((1 << (transfer.count() - 1)) - 1)
+    );
+    for frame in &others[1..] {
+        assert_eq!(
+            reassembler.on_fragment(1, &data(frame), 0),
+            Reassembly::Pending
+        );
+    }
+    match reassembler.on_fragment(1, &data(last), 0) {
+        Reassembly::Complete(received) => assert_eq!(&received[..], &payload[..]),
+        other => panic!("expected completion, got {other:?}"),
+    }
+    assert!(reassembler.is_empty());
+    // The sender did not hear our `Status` and repeats the last fragment.
+    assert_eq!(
+        reassembler.on_fragment(1, &data(&frames[0]), 0),
+        Reassembly::AlreadyComplete
+    );
+
+    transfer.on_status(0);
+    assert_eq!(
+        transfer.poll(0),
+        Step::Done(Delivery::Delivered { attempts: 1 })
+    );
+}
+
+#[test]
+fn completed_transfers_are_forgotten_after_the_timeout() {
+    let payload: Vec<u8> = (0..100).collect();
+    let frames = drain(
+        &mut Transfer::<SIZE>::new(2, 7, &payload, CONFIG).unwrap(),
+        0,
+    );
+    let mut reassembler = Reassembler::<2, SIZE>::new(10_000);
+    for frame in &frames {
+        reassembler.on_fragment(1, &data(frame), 0);
+    }
+    assert_eq!(
+        reassembler.on_fragment(1, &data(&frames[0]), 9_999),
+        Reassembly::AlreadyComplete
+    );
+
+    // The sender restarted, and numbers a new transfer like the old one.
+    let payload: Vec<u8> = (100..200).collect();
+    let frames = drain(
+        &mut Transfer::<SIZE>::new(2, 7, &payload, CONFIG).unwrap(),
+        0,
+    );
+    let (last, others) = frames.split_last().unwrap();
+    for frame in others {
+        assert_eq!(
+            reassembler.on_fragment(1, &data(frame), 10_000),
+            Reassembly::Pending
+        );
+    }
+    match reassembler.on_fragment(1, &data(last), 10_000) {
+        Reassembly::Complete(received) => assert_eq!(&received[..], &payload[..]),
+        other => panic!("expected completion, got {other:?}"),
+    }
+}
+
+#[test]
+fn missing_fragments_are_retransmitted_selectively() {
+    let payload = payload(5 * FRAGMENT_DATA_SIZE);
+    let mut transfer = Transfer::<SIZE>::new(2, 0, &payload, CONFIG).unwrap();
+    let mut reassembler = Reassembler::<1, SIZE>::new(10_000);
+
+    let frames = drain(&mut transfer, 0);
+    let mut status = None;
+    for (i, frame) in frames.iter().enumerate() {
+        // Fragments 1 and 3 are lost.
+        if i == 1 || i == 3 {
+            continue;
+        }
+        if let Reassembly::Missing(missing) = reassembler.on_fragment(1, &data(frame), 0) {
+            status = Some(missing);
+        }
+    }
+    assert_eq!(status, Some(0b01010));
+
+    transfer.on_status(0b01010);
+    let retransmitted: Vec<u8> = drain(&mut transfer, 100)
+        .iter()
+        .map(|f| data(f).index)
+        .collect();
+    assert_eq!(retransmitted, [1, 3]);
+}
+
+#[test]
+fn silence_repeats_last_fragment_then_times_out() {
+    let config = RetryConfig {
+        max_retries: 2,
+        ..CONFIG
+    };
+    let mut transfer = Transfer::<SIZE>::new(2, 0, &payload(100), config).unwrap();
+    assert_eq!(drain(&mut transfer, 0).len(), 2);
+
+    assert_eq!(transfer.poll(999), Step::Wait(1_000));
+    let repeated = drain(&mut transfer, 1_000);
+    assert_eq!(repeated.len(), 1);
+    assert_eq!(data(&repeated[0]).index, 1);
+    assert_eq!(transfer.poll(1_000), Step::Wait(3_000));
+
+    assert_eq!(drain(&mut transfer, 3_000).len(), 1);
+    assert_eq!(transfer.poll(3_000), Step::Wait(7_000));
+    assert_eq!(transfer.poll(7_000), Step::Done(Delivery::TimedOut));
+    assert!(transfer.is_done());
+}
+
+#[test]
+fn reassembly_buffers_are_bounded() {
+    let mut reassembler = Reassembler::<1, SIZE>::new(1_000);
+    let first = [0u8; FRAGMENT_DATA_SIZE];
+    let fragment = |transfer_id| Fragment {
+        transfer_id,
+        index: 0,
+        count: 2,
+        data: &first,
+    };
+    assert_eq!(
+        reassembler.on_fragment(1, &fragment(0), 0),
+        Reassembly::Pending
+    );
+    assert_eq!(
+        reassembler.on_fragment(1, &fragment(1), 500),
+        Reassembly::Rejected
+    );
+    // Once the first transfer timed out its buffer is reused.
+    assert_eq!(
+        reassembler.on_fragment(1, &fragment(1), 1_000),
+        Reassembly::Pending
+    );
+
+    // Fragments beyond the buffer size are refused.
+    let far = Fragment {
+        transfer_id: 2,
+        index: (SIZE / FRAGMENT_DATA_SIZE) as u8 + 1,
+        count: 31,
+        data: &first,
+    };
+    assert_eq!(
+        reassembler.on_fragment(3, &far, 1_000),
+        Reassembly::Rejected
+    );
+}
+
+#[test]
+fn mismatched_count_is_rejected() {
+    let mut reassembler = Reassembler::<1, SIZE>::new(1_000);
+    let first = [0u8; FRAGMENT_DATA_SIZE];
+    let fragment = Fragment {
+        transfer_id: 0,
+        index: 0,
+        count: 2,
+        data: &first,
+    };
+    reassembler.on_fragment(1, &fragment, 0);
+    let other = Fragment {
+        count: 3,
+        ..fragment
+    };
+    assert_eq!(reassembler.on_fragment(1, &other, 0), Reassembly::Rejected);
+}
+
+/// Runs a transfer over a link dropping frames in both directions with probability `loss`.
+fn lossy_transfer(loss: u32, seed: u32) -> (Delivery, Option<Vec<u8>>) {
+    let payload = payload(SIZE);
+    let mut rng = XorShift32::new(seed);
+    let mut lost = move || rng.next_u32() % 100 < loss;
+    let mut transfer = Transfer::<SIZE>::new(2, 0, &payload, CONFIG).unwrap();
+    let mut reassembler = Reassembler::<1, SIZE>::new(60_000);
+    let mut received = None;
+    let mut now = 0;
+
+    loop {
+        let frame = match transfer.poll(now) {
+            Step::Send(frame) => encoded(&frame),
+            Step::Wait(deadline) => {
+                now = deadline;
+                continue;
+            }
+            Step::Done(delivery) => return (delivery, received),
+        };
+        now += 10;
+        if lost() {
+            continue;
+        }
+        let status = match reassembler.on_fragment(1, &data(&frame), now) {
+            Reassembly::Missing(missing) => Some(missing),
+            Reassembly::Complete(payload) => {
+                received = Some(payload.to_vec());
+                Some(0)
+            }
+            Reassembly::AlreadyComplete => Some(0),
+            Reassembly::Pending | Reassembly::Rejected => None,
+        };
+        if let Some(missing) = status {
+            if !lost() {
+                transfer.on_status(missing);
+            }
+        }
+    }
+}
+
+#[test]
+fn lossy_link_delivers_intact_payload() {
+    let mut delivered = 0;
+    for seed in 1..=20 {
+        match lossy_transfer(20, seed) {
+            (Delivery::Delivered { .. }, received) => {
+                assert_eq!(received.unwrap(), payload(SIZE));
+                delivered += 1;
+            }
+            (Delivery::TimedOut, received) => {
+                // A timed out transfer may still have been reassembled if only statuses were lost.
+                if let Some(received) = received {
+                    assert_eq!(received, payload(SIZE));
+                }
+            }
+        }
+    }
+    assert!(
+        delivered >= 18,
+        "only {delivered} of 20 transfers delivered"
+    );
+}
+
+#[test]
+fn lossless_link_delivers_in_one_round() {
+    assert_eq!(
+        lossy_transfer(0, 1),
+        (Delivery::Delivered { attempts: 1 }, Some(payload(SIZE)))
+    );
+}
@@ -0,0 +1,131 @@
+use heapless::Vec;
+use lorelay_proto::ack::Delivery;
+use lorelay_proto::gateway::{
+    decode_delivery, decode_neighbours, encode_delivery, encode_neighbours, IncomingMessage,
+    NeighbourEntry, NodeStatus, OutgoingMessage, DELIVERY_SIZE, MAX_INCOMING_SIZE,
+    MAX_NEIGHBOURS_SIZE, MAX_OUTGOING_SIZE, MAX_PAYLOAD_SIZE, NEIGHBOUR_ENTRY_SIZE, STATUS_SIZE,
+};
+use lorelay_proto::message::{DecodeError, EncodeError};
+use lorelay_proto::neighbour::Neighbour;
+
+#[test]
+fn outgoing_round_trip() {
+    let message = OutgoingMessage {
+        destination_uid: 0x1234,
+        ack: true,
+        payload: Vec::from_slice(b"hello").unwrap(),
+    };
+    let mut buf = [0; MAX_OUTGOING_SIZE];
+    let len = message.encode(&mut buf).unwrap();
+    assert_eq!(&buf[..len], b"\x34\x12\x01hello");
+    assert_eq!(OutgoingMessage::decode(&buf[..len]), Ok(message.clone()));
+    assert_eq!(
+        message.encode(&mut buf[..len - 1]),
+        Err(EncodeError::BufferTooSmall)
+    );
+
+    let empty = OutgoingMessage::decode(b"\x01\x00\x00").unwrap();
+    assert_eq!(empty.destination_uid, 1);
+    assert!(!empty.ack);
+    assert!(empty.payload.is_empty());
+}
+
+#[test]
+fn rejects_invalid_outgoing() {
+    assert_eq!(
+        OutgoingMessage::decode(b"\x01\x00"),
+        Err(DecodeError::Truncated)
+    );
+    let oversized = [0; MAX_OUTGOING_SIZE + 1];
+    assert_eq!(
+        OutgoingMessage::decode(&oversized),
+        Err(DecodeError::InvalidLength(MAX_OUTGOING_SIZE as u8 + 1))
+    );
+    assert!(OutgoingMessage::decode(&oversized[..MAX_OUTGOING_SIZE]).is_ok());
+}
+
+#[test]
+fn incoming_round_trip() {
+    let message = IncomingMessage {
+        origin_uid: 0xbeef,
+        payload: Vec::from_slice(&[0xaa; MAX_PAYLOAD_SIZE]).unwrap(),
+    };
+    let mut buf = [0; MAX_INCOMING_SIZE];
+    assert_eq!(message.encode(&mut buf), Ok(MAX_INCOMING_SIZE));
+    assert_eq!(IncomingMessage::decode(&buf), Ok(message));
+    assert_eq!(IncomingMessage::decode(&[0]), Err(DecodeError::Truncated));
+}
+
+#[test]
+fn status_round_trip() {
+    let status = NodeStatus {
+        uid: 42,
+        mode: 1,
+        neighbours: 3,
+        routes: 5,
+        uptime_s: 86_400,
+    };
+    let mut buf = [0; STATUS_SIZE];
+    assert_eq!(status.encode(&mut buf), Ok(STATUS_SIZE));
+    assert_eq!(NodeStatus::decode(&buf), Ok(status));
+    assert_eq!(
+        NodeStatus::decode(&buf[..STATUS_SIZE - 1]),
+        Err(DecodeError::InvalidLength(STATUS_SIZE as u8 - 1))
+    );
+}
+
+#[test]
+fn neighbour_entries() {
+    let neighbour = Neighbour {
+        uid: 7,
+        rssi: -97,
+        snr: -4,
+        last_seen: 1_000,
+    };
+    let entry = NeighbourEntry::new(&neighbour, 13_500);
+    assert_eq!(entry.age_s, 12);
+    assert_eq!(NeighbourEntry::new(&neighbour, 500).age_s, 0, "clock skew");
+    assert_eq!(NeighbourEntry::new(&neighbour, u64::MAX).age_s, u16::MAX);
+
+    let entries = [
+        entry,
+        NeighbourEntry {
+            uid: 8,
+            rssi: -40,
+            snr: 9,
+            age_s: 0,
+        },
+    ];
+    let mut buf = [0; MAX_NEIGHBOURS_SIZE];
+    let len = encode_neighbours(&entries, &mut buf).unwrap();
+    assert_eq!(len, 2 * NEIGHBOUR_ENTRY_SIZE);
+    assert_eq!(decode_neighbours(&buf[..len]).unwrap(), entries);
+    assert!(decode_neighbours(&[]).unwrap().is_empty());
+    assert_eq!(
+        encode_neighbours(&entries, &mut buf[..len - 1]),
+        Err(EncodeError::BufferTooSmall)
+    );
+    assert_eq!(
+        decode_neighbours(&buf[..len - 1]),
+        Err(DecodeError::InvalidLength(len as u8 - 1))
+    );
+}
+
+#[test]
+fn delivery_round_trip() {
+    let mut buf = [0; DELIVERY_SIZE];
+    for (delivery, value) in [
+        (Delivery::Delivered { attempts: 3 }, 3),
+        (Delivery::TimedOut, 0),
+    ] {
+        assert_eq!(encode_delivery(&delivery, &mut buf), Ok(DELIVERY_SIZE));
+        assert_eq!(buf, [value]);
+        assert_eq!(decode_delivery(&buf), Ok(delivery));
+    }
+    assert_eq!(
+        encode_delivery(&Delivery::TimedOut, &mut []),
+        Err(EncodeError::BufferTooSmall)
+    );
+    assert_eq!(decode_delivery(&[]), Err(DecodeError::InvalidLength(0)));
+    assert_eq!(decode_delivery(&[1, 0]), Err(DecodeError::InvalidLength(2)));
+}
@@ -0,0 +1,127 @@
+use lorelay_proto::gesture::{ButtonEvent, GestureConfig, Gestures};
+
+/// Feeds `edges` of `(time, pressed)` and collects the events polled at every edge and deadline
+/// until `end`, with their time.
+fn run(config: GestureConfig, edges: &[(u64, bool)], end: u64) -> Vec<(u64, ButtonEvent)> {
+    let mut gestures = Gestures::new(config);
+    let mut edges = edges.iter().peekable();
+    let mut events = Vec::new();
+    let mut now = 0;
+    while now <= end {
+        while let Some(&(_, pressed)) = edges.next_if(|(at, _)| *at <= now) {
+            gestures.on_edge(pressed, now);
+        }
+        while let Some(event) = gestures.poll(now) {
+            events.push((now, event));
+        }
+        now = [gestures.next_deadline(), edges.peek().map(|(at, _)| *at)]
+            .into_iter()
+            .flatten()
+            .min()
+            .unwrap_or(u64::MAX);
+    }
+    events
+}
+
+fn config() -> GestureConfig {
+    GestureConfig {
+        debounce_ms: 20,
+        double_click_ms: 300,
+        long_press_ms: 800,
+        hold_interval_ms: 500,
+    }
+}
+
+#[test]
+fn click_comes_out_after_the_double_click_window() {
+    let events = run(config(), &[(100, true), (200, false)], 10_000);
+    assert_eq!(events, [(500, ButtonEvent::Click)]);
+}
+
+#[test]
+fn click_is_immediate_without_double_clicks() {
+    let config = GestureConfig {
+        double_click_ms: 0,
+        ..config()
+    };
+    let events = run(config, &[(100, true), (200, false)], 10_000);
+    assert_eq!(events, [(220, ButtonEvent::Click)]);
+}
+
+#[test]
+fn double_click() {
+    let edges = [(100, true), (200, false), (400, true), (450, false)];
+    assert_eq!(
+        run(config(), &edges, 10_000),
+        [(470, ButtonEvent::DoubleClick)]
+    );
+
+    // Too late for the second press to count.
+    let edges = [(100, true), (200, false), (600, true), (650, false)];
+    assert_eq!(
+        run(config(), &edges, 10_000),
+        [(500, ButtonEvent::Click), (950, ButtonEvent::Click)]
+    );
+}
+
+#[test]
+fn bounces_are_ignored() {
+    let edges = [
+        (100, true),
+        (103, false),
+        (105, true),
+        (110, false),
+        (112, true),
+        (200, false),
+        (204, true),
+        (207, false),
+    ];
+    // Timings count from the last bounce, the one the level settled on.
+    assert_eq!(run(config(), &edges, 10_000), [(507, ButtonEvent::Click)]);
+
+    // A glitch shorter than the debounce time is no press at all.
+    assert_eq!(run(config(), &[(100, true), (110, false)], 10_000), []);
+}
+
+#[test]
+fn long_press_then_holds() {
+    let events = run(config(), &[(100, true), (2_000, false)], 10_000);
+    assert_eq!(
+        events,
+        [
+            (900, ButtonEvent::LongPress),
+            (1_400, ButtonEvent::Hold(1_300)),
+            (1_900, ButtonEvent::Hold(1_800)),
+        ]
+    );
+}
+
+#[test]
+fn release_before_the_long_press_is_a_click() {
+    // Released at 890, debounced at 910: the release came first.
+    let events = run(config(), &[(100, true), (890, false)], 10_000);
+    assert_eq!(events, [(1_190, ButtonEvent::Click)]);
+}
+
+#[test]
+fn second_press_held_is_a_click_then_a_long_press() {
+    let edges = [(100, true), (200, false), (400, true), (1_300, false)];
+    assert_eq!(
+        run(config(), &edges, 10_000),
+        [(1_200, ButtonEvent::Click), (1_200, ButtonEvent::LongPress)]
+    );
+}
+
+#[test]
+fn late_poll_keeps_the_order_of_events() {
+    let mut gestures = Gestures::new(config());
+    gestures.on_edge(true, 100);
+    assert_eq!(gestures.next_deadline(), Some(120));
+    assert_eq!(gestures.poll(120), None);
+    gestures.on_edge(false, 200);
+    // Polled long after the fact, as if the task had been starved: the release came before the
+    // long press.
+    assert_eq!(gestures.poll(5_000), Some(ButtonEvent::Click));
+    assert_eq!(gestures.poll(5_000), None);
+    assert_eq!(gestures.next_deadline(), None);
+}
@@ -0,0 +1,173 @@
+use lorelay_proto::led::{DeviceState, LedEngine, LedPattern, Priority, PWM_PERIOD_MS};
+
+/// Times the LED changes until `end`, with the level it changes to, starting at 0.
+fn transitions(engine: &mut LedEngine, end: u64) -> Vec<(u64, bool)> {
+    let mut changes = vec![(0, engine.is_on())];
+    while let Some(now) = engine.next_deadline().filter(|&now| now <= end) {
+        engine.poll(now);
+        if engine.is_on() != changes.last().unwrap().1 {
+            changes.push((now, engine.is_on()));
+        }
+    }
+    changes
+}
+
+#[test]
+fn solid_and_off_never_change() {
+    let mut engine = LedEngine::new();
+    assert!(!engine.is_on());
+    assert_eq!(engine.next_deadline(), None);
+    assert_eq!(engine.priority(), None);
+
+    engine.set(Priority::Status, Some(LedPattern::Solid), 0);
+    assert!(engine.is_on());
+    assert_eq!(engine.next_deadline(), None);
+    engine.set(Priority::Status, Some(LedPattern::Off), 10);
+    assert!(!engine.is_on());
+    assert_eq!(engine.priority(), Some(Priority::Status));
+}
+
+#[test]
+fn blinks_then_goes_idle() {
+    let mut engine = LedEngine::new();
+    let blink = LedPattern::Blink {
+        count: 2,
+        on_ms: 100,
+        off_ms: 50,
+    };
+    engine.set(Priority::Activity, Some(blink), 0);
+    assert_eq!(
+        transitions(&mut engine, 10_000),
+        [(0, true), (100, false), (150, true), (250, false)]
+    );
+    assert_eq!(engine.next_deadline(), None);
+    assert_eq!(engine.priority(), None);
+}
+
+#[test]
+fn heartbeat_repeats() {
+    let mut engine = LedEngine::new();
+    engine.show(DeviceState::Joining, 0);
+    assert_eq!(
+        transitions(&mut engine, 1_999),
+        [
+            (0, true),
+            (80, false),
+            (200, true),
+            (280, false),
+            (1_000, true),
+            (1_080, false),
+            (1_200, true),
+            (1_280, false),
+        ]
+    );
+}
+
+#[test]
+fn sos_spells_three_dots_three_dashes_three_dots() {
+    let mut engine = LedEngine::new();
+    engine.show(DeviceState::RadioError, 0);
+    assert_eq!(engine.priority(), Some(Priority::Error));
+    let changes = transitions(&mut engine, 100_000);
+    let pulses: Vec<u64> = changes
+        .windows(2)
+        .filter(|pair| pair[0].1)
+        .map(|pair| pair[1].0 - pair[0].0)
+        .collect();
+    assert_eq!(pulses, [150, 150, 150, 450, 450, 450, 150, 150, 150]);
+    // Over once the word gap after the last dot is done.
+    assert_eq!(changes.last(), Some(&(4_050, false)));
+    assert_eq!(engine.next_deadline(), None);
+    assert_eq!(engine.priority(), None);
+}
+
+#[test]
+fn breathe_ramps_the_duty_cycle_up_and_down() {
+    let period_ms = 200;
+    let mut engine = LedEngine::new();
+    engine.set(Priority::Status, Some(LedPattern::Breathe { period_ms }), 0);
+    // Time lit in each PWM period, sampled every millisecond.
+    let mut lit = vec![0; usize::from(period_ms / PWM_PERIOD_MS)];
+    for now in 0..u64::from(period_ms) {
+        engine.poll(now);
+        if engine.is_on() {
+            lit[(now / u64::from(PWM_PERIOD_MS)) as usize] += 1;
+        }
+    }
+    assert_eq!(
+        lit,
+        [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1]
+    );
+}
+
+#[test]
+fn custom_steps() {
+    let mut engine = LedEngine::new();
+    let steps = LedPattern::Custom {
+        steps: &[10, 20, 0, 5],
+        repeat: false,
+    };
+    engine.set(Priority::Status, Some(steps), 0);
+    // The empty third step merges the two off steps around it.
+    assert_eq!(transitions(&mut engine, 1_000), [(0, true), (10, false)]);
+    assert_eq!(engine.priority(), None);
+
+    let never_on = LedPattern::Custom {
+        steps: &[0, 0],
+        repeat: true,
+    };
+    engine.set(Priority::Status, Some(never_on), 0);
+    assert_eq!(engine.priority(), None);
+}
+
+#[test]
+fn error_overrides_activity_and_gives_it_back() {
+    let mut engine = LedEngine::new();
+    engine.set(Priority::Status, Some(LedPattern::Solid), 0);
+    engine.set(Priority::Activity, Some(LedPattern::BLINK), 100);
+    engine.set(
+        Priority::Error,
+        Some(LedPattern::Blink {
+            count: 1,
+            on_ms: 300,
+            off_ms: 200,
+        }),
+        200,
+    );
+    assert_eq!(engine.priority(), Some(Priority::Error));
+
+    // The error is over at 700, the blink starts over from there, then the status shows again.
+    engine.poll(700);
+    assert_eq!(engine.priority(), Some(Priority::Activity));
+    assert!(engine.is_on());
+    assert_eq!(engine.next_deadline(), Some(1_700));
+    engine.poll(1_700);
+    assert_eq!(engine.priority(), Some(Priority::Status));
+    assert!(engine.is_on());
+
+    // Clearing a pattern hands the LED down at once.
+    engine.show(DeviceState::LowBattery, 2_000);
+    assert_eq!(engine.priority(), Some(Priority::Warning));
+    engine.set(Priority::Warning, None, 2_010);
+    assert_eq!(engine.priority(), Some(Priority::Status));
+}
+
+#[test]
+fn late_poll_catches_up() {
+    let mut engine = LedEngine::new();
+    engine.set(Priority::Status, Some(LedPattern::Heartbeat), 0);
+    engine.set(
+        Priority::Activity,
+        Some(LedPattern::Blink {
+            count: 1,
+            on_ms: 100,
+            off_ms: 100,
+        }),
+        0,
+    );
+    // The blink ended at 200, and the heartbeat is 250 ms into its cycle.
+    engine.poll(450);
+    assert_eq!(engine.priority(), Some(Priority::Status));
+    assert!(engine.is_on());
+    assert_eq!(engine.next_deadline(), Some(480));
+}
@@ -0,0 +1,322 @@
+use heapless::Vec;
+use lorelay_proto::ack::Delivery;
+use lorelay_proto::device_config::DeviceConfig;
+use lorelay_proto::gateway::{IncomingMessage, NeighbourEntry, NodeStatus, OutgoingMessage};
+use lorelay_proto::link::{
+    FrameError, Link, LinkConfig, LinkEvent, Packet, FRAME_DELIMITER, MAX_ENCODED_FRAME_SIZE,
+    MAX_PACKET_SIZE,
+};
+use lorelay_proto::message::DecodeError;
+use lorelay_proto::radio::RadioConfig;
+use lorelay_proto::range_test::{Probe, SignalStats, Summary};
+use lorelay_proto::region::Region;
+
+fn status(uptime_s: u32) -> Packet {
+    Packet::Status(NodeStatus {
+        uid: 1,
+        mode: 0,
+        neighbours: 2,
+        routes: 3,
+        uptime_s,
+    })
+}
+
+fn link<const Q: usize>(seed: u32) -> Link<Q> {
+    Link::new(LinkConfig::default(), seed)
+}
+
+/// Frames `link` has to send by `now`.
+fn transmit<const Q: usize>(link: &mut Link<Q>, now: u64) -> std::vec::Vec<std::vec::Vec<u8>> {
+    std::iter::from_fn(|| link.poll_transmit(now))
+        .map(|frame| frame.to_vec())
+        .collect()
+}
+
+fn events<const Q: usize>(link: &mut Link<Q>) -> std::vec::Vec<LinkEvent> {
+    std::iter::from_fn(|| link.poll_event()).collect()
+}
+
+/// Moves every frame from one link to the other until both are idle at `now`.
+fn exchange<const Q: usize>(a: &mut Link<Q>, b: &mut Link<Q>, now: u64) {
+    loop {
+        let from_a = transmit(a, now);
+        let from_b = transmit(b, now);
+        if from_a.is_empty() && from_b.is_empty() {
+            return;
+        }
+        from_a.iter().for_each(|frame| b.on_bytes(frame, now));
+        from_b.iter().for_each(|frame| a.on_bytes(frame, now));
+    }
+}
+
+#[test]
+fn packets_round_trip() {
+    let summary = Summary {
+        origin_uid: 9,
+        probe: Probe::new(12, &RadioConfig::default()),
+        received: 10,
+        lost: 2,
+        duplicates: 1,
+        packet_error_rate_permille: 166,
+        rssi: SignalStats {
+            last: -90,
+            min: -100,
+            max: -80,
+            mean: -91,
+        },
+        snr: SignalStats {
+            last: 3,
+            min: -2,
+            max: 8,
+            mean: 4,
+        },
+    };
+    let packets = [
+        Packet::Outgoing(OutgoingMessage {
+            destination_uid: 2,
+            ack: true,
+            payload: Vec::from_slice(&[0; 240]).unwrap(),
+        }),
+        Packet::Incoming(IncomingMessage {
+            origin_uid: 3,
+            payload: Vec::from_slice(b"\x00hi\x00").unwrap(),
+        }),
+        status(60),
+        Packet::Neighbours(
+            Vec::from_slice(&[NeighbourEntry {
+                uid: 4,
+                rssi: -70,
+                snr: 5,
+                age_s: 3,
+            }])
+            .unwrap(),
+        ),
+        Packet::RangeTestSummary(summary),
+        Packet::Config(DeviceConfig::new(Region::Eu433)),
+        Packet::SetConfig(DeviceConfig {
+            uid: Some(5),
+            radio: RadioConfig::default(),
+            mode: 1,
+            region: Region::Eu868,
+        }),
+        Packet::SetMode(2),
+        Packet::Reboot,
+        Packet::Delivery(Delivery::Delivered { attempts: 2 }),
+        Packet::Delivery(Delivery::TimedOut),
+    ];
+    let mut buf = [0; MAX_PACKET_SIZE];
+    for packet in packets {
+        let len = packet.encode(&mut buf).unwrap();
+        assert_eq!(Packet::decode(&buf[..len]), Ok(packet));
+    }
+    assert_eq!(Packet::decode(&[]), Err(DecodeError::Truncated));
+    assert_eq!(Packet::decode(&[10]), Err(DecodeError::UnknownType(10)));
+    assert_eq!(Packet::decode(&[7]), Err(DecodeError::InvalidLength(0)));
+    assert_eq!(Packet::decode(&[8, 0]), Err(DecodeError::InvalidLength(1)));
+    assert_eq!(
+        Packet::decode(&[6; 20]),
+        Err(DecodeError::InvalidLength(19))
+    );
+}
+
+#[test]
+fn delivers_packets_in_order() {
+    let (mut a, mut b) = (link::<4>(1), link::<4>(2));
+    for uptime in 0..10 {
+        assert!(a.is_ready());
+        a.send(status(uptime), 0).unwrap();
+        assert_eq!(
+            a.send(status(99), 0),
+            Err(status(99)),
+            "one packet in flight"
+        );
+        exchange(&mut a, &mut b, 0);
+        assert_eq!(events(&mut a), [LinkEvent::Delivered]);
+        assert_eq!(events(&mut b), [LinkEvent::Received(status(uptime))]);
+    }
+    assert_eq!(a.next_deadline(), None);
+    assert_eq!(b.next_deadline(), None);
+}
+
+#[test]
+fn frames_survive_byte_by_byte_reads() {
+    let (mut a, mut b) = (link::<4>(1), link::<4>(2));
+    a.send(status(1), 0).unwrap();
+    let frames = transmit(&mut a, 0);
+    assert_eq!(frames.len(), 1);
+    assert!(frames[0].len() <= MAX_ENCODED_FRAME_SIZE);
+    assert_eq!(frames[0].last(), Some(&FRAME_DELIMITER));
+    assert_eq!(frames[0].iter().filter(|&&byte| byte == 0).count(), 1);
+
+    // Stray delimiters and line noise ahead of the frame are ignored.
+    b.on_bytes(&[0, 0], 0);
+    for &byte in &frames[0] {
+        b.on_bytes(&[byte], 0);
+    }
+    assert_eq!(events(&mut b), [LinkEvent::Received(status(1))]);
+}
+
+#[test]
+fn retransmits_corrupted_frames() {
+    let (mut a, mut b) = (link::<4>(1), link::<4>(2));
+    a.send(status(1), 0).unwrap();
+    let mut frame = transmit(&mut a, 0).remove(0);
+    frame[3] ^= 0x40;
+    b.on_bytes(&frame, 0);
+    assert_eq!(events(&mut b), [LinkEvent::Invalid(FrameError::BadCrc)]);
+
+    // The peer asks for the frame again, without waiting for the timeout.
+    exchange(&mut a, &mut b, 1);
+    assert_eq!(events(&mut a), [LinkEvent::Delivered]);
+    assert_eq!(events(&mut b), [LinkEvent::Received(status(1))]);
+}
+
+#[test]
+fn rejects_truncated_frames() {
+    let (mut a, mut b) = (link::<4>(1), link::<4>(2));
+    a.send(status(1), 0).unwrap();
+    let frame = transmit(&mut a, 0).remove(0);
+    let body = &frame[..frame.len() - 1];
+
+    // Cut short by a reset of the sender, then followed by the next frame.
+    b.on_bytes(&body[..body.len() - 3], 0);
+    b.on_bytes(&[FRAME_DELIMITER], 0);
+    b.on_bytes(&body[..2], 0);
+    b.on_bytes(&[FRAME_DELIMITER], 0);
+    assert_eq!(
+        events(&mut b),
+        [
+            LinkEvent::Invalid(FrameError::BadCrc),
+            LinkEvent::Invalid(FrameError::Malformed)
+        ]
+    );
+
+    b.on_bytes(&[0xff; MAX_ENCODED_FRAME_SIZE + 1], 0);
+    b.on_bytes(&frame, 0);
+    assert_eq!(
+        events(&mut b),
+        [LinkEvent::Invalid(FrameError::Oversized)],
+        "the oversized frame swallows the next one"
+    );
+    b.on_bytes(&frame, 0);
+    assert_eq!(events(&mut b), [LinkEvent::Received(status(1))]);
+}
+
+#[test]
+fn ignores_duplicates_of_lost_acknowledgments() {
+    let (mut a, mut b) = (link::<4>(1), link::<4>(2));
+    let config = *a.config();
+    a.send(status(1), 0).unwrap();
+    let frame = transmit(&mut a, 0).remove(0);
+    b.on_bytes(&frame, 0);
+    assert_eq!(events(&mut b), [LinkEvent::Received(status(1))]);
+    // The acknowledgment is lost.
+    assert_eq!(transmit(&mut b, 0).len(), 1);
+
+    assert_eq!(a.next_deadline(), Some(config.ack_timeout));
+    assert!(transmit(&mut a, config.ack_timeout - 1).is_empty());
+    let retransmission = transmit(&mut a, config.ack_timeout).remove(0);
+    assert_eq!(retransmission, frame);
+    b.on_bytes(&retransmission, config.ack_timeout);
+    exchange(&mut a, &mut b, config.ack_timeout);
+    assert_eq!(events(&mut a), [LinkEvent::Delivered]);
+    assert_eq!(events(&mut b), [], "duplicate");
+}
+
+#[test]
+fn gives_up_after_the_retries() {
+    let mut a = link::<4>(1);
+    let config = *a.config();
+    a.send(status(1), 0).unwrap();
+    let mut now = 0;
+    for _ in 0..=config.max_retries {
+        assert_eq!(transmit(&mut a, now).len(), 1);
+        now += config.ack_timeout;
+    }
+    assert!(transmit(&mut a, now).is_empty());
+    assert_eq!(events(&mut a), [LinkEvent::Dropped(status(1))]);
+    assert!(a.is_ready());
+    assert_eq!(a.next_deadline(), None);
+}
+
+#[test]
+fn backs_off_while_the_peer_is_busy() {
+    let (mut a, mut b) = (link::<1>(1), link::<1>(2));
+    let config = *a.config();
+    a.send(status(1), 0).unwrap();
+    exchange(&mut a, &mut b, 0);
+    assert_eq!(events(&mut a), [LinkEvent::Delivered]);
+
+    // `b` did not drain its events, it has no room for the next packet.
+    a.send(status(2), 0).unwrap();
+    exchange(&mut a, &mut b, 0);
+    assert_eq!(events(&mut a), []);
+    assert_eq!(a.next_deadline(), Some(config.busy_backoff));
+
+    // Busy rejections do not count as retries.
+    let mut now = 0;
+    for _ in 0..=config.max_retries {
+        now += config.busy_backoff;
+        exchange(&mut a, &mut b, now);
+    }
+    assert_eq!(events(&mut a), []);
+
+    assert_eq!(events(&mut b), [LinkEvent::Received(status(1))]);
+    now += config.busy_backoff;
+    exchange(&mut a, &mut b, now);
+    assert_eq!(events(&mut a), [LinkEvent::Delivered]);
+    assert_eq!(events(&mut b), [LinkEvent::Received(status(2))]);
+}
+
+#[test]
+fn resynchronizes_with_a_reset_peer() {
+    let (mut a, mut b) = (link::<4>(1), link::<4>(2));
+    for uptime in 1..=2 {
+        a.send(status(uptime), 0).unwrap();
+        exchange(&mut a, &mut b, 0);
+    }
+    assert_eq!(events(&mut b).len(), 2);
+
+    // Restarted with the same seed, `a` starts over with the sequence numbers it used.
+    let mut a = link::<4>(1);
+    for uptime in 1..=2 {
+        a.send(status(uptime), 10).unwrap();
+        exchange(&mut a, &mut b, 10);
+        assert_eq!(events(&mut a), [LinkEvent::Delivered]);
+        assert_eq!(events(&mut b), [LinkEvent::Received(status(uptime))]);
+    }
+}
+
+#[test]
+fn acknowledges_undecodable_packets() {
+    let (mut a, mut b) = (link::<4>(1), link::<4>(2));
+    a.send(
+        Packet::Incoming(IncomingMessage {
+            origin_uid: 1,
+            payload: Vec::new(),
+        }),
+        0,
+    )
+    .unwrap();
+    let mut frame = transmit(&mut a, 0).remove(0);
+    // Rewrites the tag to an unknown one, and the CRC with it, as a newer peer would send.
+    let mut decoded = [0; 16];
+    let len = lorelay_proto::cobs::decode(&frame[..frame.len() - 1], &mut decoded).unwrap();
+    decoded[2] = 0x7f;
+    let crc = lorelay_proto::crc::crc16(&decoded[..len - 2]);
+    decoded[len - 2..len].copy_from_slice(&crc.to_le_bytes());
+    frame.resize(MAX_ENCODED_FRAME_SIZE, 0);
+    let encoded = lorelay_proto::cobs::encode(&decoded[..len], &mut frame).unwrap();
+    frame.truncate(encoded);
+    frame.push(FRAME_DELIMITER);
+
+    b.on_bytes(&frame, 0);
+    exchange(&mut a, &mut b, 0);
+    assert_eq!(events(&mut a), [LinkEvent::Delivered]);
+    assert_eq!(
+        events(&mut b),
+        [LinkEvent::Invalid(FrameError::InvalidPacket(
+            DecodeError::UnknownType(0x7f)
+        ))]
+    );
+}
@@ -0,0 +1,284 @@
+use lorelay_proto::message::{
+    DecodeError, EncodeError, Message, MessageBuilder, MessageType, RouteAdvert, RouteAdverts,
+    BROADCAST_UID, DEFAULT_HOP_LIMIT, HEADER_SIZE, MAX_ADVERTISED_ROUTES, MAX_BODY_SIZE,
+    MAX_MESSAGE_SIZE, MAX_PAYLOAD_SIZE, PROTOCOL_VERSION,
+};
+
+fn round_trip(message: &Message) -> Message {
+    let mut buf = [0u8; MAX_MESSAGE_SIZE];
+    let len = message.encode(&mut buf).unwrap();
+    assert_eq!(len, message.encoded_len());
+    Message::decode(&buf[..len]).unwrap()
+}
+
+#[test]
+fn ping_round_trip() {
+    let ping = MessageBuilder::new(0x1234).ping();
+    let decoded = round_trip(&ping);
+    assert_eq!(decoded, ping);
+    assert_eq!(decoded.sender_uid(), 0x1234);
+    assert_eq!(decoded.destination_uid(), BROADCAST_UID);
+}
+
+#[test]
+fn normal_round_trip() {
+    let message = MessageBuilder::new(1).normal(2, b"hello relay").unwrap();
+    let decoded = round_trip(&message);
+    assert_eq!(decoded, message);
+    assert_eq!(decoded.payload(), b"hello relay");
+    assert_eq!(decoded.destination_uid(), 2);
+}
+
+#[test]
+fn full_payload_round_trip() {
+    let payload = [0xa5; MAX_PAYLOAD_SIZE];
+    let message = MessageBuilder::new(1).normal(2, &payload).unwrap();
+    assert_eq!(message.encoded_len(), MAX_MESSAGE_SIZE);
+    assert_eq!(round_trip(&message).payload(), &payload[..]);
+}
+
+#[test]
+fn ping_with_routes_round_trip() {
+    let routes: RouteAdverts = (0..MAX_ADVERTISED_ROUTES as u16)
+        .map(|i| RouteAdvert {
+            destination_uid: 100 + i,
+            next_hop_uid: i,
+            metric: i as u8,
+        })
+        .collect();
+    let ping = MessageBuilder::new(4).ping_with_routes(routes.clone());
+    let decoded = round_trip(&ping);
+    assert_eq!(decoded, ping);
+    assert_eq!(*decoded.message_type(), MessageType::Ping { routes });
+}
+
+#[test]
+fn pong_round_trip() {
+    let pong = MessageBuilder::new(3).pong(9, 4);
+    let decoded = round_trip(&pong);
+    assert_eq!(decoded, pong);
+    assert_eq!(decoded.destination_uid(), 9);
+    assert!(decoded.payload().is_empty());
+    assert_eq!(
+        *decoded.message_type(),
+        MessageType::Pong {
+            destination_uid: 9,
+            neighbour_count: 4
+        }
+    );
+}
+
+#[test]
+fn oversized_payload_is_rejected() {
+    let payload = [0; MAX_PAYLOAD_SIZE + 1];
+    assert!(MessageBuilder::new(1).normal(2, &payload).is_none());
+}
+
+#[test]
+fn header_layout() {
+    let message = MessageBuilder::new(0x0201).normal(0x0403, &[9, 8]).unwrap();
+    let mut buf = [0u8; MAX_MESSAGE_SIZE];
+    let len = message.encode(&mut buf).unwrap();
+    #[rustfmt::skip]
+    let expected = [
+        PROTOCOL_VERSION, 0, 0x01, 0x02, 0x03, 0x04, 10,
+        0x01, 0x02, 0, 0, DEFAULT_HOP_LIMIT, 0xff, 0xff, 0,
+        9, 8,
+    ];
+    assert_eq!(&buf[..len], &expected);
+}
+
+#[test]
+fn hand_built_messages_round_trip() {
+    let built = MessageBuilder::new(1).normal(2, b"abc").unwrap();
+    let message = Message::new(1, built.message_type().clone());
+    assert_eq!(round_trip(&message), built);
+
+    let pong = Message::new(
+        3,
+        MessageType::Pong {
+            destination_uid: 4,
+            neighbour_count: 5,
+        },
+    );
+    assert_eq!(round_trip(&pong), pong);
+}
+
+#[test]
+fn rejects_normal_lengths_past_the_payload() {
+    let mut message_type = MessageBuilder::new(1)
+        .normal(2, b"abc")
+        .unwrap()
+        .message_type()
+        .clone();
+    if let MessageType::Normal { length, .. } = &mut message_type {
+        *length = MAX_PAYLOAD_SIZE as u8 + 1;
+    }
+    let message = Message::new(1, message_type);
+    let mut buf = [0u8; 2 * MAX_MESSAGE_SIZE];
+    assert_eq!(
+        message.encode(&mut buf),
+        Err(EncodeError::InvalidLength(MAX_PAYLOAD_SIZE as u8 + 1))
+    );
+    assert_eq!(message.payload().len(), MAX_PAYLOAD_SIZE);
+}
+
+#[test]
+fn encode_into_short_buffer() {
+    let message = MessageBuilder::new(1).normal(2, b"abc").unwrap();
+    let mut buf = [0u8; HEADER_SIZE + 2];
+    assert_eq!(message.encode(&mut buf), Err(EncodeError::BufferTooSmall));
+}
+
+#[test]
+fn decode_ignores_trailing_bytes() {
+    let message = MessageBuilder::new(1).normal(2, b"abc").unwrap();
+    let mut buf = [0xffu8; 100];
+    message.encode(&mut buf).unwrap();
+    assert_eq!(Message::decode(&buf).unwrap(), message);
+}
+
+#[test]
+fn decode_errors() {
+    let mut buf = [0u8; MAX_MESSAGE_SIZE];
+    let len = MessageBuilder::new(1)
+        .normal(2, b"abc")
+        .unwrap()
+        .encode(&mut buf)
+        .unwrap();
+
+    assert_eq!(Message::decode(&buf[..3]), Err(DecodeError::Truncated));
+    assert_eq!(
+        Message::decode(&buf[..len - 1]),
+        Err(DecodeError::Truncated)
+    );
+
+    let mut bad = buf;
+    bad[0] = PROTOCOL_VERSION + 1;
+    assert_eq!(
+        Message::decode(&bad),
+        Err(DecodeError::UnsupportedVersion(PROTOCOL_VERSION + 1))
+    );
+
+    let mut bad = buf;
+    bad[1] = 0x7f;
+    assert_eq!(Message::decode(&bad), Err(DecodeError::UnknownType(0x7f)));
+
+    let mut bad = buf;
+    bad[6] = MAX_BODY_SIZE as u8 + 1;
+    assert_eq!(
+        Message::decode(&bad),
+        Err(DecodeError::InvalidLength(MAX_BODY_SIZE as u8 + 1))
+    );
+
+    // Too short to hold the envelope and flags.
+    let mut bad = buf;
+    bad[6] = 7;
+    assert_eq!(Message::decode(&bad), Err(DecodeError::InvalidLength(7)));
+}
+
+#[test]
+fn ping_with_partial_route_is_rejected() {
+    let buf = [PROTOCOL_VERSION, 1, 1, 0, 0xff, 0xff, 3, 0, 0, 0];
+    assert_eq!(Message::decode(&buf), Err(DecodeError::InvalidLength(3)));
+}
+
+#[test]
+fn normal_message_type_exposes_fields() {
+    let message = MessageBuilder::new(1).normal(7, &[1, 2, 3]).unwrap();
+    match message.message_type() {
+        MessageType::Normal {
+            destination_uid,
+            origin_uid,
+            seq,
+            hop_limit,
+            next_hop_uid,
+            ack_requested,
+            attempt,
+            fragment,
+            length,
+            data,
+        } => {
+            assert_eq!(*destination_uid, 7);
+            assert_eq!(*origin_uid, 1);
+            assert_eq!(*seq, 0);
+            assert_eq!(*hop_limit, DEFAULT_HOP_LIMIT);
+            assert_eq!(*next_hop_uid, BROADCAST_UID);
+            assert!(!ack_requested);
+            assert_eq!(*attempt, 0);
+            assert!(!fragment);
+            assert_eq!(*length, 3);
+            assert_eq!(&data[..3], &[1, 2, 3]);
+        }
+        _ => panic!("expected a normal message"),
+    }
+}
+
+#[test]
+fn normal_sequence_numbers_increment() {
+    let mut builder = MessageBuilder::new(1);
+    let seqs: Vec<u16> = (0..3)
+        .map(|_| match *builder.normal(2, b"").unwrap().message_type() {
+            MessageType::Normal { seq, .. } => seq,
+            _ => unreachable!(),
+        })
+        .collect();
+    assert_eq!(seqs, [0, 1, 2]);
+}
+
+#[test]
+fn relayed_message_keeps_origin() {
+    let message = MessageBuilder::new(1)
+        .with_hop_limit(2)
+        .normal(9, b"data")
+        .unwrap();
+
+    let relayed = message.relayed_by(5, 6).unwrap();
+    assert_eq!(relayed.sender_uid(), 5);
+    assert_eq!(relayed.next_hop_uid(), 6);
+    assert_eq!(relayed.origin_uid(), 1);
+    assert_eq!(relayed.destination_uid(), 9);
+    assert_eq!(relayed.payload(), b"data");
+    assert_eq!(round_trip(&relayed), relayed);
+
+    // Only one transmission was allowed after the originator's.
+    assert!(relayed.relayed_by(6, BROADCAST_UID).is_none());
+    assert!(MessageBuilder::new(1)
+        .ping()
+        .relayed_by(5, BROADCAST_UID)
+        .is_none());
+}
+
+#[test]
+fn ack_flag_and_attempt_round_trip() {
+    let message = MessageBuilder::new(1)
+        .normal(2, b"abc")
+        .unwrap()
+        .with_ack_requested();
+    let retry = message.retransmission().retransmission();
+    let decoded = round_trip(&retry);
+    assert_eq!(decoded, retry);
+    assert!(decoded.ack_requested());
+    assert_eq!(decoded.attempt(), 2);
+    assert_eq!(decoded.seq(), message.seq());
+}
+
+#[test]
+fn ack_round_trip() {
+    let mut sender = MessageBuilder::new(1);
+    let mut receiver = MessageBuilder::new(2);
+    sender.normal(2, b"first").unwrap();
+    let message = sender.normal(2, b"second").unwrap();
+
+    let ack = receiver.ack(&message).unwrap();
+    let decoded = round_trip(&ack);
+    assert_eq!(decoded, ack);
+    assert_eq!(decoded.destination_uid(), 1);
+    assert_eq!(decoded.origin_uid(), 2);
+    assert!(decoded.payload().is_empty());
+    match *decoded.message_type() {
+        MessageType::Ack { acked_seq, .. } => assert_eq!(acked_seq, 1),
+        _ => panic!("expected an ack"),
+    }
+    assert!(receiver.ack(&MessageBuilder::new(3).ping()).is_none());
+}
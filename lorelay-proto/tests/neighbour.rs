@@ -0,0 +1,97 @@
+use lorelay_proto::neighbour::{NeighbourTable, Upsert};
+
+const TIMEOUT: u64 = 10_000;
+
+#[test]
+fn upsert_inserts_then_updates() {
+    let mut table = NeighbourTable::<4>::new(TIMEOUT);
+    assert_eq!(table.upsert(1, -80, 5, 0), Upsert::Inserted);
+    assert_eq!(table.upsert(1, -70, 7, 100), Upsert::Updated);
+
+    assert_eq!(table.len(), 1);
+    let neighbour = table.get(1).unwrap();
+    assert_eq!(
+        (neighbour.rssi, neighbour.snr, neighbour.last_seen),
+        (-70, 7, 100)
+    );
+}
+
+#[test]
+fn expired_neighbours_are_not_alive() {
+    let mut table = NeighbourTable::<4>::new(TIMEOUT);
+    table.upsert(1, -80, 0, 0);
+    table.upsert(2, -80, 0, 5_000);
+
+    let alive = |table: &NeighbourTable<4>, now| {
+        let mut uids: Vec<u16> = table.iter_alive(now).map(|n| n.uid).collect();
+        uids.sort();
+        uids
+    };
+    assert_eq!(alive(&table, 9_999), [1, 2]);
+    assert_eq!(alive(&table, 10_000), [2]);
+    assert_eq!(alive(&table, 15_000), [] as [u16; 0]);
+
+    assert_eq!(table.len(), 2);
+    assert_eq!(table.evict_expired(10_000), 1);
+    assert!(table.get(1).is_none());
+    assert!(table.get(2).is_some());
+}
+
+#[test]
+fn full_table_drops_expired_entries_first() {
+    let mut table = NeighbourTable::<2>::new(TIMEOUT);
+    table.upsert(1, -30, 0, 0);
+    table.upsert(2, -100, 0, 8_000);
+
+    // Neighbour 1 is the strongest but has expired, so it goes before the weak live one.
+    assert_eq!(table.upsert(3, -110, 0, 12_000), Upsert::Inserted);
+    assert!(table.get(1).is_none());
+    assert!(table.get(2).is_some());
+    assert!(table.get(3).is_some());
+}
+
+#[test]
+fn full_table_replaces_weakest() {
+    let mut table = NeighbourTable::<3>::new(TIMEOUT);
+    table.upsert(1, -60, 0, 0);
+    table.upsert(2, -90, 0, 0);
+    table.upsert(3, -75, 0, 0);
+
+    let replaced = table.upsert(4, -80, 0, 1_000);
+    match replaced {
+        Upsert::Replaced(evicted) => assert_eq!(evicted.uid, 2),
+        other => panic!("unexpected {:?}", other),
+    }
+    assert_eq!(table.len(), 3);
+
+    // The newcomer is now the weakest entry.
+    match table.upsert(5, -70, 0, 1_000) {
+        Upsert::Replaced(evicted) => assert_eq!(evicted.uid, 4),
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[test]
+fn full_table_rejects_weaker_newcomer() {
+    let mut table = NeighbourTable::<2>::new(TIMEOUT);
+    table.upsert(1, -60, 0, 0);
+    table.upsert(2, -70, 0, 0);
+
+    assert_eq!(table.upsert(3, -70, 0, 0), Upsert::Rejected);
+    assert_eq!(table.upsert(3, -120, 0, 0), Upsert::Rejected);
+    assert!(table.get(3).is_none());
+    assert_eq!(table.len(), table.capacity());
+}
+
+#[test]
+fn best_by_rssi() {
+    let mut table = NeighbourTable::<4>::new(TIMEOUT);
+    assert!(table.best_by_rssi().is_none());
+    table.upsert(1, -90, 0, 0);
+    table.upsert(2, -40, 0, 0);
+    table.upsert(3, -65, 0, 0);
+    assert_eq!(table.best_by_rssi().unwrap().uid, 2);
+
+    table.remove(2);
+    assert_eq!(table.best_by_rssi().unwrap().uid, 3);
+}
@@ -0,0 +1,139 @@
+use lorelay_proto::radio::{
+    Bandwidth, CodingRate, ConfigError, RadioConfig, SpreadingFactor, PRIVATE_SYNC_WORD,
+    PUBLIC_SYNC_WORD,
+};
+
+#[test]
+fn default_is_the_deployed_433_configuration() {
+    let config = RadioConfig::default();
+    assert_eq!(config.validate(), Ok(()));
+    assert_eq!(config.frequency_hz, 433_220_000);
+    assert_eq!(config.spreading_factor, SpreadingFactor::Sf10);
+    assert_eq!(config.bandwidth, Bandwidth::Khz250);
+    assert_eq!(config.coding_rate, CodingRate::Cr4_8);
+    assert!(!config.is_public_network());
+}
+
+#[test]
+fn eu868_configuration_is_valid() {
+    let config = RadioConfig {
+        frequency_hz: 868_100_000,
+        output_power: 14,
+        spreading_factor: SpreadingFactor::Sf7,
+        bandwidth: Bandwidth::Khz125,
+        coding_rate: CodingRate::Cr4_5,
+        preamble_length: 8,
+        ..RadioConfig::default()
+    };
+    assert_eq!(config.validate(), Ok(()));
+}
+
+#[test]
+fn out_of_range_values_are_rejected() {
+    let config = RadioConfig::default();
+    assert_eq!(
+        RadioConfig {
+            frequency_hz: 2_400_000_000,
+            ..config
+        }
+        .validate(),
+        Err(ConfigError::FrequencyOutOfRange(2_400_000_000))
+    );
+    assert_eq!(
+        RadioConfig {
+            output_power: 23,
+            ..config
+        }
+        .validate(),
+        Err(ConfigError::OutputPowerOutOfRange(23))
+    );
+    assert_eq!(
+        RadioConfig {
+            output_power: -10,
+            ..config
+        }
+        .validate(),
+        Err(ConfigError::OutputPowerOutOfRange(-10))
+    );
+    assert_eq!(
+        RadioConfig {
+            preamble_length: 0,
+            ..config
+        }
+        .validate(),
+        Err(ConfigError::PreambleTooShort(0))
+    );
+    assert_eq!(
+        RadioConfig {
+            sync_word: 0x42,
+            ..config
+        }
+        .validate(),
+        Err(ConfigError::UnsupportedSyncWord(0x42))
+    );
+}
+
+#[test]
+fn wide_bandwidths_need_400_mhz() {
+    let config = RadioConfig {
+        frequency_hz: 169_400_000,
+        bandwidth: Bandwidth::Khz125,
+        ..RadioConfig::default()
+    };
+    assert_eq!(config.validate(), Ok(()));
+    for bandwidth in [Bandwidth::Khz250, Bandwidth::Khz500] {
+        assert_eq!(
+            RadioConfig {
+                bandwidth,
+                ..config
+            }
+            .validate(),
+            Err(ConfigError::BandwidthTooWide(bandwidth))
+        );
+    }
+    assert_eq!(
+        RadioConfig {
+            frequency_hz: 400_000_000,
+            bandwidth: Bandwidth::Khz500,
+            ..config
+        }
+        .validate(),
+        Ok(())
+    );
+}
+
+#[test]
+fn low_spreading_factors_need_a_long_preamble() {
+    let config = RadioConfig {
+        spreading_factor: SpreadingFactor::Sf6,
+        preamble_length: 8,
+        ..RadioConfig::default()
+    };
+    assert_eq!(config.validate(), Err(ConfigError::PreambleTooShort(8)));
+    let config = RadioConfig {
+        preamble_length: 12,
+        ..config
+    };
+    assert_eq!(config.validate(), Ok(()));
+}
+
+#[test]
+fn sync_words_select_the_network() {
+    let public = RadioConfig {
+        sync_word: PUBLIC_SYNC_WORD,
+        ..RadioConfig::default()
+    };
+    assert!(public.is_public_network());
+    assert_eq!(RadioConfig::default().sync_word, PRIVATE_SYNC_WORD);
+}
+
+#[test]
+fn enum_values() {
+    let sfs: Vec<u8> = SpreadingFactor::ALL.iter().map(|sf| sf.value()).collect();
+    assert_eq!(sfs, [5, 6, 7, 8, 9, 10, 11, 12]);
+    assert_eq!(SpreadingFactor::from_value(9), Some(SpreadingFactor::Sf9));
+    assert_eq!(SpreadingFactor::from_value(4), None);
+    assert_eq!(SpreadingFactor::from_value(13), None);
+    assert_eq!(Bandwidth::Khz125.hz(), 125_000);
+    assert_eq!(CodingRate::Cr4_7.denominator(), 7);
+}
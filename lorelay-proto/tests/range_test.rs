@@ -0,0 +1,145 @@
+use lorelay_proto::message::EncodeError;
+use lorelay_proto::radio::{PacketStatus, RadioConfig, SpreadingFactor};
+use lorelay_proto::range_test::{
+    Probe, RangeStats, Reception, SignalStats, Summary, PROBE_SIZE, SUMMARY_SIZE,
+};
+
+fn probe(sequence: u32) -> Probe {
+    Probe::new(sequence, &RadioConfig::default())
+}
+
+fn status(rssi: i16, snr: i16) -> PacketStatus {
+    PacketStatus { rssi, snr }
+}
+
+#[test]
+fn probe_round_trip() {
+    let config = RadioConfig {
+        output_power: -3,
+        spreading_factor: SpreadingFactor::Sf12,
+        ..RadioConfig::default()
+    };
+    let probe = Probe::new(0xdead_beef, &config);
+    let mut buf = [0; PROBE_SIZE];
+    assert_eq!(probe.encode(&mut buf), Ok(PROBE_SIZE));
+    assert!(buf.starts_with(b"rt"));
+    assert_eq!(Probe::decode(&buf), Some(probe));
+    assert_eq!(
+        probe.encode(&mut buf[..PROBE_SIZE - 1]),
+        Err(EncodeError::BufferTooSmall)
+    );
+}
+
+#[test]
+fn rejects_other_payloads() {
+    let mut buf = [0; PROBE_SIZE];
+    probe(1).encode(&mut buf).unwrap();
+    assert_eq!(Probe::decode(&buf[..PROBE_SIZE - 1]), None);
+    assert_eq!(Probe::decode(b"hello 1"), None);
+
+    let mut invalid = buf;
+    invalid[11] = 13;
+    assert_eq!(Probe::decode(&invalid), None, "spreading factor");
+    let mut invalid = buf;
+    invalid[13] = 9;
+    assert_eq!(Probe::decode(&invalid), None, "coding rate");
+}
+
+#[test]
+fn records_gaps_and_duplicates() {
+    let mut stats = RangeStats::<10>::new(7);
+    assert_eq!(stats.summary(), None);
+    assert_eq!(stats.packet_error_rate_permille(), None);
+
+    // Probes sent before the first one heard are not counted as lost.
+    assert_eq!(
+        stats.on_probe(&probe(5), status(-80, 8)),
+        Reception::Received { missed: 0 }
+    );
+    assert_eq!(
+        stats.on_probe(&probe(6), status(-90, 2)),
+        Reception::Received { missed: 0 }
+    );
+    assert_eq!(
+        stats.on_probe(&probe(6), status(-70, 9)),
+        Reception::Duplicate
+    );
+    assert_eq!(
+        stats.on_probe(&probe(9), status(-100, -4)),
+        Reception::Received { missed: 2 }
+    );
+
+    let summary = stats.summary().unwrap();
+    assert_eq!(summary.origin_uid, 7);
+    assert_eq!(summary.probe, probe(9));
+    assert_eq!(
+        (summary.received, summary.lost, summary.duplicates),
+        (3, 2, 1)
+    );
+    assert_eq!(summary.packet_error_rate_permille, 400);
+    assert_eq!(
+        summary.rssi,
+        SignalStats {
+            last: -100,
+            min: -100,
+            max: -80,
+            mean: -90
+        }
+    );
+    assert_eq!(
+        summary.snr,
+        SignalStats {
+            last: -4,
+            min: -4,
+            max: 8,
+            mean: 2
+        }
+    );
+}
+
+#[test]
+fn packet_error_rate_covers_the_window_only() {
+    let mut stats = RangeStats::<4>::new(1);
+    stats.on_probe(&probe(0), status(-80, 5));
+    stats.on_probe(&probe(3), status(-80, 5));
+    assert_eq!(stats.packet_error_rate_permille(), Some(500));
+
+    // A gap wider than the window fills it with losses.
+    stats.on_probe(&probe(100), status(-80, 5));
+    assert_eq!(stats.packet_error_rate_permille(), Some(750));
+    assert_eq!(stats.summary().unwrap().lost, 98);
+
+    for sequence in 101..104 {
+        stats.on_probe(&probe(sequence), status(-80, 5));
+    }
+    assert_eq!(stats.packet_error_rate_permille(), Some(0));
+}
+
+#[test]
+fn restarted_sender_starts_over() {
+    let mut stats = RangeStats::<8>::new(1);
+    stats.on_probe(&probe(10), status(-80, 5));
+    stats.on_probe(&probe(12), status(-80, 5));
+    assert_eq!(
+        stats.on_probe(&probe(0), status(-60, 10)),
+        Reception::Restarted
+    );
+
+    let summary = stats.summary().unwrap();
+    assert_eq!((summary.received, summary.lost), (1, 0));
+    assert_eq!(summary.packet_error_rate_permille, 0);
+    assert_eq!(summary.rssi.min, -60);
+}
+
+#[test]
+fn summary_round_trip() {
+    let mut stats = RangeStats::<8>::new(0x1234);
+    stats.on_probe(&probe(1), status(-120, -15));
+    stats.on_probe(&probe(4), status(-30, 12));
+    let summary: Summary = stats.summary().unwrap();
+
+    let mut buf = [0; SUMMARY_SIZE];
+    assert_eq!(summary.encode(&mut buf), Ok(SUMMARY_SIZE));
+    assert_eq!(Summary::decode(&buf), Some(summary));
+    assert_eq!(Summary::decode(&buf[1..]), None);
+}
@@ -0,0 +1,65 @@
+use lorelay_proto::radio::{Bandwidth, RadioConfig};
+use lorelay_proto::region::{Region, RegionError, MAX_SUB_BANDS};
+
+#[test]
+fn default_configs_are_valid_and_compliant() {
+    for region in Region::ALL {
+        let config = region.default_config();
+        assert_eq!(config.validate(), Ok(()), "{region:?}");
+        assert_eq!(region.check(&config), Ok(()), "{region:?}");
+        assert_eq!(region.channel_hz(0), Some(config.frequency_hz));
+        assert!(region.plan().sub_bands.len() <= MAX_SUB_BANDS);
+    }
+}
+
+#[test]
+fn channels() {
+    assert_eq!(Region::Eu868.channel_hz(2), Some(868_500_000));
+    assert_eq!(Region::Eu868.channel_hz(3), None);
+    assert_eq!(Region::Us915.channel_hz(63), Some(914_900_000));
+    for index in 0..64 {
+        let frequency_hz = Region::Us915.channel_hz(index).unwrap();
+        assert!(Region::Us915
+            .sub_band(frequency_hz, Bandwidth::Khz125)
+            .is_some());
+    }
+}
+
+#[test]
+fn sub_bands_of_eu868() {
+    let (i, band) = Region::Eu868
+        .sub_band(869_525_000, Bandwidth::Khz125)
+        .unwrap();
+    assert_eq!(i, 4);
+    assert_eq!(band.duty_cycle_permille, 100);
+    let (_, band) = Region::Eu868
+        .sub_band(868_900_000, Bandwidth::Khz125)
+        .unwrap();
+    assert_eq!(band.duty_cycle_permille, 1);
+    // Straddles the 868 MHz boundary between two sub-bands.
+    assert!(Region::Eu868
+        .sub_band(868_000_000, Bandwidth::Khz125)
+        .is_none());
+    // Between sub-bands.
+    assert!(Region::Eu868
+        .sub_band(869_300_000, Bandwidth::Khz125)
+        .is_none());
+}
+
+#[test]
+fn check_rejects_foreign_frequencies_and_excess_power() {
+    let eu868 = Region::Eu868.default_config();
+    assert_eq!(
+        Region::Eu433.check(&eu868),
+        Err(RegionError::FrequencyNotAllowed(868_100_000))
+    );
+    assert_eq!(
+        Region::Eu868.check(&RadioConfig {
+            output_power: 20,
+            ..eu868
+        }),
+        Err(RegionError::OutputPowerTooHigh(20))
+    );
+    // The deployed 433 MHz configuration, at the EU433 limit.
+    assert_eq!(Region::Eu433.check(&RadioConfig::default()), Ok(()));
+}
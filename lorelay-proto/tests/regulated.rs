@@ -0,0 +1,145 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+
+use lorelay_proto::airtime::time_on_air_us;
+use lorelay_proto::channel_access::{ChannelAccess, ChannelAccessConfig, RandomBackoff};
+use lorelay_proto::duty_cycle::Denied;
+use lorelay_proto::radio::{Radio, RadioConfig};
+use lorelay_proto::region::{Region, RegionError};
+use lorelay_proto::regulated::{Regulated, RegulatedError, MAX_DUTY_CYCLE_WAIT_MS};
+use lorelay_proto::sim::{Link, Medium, SimRadio};
+
+struct NoopWaker;
+
+impl Wake for NoopWaker {
+    fn wake(self: Arc<Self>) {}
+}
+
+fn poll<F: Future + ?Sized>(future: Pin<&mut F>) -> Option<F::Output> {
+    let waker = Waker::from(Arc::new(NoopWaker));
+    match future.poll(&mut Context::from_waker(&waker)) {
+        Poll::Ready(output) => Some(output),
+        Poll::Pending => None,
+    }
+}
+
+/// Runs `future`, moving the clock of `medium` to the end of each wait, and returns when it
+/// completed.
+fn run<F: Future>(medium: &Medium, future: F) -> (F::Output, u64) {
+    let mut future = Box::pin(future);
+    loop {
+        if let Some(output) = poll(future.as_mut()) {
+            return (output, medium.now());
+        }
+        medium.advance_to(medium.next_timer().expect("the future waits for the clock"));
+    }
+}
+
+const LINK: Link = Link {
+    rssi: -90,
+    snr: 5,
+    loss_permille: 0,
+};
+
+type Regulated433<'a> = Regulated<SimRadio<'a>, &'a Medium, RandomBackoff>;
+
+fn regulated(medium: &Medium, config: RadioConfig) -> Regulated433<'_> {
+    let channel_access = ChannelAccess::new(
+        ChannelAccessConfig::default(),
+        RandomBackoff::new(1, 100, 4),
+    );
+    Regulated::new(
+        medium.add_radio(config).unwrap(),
+        medium,
+        Region::Eu433,
+        channel_access,
+    )
+    .unwrap()
+}
+
+#[test]
+fn waits_for_the_duty_cycle() {
+    let medium = Medium::new(0, 1);
+    let config = RadioConfig::default();
+    let mut radio = regulated(&medium, config);
+    assert_eq!(run(&medium, radio.send(&[0; 20])), (Ok(()), 0));
+
+    // 10%: off for ten times the airtime from the start of the frame.
+    let off_ms = (time_on_air_us(&config, 20) + 99) / 100;
+    assert!(off_ms <= MAX_DUTY_CYCLE_WAIT_MS);
+    assert_eq!(run(&medium, radio.send(&[0; 20])), (Ok(()), off_ms));
+    assert_eq!(medium.stats(0).sent, 2);
+}
+
+#[test]
+fn drops_frames_waiting_too_long() {
+    let medium = Medium::new(0, 1);
+    let config = RadioConfig::default();
+    let mut radio = regulated(&medium, config);
+    assert_eq!(run(&medium, radio.send(&[0; 200])), (Ok(()), 0));
+
+    let off_ms = (time_on_air_us(&config, 200) + 99) / 100;
+    assert!(off_ms > MAX_DUTY_CYCLE_WAIT_MS);
+    assert_eq!(
+        run(&medium, radio.send(&[0; 20])),
+        (
+            Err(RegulatedError::Airtime(Denied::Deferred { until: off_ms })),
+            0
+        )
+    );
+    medium.advance_to(off_ms);
+    assert_eq!(run(&medium, radio.send(&[0; 20])).0, Ok(()));
+    assert_eq!(medium.stats(0).sent, 2);
+}
+
+#[test]
+fn listens_before_talking() {
+    let medium = Medium::new(0, 1);
+    let config = RadioConfig::default();
+    let mut radio = regulated(&medium, config);
+    let mut other = medium.add_radio(config).unwrap();
+    medium.set_link(0, 1, Some(LINK));
+
+    let on_air_ms = (time_on_air_us(&config, 100) + 999) / 1_000;
+    assert_eq!(poll(Box::pin(other.send(&[0; 100])).as_mut()), Some(Ok(())));
+    let (sent, at) = run(&medium, radio.send(&[0; 20]));
+    assert_eq!(sent, Ok(()));
+    // Backed off in slots of 100 ms until the other frame was over.
+    assert!(at >= on_air_ms && at % 100 == 0, "{at}");
+    assert_eq!(medium.stats(0).sent, 1);
+}
+
+#[test]
+fn keeps_to_the_region() {
+    let medium = Medium::new(0, 1);
+    let loud = RadioConfig {
+        output_power: 20,
+        ..RadioConfig::default()
+    };
+    let channel_access = ChannelAccess::new(
+        ChannelAccessConfig::default(),
+        RandomBackoff::new(1, 100, 4),
+    );
+    assert!(matches!(
+        Regulated::new(
+            medium.add_radio(loud).unwrap(),
+            &medium,
+            Region::Eu433,
+            channel_access,
+        ),
+        Err(RegulatedError::Region(RegionError::OutputPowerTooHigh(20)))
+    ));
+
+    let mut radio = regulated(&medium, RadioConfig::default());
+    let eu868 = Region::Eu868.default_config();
+    assert_eq!(
+        run(&medium, radio.configure(eu868)).0,
+        Err(RegulatedError::Region(RegionError::FrequencyNotAllowed(
+            eu868.frequency_hz
+        )))
+    );
+    assert_eq!(radio.config(), RadioConfig::default());
+    assert_eq!(radio.region(), Region::Eu433);
+}
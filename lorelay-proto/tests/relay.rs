@@ -0,0 +1,308 @@
+use std::future::Future;
+use std::pin::pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+
+use lorelay_proto::ack::Delivery;
+use lorelay_proto::message::{BROADCAST_UID, MAX_MESSAGE_SIZE};
+use lorelay_proto::radio::{Radio, RadioConfig};
+use lorelay_proto::relay::{Event, Payload, Relay, RelayConfig, SendError};
+use lorelay_proto::sim::{Link, Medium, SimRadio};
+
+const LINK: Link = Link {
+    rssi: -90,
+    snr: 5,
+    loss_permille: 0,
+};
+
+struct Noop;
+
+impl Wake for Noop {
+    fn wake(self: Arc<Self>) {}
+}
+
+/// Polls a future once, with a waker that does nothing: the loop below polls everything anyway.
+fn poll_once<F: Future>(future: F) -> Option<F::Output> {
+    let waker = Waker::from(Arc::new(Noop));
+    let mut cx = Context::from_waker(&waker);
+    match pin!(future).poll(&mut cx) {
+        Poll::Ready(output) => Some(output),
+        Poll::Pending => None,
+    }
+}
+
+struct Node<'a> {
+    radio: SimRadio<'a>,
+    relay: Relay<u32>,
+    events: Vec<Event<u32>>,
+}
+
+struct Network<'a> {
+    medium: &'a Medium,
+    nodes: Vec<Node<'a>>,
+}
+
+impl<'a> Network<'a> {
+    /// Nodes 1 to `count`, each linked to the next one only.
+    fn line(medium: &'a Medium, count: u16) -> Self {
+        let nodes = (1..=count)
+            .map(|uid| Node {
+                radio: medium.add_radio(RadioConfig::default()).unwrap(),
+                relay: Relay::new(uid, RelayConfig::default(), 0, u32::from(uid)),
+                events: Vec::new(),
+            })
+            .collect();
+        for id in 1..usize::from(count) {
+            medium.set_link(id - 1, id, Some(LINK));
+        }
+        Network { medium, nodes }
+    }
+
+    fn node(&mut self, uid: u16) -> &mut Node<'a> {
+        &mut self.nodes[usize::from(uid) - 1]
+    }
+
+    /// Runs every node until the clock reaches `end`.
+    fn run_until(&mut self, end: u64) {
+        loop {
+            let now = self.medium.now();
+            for node in &mut self.nodes {
+                while let Some(received) = poll_once(node.radio.receive()) {
+                    let frame = received.unwrap();
+                    let _ = node
+                        .relay
+                        .on_frame(frame.payload(), frame.status, frame.received_at);
+                }
+                let mut buffer = [0; MAX_MESSAGE_SIZE];
+                while let Some(message) = node.relay.poll(now) {
+                    let len = message.encode(&mut buffer).unwrap();
+                    poll_once(node.radio.send(&buffer[..len])).unwrap().unwrap();
+                }
+                node.events
+                    .extend(std::iter::from_fn(|| node.relay.poll_event()));
+            }
+            let next = self
+                .nodes
+                .iter()
+                .map(|node| node.relay.next_deadline())
+                .chain(self.medium.next_arrival())
+                .min()
+                .unwrap();
+            if next > end {
+                self.medium.advance_to(end);
+                return;
+            }
+            self.medium.advance_to(next.max(now + 1));
+        }
+    }
+}
+
+#[test]
+fn discovers_routes_along_a_line() {
+    let medium = Medium::new(5, 1);
+    let mut network = Network::line(&medium, 4);
+    network.run_until(130_000);
+
+    let relay = &network.node(1).relay;
+    assert_eq!(relay.neighbours().len(), 1);
+    assert!(relay.neighbours().get(2).is_some());
+    let route = relay
+        .routing()
+        .route(4)
+        .expect("no route to the end of the line");
+    assert_eq!(route.next_hop_uid, 2);
+}
+
+#[test]
+fn acknowledged_payload_crosses_the_line() {
+    let medium = Medium::new(5, 2);
+    let mut network = Network::line(&medium, 4);
+    network.run_until(130_000);
+
+    let now = medium.now();
+    network
+        .node(1)
+        .relay
+        .send(4, b"ping", Some(7), now)
+        .unwrap();
+    network.run_until(now + 20_000);
+
+    assert_eq!(
+        network.node(4).events,
+        [Event::Received {
+            origin_uid: 1,
+            payload: Payload::from_slice(b"ping").unwrap(),
+        }]
+    );
+    assert!(matches!(
+        network.node(1).events[..],
+        [Event::Delivery(7, Delivery::Delivered { .. })]
+    ));
+}
+
+#[test]
+fn large_payload_is_fragmented() {
+    let medium = Medium::new(5, 3);
+    let mut network = Network::line(&medium, 3);
+    let payload: Vec<u8> = (0..300).map(|i| i as u8).collect();
+    network.node(1).relay.send(3, &payload, Some(1), 0).unwrap();
+    network.run_until(60_000);
+
+    assert_eq!(
+        network.node(3).events,
+        [Event::Received {
+            origin_uid: 1,
+            payload: Payload::from_slice(&payload).unwrap(),
+        }]
+    );
+    assert!(matches!(
+        network.node(1).events[..],
+        [Event::Delivery(1, Delivery::Delivered { .. })]
+    ));
+}
+
+#[test]
+fn restarted_sender_is_not_taken_for_a_duplicate() {
+    let medium = Medium::new(5, 7);
+    let mut network = Network::line(&medium, 2);
+    network.node(1).relay.send(2, b"one", Some(1), 0).unwrap();
+    network.run_until(20_000);
+
+    let now = medium.now();
+    network.node(1).relay = Relay::new(1, RelayConfig::default(), now, 1234);
+    network.node(1).relay.send(2, b"two", Some(2), now).unwrap();
+    network.run_until(now + 20_000);
+
+    let received: Vec<_> = network
+        .node(2)
+        .events
+        .iter()
+        .map(|event| match event {
+            Event::Received { payload, .. } => &payload[..],
+            Event::Delivery(..) => unreachable!(),
+        })
+        .collect();
+    assert_eq!(received, [&b"one"[..], &b"two"[..]]);
+    assert!(matches!(
+        network.node(1).events[..],
+        [
+            Event::Delivery(1, Delivery::Delivered { .. }),
+            Event::Delivery(2, Delivery::Delivered { .. })
+        ]
+    ));
+}
+
+#[test]
+fn restarted_sender_fragments_again() {
+    let medium = Medium::new(5, 8);
+    let mut network = Network::line(&medium, 2);
+    let first: Vec<u8> = (0..300).map(|i| i as u8).collect();
+    network.node(1).relay.send(2, &first, Some(1), 0).unwrap();
+    network.run_until(20_000);
+
+    let now = medium.now();
+    network.node(1).relay = Relay::new(1, RelayConfig::default(), now, 1234);
+    let second: Vec<u8> = first.iter().rev().copied().collect();
+    network
+        .node(1)
+        .relay
+        .send(2, &second, Some(2), now)
+        .unwrap();
+    network.run_until(now + 20_000);
+
+    assert_eq!(
+        network.node(2).events,
+        [
+            Event::Received {
+                origin_uid: 1,
+                payload: Payload::from_slice(&first).unwrap(),
+            },
+            Event::Received {
+                origin_uid: 1,
+                payload: Payload::from_slice(&second).unwrap(),
+            }
+        ]
+    );
+}
+
+#[test]
+fn unreachable_destination_times_out() {
+    let medium = Medium::new(5, 4);
+    let mut network = Network::line(&medium, 2);
+    network
+        .node(1)
+        .relay
+        .send(9, b"anyone?", Some(3), 0)
+        .unwrap();
+    network.run_until(120_000);
+    assert_eq!(
+        network.node(1).events,
+        [Event::Delivery(3, Delivery::TimedOut)]
+    );
+}
+
+#[test]
+fn broadcasts_are_delivered_but_never_acknowledged() {
+    let medium = Medium::new(5, 5);
+    let mut network = Network::line(&medium, 3);
+    network
+        .node(1)
+        .relay
+        .send(BROADCAST_UID, b"all", Some(5), 0)
+        .unwrap();
+    assert_eq!(
+        network.node(1).relay.poll_event(),
+        Some(Event::Delivery(5, Delivery::TimedOut))
+    );
+    network.run_until(10_000);
+    for uid in [2, 3] {
+        assert!(network.node(uid).events.contains(&Event::Received {
+            origin_uid: 1,
+            payload: Payload::from_slice(b"all").unwrap(),
+        }));
+    }
+
+    let too_large = [0; 100];
+    assert_eq!(
+        network
+            .node(1)
+            .relay
+            .send(BROADCAST_UID, &too_large, Some(6), 0),
+        Err(SendError::TooLarge(100))
+    );
+    assert_eq!(
+        network.node(1).relay.poll_event(),
+        Some(Event::Delivery(6, Delivery::TimedOut))
+    );
+}
+
+#[test]
+fn abandon_times_out_everything_in_flight() {
+    let medium = Medium::new(5, 6);
+    let mut network = Network::line(&medium, 2);
+    let relay = &mut network.node(1).relay;
+    relay.send(2, b"short", Some(1), 0).unwrap();
+    relay.send(2, &[0; 200], Some(2), 0).unwrap();
+    relay.send(2, b"no token", None, 0).unwrap();
+    // The first payload is on air, the transfer has not started yet.
+    assert!(relay.poll(0).is_some());
+    relay.abandon();
+
+    let mut outcomes: Vec<_> = std::iter::from_fn(|| relay.poll_event()).collect();
+    outcomes.sort_by_key(|event| match event {
+        Event::Delivery(token, _) => *token,
+        Event::Received { .. } => u32::MAX,
+    });
+    assert_eq!(
+        outcomes,
+        [
+            Event::Delivery(1, Delivery::TimedOut),
+            Event::Delivery(2, Delivery::TimedOut)
+        ]
+    );
+    // Nothing left to send but the discovery beacons.
+    assert_eq!(
+        relay.next_deadline(),
+        Relay::<u32>::new(1, RelayConfig::default(), 0, 1).next_deadline()
+    );
+}
@@ -0,0 +1,217 @@
+use lorelay_proto::flood::Flood;
+use lorelay_proto::message::{MessageBuilder, RouteAdvert};
+use lorelay_proto::routing::{link_cost, Routes, RoutingTable, INFINITE_METRIC};
+use std::collections::VecDeque;
+
+const TIMEOUT: u64 = 30_000;
+const STRONG: i16 = -70;
+const WEAK: i16 = -120;
+
+/// Nodes with uid `0..`, and the RSSI at which each pair hears each other.
+struct Mesh {
+    tables: Vec<RoutingTable<16>>,
+    links: Vec<(u16, u16, i16)>,
+}
+
+impl Mesh {
+    fn new(len: u16, links: &[(u16, u16, i16)]) -> Self {
+        Mesh {
+            tables: (0..len)
+                .map(|uid| RoutingTable::new(uid, TIMEOUT))
+                .collect(),
+            links: links.to_vec(),
+        }
+    }
+
+    fn grid(side: u16) -> Self {
+        let mut links = Vec::new();
+        for i in 0..side * side {
+            if i % side + 1 < side {
+                links.push((i, i + 1, STRONG));
+            }
+            if i + side < side * side {
+                links.push((i, i + side, STRONG));
+            }
+        }
+        Mesh::new(side * side, &links)
+    }
+
+    fn neighbours(&self, uid: u16) -> impl Iterator<Item = (u16, i16)> + '_ {
+        self.links.iter().filter_map(move |&(a, b, rssi)| {
+            if a == uid {
+                Some((b, rssi))
+            } else if b == uid {
+                Some((a, rssi))
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Every node beacons once, in uid order.
+    fn round(&mut self, now: u64) {
+        for uid in 0..self.tables.len() as u16 {
+            let adverts = self.tables[uid as usize].advertisement();
+            let heard_by: Vec<_> = self.neighbours(uid).collect();
+            for (neighbour, rssi) in heard_by {
+                self.tables[neighbour as usize].on_advertisement(uid, rssi, &adverts, now);
+            }
+        }
+    }
+
+    fn converge(&mut self, now: u64) {
+        for _ in 0..self.tables.len() {
+            self.round(now);
+        }
+    }
+}
+
+#[test]
+fn link_cost_grows_as_rssi_drops() {
+    assert_eq!(link_cost(-40), 1);
+    assert_eq!(link_cost(-90), 1);
+    assert_eq!(link_cost(-91), 2);
+    assert_eq!(link_cost(-110), 3);
+    assert_eq!(link_cost(-130), 4);
+}
+
+#[test]
+fn line_converges() {
+    let mut mesh = Mesh::new(4, &[(0, 1, STRONG), (1, 2, STRONG), (2, 3, STRONG)]);
+    mesh.converge(0);
+
+    assert_eq!(mesh.tables[0].next_hop(3), Some(1));
+    assert_eq!(mesh.tables[0].route(3).unwrap().metric, 3);
+    assert_eq!(mesh.tables[3].next_hop(0), Some(2));
+    assert_eq!(mesh.tables[1].next_hop(0), Some(0));
+    assert_eq!(mesh.tables[0].next_hop(0), None);
+}
+
+#[test]
+fn strong_two_hop_path_beats_weak_direct_link() {
+    let mut mesh = Mesh::new(3, &[(0, 1, STRONG), (1, 2, STRONG), (0, 2, WEAK)]);
+    mesh.converge(0);
+
+    assert_eq!(mesh.tables[0].next_hop(2), Some(1));
+    assert_eq!(mesh.tables[0].route(2).unwrap().metric, 2);
+}
+
+#[test]
+fn poison_reverse_ignores_routes_through_ourselves() {
+    let mut table = RoutingTable::<4>::new(1, TIMEOUT);
+    table.on_neighbour(0, STRONG, 0);
+
+    // Node 2 reaches node 0 through us: that is no route for us.
+    let advert = RouteAdvert {
+        destination_uid: 0,
+        next_hop_uid: 1,
+        metric: 1,
+    };
+    table.on_advertisement(2, STRONG, &[advert], 0);
+    assert_eq!(table.next_hop(0), Some(0));
+
+    // Once the direct link is gone, the poisoned advert must not bring the route back.
+    table.expire(TIMEOUT);
+    table.on_advertisement(2, STRONG, &[advert], TIMEOUT);
+    assert_eq!(table.next_hop(0), None);
+}
+
+#[test]
+fn current_next_hop_is_believed_when_route_worsens() {
+    let mut table = RoutingTable::<4>::new(0, TIMEOUT);
+    let advert = |metric| RouteAdvert {
+        destination_uid: 9,
+        next_hop_uid: 5,
+        metric,
+    };
+    table.on_advertisement(1, STRONG, &[advert(1)], 0);
+    assert_eq!(table.route(9).unwrap().metric, 2);
+
+    table.on_advertisement(1, STRONG, &[advert(5)], 100);
+    assert_eq!(table.route(9).unwrap().metric, 6);
+
+    table.on_advertisement(1, STRONG, &[advert(INFINITE_METRIC)], 200);
+    assert_eq!(table.next_hop(9), None);
+}
+
+#[test]
+fn routes_expire_then_are_forgotten() {
+    let mut table = RoutingTable::<4>::new(0, TIMEOUT);
+    table.on_neighbour(1, STRONG, 0);
+
+    table.expire(TIMEOUT - 1);
+    assert_eq!(table.next_hop(1), Some(1));
+
+    table.expire(TIMEOUT);
+    assert_eq!(table.next_hop(1), None);
+    // Still advertised as unreachable until the hold time is over.
+    assert_eq!(table.advertisement()[0].metric, INFINITE_METRIC);
+
+    table.expire(2 * TIMEOUT);
+    assert!(table.is_empty());
+}
+
+#[test]
+fn reroutes_around_broken_link() {
+    let mut mesh = Mesh::grid(3);
+    mesh.converge(0);
+    let first_hop = mesh.tables[0].next_hop(2).unwrap();
+    assert_eq!(first_hop, 1);
+
+    // 0 1 2
+    // 3 4 5
+    // 6 7 8
+    mesh.links.retain(|&(a, b, _)| (a, b) != (1, 2));
+    for step in 1..=4 {
+        let now = step * TIMEOUT;
+        for table in mesh.tables.iter_mut() {
+            table.expire(now);
+        }
+        mesh.converge(now);
+    }
+
+    assert_eq!(mesh.tables[1].next_hop(2), Some(4));
+    assert_eq!(mesh.tables[0].route(2).unwrap().metric, 4);
+}
+
+#[test]
+fn full_table_keeps_cheapest_routes() {
+    let mut table = RoutingTable::<2>::new(0, TIMEOUT);
+    table.on_neighbour(1, WEAK, 0);
+    table.on_neighbour(2, -100, 0);
+    table.on_neighbour(3, STRONG, 0);
+
+    assert_eq!(table.len(), 2);
+    assert_eq!(table.next_hop(1), None);
+    assert_eq!(table.next_hop(2), Some(2));
+    assert_eq!(table.next_hop(3), Some(3));
+}
+
+#[test]
+fn unicast_follows_the_route() {
+    let mut mesh = Mesh::grid(3);
+    mesh.converge(0);
+    let mut nodes: Vec<Flood<16>> = (0..9).map(Flood::new).collect();
+
+    let message = MessageBuilder::new(0).normal(8, b"hi").unwrap();
+    let message = nodes[0].originate(message, &mesh.tables[0]);
+    assert_eq!(message.next_hop_uid(), mesh.tables[0].next_hop(8).unwrap());
+
+    let mut delivered = Vec::new();
+    let mut transmissions = 0;
+    let mut on_air = VecDeque::from([message]);
+    while let Some(frame) = on_air.pop_front() {
+        transmissions += 1;
+        for (uid, _) in mesh.neighbours(frame.sender_uid()) {
+            let handling = nodes[uid as usize].on_message(&frame, &mesh.tables[uid as usize]);
+            if handling.deliver {
+                delivered.push(uid);
+            }
+            on_air.extend(handling.forward);
+        }
+    }
+
+    assert_eq!(delivered, [8]);
+    // 0 -> x -> y -> z -> 8: only the nodes on the path transmit.
+    assert_eq!(transmissions, 4);
+}
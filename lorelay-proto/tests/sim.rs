@@ -0,0 +1,180 @@
+use std::future::Future;
+use std::pin::pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+
+use lorelay_proto::airtime::time_on_air_us;
+use lorelay_proto::radio::{ConfigError, PacketStatus, Radio, RadioConfig};
+use lorelay_proto::sim::{Link, Medium, SimError};
+
+#[derive(Default)]
+struct Flag(AtomicBool);
+
+impl Wake for Flag {
+    fn wake(self: Arc<Self>) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Polls `future` once, raising `woken` when it asks to be polled again.
+fn poll_once<F: Future>(future: F, woken: &Arc<Flag>) -> Option<F::Output> {
+    let waker = Waker::from(woken.clone());
+    match pin!(future).poll(&mut Context::from_waker(&waker)) {
+        Poll::Ready(output) => Some(output),
+        Poll::Pending => None,
+    }
+}
+
+/// Runs a future that must not wait on the medium.
+fn now<F: Future>(future: F) -> F::Output {
+    poll_once(future, &Arc::default()).expect("future is not ready")
+}
+
+const LINK: Link = Link {
+    rssi: -90,
+    snr: 5,
+    loss_permille: 0,
+};
+
+#[test]
+fn frames_arrive_after_time_on_air_and_latency() {
+    let medium = Medium::new(3, 1);
+    let config = RadioConfig::default();
+    let mut a = medium.add_radio(config).unwrap();
+    let mut b = medium.add_radio(config).unwrap();
+    let mut c = medium.add_radio(config).unwrap();
+    medium.set_link(a.id(), b.id(), Some(LINK));
+
+    now(a.send(b"hello")).unwrap();
+    let arrival = (time_on_air_us(&config, 5) + 999) / 1_000 + 3;
+    assert_eq!(medium.next_arrival(), Some(arrival));
+
+    let woken = Arc::new(Flag::default());
+    let mut receive = Box::pin(b.receive());
+    assert_eq!(poll_once(receive.as_mut(), &woken), None);
+    medium.advance_to(arrival - 1);
+    assert!(!woken.0.load(Ordering::SeqCst));
+    medium.advance(1);
+    assert!(woken.0.load(Ordering::SeqCst));
+    let status = PacketStatus { rssi: -90, snr: 5 };
+    let frame = poll_once(receive, &woken).unwrap().unwrap();
+    assert_eq!(frame.payload(), b"hello");
+    assert_eq!(frame.status, status);
+    assert_eq!(frame.received_at, arrival);
+
+    // Not linked to the sender.
+    assert_eq!(poll_once(c.receive(), &woken), None);
+    assert_eq!(medium.next_arrival(), None);
+    assert_eq!(a.stats().sent, 1);
+    assert_eq!(b.stats().received, 1);
+    assert_eq!(c.stats().received, 0);
+}
+
+#[test]
+fn lossy_links_drop_frames() {
+    let medium = Medium::new(0, 7);
+    let mut a = medium.add_radio(RadioConfig::default()).unwrap();
+    let b = medium.add_radio(RadioConfig::default()).unwrap();
+    let c = medium.add_radio(RadioConfig::default()).unwrap();
+    medium.set_link(
+        0,
+        1,
+        Some(Link {
+            loss_permille: 1000,
+            ..LINK
+        }),
+    );
+    medium.set_link(
+        0,
+        2,
+        Some(Link {
+            loss_permille: 500,
+            ..LINK
+        }),
+    );
+    for _ in 0..1000 {
+        now(a.send(b"x")).unwrap();
+    }
+    assert_eq!(b.stats().lost, 1000);
+    let lost = c.stats().lost;
+    assert!((400..600).contains(&lost), "{lost}");
+
+    medium.set_link(0, 2, None);
+    assert_eq!(medium.link(2, 0), None);
+}
+
+#[test]
+fn radios_only_hear_their_channel() {
+    let medium = Medium::new(0, 1);
+    let config = RadioConfig::default();
+    let mut a = medium.add_radio(config).unwrap();
+    let mut b = medium.add_radio(config).unwrap();
+    medium.set_link(0, 1, Some(LINK));
+    let other = RadioConfig {
+        frequency_hz: 433_420_000,
+        ..config
+    };
+    now(b.configure(other)).unwrap();
+    now(a.send(b"lost")).unwrap();
+    assert_eq!(medium.next_arrival(), None);
+
+    now(a.configure(other)).unwrap();
+    now(a.send(b"heard")).unwrap();
+    medium.advance(10_000);
+    assert_eq!(now(b.receive()).unwrap().payload(), b"heard");
+}
+
+#[test]
+fn invalid_configuration_is_refused() {
+    let medium = Medium::new(0, 1);
+    let config = RadioConfig::default();
+    let mut a = medium.add_radio(config).unwrap();
+    let invalid = RadioConfig {
+        output_power: 30,
+        ..config
+    };
+    assert_eq!(
+        now(a.configure(invalid)),
+        Err(SimError::Invalid(ConfigError::OutputPowerOutOfRange(30)))
+    );
+    assert_eq!(a.config(), config);
+    assert!(medium.add_radio(invalid).is_err());
+}
+
+#[test]
+fn cad_detects_linked_transmissions() {
+    let medium = Medium::new(0, 1);
+    let config = RadioConfig::default();
+    let mut a = medium.add_radio(config).unwrap();
+    let mut b = medium.add_radio(config).unwrap();
+    let mut c = medium.add_radio(config).unwrap();
+    medium.set_link(0, 1, Some(LINK));
+
+    assert_eq!(now(b.cad()), Ok(false));
+    now(a.send(&[0; 40])).unwrap();
+    assert_eq!(now(b.cad()), Ok(true));
+    assert_eq!(now(c.cad()), Ok(false));
+    // The sender does not hear itself.
+    assert_eq!(now(a.cad()), Ok(false));
+
+    medium.advance((time_on_air_us(&config, 40) + 999) / 1_000);
+    assert_eq!(now(b.cad()), Ok(false));
+}
+
+#[test]
+fn long_frames() {
+    let medium = Medium::new(0, 1);
+    let mut a = medium.add_radio(RadioConfig::default()).unwrap();
+    let mut b = medium.add_radio(RadioConfig::default()).unwrap();
+    medium.set_link(0, 1, Some(LINK));
+    assert_eq!(now(a.send(&[0; 256])), Err(SimError::FrameTooLong(256)));
+
+    now(a.send(&[7; 200])).unwrap();
+    medium.advance(10_000);
+    assert_eq!(now(b.receive()).unwrap().payload(), [7; 200]);
+    assert_eq!(
+        a.stats().airtime_us,
+        time_on_air_us(&RadioConfig::default(), 200)
+    );
+}
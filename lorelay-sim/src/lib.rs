@@ -0,0 +1,13 @@
+//! Host simulator of a lorelay network: the relays of `lorelay_proto`, the ones the firmware
+//! runs, on simulated radios placed in a plane.
+//!
+//! Links follow from the distance between nodes through a log-distance path loss model, giving
+//! the RSSI, SNR and loss rate of every pair. Scenarios are read from JSON, see `scenarios/`.
+pub mod network;
+pub mod propagation;
+pub mod report;
+pub mod scenario;
+
+pub use network::simulate;
+pub use report::Report;
+pub use scenario::{Scenario, ScenarioError};
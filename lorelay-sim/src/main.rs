@@ -0,0 +1,49 @@
+//! Runs lorelay scenarios on the host and prints what came out of them.
+//!
+//! `lorelay-sim [--json] <scenario.json>...` exits with an error when a run misses the
+//! expectations of its scenario.
+use std::process::ExitCode;
+
+use lorelay_sim::{simulate, Report, Scenario};
+
+/// Runs the scenario at `path`, returning its report and the expectations it missed.
+fn run(path: &str) -> Result<(Report, Vec<String>), String> {
+    let json = std::fs::read_to_string(path).map_err(|err| err.to_string())?;
+    let scenario = Scenario::from_json(&json).map_err(|err| err.to_string())?;
+    let report = simulate(&scenario).map_err(|err| err.to_string())?;
+    let failures = report.check(&scenario.expect);
+    Ok((report, failures))
+}
+
+fn main() -> ExitCode {
+    let (flags, paths): (Vec<String>, Vec<String>) = std::env::args()
+        .skip(1)
+        .partition(|arg| arg.starts_with("--"));
+    let json = flags.iter().any(|flag| flag == "--json");
+    if paths.is_empty() || flags.iter().any(|flag| flag != "--json") {
+        eprintln!("usage: lorelay-sim [--json] <scenario.json>...");
+        return ExitCode::from(2);
+    }
+
+    let mut status = ExitCode::SUCCESS;
+    for path in &paths {
+        let (report, failures) = match run(path) {
+            Ok(run) => run,
+            Err(err) => {
+                eprintln!("{path}: {err}");
+                return ExitCode::from(2);
+            }
+        };
+        if json {
+            let report = serde_json::to_string_pretty(&report).expect("reports serialize");
+            println!("{report}");
+        } else {
+            println!("{report}");
+        }
+        for failure in failures {
+            eprintln!("{path}: {failure}");
+            status = ExitCode::FAILURE;
+        }
+    }
+    status
+}
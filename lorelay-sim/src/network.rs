@@ -0,0 +1,323 @@
+//! Runs the relays of a scenario against a simulated medium, in virtual time.
+//!
+//! Every node is polled in turn whenever something may have happened: a frame arrived, a relay
+//! deadline or a wait passed, a payload is due or the topology changes. The radios are regulated
+//! as in the firmware, so a send may wait for duty cycle budget or a clear channel; the node then
+//! keeps the send and polls it on the next steps, hearing nothing meanwhile. A single thread runs
+//! the whole network.
+use core::future::Future;
+use core::pin::{pin, Pin};
+use core::task::{Context, Poll};
+use std::sync::Arc;
+use std::task::{Wake, Waker};
+
+use lorelay_proto::ack::Delivery;
+use lorelay_proto::airtime::time_on_air_us;
+use lorelay_proto::channel_access::{ChannelAccess, ChannelAccessConfig, RandomBackoff};
+use lorelay_proto::message::{BROADCAST_UID, MAX_MESSAGE_SIZE};
+use lorelay_proto::radio::{Radio, RadioConfig};
+use lorelay_proto::regulated::Regulated;
+use lorelay_proto::relay::{Event, Relay};
+use lorelay_proto::sim::{Medium, SimRadio};
+
+use crate::propagation;
+use crate::report::{Latency, NodeReport, Report};
+use crate::scenario::{Action, NodeSpec, Scenario, ScenarioError, REGION, SEQUENCE_SIZE};
+
+struct NoopWaker;
+
+impl Wake for NoopWaker {
+    fn wake(self: Arc<Self>) {}
+}
+
+/// Polls `future` once; nothing needs waking since every node is polled on each step.
+fn poll_once<F: Future>(future: F, waker: &Waker) -> Option<F::Output> {
+    match pin!(future).poll(&mut Context::from_waker(waker)) {
+        Poll::Ready(output) => Some(output),
+        Poll::Pending => None,
+    }
+}
+
+type NodeRadio<'a> = Regulated<SimRadio<'a>, &'a Medium, RandomBackoff>;
+
+/// Send still waiting, which gives the radio back once over.
+type PendingSend<'a> = Pin<Box<dyn Future<Output = NodeRadio<'a>> + 'a>>;
+
+struct Node<'a> {
+    uid: u16,
+    /// Id of the radio in the medium.
+    id: usize,
+    /// `None` while `pending` holds it.
+    radio: Option<NodeRadio<'a>>,
+    pending: Option<PendingSend<'a>>,
+    /// Payloads are identified by their index in `Network::payloads`.
+    relay: Relay<u32>,
+}
+
+/// A payload handed to a relay.
+struct Payload {
+    to: u16,
+    sent_at: u64,
+    delivered_at: Option<u64>,
+}
+
+struct Network<'a> {
+    medium: &'a Medium,
+    config: RadioConfig,
+    nodes: Vec<Node<'a>>,
+    positions: Vec<NodeSpec>,
+    partition: Vec<Vec<u16>>,
+    payloads: Vec<Payload>,
+    acknowledged: u32,
+    timed_out: u32,
+}
+
+impl Network<'_> {
+    /// Sets every link from the positions of the nodes and the partition.
+    fn connect(&self, scenario: &Scenario) {
+        let group = |uid: u16| self.partition.iter().position(|group| group.contains(&uid));
+        for (a, first) in self.positions.iter().enumerate() {
+            for (b, second) in self.positions.iter().enumerate().skip(a + 1) {
+                let separated = matches!(
+                    (group(first.uid), group(second.uid)),
+                    (Some(x), Some(y)) if x != y
+                );
+                let distance = (first.x - second.x).hypot(first.y - second.y);
+                let link = propagation::link(&scenario.propagation, &self.config, distance)
+                    .filter(|_| !separated);
+                self.medium.set_link(a, b, link);
+            }
+        }
+    }
+
+    fn apply(&mut self, action: &Action, scenario: &Scenario) {
+        match action {
+            Action::Partition { groups } => self.partition = groups.clone(),
+            Action::Heal => self.partition.clear(),
+            Action::Move { uid, x, y } => {
+                if let Some(node) = self.positions.iter_mut().find(|node| node.uid == *uid) {
+                    node.x = *x;
+                    node.y = *y;
+                }
+            }
+        }
+        self.connect(scenario);
+    }
+
+    fn send(&mut self, index: usize, to: u16, size: usize, ack: bool, now: u64) {
+        let id = self.payloads.len() as u32;
+        let mut payload = vec![0x5a; size];
+        payload[..SEQUENCE_SIZE].copy_from_slice(&id.to_le_bytes());
+        self.payloads.push(Payload {
+            to,
+            sent_at: now,
+            delivered_at: None,
+        });
+        // A refused payload is never delivered, and times out if acknowledged.
+        let _ = self.nodes[index]
+            .relay
+            .send(to, &payload, ack.then_some(id), now);
+    }
+
+    /// Lets every node handle what it received and send what is due.
+    fn step(&mut self, now: u64, waker: &Waker) {
+        let mut buffer = [0; MAX_MESSAGE_SIZE];
+        for node in &mut self.nodes {
+            if let Some(pending) = &mut node.pending {
+                let Poll::Ready(radio) = pending.as_mut().poll(&mut Context::from_waker(waker))
+                else {
+                    continue;
+                };
+                node.radio = Some(radio);
+                node.pending = None;
+            }
+            let radio = node.radio.as_mut().expect("no send is pending");
+            while let Some(received) = poll_once(radio.receive(), waker) {
+                // Simulated radios never fail to receive, and undecodable frames are dropped.
+                if let Ok(frame) = received {
+                    let _ = node
+                        .relay
+                        .on_frame(frame.payload(), frame.status, frame.received_at);
+                }
+            }
+            while let Some(message) = node.relay.poll(now) {
+                let len = message
+                    .encode(&mut buffer)
+                    .expect("buffer is sized for the largest message");
+                let frame = buffer[..len].to_vec();
+                let mut radio = node.radio.take().expect("no send is pending");
+                // Frames the region does not allow or the channel keeps busy are lost.
+                let mut send: PendingSend = Box::pin(async move {
+                    let _ = radio.send(&frame).await;
+                    radio
+                });
+                match send.as_mut().poll(&mut Context::from_waker(waker)) {
+                    Poll::Ready(radio) => node.radio = Some(radio),
+                    Poll::Pending => {
+                        node.pending = Some(send);
+                        break;
+                    }
+                }
+            }
+            while let Some(event) = node.relay.poll_event() {
+                match event {
+                    Event::Received { payload, .. } => {
+                        let Some(id) = payload.get(..SEQUENCE_SIZE) else {
+                            continue;
+                        };
+                        let id = u32::from_le_bytes(id.try_into().unwrap());
+                        let sent = self.payloads.get_mut(id as usize);
+                        if let Some(sent) = sent.filter(|sent| sent.to == node.uid) {
+                            sent.delivered_at.get_or_insert(now);
+                        }
+                    }
+                    Event::Delivery(_, Delivery::Delivered { .. }) => self.acknowledged += 1,
+                    Event::Delivery(_, Delivery::TimedOut) => self.timed_out += 1,
+                }
+            }
+        }
+    }
+
+    fn report(&self, scenario: &Scenario) -> Report {
+        let unicast = self.payloads.iter().filter(|sent| sent.to != BROADCAST_UID);
+        let mut latencies: Vec<u64> = unicast
+            .clone()
+            .filter_map(|sent| Some(sent.delivered_at? - sent.sent_at))
+            .collect();
+        let sent = unicast.count() as u32;
+        let delivered = latencies.len() as u32;
+        let now = self.medium.now();
+        let nodes = self
+            .nodes
+            .iter()
+            .map(|node| {
+                let stats = self.medium.stats(node.id);
+                NodeReport {
+                    uid: node.uid,
+                    frames_sent: stats.sent,
+                    frames_received: stats.received,
+                    frames_lost: stats.lost,
+                    airtime_ms: stats.airtime_us / 1_000,
+                    duty_cycle: stats.airtime_us as f64 / 10.0 / now.max(1) as f64,
+                    neighbours: node.relay.neighbours().iter_alive(now).count(),
+                    routes: node
+                        .relay
+                        .routing()
+                        .iter()
+                        .filter(|route| route.is_reachable())
+                        .count(),
+                }
+            })
+            .collect();
+        Report {
+            name: scenario.name.clone(),
+            duration_ms: scenario.duration_ms,
+            sent,
+            delivered,
+            delivery_ratio: if sent == 0 {
+                1.0
+            } else {
+                f64::from(delivered) / f64::from(sent)
+            },
+            acknowledged: self.acknowledged,
+            timed_out: self.timed_out,
+            latency: Latency::from_samples(&mut latencies),
+            nodes,
+        }
+    }
+}
+
+/// Radio of node `uid`, regulated as in the firmware.
+fn regulated(medium: &Medium, config: RadioConfig, uid: u16, seed: u32) -> NodeRadio<'_> {
+    let radio = medium
+        .add_radio(config)
+        .expect("the configuration is validated");
+    // One backoff slot lets the longest frame through.
+    let slot_ms = time_on_air_us(&config, MAX_MESSAGE_SIZE) / 1_000;
+    let backoff = RandomBackoff::new(seed ^ u32::from(uid), slot_ms, 4);
+    let channel_access = ChannelAccess::new(ChannelAccessConfig::default(), backoff);
+    Regulated::new(radio, medium, REGION, channel_access)
+        .unwrap_or_else(|_| unreachable!("the configuration is checked against the region"))
+}
+
+/// Runs `scenario` to its end.
+pub fn simulate(scenario: &Scenario) -> Result<Report, ScenarioError> {
+    scenario.validate()?;
+    let config = scenario.radio.config()?;
+    let relay_config = scenario.relay.config();
+    let medium = Medium::new(scenario.latency_ms, scenario.seed);
+    let nodes = scenario
+        .nodes
+        .iter()
+        .enumerate()
+        .map(|(id, spec)| Node {
+            uid: spec.uid,
+            id,
+            radio: Some(regulated(&medium, config, spec.uid, scenario.seed)),
+            pending: None,
+            relay: Relay::new(
+                spec.uid,
+                relay_config,
+                0,
+                scenario.seed ^ u32::from(spec.uid).rotate_left(16),
+            ),
+        })
+        .collect();
+    let mut network = Network {
+        medium: &medium,
+        config,
+        nodes,
+        positions: scenario.nodes.clone(),
+        partition: Vec::new(),
+        payloads: Vec::new(),
+        acknowledged: 0,
+        timed_out: 0,
+    };
+    network.connect(scenario);
+
+    let mut changes: Vec<_> = scenario.changes.iter().collect();
+    changes.sort_by_key(|change| change.at_ms);
+    let mut changes = changes.into_iter().peekable();
+    let mut sends: Vec<_> = scenario
+        .traffic
+        .iter()
+        .flat_map(|flow| {
+            (0..u64::from(flow.count)).map(move |i| (flow.start_ms + i * flow.interval_ms, flow))
+        })
+        .collect();
+    sends.sort_by_key(|(at, _)| *at);
+    let mut sends = sends.into_iter().peekable();
+
+    let waker = Waker::from(Arc::new(NoopWaker));
+    loop {
+        let now = medium.now();
+        while let Some(change) = changes.next_if(|change| change.at_ms <= now) {
+            network.apply(&change.action, scenario);
+        }
+        while let Some((_, flow)) = sends.next_if(|(at, _)| *at <= now) {
+            let index = scenario.index_of(flow.from)?;
+            network.send(index, flow.to, flow.size, flow.ack, now);
+        }
+        network.step(now, &waker);
+
+        let next = network
+            .nodes
+            .iter()
+            // Nodes waiting to send do nothing else until the wait is over.
+            .filter(|node| node.pending.is_none())
+            .map(|node| node.relay.next_deadline())
+            .chain(medium.next_arrival())
+            .chain(medium.next_timer())
+            .chain(changes.peek().map(|change| change.at_ms))
+            .chain(sends.peek().map(|(at, _)| *at))
+            .min()
+            .unwrap_or(u64::MAX);
+        if next > scenario.duration_ms {
+            medium.advance_to(scenario.duration_ms);
+            break;
+        }
+        medium.advance_to(next.max(now + 1));
+    }
+
+    Ok(network.report(scenario))
+}
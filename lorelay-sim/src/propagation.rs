@@ -0,0 +1,47 @@
+//! From node positions to the links of the simulated medium.
+use lorelay_proto::airtime::demodulation_floor_db;
+use lorelay_proto::radio::RadioConfig;
+use lorelay_proto::sim::Link;
+
+use crate::scenario::Propagation;
+
+/// Free space path loss over `distance_m` at `frequency_hz`, in dB.
+pub fn free_space_loss_db(frequency_hz: u32, distance_m: f32) -> f32 {
+    20.0 * distance_m.log10() + 20.0 * (frequency_hz as f32).log10() - 147.55
+}
+
+/// Path loss over `distance_m`, distances under a meter counting as one.
+pub fn path_loss_db(model: &Propagation, frequency_hz: u32, distance_m: f32) -> f32 {
+    let reference = model
+        .reference_loss_db
+        .unwrap_or_else(|| free_space_loss_db(frequency_hz, 1.0));
+    reference + 10.0 * model.exponent * distance_m.max(1.0).log10()
+}
+
+/// Thermal noise over the bandwidth of `config`, plus the noise figure, in dBm.
+pub fn noise_floor_dbm(model: &Propagation, config: &RadioConfig) -> f32 {
+    -174.0 + 10.0 * (config.bandwidth.hz() as f32).log10() + model.noise_figure_db
+}
+
+/// Link between two nodes `distance_m` apart, `None` if they cannot hear each other.
+pub fn link(model: &Propagation, config: &RadioConfig, distance_m: f32) -> Option<Link> {
+    let rssi =
+        f32::from(config.output_power) - path_loss_db(model, config.frequency_hz, distance_m);
+    let snr = rssi - noise_floor_dbm(model, config);
+    let margin = snr - demodulation_floor_db(config.spreading_factor);
+    if margin < 0.0 {
+        return None;
+    }
+    let fading = if margin < model.fade_margin_db {
+        1.0 - margin / model.fade_margin_db
+    } else {
+        0.0
+    };
+    let base = f32::from(model.loss_permille.min(1_000));
+    let loss = base + (1_000.0 - base) * fading;
+    Some(Link {
+        rssi: rssi.round() as i16,
+        snr: snr.round() as i16,
+        loss_permille: loss.round() as u16,
+    })
+}
@@ -0,0 +1,128 @@
+//! What came out of a simulation run.
+use core::fmt;
+
+use serde::Serialize;
+
+use crate::scenario::Expectations;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    pub name: String,
+    pub duration_ms: u64,
+    /// Payloads handed to the relays, broadcasts aside.
+    pub sent: u32,
+    /// Payloads that reached their destination.
+    pub delivered: u32,
+    pub delivery_ratio: f64,
+    /// Outcomes of the payloads sent with an acknowledgment.
+    pub acknowledged: u32,
+    pub timed_out: u32,
+    /// Time from sending a payload to its delivery, `None` if none was delivered.
+    pub latency: Option<Latency>,
+    pub nodes: Vec<NodeReport>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Latency {
+    pub min_ms: u64,
+    pub mean_ms: f64,
+    pub p95_ms: u64,
+    pub max_ms: u64,
+}
+
+impl Latency {
+    pub fn from_samples(samples: &mut [u64]) -> Option<Self> {
+        samples.sort_unstable();
+        let (&min_ms, &max_ms) = (samples.first()?, samples.last()?);
+        let p95 = (samples.len() * 95 + 99) / 100;
+        Some(Latency {
+            min_ms,
+            mean_ms: samples.iter().sum::<u64>() as f64 / samples.len() as f64,
+            p95_ms: samples[p95 - 1],
+            max_ms,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NodeReport {
+    pub uid: u16,
+    pub frames_sent: u32,
+    pub frames_received: u32,
+    /// Frames lost on their way to this node.
+    pub frames_lost: u32,
+    pub airtime_ms: u64,
+    /// Share of the run spent transmitting, in percent.
+    pub duty_cycle: f64,
+    /// Neighbours and routes known at the end of the run.
+    pub neighbours: usize,
+    pub routes: usize,
+}
+
+impl Report {
+    /// Describes every expectation the run does not meet.
+    pub fn check(&self, expect: &Expectations) -> Vec<String> {
+        let mut failures = Vec::new();
+        if let Some(min) = expect.min_delivery_ratio {
+            if self.delivery_ratio < min {
+                failures.push(format!(
+                    "delivery ratio {:.3} is under {min}",
+                    self.delivery_ratio
+                ));
+            }
+        }
+        if let Some(max) = expect.max_mean_latency_ms {
+            match self.latency {
+                Some(latency) if latency.mean_ms > max => failures.push(format!(
+                    "mean latency {:.0} ms is over {max} ms",
+                    latency.mean_ms
+                )),
+                Some(_) => {}
+                None => failures.push("no payload delivered to measure latency".into()),
+            }
+        }
+        failures
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} ({} s)", self.name, self.duration_ms / 1_000)?;
+        writeln!(
+            f,
+            "delivered {}/{} ({:.1}%), acknowledged {}, timed out {}",
+            self.delivered,
+            self.sent,
+            self.delivery_ratio * 100.0,
+            self.acknowledged,
+            self.timed_out
+        )?;
+        if let Some(latency) = self.latency {
+            writeln!(
+                f,
+                "latency min {} ms, mean {:.0} ms, p95 {} ms, max {} ms",
+                latency.min_ms, latency.mean_ms, latency.p95_ms, latency.max_ms
+            )?;
+        }
+        writeln!(
+            f,
+            "{:>6} {:>6} {:>6} {:>6} {:>10} {:>6} {:>10} {:>6}",
+            "uid", "sent", "recv", "lost", "airtime ms", "duty %", "neighbours", "routes"
+        )?;
+        for node in &self.nodes {
+            writeln!(
+                f,
+                "{:>6} {:>6} {:>6} {:>6} {:>10} {:>6.2} {:>10} {:>6}",
+                node.uid,
+                node.frames_sent,
+                node.frames_received,
+                node.frames_lost,
+                node.airtime_ms,
+                node.duty_cycle,
+                node.neighbours,
+                node.routes
+            )?;
+        }
+        Ok(())
+    }
+}
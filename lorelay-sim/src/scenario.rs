@@ -0,0 +1,285 @@
+//! Scenarios, as loaded from JSON files: where the nodes are, what they send and how the
+//! topology changes along the way.
+use core::fmt;
+
+use lorelay_proto::message::BROADCAST_UID;
+use lorelay_proto::radio::{ConfigError, RadioConfig, SpreadingFactor};
+use lorelay_proto::region::{Region, RegionError};
+use lorelay_proto::relay::{RelayConfig, PAYLOAD_CAPACITY};
+use serde::Deserialize;
+
+/// Bytes of every generated payload taken by the sequence number identifying it.
+pub const SEQUENCE_SIZE: usize = 4;
+
+/// Region the nodes follow the rules of, the one of the default radio configuration.
+pub const REGION: Region = Region::Eu433;
+
+#[derive(Debug)]
+pub enum ScenarioError {
+    Parse(serde_json::Error),
+    Radio(ConfigError),
+    /// The radio configuration breaks the rules of `REGION`.
+    Region(RegionError),
+    SpreadingFactor(u8),
+    /// Zero and the broadcast address cannot be given to a node.
+    InvalidUid(u16),
+    DuplicateUid(u16),
+    /// A flow or a change refers to a node that is not in the scenario.
+    UnknownNode(u16),
+    /// Payloads carry their sequence number and must fit in a relay payload.
+    PayloadSize(usize),
+}
+
+impl fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScenarioError::Parse(err) => write!(f, "invalid scenario: {err}"),
+            ScenarioError::Radio(err) => write!(f, "invalid radio configuration: {err:?}"),
+            ScenarioError::Region(err) => write!(f, "radio configuration not allowed: {err:?}"),
+            ScenarioError::SpreadingFactor(sf) => write!(f, "no spreading factor {sf}"),
+            ScenarioError::InvalidUid(uid) => write!(f, "uid {uid:#06x} is reserved"),
+            ScenarioError::DuplicateUid(uid) => write!(f, "uid {uid} is given to several nodes"),
+            ScenarioError::UnknownNode(uid) => write!(f, "no node {uid}"),
+            ScenarioError::PayloadSize(size) => write!(
+                f,
+                "payload size {size} is outside {SEQUENCE_SIZE}..={PAYLOAD_CAPACITY}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ScenarioError {}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    #[serde(default)]
+    pub name: String,
+    /// Drives frame losses and the random delays of the nodes.
+    #[serde(default = "default_seed")]
+    pub seed: u32,
+    /// Simulated time, in milliseconds.
+    pub duration_ms: u64,
+    /// Added to the time on air of every frame.
+    #[serde(default)]
+    pub latency_ms: u64,
+    #[serde(default)]
+    pub radio: RadioSettings,
+    #[serde(default)]
+    pub propagation: Propagation,
+    #[serde(default)]
+    pub relay: RelaySettings,
+    pub nodes: Vec<NodeSpec>,
+    #[serde(default)]
+    pub traffic: Vec<Flow>,
+    #[serde(default)]
+    pub changes: Vec<Change>,
+    /// Checked by `Report::check`, so that scenarios can be run as regression tests.
+    #[serde(default)]
+    pub expect: Expectations,
+}
+
+fn default_seed() -> u32 {
+    1
+}
+
+/// Changes to the default radio configuration, shared by every node.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RadioSettings {
+    pub spreading_factor: Option<u8>,
+    /// Transmit power in dBm.
+    pub output_power: Option<i8>,
+}
+
+impl RadioSettings {
+    pub fn config(&self) -> Result<RadioConfig, ScenarioError> {
+        let mut config = RadioConfig::default();
+        if let Some(sf) = self.spreading_factor {
+            config.spreading_factor =
+                SpreadingFactor::from_value(sf).ok_or(ScenarioError::SpreadingFactor(sf))?;
+            config.preamble_length = config.preamble_length.max(config.min_preamble_length());
+        }
+        if let Some(power) = self.output_power {
+            config.output_power = power;
+        }
+        config.validate().map_err(ScenarioError::Radio)?;
+        REGION.check(&config).map_err(ScenarioError::Region)?;
+        Ok(config)
+    }
+}
+
+/// Log-distance path loss model.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct Propagation {
+    /// 2 in free space, 2.7 to 3.5 in built-up areas.
+    pub exponent: f32,
+    /// Loss at one meter, free space loss at the radio frequency if not given.
+    pub reference_loss_db: Option<f32>,
+    /// Noise figure of the receiver.
+    pub noise_figure_db: f32,
+    /// Margin above the demodulation floor under which frames start getting lost, the loss
+    /// growing linearly to all frames at the floor.
+    pub fade_margin_db: f32,
+    /// Frames lost on every link whatever its margin, in thousandths.
+    pub loss_permille: u16,
+}
+
+impl Default for Propagation {
+    fn default() -> Self {
+        Propagation {
+            exponent: 3.0,
+            reference_loss_db: None,
+            noise_figure_db: 6.0,
+            fade_margin_db: 3.0,
+            loss_permille: 0,
+        }
+    }
+}
+
+/// Changes to the default relay configuration, shared by every node.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RelaySettings {
+    pub beacon_interval_ms: Option<u64>,
+    pub beacon_jitter_ms: Option<u64>,
+    /// Milliseconds a neighbour stays in the table after its last frame.
+    pub neighbour_ttl_ms: Option<u64>,
+    pub relay_jitter_ms: Option<u64>,
+    pub ack_timeout_ms: Option<u64>,
+    pub max_retries: Option<u8>,
+}
+
+impl RelaySettings {
+    pub fn config(&self) -> RelayConfig {
+        let mut config = RelayConfig::default();
+        let beacon = &mut config.beacon;
+        beacon.interval = self.beacon_interval_ms.unwrap_or(beacon.interval);
+        beacon.jitter = self.beacon_jitter_ms.unwrap_or(beacon.jitter);
+        beacon.ttl = self.neighbour_ttl_ms.unwrap_or(beacon.ttl);
+        config.relay_jitter = self.relay_jitter_ms.unwrap_or(config.relay_jitter);
+        config.retry.timeout = self.ack_timeout_ms.unwrap_or(config.retry.timeout);
+        config.retry.max_retries = self.max_retries.unwrap_or(config.retry.max_retries);
+        config
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NodeSpec {
+    pub uid: u16,
+    /// Position in meters.
+    pub x: f32,
+    pub y: f32,
+}
+
+/// Payloads sent by a node at regular intervals.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Flow {
+    pub from: u16,
+    /// Destination uid, `65535` to broadcast.
+    pub to: u16,
+    pub start_ms: u64,
+    #[serde(default)]
+    pub interval_ms: u64,
+    #[serde(default = "default_count")]
+    pub count: u32,
+    /// Payload size in bytes, fragmented above a message.
+    #[serde(default = "default_size")]
+    pub size: usize,
+    /// Asks the destination to acknowledge every payload.
+    #[serde(default)]
+    pub ack: bool,
+}
+
+fn default_count() -> u32 {
+    1
+}
+
+fn default_size() -> usize {
+    16
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Change {
+    pub at_ms: u64,
+    #[serde(flatten)]
+    pub action: Action,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Action {
+    /// Cuts every link between nodes of different groups; nodes in no group keep their links.
+    Partition {
+        groups: Vec<Vec<u16>>,
+    },
+    /// Ends the partition.
+    Heal,
+    Move {
+        uid: u16,
+        x: f32,
+        y: f32,
+    },
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Expectations {
+    /// Share of the payloads reaching their destination, broadcasts aside.
+    pub min_delivery_ratio: Option<f64>,
+    pub max_mean_latency_ms: Option<f64>,
+}
+
+impl Scenario {
+    pub fn from_json(json: &str) -> Result<Self, ScenarioError> {
+        let scenario: Scenario = serde_json::from_str(json).map_err(ScenarioError::Parse)?;
+        scenario.validate()?;
+        Ok(scenario)
+    }
+
+    pub fn validate(&self) -> Result<(), ScenarioError> {
+        self.radio.config()?;
+        for (i, node) in self.nodes.iter().enumerate() {
+            if node.uid == 0 || node.uid == BROADCAST_UID {
+                return Err(ScenarioError::InvalidUid(node.uid));
+            }
+            if self.nodes[..i].iter().any(|other| other.uid == node.uid) {
+                return Err(ScenarioError::DuplicateUid(node.uid));
+            }
+        }
+        for flow in &self.traffic {
+            self.index_of(flow.from)?;
+            if flow.to != BROADCAST_UID {
+                self.index_of(flow.to)?;
+            }
+            if !(SEQUENCE_SIZE..=PAYLOAD_CAPACITY).contains(&flow.size) {
+                return Err(ScenarioError::PayloadSize(flow.size));
+            }
+        }
+        for change in &self.changes {
+            match &change.action {
+                Action::Partition { groups } => {
+                    for uid in groups.iter().flatten() {
+                        self.index_of(*uid)?;
+                    }
+                }
+                Action::Heal => {}
+                Action::Move { uid, .. } => {
+                    self.index_of(*uid)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Index of the node `uid`, which is also the id of its radio.
+    pub fn index_of(&self, uid: u16) -> Result<usize, ScenarioError> {
+        self.nodes
+            .iter()
+            .position(|node| node.uid == uid)
+            .ok_or(ScenarioError::UnknownNode(uid))
+    }
+}
@@ -0,0 +1,113 @@
+use lorelay_proto::radio::RadioConfig;
+use lorelay_sim::scenario::Propagation;
+use lorelay_sim::{propagation, simulate, Scenario, ScenarioError};
+
+fn run(file: &str) {
+    let path = format!("{}/scenarios/{file}", env!("CARGO_MANIFEST_DIR"));
+    let scenario = Scenario::from_json(&std::fs::read_to_string(path).unwrap()).unwrap();
+    let report = simulate(&scenario).unwrap();
+    assert_eq!(
+        report.check(&scenario.expect),
+        Vec::<String>::new(),
+        "{report}"
+    );
+}
+
+#[test]
+fn line() {
+    run("line.json");
+}
+
+#[test]
+fn grid() {
+    run("grid.json");
+}
+
+#[test]
+fn partition() {
+    run("partition.json");
+}
+
+#[test]
+fn runs_are_deterministic() {
+    let json = r#"{
+        "duration_ms": 400000,
+        "propagation": { "loss_permille": 200 },
+        "nodes": [{ "uid": 1, "x": 0, "y": 0 }, { "uid": 2, "x": 5000, "y": 0 }],
+        "traffic": [{ "from": 1, "to": 2, "start_ms": 200000, "interval_ms": 5000, "count": 20 }]
+    }"#;
+    let scenario = Scenario::from_json(json).unwrap();
+    let first = simulate(&scenario).unwrap();
+    let second = simulate(&scenario).unwrap();
+    assert_eq!(first.sent, 20);
+    assert!(first.delivered < 20);
+    assert_eq!(first.delivered, second.delivered);
+    assert_eq!(first.latency, second.latency);
+}
+
+#[test]
+fn partitioned_nodes_do_not_hear_each_other() {
+    let json = r#"{
+        "duration_ms": 600000,
+        "nodes": [{ "uid": 1, "x": 0, "y": 0 }, { "uid": 2, "x": 100, "y": 0 }],
+        "traffic": [{ "from": 1, "to": 2, "start_ms": 100000 }],
+        "changes": [{ "at_ms": 0, "action": "partition", "groups": [[1], [2]] }]
+    }"#;
+    let report = simulate(&Scenario::from_json(json).unwrap()).unwrap();
+    assert_eq!(report.delivered, 0);
+    assert!(report.nodes.iter().all(|node| node.frames_received == 0));
+}
+
+#[test]
+fn invalid_scenarios_are_refused() {
+    let nodes = r#"[{ "uid": 1, "x": 0, "y": 0 }, { "uid": 2, "x": 0, "y": 0 }]"#;
+    let scenario = |extra: &str| {
+        Scenario::from_json(&format!(
+            r#"{{ "duration_ms": 1000, "nodes": {nodes} {extra} }}"#
+        ))
+    };
+    assert!(scenario("").is_ok());
+    assert!(matches!(
+        scenario(r#", "traffic": [{ "from": 1, "to": 3, "start_ms": 0 }]"#),
+        Err(ScenarioError::UnknownNode(3))
+    ));
+    assert!(matches!(
+        scenario(r#", "traffic": [{ "from": 1, "to": 2, "start_ms": 0, "size": 600 }]"#),
+        Err(ScenarioError::PayloadSize(600))
+    ));
+    assert!(matches!(
+        scenario(r#", "radio": { "spreading_factor": 13 }"#),
+        Err(ScenarioError::SpreadingFactor(13))
+    ));
+    assert!(matches!(
+        scenario(r#", "colour": "blue""#),
+        Err(ScenarioError::Parse(_))
+    ));
+    assert!(matches!(
+        Scenario::from_json(
+            r#"{ "duration_ms": 1000, "nodes": [{ "uid": 1, "x": 0, "y": 0 }, { "uid": 1, "x": 1, "y": 0 }] }"#
+        ),
+        Err(ScenarioError::DuplicateUid(1))
+    ));
+}
+
+#[test]
+fn links_weaken_with_distance() {
+    let model = Propagation::default();
+    let config = RadioConfig::default();
+    // Free space loss at 433 MHz over a meter.
+    let reference = propagation::free_space_loss_db(config.frequency_hz, 1.0);
+    assert!((reference - 25.2).abs() < 0.1, "{reference}");
+    // 30 dB per decade with the default exponent.
+    let decade = propagation::path_loss_db(&model, config.frequency_hz, 1_000.0)
+        - propagation::path_loss_db(&model, config.frequency_hz, 100.0);
+    assert!((decade - 30.0).abs() < 0.01);
+
+    let near = propagation::link(&model, &config, 1_000.0).unwrap();
+    let far = propagation::link(&model, &config, 6_000.0).unwrap();
+    assert!(far.rssi < near.rssi && far.snr < near.snr);
+    assert_eq!(near.loss_permille, 0);
+    // Within the fade margin of the demodulation floor.
+    assert!((1..1_000).contains(&far.loss_permille), "{far:?}");
+    assert_eq!(propagation::link(&model, &config, 10_000.0), None);
+}